@@ -0,0 +1,136 @@
+use std::error::Error;
+
+use chrono::{DateTime, Local};
+use influxdb::ReadQuery;
+use log::info;
+use n3rgy_rs::models::{EnergyType, RequestType};
+
+use crate::load::DERIVED_MEASUREMENTS;
+use crate::sink::Sink;
+
+/// What `admin delete` removes.
+pub struct Deletion<'a> {
+    /// Measurement readings and prices were written to.
+    pub measurement: &'a str,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub fuel: Option<EnergyType>,
+    pub request_type: Option<RequestType>,
+    pub property: Option<&'a str>,
+}
+
+impl Deletion<'_> {
+    /// A `DELETE` per measurement holding points derived from the readings,
+    /// narrowed to the range and any fuel, type and property given.
+    pub fn statements(&self) -> Vec<String> {
+        let mut conditions = vec![format!(
+            "time >= '{}' AND time <= '{}'",
+            self.start.to_utc().to_rfc3339(),
+            self.end.to_utc().to_rfc3339()
+        )];
+        let tags = [
+            (
+                "fuel",
+                self.fuel.map(|fuel| fuel.to_string().to_lowercase()),
+            ),
+            (
+                "type",
+                self.request_type
+                    .map(|kind| kind.to_string().to_lowercase()),
+            ),
+            ("property", self.property.map(str::to_string)),
+        ];
+        for (tag, value) in tags {
+            if let Some(value) = value {
+                conditions.push(format!("\"{}\" = '{}'", tag, value.replace('\'', "\\'")));
+            }
+        }
+
+        [self.measurement]
+            .iter()
+            .chain(&DERIVED_MEASUREMENTS)
+            .map(|measurement| {
+                format!(
+                    "DELETE FROM \"{}\" WHERE {}",
+                    measurement,
+                    conditions.join(" AND ")
+                )
+            })
+            .collect()
+    }
+}
+
+/// Run the deletion against InfluxDB, or print it for `--dry-run`.
+pub async fn delete(sink: &Sink, deletion: &Deletion<'_>) -> Result<(), Box<dyn Error>> {
+    match sink {
+        Sink::InfluxDb(client) => {
+            for statement in deletion.statements() {
+                info!("{}", statement);
+                client.query(ReadQuery::new(statement)).await?;
+            }
+            Ok(())
+        }
+        Sink::DryRun => {
+            for statement in deletion.statements() {
+                println!("{}", statement);
+            }
+            Ok(())
+        }
+        _ => Err("only InfluxDB supports deleting points".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{TimeZone, Utc};
+    use clap::ValueEnum;
+    use n3rgy_rs::aggregate::Period;
+
+    use crate::load::{
+        CAP_COST_MEASUREMENT, CARBON_MEASUREMENT, COST_MEASUREMENT, DEFAULT_MEASUREMENT,
+        DEGREE_DAYS_MEASUREMENT, EV_SESSION_MEASUREMENT, QUARANTINE_MEASUREMENT, SOLAR_MEASUREMENT,
+        STANDING_CHARGE_MEASUREMENT, UPCOMING_MEASUREMENT,
+    };
+    use crate::tariff_change;
+
+    #[test]
+    fn statements_cover_every_measurement_the_loader_writes() {
+        let deletion = Deletion {
+            measurement: DEFAULT_MEASUREMENT,
+            start: Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap().into(),
+            end: Utc.with_ymd_and_hms(2024, 6, 2, 0, 0, 0).unwrap().into(),
+            fuel: Some(EnergyType::Electricity),
+            request_type: None,
+            property: None,
+        };
+        let statements = deletion.statements();
+        let written = [
+            DEFAULT_MEASUREMENT,
+            COST_MEASUREMENT,
+            CAP_COST_MEASUREMENT,
+            CARBON_MEASUREMENT,
+            QUARANTINE_MEASUREMENT,
+            STANDING_CHARGE_MEASUREMENT,
+            UPCOMING_MEASUREMENT,
+            DEGREE_DAYS_MEASUREMENT,
+            SOLAR_MEASUREMENT,
+            EV_SESSION_MEASUREMENT,
+            tariff_change::MEASUREMENT,
+        ]
+        .into_iter()
+        .chain(Period::value_variants().iter().map(Period::measurement));
+        for measurement in written {
+            let delete = format!("DELETE FROM \"{}\" WHERE ", measurement);
+            assert!(
+                statements.iter().any(|s| s.starts_with(&delete)),
+                "{} is never deleted",
+                measurement
+            );
+        }
+        assert!(statements
+            .iter()
+            .all(|s| s.ends_with("AND \"fuel\" = 'electricity'")));
+    }
+}
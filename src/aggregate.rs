@@ -0,0 +1,435 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Datelike, Days, Duration, DurationRound, NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use clap::ValueEnum;
+use influxdb::{InfluxDbWriteable, WriteQuery};
+
+use crate::cost::Cost;
+use crate::models::{ConsumptionReading, Resource};
+use crate::settlement::{settlement_date, uk_midnight, PERIOD_MINUTES};
+
+/// Calendar period half-hourly readings are rolled up into.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, ValueEnum)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    /// Measurement the period's totals are written to.
+    pub const fn measurement(&self) -> &'static str {
+        match self {
+            Period::Day => "energy_daily",
+            Period::Week => "energy_weekly",
+            Period::Month => "energy_monthly",
+        }
+    }
+
+    /// UK midnight at the start of the day, week (Monday) or month containing `time`.
+    pub fn bucket_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        self.date_start(time.with_timezone(&London).date_naive())
+    }
+
+    /// UK midnight at the start of the day, week or month containing `date`.
+    pub fn date_start(&self, date: NaiveDate) -> DateTime<Utc> {
+        uk_midnight(self.first_day(date))
+    }
+
+    /// First day of the day, week or month containing `date`.
+    pub fn first_day(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Day => date,
+            Period::Week => date - Days::new(date.weekday().num_days_from_monday() as u64),
+            Period::Month => date.with_day(1).unwrap(),
+        }
+    }
+}
+
+/// Coarser interval half-hourly consumption is summed into before it is written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Resample {
+    #[value(name = "1h")]
+    Hour,
+    #[value(name = "1d")]
+    Day,
+}
+
+impl Resample {
+    /// Start of the hour, or UK day, containing `time`.
+    pub fn bucket_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Resample::Hour => time.duration_trunc(Duration::hours(1)).unwrap(),
+            Resample::Day => uk_midnight(time.with_timezone(&London).date_naive()),
+        }
+    }
+
+    /// Start of the bucket after the one starting at `start`.
+    fn bucket_end(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Resample::Hour => start + Duration::hours(1),
+            Resample::Day => uk_midnight(start.with_timezone(&London).date_naive() + Days::new(1)),
+        }
+    }
+
+    /// Written as the `granularity` tag of resampled readings.
+    pub fn granularity(&self) -> &'static str {
+        match self {
+            Resample::Hour => "hour",
+            Resample::Day => "day",
+        }
+    }
+}
+
+/// Totals for one period of one resource.
+#[derive(Clone, Debug)]
+pub struct Aggregate {
+    pub period: Period,
+    pub time: DateTime<Utc>,
+    pub resource: String,
+    pub consumption: f64,
+    pub readings: u64,
+    pub cost: Option<f64>,
+}
+
+impl InfluxDbWriteable for Aggregate {
+    fn into_query<I: Into<String>>(self, name: I) -> WriteQuery {
+        let query = WriteQuery::new(self.time, name)
+            .add_field("consumption", self.consumption)
+            .add_field("readings", self.readings);
+        let mut query = Resource::parse(&self.resource).add_tags(query);
+        if let Some(cost) = self.cost {
+            query = query.add_field("cost", cost);
+        }
+        query
+    }
+}
+
+/// Accumulates readings and costs into per-period totals, ignoring repeats of a
+/// reading already seen (e.g. at window seams).
+pub struct Aggregator {
+    periods: Vec<Period>,
+    since: DateTime<Utc>,
+    seen_readings: HashSet<(String, DateTime<Utc>)>,
+    seen_costs: HashSet<(String, DateTime<Utc>)>,
+    buckets: BTreeMap<(Period, String, DateTime<Utc>), Aggregate>,
+}
+
+impl Aggregator {
+    pub fn new(periods: Vec<Period>) -> Aggregator {
+        Aggregator {
+            periods,
+            since: DateTime::<Utc>::MIN_UTC,
+            seen_readings: HashSet::new(),
+            seen_costs: HashSet::new(),
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Ignore readings and costs stamped at or before `since`, such as the one
+    /// at the start of a range closing the period before it, which would
+    /// otherwise overwrite that period's total with a part of it.
+    pub fn since(mut self, since: DateTime<Utc>) -> Aggregator {
+        self.since = since;
+        self
+    }
+
+    pub fn add_reading(&mut self, reading: &ConsumptionReading) {
+        if reading.time <= self.since
+            || !self
+                .seen_readings
+                .insert((reading.resource.clone(), reading.time))
+        {
+            return;
+        }
+        for period in self.periods.clone() {
+            let bucket = self.bucket(period, &reading.resource, reading.time);
+            bucket.consumption += reading.consumption;
+            bucket.readings += 1;
+        }
+    }
+
+    pub fn add_cost(&mut self, cost: &Cost) {
+        if cost.time <= self.since || !self.seen_costs.insert((cost.resource.clone(), cost.time)) {
+            return;
+        }
+        for period in self.periods.clone() {
+            let bucket = self.bucket(period, &cost.resource, cost.time);
+            *bucket.cost.get_or_insert(0.0) += cost.total;
+        }
+    }
+
+    pub fn finish(self) -> Vec<Aggregate> {
+        self.buckets.into_values().collect()
+    }
+
+    /// The bucket of the reading or cost stamped `time`, the end of the half
+    /// hour or day it covers.
+    fn bucket(&mut self, period: Period, resource: &str, time: DateTime<Utc>) -> &mut Aggregate {
+        let start = period.date_start(settlement_date(time));
+        self.buckets
+            .entry((period, resource.to_string(), start))
+            .or_insert_with(|| Aggregate {
+                period,
+                time: start,
+                resource: resource.to_string(),
+                consumption: 0.0,
+                readings: 0,
+                cost: None,
+            })
+    }
+}
+
+/// A [`Resample`] bucket's summed reading.
+pub struct Resampled {
+    pub reading: ConsumptionReading,
+    /// Whether any reading summed into it was anomalous.
+    pub anomaly: bool,
+}
+
+/// Sums readings into [`Resample`] buckets per resource, ignoring repeats of a
+/// reading already seen (e.g. at window seams) and holding each bucket back
+/// until readings up to its end have been added.
+pub struct Resampler {
+    interval: Resample,
+    since: DateTime<Utc>,
+    seen: HashSet<(String, DateTime<Utc>)>,
+    buckets: BTreeMap<(DateTime<Utc>, String), Resampled>,
+}
+
+impl Resampler {
+    pub fn new(interval: Resample) -> Resampler {
+        Resampler {
+            interval,
+            since: DateTime::<Utc>::MIN_UTC,
+            seen: HashSet::new(),
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Ignore readings stamped at or before `since`, as [`Aggregator::since`].
+    pub fn since(mut self, since: DateTime<Utc>) -> Resampler {
+        self.since = since;
+        self
+    }
+
+    /// Add a half-hourly reading to the bucket its half hour falls in. Buckets
+    /// are stamped at their end, like the readings, take the status of their
+    /// readings when they agree, else `mixed`, and are anomalous when any of
+    /// their readings is.
+    pub fn add(&mut self, reading: ConsumptionReading, anomaly: bool) {
+        if reading.time <= self.since || !self.seen.insert((reading.resource.clone(), reading.time))
+        {
+            return;
+        }
+        let half_hour_start = reading.time - Duration::minutes(PERIOD_MINUTES);
+        let start = self.interval.bucket_start(half_hour_start);
+        match self.buckets.entry((start, reading.resource.clone())) {
+            Entry::Occupied(bucket) => {
+                let bucket = bucket.into_mut();
+                bucket.reading.consumption += reading.consumption;
+                if bucket.reading.status != reading.status {
+                    bucket.reading.status = "mixed".to_string();
+                }
+                bucket.anomaly |= anomaly;
+            }
+            Entry::Vacant(bucket) => {
+                bucket.insert(Resampled {
+                    reading: ConsumptionReading {
+                        time: self.interval.bucket_end(start),
+                        granularity: self.interval.granularity().to_string(),
+                        ..reading
+                    },
+                    anomaly,
+                });
+            }
+        }
+    }
+
+    /// Take the buckets that end at or before `until`, once every reading up
+    /// to `until` has been added.
+    pub fn complete(&mut self, until: DateTime<Utc>) -> Vec<Resampled> {
+        let mut complete = Vec::new();
+        while let Some(entry) = self.buckets.first_entry() {
+            if self.interval.bucket_end(entry.key().0) > until {
+                break;
+            }
+            complete.push(entry.remove());
+        }
+        // only a repeat of the reading at `until` can still arrive
+        self.seen.retain(|(_, time)| *time >= until);
+        complete
+    }
+
+    /// Take the remaining buckets, however complete.
+    pub fn finish(self) -> Vec<Resampled> {
+        self.buckets.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    /// A half-hourly reading of one kWh ending at `time`.
+    fn reading(time: DateTime<Utc>) -> ConsumptionReading {
+        ConsumptionReading {
+            time,
+            consumption: 1.0,
+            resource: "/electricity/consumption/1".to_string(),
+            status: "valid".to_string(),
+            unit: "kWh".to_string(),
+            granularity: "halfhour".to_string(),
+        }
+    }
+
+    /// Every half hour from UK midnight starting `from` to UK midnight
+    /// starting `to`, by the time each ends.
+    fn half_hours(from: NaiveDate, to: NaiveDate) -> Vec<DateTime<Utc>> {
+        let (start, end) = (uk_midnight(from), uk_midnight(to));
+        let mut times = Vec::new();
+        let mut time = start + Duration::minutes(PERIOD_MINUTES);
+        while time <= end {
+            times.push(time);
+            time += Duration::minutes(PERIOD_MINUTES);
+        }
+        times
+    }
+
+    /// Daily totals over `from..to` as `(UK midnight, readings)`.
+    fn daily(from: NaiveDate, to: NaiveDate) -> Vec<(DateTime<Utc>, u64)> {
+        let mut aggregator = Aggregator::new(vec![Period::Day]);
+        for time in half_hours(from, to) {
+            aggregator.add_reading(&reading(time));
+        }
+        aggregator
+            .finish()
+            .into_iter()
+            .map(|day| (day.time, day.readings))
+            .collect()
+    }
+
+    #[test]
+    fn days_follow_the_spring_clock_change() {
+        assert_eq!(
+            daily(date(3, 30), date(4, 1)),
+            vec![
+                (Utc.with_ymd_and_hms(2024, 3, 30, 0, 0, 0).unwrap(), 48),
+                (Utc.with_ymd_and_hms(2024, 3, 31, 0, 0, 0).unwrap(), 46),
+            ]
+        );
+    }
+
+    #[test]
+    fn days_follow_the_autumn_clock_change() {
+        assert_eq!(
+            daily(date(10, 26), date(10, 28)),
+            vec![
+                (Utc.with_ymd_and_hms(2024, 10, 25, 23, 0, 0).unwrap(), 48),
+                (Utc.with_ymd_and_hms(2024, 10, 26, 23, 0, 0).unwrap(), 50),
+            ]
+        );
+    }
+
+    #[test]
+    fn reading_at_midnight_closes_the_day_before() {
+        let mut aggregator = Aggregator::new(vec![Period::Day, Period::Month]);
+        // 00:00 BST on 1 April ends the last half hour of 31 March
+        aggregator.add_reading(&reading(uk_midnight(date(4, 1))));
+        let buckets: Vec<_> = aggregator
+            .finish()
+            .into_iter()
+            .map(|bucket| (bucket.period, bucket.time))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (Period::Day, uk_midnight(date(3, 31))),
+                (Period::Month, uk_midnight(date(3, 1))),
+            ]
+        );
+    }
+
+    /// Buckets of every half hour over `from..to` as `(end, kWh)`.
+    fn resampled(interval: Resample, from: NaiveDate, to: NaiveDate) -> Vec<(DateTime<Utc>, f64)> {
+        let mut resampler = Resampler::new(interval);
+        for time in half_hours(from, to) {
+            resampler.add(reading(time), false);
+        }
+        resampler
+            .complete(uk_midnight(to))
+            .into_iter()
+            .map(|bucket| (bucket.reading.time, bucket.reading.consumption))
+            .collect()
+    }
+
+    #[test]
+    fn resampled_days_follow_the_clock_changes() {
+        let day = Resample::Day;
+        // 31 March is 23 hours long, 27 October 25
+        for (from, hours, kwh) in [(date(3, 30), 23, 46.0), (date(10, 26), 25, 50.0)] {
+            let start = day.bucket_start(uk_midnight(from + Days::new(1)));
+            assert_eq!(day.bucket_end(start) - start, Duration::hours(hours));
+            assert_eq!(
+                resampled(day, from, from + Days::new(2)),
+                vec![(start, 48.0), (day.bucket_end(start), kwh)]
+            );
+        }
+    }
+
+    #[test]
+    fn resampled_hours_stay_an_hour_long_across_the_clock_changes() {
+        let hour = Resample::Hour;
+        for (from, hours) in [(date(3, 30), 47), (date(10, 26), 49)] {
+            let buckets = resampled(hour, from, from + Days::new(2));
+            assert_eq!(buckets.len(), hours);
+            assert_eq!(buckets[0].0, uk_midnight(from) + Duration::hours(1));
+            for pair in buckets.windows(2) {
+                assert_eq!(pair[1].0 - pair[0].0, Duration::hours(1));
+            }
+            assert!(buckets.iter().all(|(_, kwh)| *kwh == 2.0));
+        }
+        // the repeated hour of 27 October starts once in BST and once in GMT
+        let first = Utc.with_ymd_and_hms(2024, 10, 27, 0, 15, 0).unwrap();
+        let second = Utc.with_ymd_and_hms(2024, 10, 27, 1, 15, 0).unwrap();
+        assert_ne!(hour.bucket_start(first), hour.bucket_start(second));
+        assert_eq!(
+            hour.bucket_end(hour.bucket_start(first)),
+            hour.bucket_start(second)
+        );
+    }
+
+    #[test]
+    fn resampled_days_complete_at_uk_midnight() {
+        let mut resampler = Resampler::new(Resample::Day);
+        for time in half_hours(date(10, 26), date(10, 28)) {
+            resampler.add(reading(time), false);
+        }
+        let midnight = uk_midnight(date(10, 27));
+        assert_eq!(
+            midnight,
+            Utc.with_ymd_and_hms(2024, 10, 26, 23, 0, 0).unwrap()
+        );
+        let first: Vec<_> = resampler
+            .complete(midnight - Duration::minutes(PERIOD_MINUTES))
+            .into_iter()
+            .chain(resampler.complete(midnight))
+            .map(|bucket| bucket.reading.time)
+            .collect();
+        assert_eq!(first, vec![midnight]);
+        let rest: Vec<_> = resampler
+            .finish()
+            .into_iter()
+            .map(|bucket| bucket.reading.time)
+            .collect();
+        assert_eq!(rest, vec![uk_midnight(date(10, 28))]);
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use log::{error, warn};
+use n3rgy_rs::models::EnergyType;
+use serde_json::json;
+
+use crate::config::{AlertMetric, AlertRule};
+use crate::load::{Loader, Target};
+use crate::webhook::Webhook;
+
+/// Threshold rules checked against stored data after each daemon sync.
+pub struct Alerts {
+    pub rules: Vec<AlertRule>,
+    /// Where alerts are POSTed, as well as being logged.
+    pub webhook: Option<Webhook>,
+    /// Rules already raised, by rule, target and UK day, so each fires once a day.
+    raised: HashSet<(String, String, NaiveDate)>,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<AlertRule>, webhook: Option<Webhook>) -> Alerts {
+        Alerts {
+            rules,
+            webhook,
+            raised: HashSet::new(),
+        }
+    }
+
+    /// Raise every rule today's totals for `targets` break.
+    pub async fn evaluate(&mut self, loader: &Loader, targets: &[Target], energy_type: EnergyType) {
+        let today = Utc::now().with_timezone(&London).date_naive();
+        for rule in &self.rules {
+            if rule.fuel.is_some_and(|fuel| fuel != energy_type) {
+                continue;
+            }
+            for target in targets {
+                let key = (rule.name.clone(), target.describe(), today);
+                if self.raised.contains(&key) {
+                    continue;
+                }
+                let cost = matches!(rule.metric, AlertMetric::Cost);
+                let total = match loader
+                    .daily_totals(target, energy_type, cost, Utc::now())
+                    .await
+                {
+                    Ok(totals) => totals.last().map(|(_, total)| *total),
+                    Err(e) => {
+                        error!("could not check alert {}: {}", rule.name, e);
+                        continue;
+                    }
+                };
+                let Some(total) = total.filter(|total| *total > rule.daily_above) else {
+                    continue;
+                };
+
+                let text = format!(
+                    "{}: {} {} today is {:.2}, over {:.2}",
+                    rule.name,
+                    target.describe(),
+                    rule.metric,
+                    total,
+                    rule.daily_above
+                );
+                warn!("{}", text);
+                if let Some(webhook) = &self.webhook {
+                    // `text` is what Slack-compatible webhooks display
+                    let body = json!({
+                        "text": text,
+                        "rule": rule.name,
+                        "property": target.label,
+                        "fuel": energy_type.to_string().to_lowercase(),
+                        "metric": rule.metric.to_string(),
+                        "date": today.to_string(),
+                        "value": total,
+                        "threshold": rule.daily_above,
+                    });
+                    webhook.post(&body).await;
+                }
+                self.raised.insert(key);
+            }
+        }
+    }
+}
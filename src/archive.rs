@@ -0,0 +1,164 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io;
+
+use clap::ValueEnum;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use serde_json::Value;
+
+use crate::line_protocol::{self, Point};
+use crate::parquet_file;
+use crate::template::{Template, VARIABLES};
+
+/// Placeholders an object key may use: a point's tags, plus its measurement
+/// and UK local date.
+pub const KEY_VARIABLES: [&str; 8] = [
+    VARIABLES[0],
+    VARIABLES[1],
+    VARIABLES[2],
+    VARIABLES[3],
+    VARIABLES[4],
+    VARIABLES[5],
+    "measurement",
+    "date",
+];
+
+/// Object key used when none is given, before the format's extension.
+pub const DEFAULT_KEY: &str = "{measurement}/fuel={fuel}/date={date}";
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ArchiveFormat {
+    /// A header row then one row per point, with a column per tag and field
+    Csv,
+    /// One `{measurement, time, tags, fields}` object per line
+    Ndjson,
+    /// Zstandard-compressed Parquet, with the same columns as CSV
+    Parquet,
+}
+
+impl ArchiveFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Csv => "csv",
+            ArchiveFormat::Ndjson => "ndjson",
+            ArchiveFormat::Parquet => "parquet",
+        }
+    }
+}
+
+/// Uploads each batch to S3-compatible object storage, one object per key the
+/// batch's points render to. An object already at a key is replaced, so
+/// re-running a window rewrites its objects rather than duplicating them.
+pub struct Archive {
+    pub store: AmazonS3,
+    pub key: Template,
+    pub format: ArchiveFormat,
+}
+
+impl Archive {
+    /// Credentials and region come from the usual `AWS_` environment
+    /// variables; `endpoint` points at other S3-compatible storage, e.g. MinIO.
+    pub fn new(
+        bucket: &str,
+        endpoint: Option<&str>,
+        key: Template,
+        format: ArchiveFormat,
+    ) -> object_store::Result<Archive> {
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+        if let Some(endpoint) = endpoint {
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://"));
+        }
+        Ok(Archive {
+            store: builder.build()?,
+            key,
+            format,
+        })
+    }
+
+    /// Upload points given as line protocol, returning how many were written.
+    pub async fn upload(&self, lines: &[String]) -> Result<usize, Box<dyn Error>> {
+        let mut objects: BTreeMap<String, Vec<Point>> = BTreeMap::new();
+        for line in lines {
+            let point = line_protocol::parse(line)?;
+            objects.entry(self.key(&point)).or_default().push(point);
+        }
+
+        for (key, points) in &objects {
+            let body = match self.format {
+                ArchiveFormat::Csv => csv(points).into_bytes(),
+                ArchiveFormat::Ndjson => points
+                    .iter()
+                    .map(|point| point.to_json().to_string() + "\n")
+                    .collect::<String>()
+                    .into_bytes(),
+                ArchiveFormat::Parquet => parquet_file::write(points)?,
+            };
+            let path =
+                Path::parse(key).map_err(|e| format!("{} is not a valid key: {}", key, e))?;
+            self.store
+                .put(&path, PutPayload::from(body))
+                .await
+                .map_err(|e| io::Error::other(format!("could not upload {}: {}", key, e)))?;
+        }
+        Ok(lines.len())
+    }
+
+    fn key(&self, point: &Point) -> String {
+        let mut values: BTreeMap<&str, String> = KEY_VARIABLES
+            .iter()
+            .filter_map(|name| Some((*name, point.tags.get(*name)?.replace('/', "_"))))
+            .collect();
+        values.insert("measurement", point.measurement.replace('/', "_"));
+        values.insert("date", point.uk_date().to_string());
+        format!("{}.{}", self.key.render(&values), self.format.extension())
+    }
+}
+
+fn csv(points: &[Point]) -> String {
+    let tags: BTreeSet<&str> = points
+        .iter()
+        .flat_map(|point| point.tags.keys().map(String::as_str))
+        .collect();
+    let fields: BTreeSet<&str> = points
+        .iter()
+        .flat_map(|point| point.fields.iter().map(|(field, _)| field.as_str()))
+        .collect();
+
+    let mut header = vec!["time", "measurement"];
+    header.extend(&tags);
+    header.extend(&fields);
+    let mut body = csv_row(header.iter().map(|name| name.to_string()));
+    for point in points {
+        let values = point.fields.iter().cloned().collect::<BTreeMap<_, _>>();
+        let row = [point.time.to_rfc3339(), point.measurement.clone()]
+            .into_iter()
+            .chain(
+                tags.iter()
+                    .map(|tag| point.tags.get(*tag).cloned().unwrap_or_default()),
+            )
+            .chain(fields.iter().map(|field| match values.get(*field) {
+                Some(Value::String(text)) => text.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            }));
+        body.push_str(&csv_row(row));
+    }
+    body
+}
+
+fn csv_row(values: impl Iterator<Item = String>) -> String {
+    let values: Vec<String> = values
+        .map(|value| {
+            if value.contains([',', '"', '\n']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value
+            }
+        })
+        .collect();
+    values.join(",") + "\n"
+}
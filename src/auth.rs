@@ -0,0 +1,78 @@
+use n3rgy_rs::secret::SecretString;
+#[cfg(feature = "keyring")]
+use n3rgy_rs::N3rgyClient;
+
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "n3rgy-rs";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "api-token";
+
+#[cfg(feature = "keyring")]
+fn entry() -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).map_err(|e| e.to_string())
+}
+
+/// The token saved by `auth login`, if there is one.
+#[cfg(feature = "keyring")]
+pub fn stored_token() -> Result<Option<SecretString>, String> {
+    match entry()?.get_password() {
+        Ok(token) => Ok(Some(SecretString::new(token))),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("could not read the OS keyring: {}", e)),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn stored_token() -> Result<Option<SecretString>, String> {
+    Ok(None)
+}
+
+/// Prompt for the API token, check n3rgy accepts it and save it in the OS keyring.
+#[cfg(feature = "keyring")]
+pub async fn login(http_client: &reqwest::Client, base_url: &str) -> Result<(), String> {
+    let token = rpassword::prompt_password("n3rgy API token: ").map_err(|e| e.to_string())?;
+    let token = SecretString::new(token.trim());
+    let client = N3rgyClient::new(token.clone())
+        .with_http_client(http_client.clone())
+        .with_base_url(base_url);
+    if !client.check_access().await.map_err(|e| e.to_string())? {
+        return Err(
+            "n3rgy rejected the token: it is invalid or consent has not been granted".to_string(),
+        );
+    }
+    entry()?
+        .set_password(token.expose())
+        .map_err(|e| format!("could not save the token to the OS keyring: {}", e))?;
+    println!("Token saved to the OS keyring.");
+    Ok(())
+}
+
+#[cfg(not(feature = "keyring"))]
+pub async fn login(_http_client: &reqwest::Client, _base_url: &str) -> Result<(), String> {
+    Err(not_supported())
+}
+
+/// Remove the token saved by `auth login`.
+#[cfg(feature = "keyring")]
+pub fn logout() -> Result<(), String> {
+    match entry()?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {
+            println!("Token removed from the OS keyring.");
+            Ok(())
+        }
+        Err(e) => Err(format!(
+            "could not remove the token from the OS keyring: {}",
+            e
+        )),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn logout() -> Result<(), String> {
+    Err(not_supported())
+}
+
+#[cfg(not(feature = "keyring"))]
+fn not_supported() -> String {
+    "this build of n3rgy-rs has no keyring support".to_string()
+}
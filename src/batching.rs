@@ -0,0 +1,104 @@
+use chrono::{DateTime, Duration, Local, Utc};
+use log::debug;
+
+/// The n3rgy API rejects requests spanning more than 90 days.
+pub const MAX_WINDOW_DAYS: i64 = 90;
+
+/// A `(start, end)` pair covering a single API request.
+pub type Window = (DateTime<Local>, DateTime<Local>);
+
+/// The resolution of the API's `start` and `end` parameters. n3rgy includes
+/// readings at both ends of a range, so windows stop this far short of the next
+/// window's start.
+const SEAM: Duration = Duration::minutes(1);
+
+/// Split `start..end` into consecutive windows of at most `chunk_days` days,
+/// capped at [`MAX_WINDOW_DAYS`].
+///
+/// Windows after the first start on a UTC midnight, and each window ends just
+/// before the next begins so the reading on the boundary is only requested once.
+pub fn date_windows(start: DateTime<Local>, end: DateTime<Local>, chunk_days: i64) -> Vec<Window> {
+    let chunk = Duration::days(chunk_days.clamp(1, MAX_WINDOW_DAYS));
+    let mut windows = Vec::new();
+    let mut window_start = start;
+    let mut boundary = utc_midnight(start) + chunk;
+    while boundary < end {
+        windows.push((window_start, boundary - SEAM));
+        window_start = boundary;
+        boundary += chunk;
+    }
+    windows.push((window_start, end));
+    if windows.len() > 1 {
+        debug!(
+            "requested more than {} days of data, chunking requests",
+            chunk.num_days()
+        );
+    }
+    windows
+}
+
+/// The UTC midnight at or before `time`.
+fn utc_midnight(time: DateTime<Local>) -> DateTime<Local> {
+    time.with_timezone(&Utc)
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .with_timezone(&Local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Utc.with_ymd_and_hms(2024, 1, day, hour, minute, 0)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn short_range_is_one_window() {
+        let (start, end) = (at(1, 0, 0), at(3, 0, 0));
+        assert_eq!(date_windows(start, end, 7), vec![(start, end)]);
+    }
+
+    #[test]
+    fn splits_into_chunks() {
+        let windows = date_windows(at(1, 0, 0), at(22, 0, 0), 7);
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0].0, at(1, 0, 0));
+        assert_eq!(windows[1].0, at(8, 0, 0));
+        assert_eq!(windows[2], (at(15, 0, 0), at(22, 0, 0)));
+    }
+
+    #[test]
+    fn later_windows_start_on_utc_midnight() {
+        let windows = date_windows(at(1, 13, 30), at(10, 6, 0), 2);
+        assert_eq!(windows[0].0, at(1, 13, 30));
+        for (start, _) in &windows[1..] {
+            assert_eq!(start.with_timezone(&Utc).time(), chrono::NaiveTime::MIN);
+        }
+        assert_eq!(windows[1].0, at(3, 0, 0));
+        assert_eq!(windows.last().unwrap().1, at(10, 6, 0));
+    }
+
+    #[test]
+    fn windows_stop_a_minute_short_of_the_next() {
+        let windows = date_windows(at(1, 0, 0), at(10, 0, 0), 3);
+        for pair in windows.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0 - SEAM);
+        }
+    }
+
+    #[test]
+    fn clamps_chunk_days() {
+        let start = at(1, 0, 0);
+        assert_eq!(date_windows(start, at(4, 0, 0), 0).len(), 3);
+        let end = start + Duration::days(MAX_WINDOW_DAYS + 1);
+        let windows = date_windows(start, end, 365);
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows[1].0, start + Duration::days(MAX_WINDOW_DAYS));
+    }
+}
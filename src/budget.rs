@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use n3rgy_rs::models::Resource;
+use n3rgy_rs::settlement::uk_midnight;
+use serde::Serialize;
+
+use crate::load::COST_MEASUREMENT;
+use crate::sink::Sink;
+
+#[derive(Serialize)]
+pub struct BudgetReport {
+    /// E.g. `2024-01`.
+    pub month: String,
+    pub month_to_date: f64,
+    /// Last UK day with cost stored, which the projection runs on from.
+    pub data_until: Option<NaiveDate>,
+    /// Average daily cost over the recent days the projection uses.
+    pub daily_average: f64,
+    pub projected: f64,
+    pub budget: Option<f64>,
+    /// Budget left after the projected spend, negative when over.
+    pub projected_remaining: Option<f64>,
+}
+
+/// Sum this month's stored cost, project it to the month end from the average
+/// of the last `recent_days` days with cost, and compare it with `budget`.
+pub async fn run(
+    sink: &Sink,
+    tags: &BTreeMap<String, String>,
+    budget: Option<f64>,
+    recent_days: u32,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let today = Utc::now().with_timezone(&London).date_naive();
+    let report = build_report(sink, tags, budget, recent_days, today).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_table(&report);
+    }
+    Ok(())
+}
+
+async fn build_report(
+    sink: &Sink,
+    tags: &BTreeMap<String, String>,
+    budget: Option<f64>,
+    recent_days: u32,
+    today: NaiveDate,
+) -> Result<BudgetReport, Box<dyn Error>> {
+    let month_start = today.with_day(1).unwrap();
+    let month_end = month_start
+        .checked_add_months(chrono::Months::new(1))
+        .unwrap();
+    // the average may reach back into last month early on
+    let from = month_start.min(today - Duration::days(recent_days.into()));
+    let days: Vec<(NaiveDate, f64)> = sink
+        .daily_sums(
+            COST_MEASUREMENT,
+            "total",
+            &Resource::default(),
+            tags,
+            uk_midnight(from),
+        )
+        .await?
+        .into_iter()
+        .map(|(time, sum)| (uk_date(time), sum))
+        .collect();
+
+    let month_to_date = days
+        .iter()
+        .filter(|(date, _)| *date >= month_start)
+        .map(|(_, sum)| sum)
+        .sum();
+    let data_until = days.iter().map(|(date, _)| *date).max();
+    let recent: Vec<f64> = days
+        .iter()
+        .rev()
+        .take(recent_days as usize)
+        .map(|(_, sum)| *sum)
+        .collect();
+    let daily_average = if recent.is_empty() {
+        0.0
+    } else {
+        recent.iter().sum::<f64>() / recent.len() as f64
+    };
+    let projected_from = data_until.map_or(month_start, |date| {
+        (date + Duration::days(1)).max(month_start)
+    });
+    let days_left = (month_end - projected_from).num_days().max(0);
+    let projected = month_to_date + daily_average * days_left as f64;
+
+    Ok(BudgetReport {
+        month: month_start.format("%Y-%m").to_string(),
+        month_to_date,
+        data_until,
+        daily_average,
+        projected,
+        budget,
+        projected_remaining: budget.map(|budget| budget - projected),
+    })
+}
+
+/// The UK date of a day bucket InfluxDB starts at local midnight.
+fn uk_date(time: DateTime<Utc>) -> NaiveDate {
+    time.with_timezone(&London).date_naive()
+}
+
+fn print_table(report: &BudgetReport) {
+    println!("Budget report for {}", report.month);
+    let row = |label: &str, value: f64| println!("{:<20} {:>12.2}", label, value);
+    row("Month to date", report.month_to_date);
+    row("Daily average", report.daily_average);
+    row("Projected", report.projected);
+    if let (Some(budget), Some(remaining)) = (report.budget, report.projected_remaining) {
+        row("Budget", budget);
+        row("Projected remaining", remaining);
+        if remaining < 0.0 {
+            println!("Projected to exceed the budget by {:.2}", -remaining);
+        }
+    }
+    match report.data_until {
+        Some(date) => println!("Cost stored up to {}", date),
+        None => println!("No cost stored yet, load it with --compute-cost"),
+    }
+}
@@ -0,0 +1,130 @@
+//! Raw n3rgy data responses kept on disk for a while, so re-runs, e.g. into a
+//! new sink, and requests inside a window fetched recently don't go back to
+//! the API.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration as StdDuration, SystemTime};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+const RANGE_FORMAT: &str = "%Y%m%d%H%M";
+
+#[derive(Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: StdDuration,
+    refresh: bool,
+}
+
+impl ResponseCache {
+    /// Responses saved under `dir` are served for `ttl` after they were fetched.
+    pub fn new(dir: impl Into<PathBuf>, ttl: StdDuration) -> ResponseCache {
+        ResponseCache {
+            dir: dir.into(),
+            ttl,
+            refresh: false,
+        }
+    }
+
+    /// Always fetch from the API, still saving what comes back.
+    pub fn refreshing(mut self) -> ResponseCache {
+        self.refresh = true;
+        self
+    }
+
+    /// A fresh response for `series`, e.g.
+    /// `<meter key>/electricity_consumption_1_halfhour`, whose range covers `start..end`, preferring one for exactly that range.
+    /// Returns the body and whether it was for exactly that range.
+    pub(crate) fn lookup(
+        &self,
+        series: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> io::Result<Option<(String, bool)>> {
+        if self.refresh {
+            return Ok(None);
+        }
+        let entries = match fs::read_dir(self.dir.join(series)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut best: Option<(PathBuf, bool)> = None;
+        for entry in entries {
+            let entry = entry?;
+            let Some((cached_start, cached_end)) = parse_name(&entry.file_name().to_string_lossy())
+            else {
+                continue;
+            };
+            if cached_start > start || cached_end < end || !self.fresh(&entry.metadata()?) {
+                continue;
+            }
+            let exact = cached_start == start && cached_end == end;
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_exact)| exact && !best_exact)
+            {
+                best = Some((entry.path(), exact));
+            }
+        }
+        match best {
+            Some((path, exact)) => Ok(Some((fs::read_to_string(path)?, exact))),
+            None => Ok(None),
+        }
+    }
+
+    /// Keep the body of a successful response for `series` over `start..end`.
+    pub(crate) fn store(
+        &self,
+        series: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        body: &str,
+    ) -> io::Result<()> {
+        let dir = self.dir.join(series);
+        fs::create_dir_all(&dir)?;
+        let name = format!(
+            "{}_{}.json",
+            start.format(RANGE_FORMAT),
+            end.format(RANGE_FORMAT)
+        );
+        // written aside and renamed, so a concurrent lookup never reads half a body
+        let partial = dir.join(format!("{}.partial", name));
+        fs::write(&partial, body)?;
+        fs::rename(partial, dir.join(name))
+    }
+
+    fn fresh(&self, metadata: &fs::Metadata) -> bool {
+        metadata
+            .modified()
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age <= self.ttl)
+    }
+}
+
+/// Directory name for one meter's responses from one deployment of the API: a
+/// 64-bit FNV-1a hash of the base URL and token, so the token isn't written to
+/// disk and stays the same across builds.
+pub(crate) fn meter_key(base_url: &str, token: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in base_url.bytes().chain([0]).chain(token.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// The range in a name written by [`ResponseCache::store`].
+fn parse_name(name: &str) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let (start, end) = name.strip_suffix(".json")?.split_once('_')?;
+    let parse = |value| {
+        NaiveDateTime::parse_from_str(value, RANGE_FORMAT)
+            .ok()
+            .map(|time| time.and_utc())
+    };
+    Some((parse(start)?, parse(end)?))
+}
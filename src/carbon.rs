@@ -0,0 +1,213 @@
+//! Client for the National Grid ESO Carbon Intensity API, giving the grams of
+//! CO₂ emitted per kWh of GB electricity each half hour, nationally or for the
+//! region around a postcode.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use influxdb::InfluxDbWriteable;
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::conversion::is_cubic_metres;
+use crate::error::Error;
+use crate::models::ConsumptionReading;
+
+pub const CARBON_INTENSITY_BASE_URL: &str = "https://api.carbonintensity.org.uk/";
+
+/// Longest range the API answers in one request.
+const MAX_DAYS: i64 = 14;
+/// How the API writes times, e.g. `2024-01-20T12:30Z`.
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%MZ";
+
+#[derive(Deserialize)]
+struct National {
+    data: Vec<Period>,
+}
+
+#[derive(Deserialize)]
+struct Regional {
+    data: Region,
+}
+
+#[derive(Deserialize)]
+struct Region {
+    data: Vec<Period>,
+}
+
+#[derive(Deserialize)]
+struct Period {
+    to: String,
+    intensity: Intensity,
+}
+
+#[derive(Deserialize)]
+struct Intensity {
+    forecast: Option<f64>,
+    actual: Option<f64>,
+}
+
+/// A half-hourly electricity reading's carbon footprint.
+#[derive(InfluxDbWriteable, Clone, Debug)]
+pub struct Emissions {
+    pub time: DateTime<Utc>,
+    pub consumption: f64,
+    /// Grid carbon intensity over the half hour, in gCO₂/kWh.
+    pub intensity: f64,
+    /// `consumption × intensity`, in gCO₂.
+    pub emissions: f64,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    ///
+    /// [`Resource::add_tags`]: crate::models::Resource::add_tags
+    #[influxdb(ignore)]
+    pub resource: String,
+}
+
+#[derive(Clone)]
+pub struct CarbonIntensityClient {
+    http: reqwest::Client,
+    base_url: String,
+    /// Outward code of the postcode whose regional forecast is used instead of
+    /// the national figures.
+    postcode: Option<String>,
+}
+
+impl Default for CarbonIntensityClient {
+    fn default() -> Self {
+        CarbonIntensityClient::new()
+    }
+}
+
+impl CarbonIntensityClient {
+    pub fn new() -> CarbonIntensityClient {
+        CarbonIntensityClient {
+            http: reqwest::Client::new(),
+            base_url: CARBON_INTENSITY_BASE_URL.to_string(),
+            postcode: None,
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> CarbonIntensityClient {
+        self.http = http;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> CarbonIntensityClient {
+        let mut base_url = base_url.into();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        self.base_url = base_url;
+        self
+    }
+
+    /// Use the regional forecast for `postcode`, full or just its outward code,
+    /// e.g. `RG10 1AA` or `RG10`. Regions only have forecasts, not actuals.
+    pub fn with_postcode(mut self, postcode: &str) -> CarbonIntensityClient {
+        let postcode = postcode.trim().to_uppercase();
+        let outward = match postcode.split_once(' ') {
+            Some((outward, _)) => outward.to_string(),
+            // an inward code is always three characters
+            None if postcode.len() > 4 => postcode[..postcode.len() - 3].to_string(),
+            None => postcode,
+        };
+        self.postcode = Some(outward);
+        self
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        let error = |reason: String| Error::CarbonIntensity {
+            url: url.to_string(),
+            reason,
+        };
+        let res = self
+            .http
+            .get(url)
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .map_err(|e| error(e.to_string()))?;
+        if !res.status().is_success() {
+            return Err(error(format!("responded {}", res.status())));
+        }
+        let body = res.text().await.map_err(|e| error(e.to_string()))?;
+        serde_json::from_str(&body).map_err(|source| Error::UnexpectedBody {
+            url: url.to_string(),
+            source,
+        })
+    }
+
+    /// The intensity of each half hour ending between `start` and `end`, keyed
+    /// by its end as readings are. Nationally this is the actual intensity
+    /// where it's been measured, else the forecast.
+    pub async fn intensity(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<BTreeMap<DateTime<Utc>, f64>, Error> {
+        let mut intensities = BTreeMap::new();
+        let mut from = start - Duration::minutes(30);
+        while from < end {
+            let to = std::cmp::min(from + Duration::days(MAX_DAYS), end);
+            let range = format!("{}/{}", from.format(TIME_FORMAT), to.format(TIME_FORMAT));
+            let periods = match &self.postcode {
+                Some(postcode) => {
+                    let url = format!(
+                        "{}regional/intensity/{}/postcode/{}",
+                        self.base_url, range, postcode
+                    );
+                    self.get::<Regional>(&url).await?.data.data
+                }
+                None => {
+                    let url = format!("{}intensity/{}", self.base_url, range);
+                    self.get::<National>(&url).await?.data
+                }
+            };
+            for period in periods {
+                let Ok(time) = NaiveDateTime::parse_from_str(&period.to, TIME_FORMAT) else {
+                    debug!("skipping a period ending {}", period.to);
+                    continue;
+                };
+                let time = time.and_utc();
+                if let Some(intensity) = period.intensity.actual.or(period.intensity.forecast) {
+                    if start <= time && time <= end {
+                        intensities.insert(time, intensity);
+                    }
+                }
+            }
+            from = to;
+        }
+        Ok(intensities)
+    }
+}
+
+/// The emissions of each kWh reading with a known intensity; readings in m³
+/// are skipped.
+pub fn carbon_emissions<C>(
+    consumption: C,
+    intensities: &BTreeMap<DateTime<Utc>, f64>,
+) -> Vec<Emissions>
+where
+    C: IntoIterator,
+    C::Item: Borrow<ConsumptionReading>,
+{
+    consumption
+        .into_iter()
+        .filter_map(|reading| {
+            let reading = reading.borrow();
+            if is_cubic_metres(&reading.unit) {
+                return None;
+            }
+            let intensity = *intensities.get(&reading.time)?;
+            Some(Emissions {
+                time: reading.time,
+                consumption: reading.consumption,
+                intensity,
+                emissions: reading.consumption * intensity,
+                resource: reading.resource.clone(),
+            })
+        })
+        .collect()
+}
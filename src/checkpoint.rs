@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+
+/// The end of the last window loaded for each fuel, data type and element,
+/// persisted so an interrupted run can resume where it stopped.
+pub struct Checkpoint {
+    path: PathBuf,
+    loaded: Mutex<BTreeMap<String, DateTime<Utc>>>,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint file, starting afresh if it doesn't exist yet.
+    pub fn open(path: PathBuf) -> io::Result<Checkpoint> {
+        let loaded = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Checkpoint {
+            path,
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<DateTime<Utc>> {
+        self.loaded.lock().unwrap().get(key).copied()
+    }
+
+    /// Record that everything up to `end` has been loaded for `key`.
+    pub fn record(&self, key: &str, end: DateTime<Utc>) {
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.insert(key.to_string(), end);
+        let result = serde_json::to_string_pretty(&*loaded)
+            .map_err(io::Error::from)
+            .and_then(|contents| fs::write(&self.path, contents));
+        if let Err(e) = result {
+            warn!(
+                "could not save checkpoint to {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
@@ -1,36 +1,1791 @@
-use chrono::{DateTime, Local};
-use clap::{builder::TypedValueParser, Parser};
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Mutex;
 
-use crate::models::{EnergyType, RequestType};
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use clap::parser::ValueSource;
+use clap::{
+    builder::{Resettable, TypedValueParser},
+    ArgGroup, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
+use clap_complete::Shell;
+use croner::Cron;
+use log::{error, warn};
+
+use n3rgy_rs::aggregate::{Period, Resample};
+use n3rgy_rs::carbon::CARBON_INTENSITY_BASE_URL;
+use n3rgy_rs::client::{Window, MAX_WINDOW_DAYS, N3RGY_BASE_URL, N3RGY_SANDBOX_URL};
+use n3rgy_rs::conversion::{DEFAULT_CALORIFIC_VALUE, DEFAULT_VOLUME_CORRECTION};
+use n3rgy_rs::glowmarkt::GLOWMARKT_BASE_URL;
+use n3rgy_rs::models::{EnergyType, Granularity, PriceUnit, Pricing, RequestType};
+use n3rgy_rs::octopus::OCTOPUS_BASE_URL;
+use n3rgy_rs::recording::Recording;
+use n3rgy_rs::secret::SecretString;
+use n3rgy_rs::settlement::uk_midnight;
+use n3rgy_rs::source::Provider;
+use n3rgy_rs::weather::{OPEN_METEO_ARCHIVE_URL, OPEN_METEO_FORECAST_URL};
+
+#[cfg(feature = "s3")]
+use crate::archive::{self, ArchiveFormat};
+use crate::config::{Config, RESERVED_TAGS};
+use crate::graphite;
+use crate::http::HttpArgs;
+use crate::import::ImportFormat;
+use crate::load::DEFAULT_MEASUREMENT;
+use crate::log_file::Rotation;
+use crate::partition::{Compression, Partition};
+use crate::plot::Style;
+use crate::sink::InfluxAuth;
+use crate::template::Template;
+
+/// Unprefixed environment variables read by earlier releases, and the
+/// `N3RGY_`-prefixed names that replace them.
+const LEGACY_ENV: [(&str, &str); 9] = [
+    ("API_TOKEN", "N3RGY_API_TOKEN"),
+    ("API_TOKEN_FILE", "N3RGY_API_TOKEN_FILE"),
+    ("INFLUX_URI", "N3RGY_INFLUX_URI"),
+    ("INFLUX_DATABASE", "N3RGY_INFLUX_DATABASE"),
+    ("INFLUX_TOKEN", "N3RGY_INFLUX_TOKEN"),
+    ("INFLUX_TOKEN_FILE", "N3RGY_INFLUX_TOKEN_FILE"),
+    ("CONSENT_EXPIRES", "N3RGY_CONSENT_EXPIRES"),
+    ("PROXY_USERNAME", "N3RGY_PROXY_USERNAME"),
+    ("PROXY_PASSWORD", "N3RGY_PROXY_PASSWORD"),
+];
+
+/// The command line, reading each deprecated unprefixed variable in place of
+/// its `N3RGY_` name where only the old one is set, so the environment itself
+/// is left alone.
+pub fn command() -> clap::Command {
+    let legacy: Vec<_> = LEGACY_ENV
+        .into_iter()
+        .filter(|(legacy, current)| env::var_os(legacy).is_some() && env::var_os(current).is_none())
+        .collect();
+    for (legacy, current) in &legacy {
+        warn!("{} is deprecated, set {} instead", legacy, current);
+    }
+    with_legacy_env(Cli::command(), &legacy)
+}
+
+fn with_legacy_env(
+    command: clap::Command,
+    legacy: &[(&'static str, &'static str)],
+) -> clap::Command {
+    command
+        .mut_args(|arg| {
+            let renamed = legacy
+                .iter()
+                .find(|(_, current)| arg.get_env() == Some(OsStr::new(current)));
+            match renamed {
+                Some((legacy, _)) => arg.env(legacy),
+                None => arg,
+            }
+        })
+        .mut_subcommands(|subcommand| with_legacy_env(subcommand, legacy))
+}
+
+/// Parse this process's command line with [`command_from`], exiting on errors
+/// as clap does.
+pub fn parse() -> Cli {
+    let args: Vec<String> = env::args().collect();
+    let command = command_from(&args).unwrap_or_else(|e| {
+        error!("{}", e);
+        process::exit(1);
+    });
+    Cli::from_arg_matches(&command.get_matches_from(&args)).unwrap_or_else(|e| e.exit())
+}
+
+/// As [`parse`], reporting errors rather than exiting.
+pub fn try_parse() -> Result<Cli, String> {
+    let args: Vec<String> = env::args().collect();
+    let matches = command_from(&args)?
+        .try_get_matches_from(&args)
+        .map_err(|e| e.to_string())?;
+    Cli::from_arg_matches(&matches).map_err(|e| e.to_string())
+}
+
+/// Settings that stand in for one another, by environment variable. Where the
+/// command line or environment gives any of them, the profile supplies none.
+const ALTERNATIVES: [&[&str]; 3] = [
+    &["N3RGY_API_TOKEN", "N3RGY_API_TOKEN_FILE"],
+    &[
+        "N3RGY_INFLUX_TOKEN",
+        "N3RGY_INFLUX_TOKEN_FILE",
+        "N3RGY_INFLUX_USERNAME",
+        "N3RGY_INFLUX_PASSWORD",
+    ],
+    &["N3RGY_BASE_URL", "N3RGY_SANDBOX"],
+];
+
+/// [`command`] for the command line in `args`, with the settings of the profile
+/// named by `--profile` as defaults for the options they stand in for. Flags
+/// and environment variables still win, and an option the profile supplies is
+/// no longer required.
+pub fn command_from(args: &[String]) -> Result<clap::Command, String> {
+    let command = command();
+    PROFILE_SETTINGS.lock().unwrap().clear();
+    let Some(name) = early_arg(args, "--profile").or_else(|| env::var("N3RGY_PROFILE").ok()) else {
+        return Ok(command);
+    };
+    let path = early_arg(args, "--config")
+        .or_else(|| env::var("N3RGY_CONFIG").ok())
+        .ok_or_else(|| format!("--profile {} needs a --config file to read it from", name))?;
+    let config = Config::load(Path::new(&path)).map_err(|e| e.to_string())?;
+    let profile = config
+        .profiles
+        .get(&name)
+        .ok_or_else(|| format!("{} has no profile named {}", path, name))?;
+
+    // a first pass, errors and all, for the options given explicitly
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+        .ok();
+    let mut built = command.clone();
+    built.build();
+    Ok(with_profile(
+        command,
+        &built,
+        matches.as_ref(),
+        &profile.settings(),
+    ))
+}
+
+fn with_profile(
+    command: clap::Command,
+    built: &clap::Command,
+    matches: Option<&ArgMatches>,
+    settings: &[(&'static str, String)],
+) -> clap::Command {
+    // a global option may be given after a subcommand
+    let given = |arg: &clap::Arg| {
+        let id = arg.get_id().as_str();
+        let mut level = matches;
+        while let Some(matches) = level {
+            if matches.try_get_raw(id).is_ok_and(|values| values.is_some())
+                && matches!(
+                    matches.value_source(id),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+            {
+                return true;
+            }
+            level = matches
+                .subcommand()
+                .filter(|_| arg.is_global_set())
+                .map(|(_, matches)| matches);
+        }
+        false
+    };
+    let explicit: Vec<&clap::Arg> = built.get_arguments().filter(|arg| given(arg)).collect();
+    let blocked = |arg: &clap::Arg| {
+        let name = env_name(arg);
+        let alternatives = ALTERNATIVES
+            .iter()
+            .find(|names| name.is_some_and(|name| names.contains(&name)));
+        explicit.iter().any(|given| {
+            given.get_id() == arg.get_id()
+                || alternatives.is_some_and(|names| {
+                    env_name(given).is_some_and(|given| names.contains(&given))
+                })
+                || built
+                    .get_arg_conflicts_with(arg)
+                    .iter()
+                    .any(|other| other.get_id() == given.get_id())
+                || built
+                    .get_arg_conflicts_with(given)
+                    .iter()
+                    .any(|other| other.get_id() == arg.get_id())
+        })
+    };
+    // global options are left to the command that defines them
+    let defaults: Vec<(&clap::Id, &'static str, &String)> = built
+        .get_arguments()
+        .filter(|arg| {
+            command
+                .get_arguments()
+                .any(|own| own.get_id() == arg.get_id())
+        })
+        .filter_map(|arg| {
+            let name = env_name(arg)?;
+            let (setting, value) = settings.iter().find(|(setting, _)| *setting == name)?;
+            (!blocked(arg)).then_some((arg.get_id(), *setting, value))
+        })
+        .collect();
+    let supplied: Vec<&'static str> = defaults.iter().map(|(_, name, _)| *name).collect();
+    PROFILE_SETTINGS.lock().unwrap().extend(&supplied);
+    // an option is no longer required once the profile supplies it, or one of
+    // its alternatives
+    let relaxed = |name: &str| {
+        supplied.contains(&name)
+            || ALTERNATIVES
+                .iter()
+                .any(|names| names.contains(&name) && names.iter().any(|n| supplied.contains(n)))
+    };
+
+    command
+        .mut_args(|arg| {
+            let arg = match defaults.iter().find(|(id, _, _)| *id == arg.get_id()) {
+                // kept out of --help, as it may be a secret
+                Some((_, _, value)) if arg.get_action().takes_values() => arg
+                    .default_value(value.to_string())
+                    .hide_default_value(true),
+                Some((_, _, value)) => arg.default_value(value.to_string()),
+                None => arg,
+            };
+            if env_name(&arg).is_some_and(relaxed) {
+                arg.required_unless_present(Resettable::Reset)
+            } else {
+                arg
+            }
+        })
+        .mut_subcommands(|subcommand| {
+            let Some(built) = built.find_subcommand(subcommand.get_name()) else {
+                return subcommand;
+            };
+            let matches = matches.and_then(|m| m.subcommand_matches(subcommand.get_name()));
+            with_profile(subcommand, built, matches, settings)
+        })
+}
+
+/// The `N3RGY_` name for an environment variable, where `name` is the
+/// deprecated one [`command`] reads in its place.
+fn current_env(name: &str) -> &str {
+    LEGACY_ENV
+        .iter()
+        .find(|(legacy, _)| *legacy == name)
+        .map_or(name, |(_, current)| current)
+}
+
+/// The `N3RGY_` environment variable `arg` is read from, if any.
+fn env_name(arg: &clap::Arg) -> Option<&str> {
+    arg.get_env().and_then(OsStr::to_str).map(current_env)
+}
+
+/// Settings the profile supplied to the last command built, by environment
+/// variable.
+static PROFILE_SETTINGS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+/// Whether the setting for the environment variable `name` came from the
+/// profile.
+pub fn from_profile(name: &str) -> bool {
+    PROFILE_SETTINGS
+        .lock()
+        .unwrap()
+        .contains(&current_env(name))
+}
+
+/// Rotated log files kept by default.
+const DEFAULT_LOG_KEEP: usize = 5;
+
+#[derive(Args)]
+pub struct LogArgs {
+    /// Also append log output to this file, e.g. where journald isn't in use
+    #[arg(long, env = "N3RGY_LOG_FILE", global = true)]
+    pub log_file: Option<PathBuf>,
+    /// Start a new log file once the current one would grow past this many MiB
+    #[arg(
+        long,
+        env = "N3RGY_LOG_MAX_SIZE",
+        global = true,
+        requires = "log_file",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub log_max_size: Option<u64>,
+    /// Start a new log file every hour or day
+    #[arg(
+        long,
+        env = "N3RGY_LOG_ROTATE",
+        global = true,
+        value_enum,
+        requires = "log_file"
+    )]
+    pub log_rotate: Option<Rotation>,
+    /// Rotated log files to keep, as FILE.1 (the newest) to FILE.N
+    #[arg(long, env = "N3RGY_LOG_KEEP", global = true, default_value_t = DEFAULT_LOG_KEEP)]
+    pub log_keep: usize,
+}
+
+impl LogArgs {
+    /// The log settings, read ahead of clap parsing so the log file receives
+    /// anything logged before then. Values clap would reject are ignored here
+    /// and left for it to report.
+    pub fn early() -> LogArgs {
+        let args: Vec<String> = env::args().collect();
+        let arg = |flag, var| early_arg(&args, flag).or_else(|| env::var(var).ok());
+        LogArgs {
+            log_file: arg("--log-file", "N3RGY_LOG_FILE").map(PathBuf::from),
+            log_max_size: arg("--log-max-size", "N3RGY_LOG_MAX_SIZE")
+                .and_then(|size| size.parse().ok())
+                .filter(|size| *size > 0),
+            log_rotate: arg("--log-rotate", "N3RGY_LOG_ROTATE")
+                .and_then(|rotation| <Rotation as ValueEnum>::from_str(&rotation, false).ok()),
+            log_keep: arg("--log-keep", "N3RGY_LOG_KEEP")
+                .and_then(|keep| keep.parse().ok())
+                .unwrap_or(DEFAULT_LOG_KEEP),
+        }
+    }
+}
+
+/// The value of `--flag value` or `--flag=value`, ahead of clap parsing.
+fn early_arg(args: &[String], flag: &str) -> Option<String> {
+    let mut args = args.iter().take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg.strip_prefix(flag).and_then(|v| v.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
 
 #[derive(Parser)]
-#[command(about = "Pull data from n3rgy API")]
+#[command(
+    about = "Pull data from n3rgy API",
+    arg_required_else_help = true,
+    after_help = crate::exit::HELP
+)]
 pub struct Cli {
-    #[arg(value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
-    pub start_date: DateTime<Local>,
-    #[arg(value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
-    pub end_date: DateTime<Local>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Print a man page for packagers and exit
+    #[arg(long, hide = true, exclusive = true)]
+    pub generate_man: bool,
+    /// TOML config file describing the meters to load
+    #[arg(long, env = "N3RGY_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Profile in the config file to take the token, InfluxDB settings and tags from
+    #[arg(long, env = "N3RGY_PROFILE", global = true, requires = "config")]
+    pub profile: Option<String>,
+    #[command(flatten)]
+    pub http: HttpArgs,
+    #[command(flatten)]
+    pub log: LogArgs,
+    /// Base URL of the n3rgy consumer API
+    #[arg(long, env = "N3RGY_BASE_URL", global = true, default_value = N3RGY_BASE_URL)]
+    pub base_url: String,
+    /// Use n3rgy's sandbox environment instead of live data, in place of any
+    /// --base-url or N3RGY_BASE_URL
+    #[arg(long, env = "N3RGY_SANDBOX", global = true)]
+    pub sandbox: bool,
+    /// Save every n3rgy response under this directory
+    #[arg(long, env = "N3RGY_RECORD", global = true, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Answer n3rgy requests from responses saved with --record instead of the
+    /// network; any --api-token will do
+    #[arg(long, env = "N3RGY_REPLAY", global = true)]
+    pub replay: Option<PathBuf>,
+    /// POST a message here when loading fails, e.g. a Slack or Discord webhook
+    #[arg(long, env = "N3RGY_NOTIFY_WEBHOOK", global = true)]
+    pub notify_webhook: Option<String>,
+    /// Hold a lock on this file while loading, so an overrunning run and the
+    /// next scheduled one can't overlap; the second exits with code 8
+    #[arg(long, env = "N3RGY_LOCK_FILE", global = true)]
+    pub lock_file: Option<PathBuf>,
+    /// Wait for the run holding --lock-file to finish instead of exiting
+    #[arg(
+        long,
+        env = "N3RGY_WAIT_FOR_LOCK",
+        global = true,
+        requires = "lock_file"
+    )]
+    pub wait_for_lock: bool,
+    /// Export traces and metrics over OTLP/HTTP to this collector, e.g.
+    /// http://localhost:4318
+    #[cfg(feature = "otel")]
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", global = true)]
+    pub otlp_endpoint: Option<String>,
+    /// Report panics and failed syncs to this Sentry DSN
+    #[cfg(feature = "sentry")]
+    #[arg(long, env = "SENTRY_DSN", global = true, hide_env_values = true)]
+    pub sentry_dsn: Option<String>,
+}
+
+impl Cli {
+    pub fn recording(&self) -> Option<Recording> {
+        match (&self.record, &self.replay) {
+            (Some(dir), _) => Some(Recording::Record(dir.clone())),
+            (None, Some(dir)) => Some(Recording::Replay(dir.clone())),
+            (None, None) => None,
+        }
+    }
+
+    /// The sandbox when asked for, else `--base-url`, so a base URL set in the
+    /// environment doesn't stop `--sandbox` being used.
+    pub fn api_base_url(&self) -> &str {
+        if self.sandbox {
+            N3RGY_SANDBOX_URL
+        } else {
+            &self.base_url
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Load consumption or tariff data for a date range
+    Fetch(FetchArgs),
+    /// Load the tariff for a date range
+    Tariff(TariffArgs),
+    /// Load all history n3rgy holds, resuming from a checkpoint file
+    Backfill(BackfillArgs),
+    /// Load the most recent data once, e.g. from cron
+    Sync(SyncArgs),
+    /// Keep re-syncing recent data on an interval and serve Prometheus metrics
+    Serve(Box<ServeArgs>),
+    /// List the fuels, meter elements and date ranges available to the token
+    List(TokenArgs),
+    /// Save the API token in the OS keyring, or remove it
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print a systemd unit running `serve` with readiness and watchdog
+    /// notifications
+    SystemdUnit(SystemdUnitArgs),
+    /// Check the config file, API token, consent and InfluxDB connection
+    Doctor(DoctorArgs),
+    /// Summarise consumption and cost across every meter in the config file
+    FleetReport(FleetReportArgs),
+    /// Print consumption and cost per day, week or month, with their spread
+    Stats(StatsArgs),
+    /// Chart daily consumption in the terminal
+    Plot(PlotArgs),
+    /// Report on stored or fetched data
+    Report {
+        #[command(subcommand)]
+        command: ReportCommand,
+    },
+    /// Check whether consent has been granted for an in-home display, and
+    /// optionally wait until its token becomes usable. Consent itself is
+    /// granted through n3rgy's web form
+    Consent(ConsentArgs),
+    /// Write points from archived line protocol, NDJSON, CSV or Parquet files
+    /// through the sink, e.g. to move to another database, without the API
+    Import(ImportArgs),
+    /// Copy points from an InfluxDB 1.x database to the sink, e.g. an InfluxDB
+    /// 2.x bucket through its v1 compatibility API
+    Migrate(MigrateArgs),
+    /// Rewrite stored points under the current tag schema, or into another
+    /// measurement, optionally dropping the series they came from
+    Retag(RetagArgs),
+    /// Maintain stored data
+    Admin {
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+    /// Set up Grafana to chart the stored data
+    Grafana {
+        #[command(subcommand)]
+        command: GrafanaCommand,
+    },
+    /// Check the config file and command lines that use it
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Serve canned consumption and tariff data shaped like the n3rgy API
+    #[cfg(feature = "mock-server")]
+    MockServer(MockServerArgs),
+}
+
+impl Command {
+    /// Whether the command writes points, and so takes the --lock-file.
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Command::Fetch(_)
+                | Command::Tariff(_)
+                | Command::Backfill(_)
+                | Command::Sync(_)
+                | Command::Serve(_)
+                | Command::Import(_)
+                | Command::Migrate(_)
+                | Command::Retag(_)
+                | Command::Admin { .. }
+        )
+    }
+}
+
+#[derive(Subcommand)]
+pub enum AuthCommand {
+    /// Prompt for the API token, verify it and save it in the OS keyring
+    Login,
+    /// Remove the saved API token
+    Logout,
+}
+
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Delete stored readings, prices and the points derived from them within
+    /// a range, e.g. to purge estimated data before re-fetching it
+    Delete(Box<DeleteArgs>),
+}
+
+#[derive(Subcommand)]
+pub enum GrafanaCommand {
+    /// Print a dashboard of consumption, cost and unit rates, or push it to
+    /// Grafana
+    ExportDashboard(ExportDashboardArgs),
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Check the config file and a command line without running it, then
+    /// print the settings it would run with, secrets redacted
+    Validate(ValidateArgs),
+}
+
+#[derive(Subcommand)]
+pub enum ReportCommand {
+    /// Sum month-to-date cost, project the month end and compare it with the budget
+    Budget(Box<BudgetArgs>),
+    /// List missing half hours, flagged readings, runs of zeroes and spikes,
+    /// to decide which windows to re-fetch
+    Quality(QualityArgs),
+    /// Email a summary of the last week or month of stored consumption and
+    /// cost, compared with the one before
+    #[cfg(feature = "email")]
+    Email(Box<EmailArgs>),
+}
+
+#[derive(Args)]
+pub struct FetchArgs {
+    #[command(flatten)]
+    pub range: RangeArgs,
     pub energy_type: EnergyType,
     pub request_type: RequestType,
-    #[clap(env)]
-    pub api_token: String,
-    #[clap(env)]
-    pub influx_uri: String,
-    #[clap(env)]
-    pub influx_database: String,
-    #[clap(env)]
-    pub influx_token: String,
+    #[command(flatten)]
+    pub api: ApiArgs,
+    #[command(flatten)]
+    pub load: LoadArgs,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct TariffArgs {
+    #[command(flatten)]
+    pub range: RangeArgs,
+    /// Fetch prices published ahead, from now to --end (default the end of
+    /// tomorrow), and write them to the `upcoming_price` measurement
+    #[arg(long, env = "N3RGY_UPCOMING", conflicts_with = "start")]
+    pub upcoming: bool,
+    pub energy_type: EnergyType,
+    #[command(flatten)]
+    pub api: ApiArgs,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+/// The dates to load, defaulting to the whole of yesterday.
+#[derive(Args)]
+pub struct PricingArgs {
+    /// Unit to store tariff prices and costs in
+    #[arg(long, env = "N3RGY_PRICE_UNIT", value_enum, default_value = "pence")]
+    pub price_unit: PriceUnit,
+    /// VAT to add to tariff prices and costs, as a percentage, e.g. 5
+    #[arg(long, env = "N3RGY_APPLY_VAT", default_value_t = 0.0)]
+    pub apply_vat: f64,
+}
+
+impl PricingArgs {
+    pub fn pricing(&self) -> Pricing {
+        Pricing {
+            unit: self.price_unit,
+            vat: self.apply_vat,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct RangeArgs {
+    /// Start of the range, defaults to UK midnight at the start of the day
+    /// before --end
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub start: Option<DateTime<Local>>,
+    /// End of the range, defaults to UK midnight this morning
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub end: Option<DateTime<Local>>,
+}
+
+impl RangeArgs {
+    pub fn window(&self) -> Result<Window, String> {
+        let end = self.end.unwrap_or_else(|| {
+            uk_midnight(Utc::now().with_timezone(&London).date_naive()).with_timezone(&Local)
+        });
+        let start = self.start.unwrap_or_else(|| {
+            let day = end.with_timezone(&London).date_naive() - Duration::days(1);
+            uk_midnight(day).with_timezone(&Local)
+        });
+        validate_range(start, end)
+    }
+
+    /// From now to `--end`, defaulting to midnight at the end of tomorrow.
+    pub fn upcoming_window(&self) -> Result<Window, String> {
+        let now = Local::now();
+        let end = self.end.unwrap_or_else(|| {
+            (now.date_naive() + Duration::days(2))
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_local_timezone(Local)
+                .earliest()
+                .unwrap()
+        });
+        if end <= now {
+            return Err(format!("end {} is not in the future", end));
+        }
+        Ok((now, end))
+    }
+}
+
+fn parse_schedule(expression: String) -> Result<Cron, String> {
+    Cron::new(&expression)
+        .parse()
+        .map_err(|e| format!("invalid cron expression: {}", e))
+}
+
+/// Reject empty or future ranges, trimming an end date in the future to now.
+pub fn validate_range(start: DateTime<Local>, end: DateTime<Local>) -> Result<Window, String> {
+    let now = Local::now();
+    if start >= now {
+        return Err(format!("start {} is in the future", start));
+    }
+    if end <= start {
+        return Err(format!("end {} is not after start {}", end, start));
+    }
+    Ok((start, std::cmp::min(end, now)))
+}
+
+#[derive(Args)]
+pub struct BackfillArgs {
+    /// Only backfill this fuel, rather than every fuel the meter reports
+    #[arg(long, env = "N3RGY_FUEL")]
+    pub fuel: Option<EnergyType>,
+    #[arg(
+        long,
+        env = "N3RGY_REQUEST_TYPE",
+        value_enum,
+        default_value = "consumption"
+    )]
+    pub request_type: RequestType,
+    /// File recording how far each element has been loaded
+    #[arg(long, env = "N3RGY_CHECKPOINT", default_value = "n3rgy-backfill.json")]
+    pub checkpoint: PathBuf,
+    #[command(flatten)]
+    pub api: ApiArgs,
+    #[command(flatten)]
+    pub load: LoadArgs,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct SyncArgs {
+    pub energy_type: EnergyType,
+    pub request_type: RequestType,
+    /// Hours of recent data to request
+    #[arg(long, env = "N3RGY_LOOKBACK_HOURS", default_value_t = 48)]
+    pub lookback_hours: i64,
+    /// Start after the newest point already in InfluxDB, using the lookback only
+    /// when nothing has been stored yet; not available with the other sinks
+    #[arg(
+        long,
+        env = "N3RGY_SINCE_LAST",
+        conflicts_with_all = [
+            "dry_run",
+            "output",
+            "http_sink_url",
+            "victoria_metrics_url",
+            "graphite",
+            "duckdb"
+        ]
+    )]
+    #[cfg_attr(feature = "s3", arg(conflicts_with = "s3_bucket"))]
+    pub since_last: bool,
+    #[command(flatten)]
+    pub api: ApiArgs,
+    #[command(flatten)]
+    pub load: LoadArgs,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    pub energy_type: EnergyType,
+    pub request_type: RequestType,
+    /// Seconds between syncs
+    #[arg(long, env = "N3RGY_INTERVAL", default_value_t = 3600)]
+    pub interval: u64,
+    /// Sync on a cron expression in local time instead of every --interval,
+    /// e.g. "15 */2 * * *" for a quarter past every other hour
+    #[arg(
+        long,
+        env = "N3RGY_SCHEDULE",
+        conflicts_with = "interval",
+        value_parser = clap::builder::StringValueParser::new().try_map(parse_schedule)
+    )]
+    pub schedule: Option<Cron>,
+    /// Delay each sync by a random number of seconds up to this, so
+    /// deployments on the same schedule don't all sync at once
+    #[arg(long, env = "N3RGY_JITTER", default_value_t = 0)]
+    pub jitter: u64,
+    /// Hours of recent data re-requested on each sync
+    #[arg(long, env = "N3RGY_LOOKBACK_HOURS", default_value_t = 48)]
+    pub lookback_hours: i64,
+    /// Address to serve Prometheus metrics, the /healthz and /readyz probes,
+    /// /status and POST /sync on
+    #[arg(long, env = "N3RGY_METRICS_ADDR", default_value = "0.0.0.0:9184")]
+    pub metrics_addr: SocketAddr,
+    /// Seconds without a successful sync before /healthz reports unhealthy,
+    /// by default it only checks the daemon is responding
+    #[arg(long, env = "N3RGY_HEALTH_STALE_AFTER")]
+    pub health_stale_after: Option<u64>,
+    /// POST `[[alerts]]` from the config file to this URL as they are raised,
+    /// e.g. a Slack incoming webhook
+    #[arg(long, env = "N3RGY_ALERT_WEBHOOK")]
+    pub alert_webhook: Option<String>,
+    /// Consecutive failed syncs before --notify-webhook is told, 1 for every one
+    #[arg(long, env = "N3RGY_NOTIFY_AFTER", default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    pub notify_after: u32,
+    /// Reload when the --config file changes, as on SIGHUP. The metrics
+    /// address, MQTT, HTTP and global options still need a restart
+    #[arg(long, env = "N3RGY_WATCH_CONFIG")]
+    pub watch_config: bool,
+    #[cfg(feature = "mqtt")]
+    #[command(flatten)]
+    pub mqtt: MqttArgs,
+    #[command(flatten)]
+    pub api: ApiArgs,
+    #[command(flatten)]
+    pub load: LoadArgs,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+/// Where `serve` subscribes to a Glow CAD's live readings.
+#[cfg(feature = "mqtt")]
+#[derive(Args, Clone)]
+pub struct MqttArgs {
+    /// MQTT broker a Glow CAD publishes to, e.g. mqtt://glow.lan:1883 or
+    /// mqtts://broker:8883, to also write its live power readings
+    #[arg(long, env = "N3RGY_MQTT_URL", value_parser = parse_broker)]
+    pub mqtt_url: Option<reqwest::Url>,
+    #[arg(long, env = "N3RGY_MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+    #[arg(long, env = "N3RGY_MQTT_PASSWORD", hide_env_values = true)]
+    pub mqtt_password: Option<SecretString>,
+    /// Topics to subscribe to; messages without a Glow meter reading are
+    /// ignored
+    #[arg(long, env = "N3RGY_MQTT_TOPIC", default_value = "glow/#")]
+    pub mqtt_topic: String,
+}
+
+#[cfg(feature = "mqtt")]
+fn parse_broker(url: &str) -> Result<reqwest::Url, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "mqtt" | "mqtts") {
+        return Err(format!("{} isn't an mqtt:// or mqtts:// URL", url));
+    }
+    if url.host_str().is_none() {
+        return Err(format!("{} has no host", url));
+    }
+    Ok(url)
+}
+
+#[derive(Args)]
+pub struct TokenArgs {
+    /// n3rgy API token, defaults to the one saved by `auth login`
+    #[arg(long, env = "N3RGY_API_TOKEN", hide_env_values = true)]
+    pub api_token: Option<SecretString>,
+    /// File containing the API token, e.g. a mounted container secret
+    #[arg(long, env = "N3RGY_API_TOKEN_FILE", conflicts_with = "api_token")]
+    pub api_token_file: Option<PathBuf>,
+    /// n3rgy API token, as a positional argument in place of --api-token
+    #[arg(
+        value_name = "API_TOKEN",
+        conflicts_with_all = ["api_token", "api_token_file"]
+    )]
+    pub api_token_arg: Option<SecretString>,
+}
+
+impl TokenArgs {
+    pub fn api_token(&self) -> io::Result<Option<SecretString>> {
+        secret(
+            &self.api_token.clone().or(self.api_token_arg.clone()),
+            &self.api_token_file,
+        )
+    }
+
+    /// Whether a token was given at all, by flag, file or position.
+    pub fn given(&self) -> bool {
+        self.api_token.is_some() || self.api_token_file.is_some() || self.api_token_arg.is_some()
+    }
 }
 
-fn parse_dt(value: String) -> Result<chrono::DateTime<Local>, chrono::ParseError> {
+/// How data is requested from n3rgy.
+#[derive(Args)]
+pub struct ApiArgs {
+    /// Smart meter data provider to load from
+    #[arg(long, env = "N3RGY_SOURCE", value_enum, default_value_t)]
+    pub source: Provider,
+    /// Also read consumption from these sources and write one series merged
+    /// with --source's, taking actual readings over estimates and trusting
+    /// --source first, e.g. `octopus`
+    #[arg(long, env = "N3RGY_RECONCILE_WITH", value_enum, value_delimiter = ',')]
+    pub reconcile_with: Vec<Provider>,
+    /// Readings further apart than this, in --source's unit, are reported as
+    /// discrepancies
+    #[arg(long, env = "N3RGY_RECONCILE_TOLERANCE", default_value_t = 0.01)]
+    pub reconcile_tolerance: f64,
+    /// Append each discrepancy to this CSV file, besides logging how many
+    /// there were
+    #[arg(long, env = "N3RGY_RECONCILE_REPORT")]
+    pub reconcile_report: Option<PathBuf>,
+    /// Bright app login, for --source glowmarkt
+    #[arg(long, env = "N3RGY_GLOWMARKT_USERNAME")]
+    pub glowmarkt_username: Option<String>,
+    #[arg(long, env = "N3RGY_GLOWMARKT_PASSWORD", hide_env_values = true)]
+    pub glowmarkt_password: Option<SecretString>,
+    /// Base URL of the Glowmarkt API
+    #[arg(long, env = "N3RGY_GLOWMARKT_URL", default_value = GLOWMARKT_BASE_URL)]
+    pub glowmarkt_url: String,
+    /// Octopus Energy API key and account number, e.g. A-1234ABCD, for
+    /// --source octopus
+    #[arg(long, env = "N3RGY_OCTOPUS_API_KEY", hide_env_values = true)]
+    pub octopus_api_key: Option<SecretString>,
+    #[arg(long, env = "N3RGY_OCTOPUS_ACCOUNT")]
+    pub octopus_account: Option<String>,
+    /// Base URL of the Octopus Energy API
+    #[arg(long, env = "N3RGY_OCTOPUS_URL", default_value = OCTOPUS_BASE_URL)]
+    pub octopus_url: String,
+    /// Octopus gas readings are in kWh, as from SMETS1 meters, rather than m³
+    #[arg(long, env = "N3RGY_OCTOPUS_GAS_KWH")]
+    pub octopus_gas_kwh: bool,
+    #[command(flatten)]
+    pub token: TokenArgs,
+    /// Meter element to read, or `all` to load every element the meter reports
+    #[arg(long, env = "N3RGY_ELEMENT", default_value = "1")]
+    pub element: ElementSelection,
+    /// Days of data per API request, up to n3rgy's limit of 90
+    #[arg(long, env = "N3RGY_CHUNK_DAYS", default_value_t = MAX_WINDOW_DAYS, value_parser = clap::value_parser!(i64).range(1..=MAX_WINDOW_DAYS))]
+    pub chunk_days: i64,
+    /// Seconds to wait between API requests for consecutive windows
+    #[arg(long, env = "N3RGY_REQUEST_DELAY", default_value_t = 0.0, value_parser = parse_delay)]
+    pub request_delay: f64,
+    /// Seconds to keep polling a window n3rgy is still retrieving before deferring it
+    #[arg(long, env = "N3RGY_PENDING_DEADLINE", default_value_t = 300)]
+    pub pending_deadline: u64,
+    /// Log a window that fails to load and carry on with the rest, listing failures at the end
+    #[arg(long, env = "N3RGY_KEEP_GOING")]
+    pub keep_going: bool,
+    /// Date the token's data consent lapses, used to warn before access is lost
+    #[arg(long, env = "N3RGY_CONSENT_EXPIRES")]
+    pub consent_expires: Option<NaiveDate>,
+    /// Keep raw API responses under this directory and answer repeated or
+    /// overlapping requests from them
+    #[arg(long, env = "N3RGY_CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+    /// Hours a cached response is served for
+    #[arg(long, env = "N3RGY_CACHE_TTL", default_value_t = 24)]
+    pub cache_ttl: u64,
+    /// Fetch everything from the API again, refreshing the cache
+    #[arg(long, env = "N3RGY_FORCE_REFRESH", requires = "cache_dir")]
+    pub force_refresh: bool,
+}
+
+/// How consumption readings are transformed before they are written.
+#[derive(Args)]
+pub struct LoadArgs {
+    /// Interval of consumption readings, `day` gives far fewer points for long ranges
+    #[arg(long, env = "N3RGY_GRANULARITY", value_enum, default_value_t = Granularity::HalfHour)]
+    pub granularity: Granularity,
+    /// Also write gas readings converted between m³ and kWh
+    #[arg(long, env = "N3RGY_CONVERT_GAS")]
+    pub convert_gas: bool,
+    /// Calorific value of the gas supply in MJ/m³, as printed on the bill
+    #[arg(long, env = "N3RGY_CALORIFIC_VALUE", default_value_t = DEFAULT_CALORIFIC_VALUE)]
+    pub calorific_value: f64,
+    /// Volume correction factor for gas temperature and pressure
+    #[arg(long, env = "N3RGY_VOLUME_CORRECTION", default_value_t = DEFAULT_VOLUME_CORRECTION)]
+    pub volume_correction: f64,
+    /// Also fetch the tariff and write the cost of each reading to a `cost` measurement
+    #[arg(long, env = "N3RGY_COMPUTE_COST")]
+    pub compute_cost: bool,
+    /// Also write daily, weekly and/or monthly consumption totals, e.g. `day,month`
+    #[arg(long, env = "N3RGY_AGGREGATE", value_enum, value_delimiter = ',')]
+    pub aggregate: Vec<Period>,
+    /// Sum half-hourly consumption into hourly or daily points instead of writing each reading
+    #[arg(long, env = "N3RGY_RESAMPLE", value_enum)]
+    pub resample: Option<Resample>,
+    /// Tag half-hourly electricity readings with their GB settlement period, 1-48 (46 or 50 when the clocks change)
+    #[arg(long, env = "N3RGY_SETTLEMENT_PERIOD")]
+    pub settlement_period: bool,
+    /// Also write each reading's UK local time, with its offset, as a `local_time` field
+    #[arg(long, env = "N3RGY_LOCAL_TIME")]
+    pub local_time: bool,
+    /// Also write the grid carbon intensity (gCO₂/kWh) and emissions (gCO₂) of
+    /// each electricity reading to a `carbon` measurement, from the National
+    /// Grid ESO Carbon Intensity API
+    #[arg(long, env = "N3RGY_CARBON_INTENSITY")]
+    pub carbon_intensity: bool,
+    /// Use the regional intensity forecast for this postcode, e.g. `RG10` or
+    /// `RG10 1AA`, rather than the national figures
+    #[arg(long, env = "N3RGY_CARBON_POSTCODE", requires = "carbon_intensity")]
+    pub carbon_postcode: Option<String>,
+    /// Base URL of the Carbon Intensity API
+    #[arg(long, env = "N3RGY_CARBON_INTENSITY_URL", default_value = CARBON_INTENSITY_BASE_URL)]
+    pub carbon_intensity_url: String,
+    /// Electricity element holding exported generation, e.g. `2`. The other
+    /// elements' consumption is also written with the export and net usage to
+    /// a `solar` measurement, and with estimated generation and
+    /// self-consumption when the config file has `[solar]`
+    #[arg(long, env = "N3RGY_EXPORT_ELEMENT")]
+    pub export_element: Option<u8>,
+    /// Base URL of Open-Meteo's historical weather API, for the `[degree_days]`
+    /// temperatures and `[solar]` irradiance
+    #[arg(long, env = "N3RGY_OPEN_METEO_ARCHIVE_URL", default_value = OPEN_METEO_ARCHIVE_URL)]
+    pub open_meteo_archive_url: String,
+    /// Base URL of Open-Meteo's forecast API, for recent days the archive
+    /// doesn't hold yet
+    #[arg(long, env = "N3RGY_OPEN_METEO_URL", default_value = OPEN_METEO_FORECAST_URL)]
+    pub open_meteo_url: String,
+}
+
+/// Where points are written: InfluxDB, unless another sink is chosen.
+#[derive(Args)]
+#[command(group(ArgGroup::new("sink").multiple(false)))]
+pub struct InfluxArgs {
+    #[arg(
+        long,
+        env = "N3RGY_INFLUX_URI",
+        required_unless_present_any = ["sink", "influx_uri_arg"]
+    )]
+    pub influx_uri: Option<String>,
+    #[arg(
+        long,
+        env = "N3RGY_INFLUX_DATABASE",
+        required_unless_present_any = ["sink", "influx_database_arg"]
+    )]
+    pub influx_database: Option<String>,
+    #[arg(
+        long,
+        env = "N3RGY_INFLUX_TOKEN",
+        required_unless_present_any = [
+            "sink",
+            "influx_token_file",
+            "influx_username",
+            "influx_token_arg"
+        ],
+        hide_env_values = true
+    )]
+    pub influx_token: Option<SecretString>,
+    /// File containing the InfluxDB token, e.g. a mounted container secret
+    #[arg(long, env = "N3RGY_INFLUX_TOKEN_FILE", conflicts_with = "influx_token")]
+    pub influx_token_file: Option<PathBuf>,
+    /// InfluxDB URI, as a positional argument in place of --influx-uri
+    #[arg(value_name = "INFLUX_URI", conflicts_with = "influx_uri")]
+    pub influx_uri_arg: Option<String>,
+    /// InfluxDB database, in place of --influx-database
+    #[arg(value_name = "INFLUX_DATABASE", conflicts_with = "influx_database")]
+    pub influx_database_arg: Option<String>,
+    /// InfluxDB token, in place of --influx-token
+    #[arg(
+        value_name = "INFLUX_TOKEN",
+        conflicts_with_all = ["influx_token", "influx_token_file"]
+    )]
+    pub influx_token_arg: Option<SecretString>,
+    /// InfluxDB 1.x user to authenticate as instead of using a token
+    #[arg(
+        long,
+        env = "N3RGY_INFLUX_USERNAME",
+        requires = "influx_password",
+        conflicts_with_all = ["influx_token", "influx_token_file"]
+    )]
+    pub influx_username: Option<String>,
+    #[arg(
+        long,
+        env = "N3RGY_INFLUX_PASSWORD",
+        requires = "influx_username",
+        hide_env_values = true
+    )]
+    pub influx_password: Option<SecretString>,
+    /// Print the line protocol that would be written instead of writing to InfluxDB
+    #[arg(long, env = "N3RGY_DRY_RUN", group = "sink")]
+    pub dry_run: bool,
+    /// Append InfluxDB line protocol to this file instead of writing to InfluxDB,
+    /// `-` for stdout. The name may use the point's tags {fuel}, {type},
+    /// {element}, {mpxn}, {property} and {profile}, plus {measurement} and the
+    /// UK {date}, e.g. `energy/{fuel}-{date}.lp`
+    #[arg(long, env = "N3RGY_OUTPUT", group = "sink")]
+    pub output: Option<PathBuf>,
+    /// Compress the output file, adding `.gz` or `.zst` to its name
+    #[arg(
+        long,
+        env = "N3RGY_OUTPUT_COMPRESSION",
+        value_enum,
+        requires = "output"
+    )]
+    pub output_compression: Option<Compression>,
+    /// Write one output file per UK day or month, named with {date}
+    #[arg(long, env = "N3RGY_OUTPUT_PARTITION", value_enum, requires = "output")]
+    pub output_partition: Option<Partition>,
+    /// Upload each batch to this S3 bucket instead of writing to InfluxDB, with
+    /// credentials and region from the usual `AWS_` environment variables
+    #[cfg(feature = "s3")]
+    #[arg(
+        long,
+        env = "N3RGY_S3_BUCKET",
+        group = "sink",
+        conflicts_with_all = ["spool_dir", "skip_existing"]
+    )]
+    pub s3_bucket: Option<String>,
+    /// S3-compatible endpoint to upload to instead of AWS, e.g. http://minio:9000
+    #[cfg(feature = "s3")]
+    #[arg(long, env = "N3RGY_S3_ENDPOINT", requires = "s3_bucket")]
+    pub s3_endpoint: Option<String>,
+    /// Object key, before the format's extension, e.g. `energy/fuel={fuel}/date={date}`;
+    /// placeholders are the point's tags {fuel}, {type}, {element}, {mpxn},
+    /// {property} and {profile}, plus {measurement} and the UK {date}
+    #[cfg(feature = "s3")]
+    #[arg(
+        long,
+        env = "N3RGY_S3_KEY",
+        default_value = archive::DEFAULT_KEY,
+        value_parser = parse_s3_key,
+        requires = "s3_bucket"
+    )]
+    pub s3_key: Template,
+    #[cfg(feature = "s3")]
+    #[arg(
+        long,
+        env = "N3RGY_S3_FORMAT",
+        value_enum,
+        default_value = "ndjson",
+        requires = "s3_bucket"
+    )]
+    pub s3_format: ArchiveFormat,
+    /// Write points to this DuckDB database instead of InfluxDB, one table per
+    /// measurement with a column per tag and field, replacing rows with the
+    /// same time and tags
+    #[arg(long, env = "N3RGY_DUCKDB", value_name = "PATH", group = "sink")]
+    pub duckdb: Option<PathBuf>,
+    /// The `duckdb` CLI used to write to the database
+    #[arg(
+        long,
+        env = "N3RGY_DUCKDB_CLI",
+        default_value = "duckdb",
+        requires = "duckdb"
+    )]
+    pub duckdb_cli: PathBuf,
+    /// Send points to Graphite's plaintext listener at this `host:port` instead
+    /// of writing to InfluxDB, e.g. carbon:2003
+    #[arg(long, env = "N3RGY_GRAPHITE", value_name = "HOST:PORT", group = "sink")]
+    pub graphite: Option<String>,
+    /// Graphite metric path, e.g. `energy.{fuel}.{type}`; placeholders are the
+    /// point's tags {fuel}, {type}, {element}, {mpxn}, {property} and {profile},
+    /// plus {measurement} and {field}, with `.{field}` added when it isn't used
+    #[arg(
+        long,
+        env = "N3RGY_GRAPHITE_PATH",
+        default_value = graphite::DEFAULT_PATH,
+        value_parser = parse_graphite_path,
+        requires = "graphite"
+    )]
+    pub graphite_path: Template,
+    /// Import points into VictoriaMetrics at this URL instead of writing to
+    /// InfluxDB, e.g. http://victoria:8428; series are named `{measurement}_{field}`
+    /// and labelled with the point's tags
+    #[arg(long, env = "N3RGY_VICTORIA_METRICS_URL", group = "sink")]
+    pub victoria_metrics_url: Option<String>,
+    /// POST each batch of points as JSON to this URL instead of writing to InfluxDB
+    #[arg(long, env = "N3RGY_HTTP_SINK_URL", group = "sink")]
+    pub http_sink_url: Option<String>,
+    /// Header sent with each POST, e.g. `--http-sink-header "Authorization: Bearer ..."`;
+    /// may be repeated
+    #[arg(
+        long,
+        env = "N3RGY_HTTP_SINK_HEADERS",
+        value_name = "NAME: VALUE",
+        value_delimiter = '\n',
+        value_parser = parse_header,
+        requires = "http_sink_url",
+        hide_env_values = true
+    )]
+    pub http_sink_header: Vec<(String, String)>,
+    /// Body to POST, with {points} replaced by the batch as a JSON array of
+    /// `{measurement, time, tags, fields}` objects and {count} by its length,
+    /// e.g. `{"source": "n3rgy", "readings": {points}}`; the array alone by default
+    #[arg(long, env = "N3RGY_HTTP_SINK_TEMPLATE", requires = "http_sink_url")]
+    pub http_sink_template: Option<String>,
+    /// Check InfluxDB for readings already loaded and only write new ones; the
+    /// other sinks can't be read back, so can't be used with it
+    #[arg(
+        long,
+        env = "N3RGY_SKIP_EXISTING",
+        conflicts_with_all = [
+            "dry_run",
+            "output",
+            "http_sink_url",
+            "victoria_metrics_url",
+            "graphite",
+            "duckdb"
+        ]
+    )]
+    pub skip_existing: bool,
+    /// Keep batches in this directory while InfluxDB can't be reached and retry
+    /// them on the next run, moving any it then rejects to a `dead-letter` file
+    #[arg(
+        long,
+        env = "N3RGY_SPOOL_DIR",
+        conflicts_with_all = [
+            "dry_run",
+            "output",
+            "http_sink_url",
+            "victoria_metrics_url",
+            "graphite",
+            "duckdb"
+        ]
+    )]
+    pub spool_dir: Option<PathBuf>,
+    /// Windows to write at once while later ones are still being fetched
+    #[arg(long, env = "N3RGY_WRITE_CONCURRENCY", default_value = "1")]
+    pub write_concurrency: NonZeroUsize,
+    /// Write a `tariff_change` point wherever the standing charge differs from
+    /// the one before it, or a unit rate from the same half hour the day
+    /// before, for annotating dashboards
+    #[arg(long, env = "N3RGY_TARIFF_CHANGES")]
+    pub tariff_changes: bool,
+    /// Also POST each tariff change as JSON to this URL
+    #[arg(long, env = "N3RGY_TARIFF_CHANGE_WEBHOOK", requires = "tariff_changes")]
+    pub tariff_change_webhook: Option<String>,
+    /// Write standing charges to a daily `standing_charge` measurement, per day
+    /// at UK midnight, instead of alongside the unit rates
+    #[arg(long, env = "N3RGY_STANDING_CHARGE_SERIES")]
+    pub standing_charge_series: bool,
+    #[command(flatten)]
+    pub pricing: PricingArgs,
+    /// Measurement to write readings and tariff prices to
+    #[arg(long, env = "N3RGY_MEASUREMENT", default_value = DEFAULT_MEASUREMENT)]
+    pub measurement: String,
+    /// Measurement name built from each point, e.g. `{fuel}_{type}`; placeholders
+    /// are {fuel}, {type}, {element}, {mpxn}, {property} and {profile}
+    #[arg(
+        long,
+        env = "N3RGY_MEASUREMENT_TEMPLATE",
+        conflicts_with = "measurement"
+    )]
+    pub measurement_template: Option<Template>,
+    /// Extra tag added to every point, e.g. `--tag site=home` or
+    /// `--tag site={profile}`; may be repeated
+    #[arg(
+        long = "tag",
+        env = "N3RGY_TAGS",
+        value_name = "KEY=VALUE",
+        value_delimiter = ',',
+        value_parser = parse_tag
+    )]
+    pub tags: Vec<(String, Template)>,
+}
+
+impl InfluxArgs {
+    /// `--influx-uri`, or else the positional URI.
+    pub fn uri(&self) -> Option<&str> {
+        self.influx_uri
+            .as_deref()
+            .or(self.influx_uri_arg.as_deref())
+    }
+
+    pub fn database(&self) -> Option<&str> {
+        self.influx_database
+            .as_deref()
+            .or(self.influx_database_arg.as_deref())
+    }
+
+    /// How to authenticate to InfluxDB, from the flags or the positional token.
+    pub fn auth(&self) -> io::Result<Option<InfluxAuth>> {
+        influx_auth(
+            &self.influx_token.clone().or(self.influx_token_arg.clone()),
+            &self.influx_token_file,
+            &self.influx_username,
+            &self.influx_password,
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ElementSelection {
+    Number(u8),
+    All,
+}
+
+impl FromStr for ElementSelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("all") {
+            return Ok(ElementSelection::All);
+        }
+        value
+            .parse()
+            .map(ElementSelection::Number)
+            .map_err(|_| format!("{} is not an element number or `all`", value))
+    }
+}
+
+#[derive(Args)]
+pub struct DoctorArgs {
+    #[arg(long, env = "N3RGY_API_TOKEN", hide_env_values = true)]
+    pub api_token: Option<SecretString>,
+    #[arg(long, env = "N3RGY_API_TOKEN_FILE", conflicts_with = "api_token")]
+    pub api_token_file: Option<PathBuf>,
+    #[arg(long, env = "N3RGY_INFLUX_URI")]
+    pub influx_uri: Option<String>,
+    #[arg(long, env = "N3RGY_INFLUX_DATABASE")]
+    pub influx_database: Option<String>,
+    #[arg(long, env = "N3RGY_INFLUX_TOKEN", hide_env_values = true)]
+    pub influx_token: Option<SecretString>,
+    #[arg(long, env = "N3RGY_INFLUX_TOKEN_FILE", conflicts_with = "influx_token")]
+    pub influx_token_file: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "N3RGY_INFLUX_USERNAME",
+        requires = "influx_password",
+        conflicts_with_all = ["influx_token", "influx_token_file"]
+    )]
+    pub influx_username: Option<String>,
+    #[arg(
+        long,
+        env = "N3RGY_INFLUX_PASSWORD",
+        requires = "influx_username",
+        hide_env_values = true
+    )]
+    pub influx_password: Option<SecretString>,
+    /// Date the token's data consent lapses
+    #[arg(long, env = "N3RGY_CONSENT_EXPIRES")]
+    pub consent_expires: Option<NaiveDate>,
+}
+
+#[derive(Args)]
+pub struct BudgetArgs {
+    /// Monthly budget, in place of `monthly_budget` from the config file
+    #[arg(long, env = "N3RGY_BUDGET")]
+    pub budget: Option<f64>,
+    /// Days of recent cost to average when projecting the month end
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u32).range(1..))]
+    pub recent_days: u32,
+    /// Only count the cost of this property from the config file
+    #[arg(long)]
+    pub property: Option<String>,
+    /// Print the report as JSON instead of a table
+    #[arg(long)]
+    pub json: bool,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Start of the range, defaults to 30 days before --end
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub start: Option<DateTime<Local>>,
+    /// End of the range, defaults to now
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub end: Option<DateTime<Local>>,
+    pub energy_type: EnergyType,
+    /// Period to total consumption over
+    #[arg(long, value_enum, default_value = "day")]
+    pub period: Period,
+    /// Also fetch the tariff and total the cost
+    #[arg(long)]
+    pub cost: bool,
+    #[command(flatten)]
+    pub pricing: PricingArgs,
+    #[command(flatten)]
+    pub token: TokenArgs,
+}
+
+impl StatsArgs {
+    pub fn window(&self) -> Result<Window, String> {
+        let end = self.end.unwrap_or_else(Local::now);
+        let start = self.start.unwrap_or(end - Duration::days(30));
+        validate_range(start, end)
+    }
+}
+
+#[derive(Args)]
+pub struct PlotArgs {
+    /// Start of the range, defaults to 30 days before --end
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub start: Option<DateTime<Local>>,
+    /// End of the range, defaults to now
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub end: Option<DateTime<Local>>,
+    pub energy_type: EnergyType,
+    #[arg(long, value_enum, default_value = "bars")]
+    pub style: Style,
+    /// Draw with ASCII characters only, for terminals without Unicode
+    #[arg(long)]
+    pub ascii: bool,
+    #[command(flatten)]
+    pub token: TokenArgs,
+}
+
+impl PlotArgs {
+    pub fn window(&self) -> Result<Window, String> {
+        let end = self.end.unwrap_or_else(Local::now);
+        let start = self.start.unwrap_or(end - Duration::days(30));
+        validate_range(start, end)
+    }
+}
+
+/// The paths take every positional argument, so the InfluxDB settings are only
+/// given as flags here.
+#[derive(Args)]
+#[command(
+    mut_arg("influx_uri_arg", |arg| arg.long("influx-uri-arg").hide(true)),
+    mut_arg("influx_database_arg", |arg| arg.long("influx-database-arg").hide(true)),
+    mut_arg("influx_token_arg", |arg| arg.long("influx-token-arg").hide(true))
+)]
+pub struct ImportArgs {
+    /// Files to import, or directories to import every file under; `.gz` and
+    /// `.zst` files are decompressed
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// Format of the files, by default taken from each file's extension
+    #[arg(long, value_enum)]
+    pub format: Option<ImportFormat>,
+    /// Points to write at once
+    #[arg(long, default_value = "5000")]
+    pub batch_size: NonZeroUsize,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct MigrateArgs {
+    /// URI of the InfluxDB 1.x server to copy from
+    #[arg(long, env = "N3RGY_MIGRATE_FROM_URI")]
+    pub from_uri: String,
+    /// Database to copy from
+    #[arg(long, env = "N3RGY_MIGRATE_FROM_DATABASE")]
+    pub from_database: String,
+    #[arg(long, env = "N3RGY_MIGRATE_FROM_USERNAME", requires = "from_password")]
+    pub from_username: Option<String>,
+    #[arg(
+        long,
+        env = "N3RGY_MIGRATE_FROM_PASSWORD",
+        requires = "from_username",
+        hide_env_values = true
+    )]
+    pub from_password: Option<SecretString>,
+    /// Measurement to copy; may be repeated
+    #[arg(long = "from-measurement", default_value = DEFAULT_MEASUREMENT)]
+    pub from_measurements: Vec<String>,
+    /// Replace the resource path `measurement` tag older releases wrote with
+    /// the fuel, mpxn, element and type tags written now
+    #[arg(long)]
+    pub new_tag_schema: bool,
+    /// Days of points to copy at once
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(1..))]
+    pub chunk_days: i64,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct SystemdUnitArgs {
+    /// Binary the unit runs, defaults to this one
+    #[arg(long)]
+    pub exec: Option<PathBuf>,
+    /// File of N3RGY_* variables the unit reads its settings from
+    #[arg(long, default_value = "/etc/n3rgy-rs/env")]
+    pub env_file: PathBuf,
+    /// Seconds without a watchdog ping before systemd restarts the daemon
+    #[arg(long, default_value_t = 120, value_parser = clap::value_parser!(u64).range(1..))]
+    pub watchdog_sec: u64,
+    /// Arguments to `serve`, e.g. `electricity consumption --interval 1800`
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        default_values = ["electricity", "consumption"]
+    )]
+    pub serve_args: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    /// Also connect to the InfluxDB sink the command would write to
+    #[arg(long)]
+    pub connect: bool,
+    /// The command line to check, as it would be run, e.g.
+    /// `-- serve electricity consumption --interval 1800`
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct ExportDashboardArgs {
+    /// Measurement readings and prices were written to
+    #[arg(long, env = "N3RGY_MEASUREMENT", default_value = DEFAULT_MEASUREMENT)]
+    pub measurement: String,
+    #[arg(long, default_value = "Energy")]
+    pub title: String,
+    /// UID of the InfluxDB datasource, otherwise Grafana asks for one on import
+    #[arg(long, env = "N3RGY_GRAFANA_DATASOURCE")]
+    pub datasource_uid: Option<String>,
+    /// Grafana to create the dashboard in, rather than printing it
+    #[arg(long, env = "N3RGY_GRAFANA_URL", requires_all = ["grafana_token", "datasource_uid"])]
+    pub grafana_url: Option<String>,
+    /// Service account token with permission to write dashboards
+    #[arg(long, env = "N3RGY_GRAFANA_TOKEN", hide_env_values = true)]
+    pub grafana_token: Option<SecretString>,
+    /// UID of the folder to create the dashboard in
+    #[arg(long, requires = "grafana_url")]
+    pub folder_uid: Option<String>,
+}
+
+#[derive(Args)]
+pub struct DeleteArgs {
+    /// Start of the range to delete
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub start: DateTime<Local>,
+    /// End of the range to delete, inclusive
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub end: DateTime<Local>,
+    /// Only delete points for this fuel
+    #[arg(long, value_enum)]
+    pub fuel: Option<EnergyType>,
+    /// Only delete consumption or tariff points
+    #[arg(long = "type", value_enum)]
+    pub request_type: Option<RequestType>,
+    /// Only delete points for this property from the config file
+    #[arg(long)]
+    pub property: Option<String>,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct RetagArgs {
+    /// Measurement to rewrite
+    #[arg(long, default_value = DEFAULT_MEASUREMENT)]
+    pub from_measurement: String,
+    /// Write the points to this measurement instead of back to the same one
+    #[arg(long)]
+    pub to_measurement: Option<String>,
+    /// Drop the series the points were read from once they're all rewritten
+    #[arg(long)]
+    pub delete_old: bool,
+    /// Days of points to rewrite at once
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(i64).range(1..))]
+    pub chunk_days: i64,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct QualityArgs {
+    /// Start of the range, defaults to 30 days before --end
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub start: Option<DateTime<Local>>,
+    /// End of the range, defaults to now
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub end: Option<DateTime<Local>>,
+    pub energy_type: EnergyType,
+    /// Shortest run of zero readings to list, in half hours
+    #[arg(long, default_value_t = 6)]
+    pub min_zero_run: usize,
+    /// List readings over this many times the range's median as spikes
+    #[arg(long, default_value_t = 5.0)]
+    pub spike_factor: f64,
+    /// Print the report as JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+    #[command(flatten)]
+    pub token: TokenArgs,
+}
+
+impl QualityArgs {
+    pub fn window(&self) -> Result<Window, String> {
+        let end = self.end.unwrap_or_else(Local::now);
+        let start = self.start.unwrap_or(end - Duration::days(30));
+        validate_range(start, end)
+    }
+}
+
+#[cfg(feature = "email")]
+#[derive(Args)]
+pub struct EmailArgs {
+    /// Period to summarise
+    #[arg(long, value_enum, default_value = "week")]
+    pub period: Period,
+    /// Only count this property's consumption and cost
+    #[arg(long)]
+    pub property: Option<String>,
+    /// Print the summary instead of emailing it
+    #[arg(long)]
+    pub print: bool,
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+}
+
+#[derive(Args)]
+pub struct FleetReportArgs {
+    /// Start of the reporting period, defaults to 30 days ago
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub start: Option<DateTime<Local>>,
+    /// End of the reporting period, defaults to now
+    #[arg(long, value_parser = clap::builder::StringValueParser::new().try_map(parse_dt))]
+    pub end: Option<DateTime<Local>>,
+    /// Print the report as JSON instead of a table
+    #[arg(long)]
+    pub json: bool,
+    #[command(flatten)]
+    pub pricing: PricingArgs,
+}
+
+impl FleetReportArgs {
+    pub fn window(&self) -> Result<Window, String> {
+        let end = self.end.unwrap_or_else(Local::now);
+        let start = self.start.unwrap_or(end - Duration::days(30));
+        validate_range(start, end)
+    }
+}
+
+#[cfg(feature = "mock-server")]
+#[derive(Args)]
+pub struct MockServerArgs {
+    /// Address to listen on
+    #[arg(long, env = "N3RGY_MOCK_ADDR", default_value = "127.0.0.1:8080")]
+    pub addr: SocketAddr,
+    /// Days of history to serve, up to now
+    #[arg(long, env = "N3RGY_MOCK_HISTORY_DAYS", default_value_t = 400)]
+    pub history_days: i64,
+}
+
+#[derive(Args)]
+pub struct ConsentArgs {
+    /// MAC address printed on the in-home display
+    #[arg(long)]
+    pub ihd_mac: String,
+    /// Poll until consent has been granted
+    #[arg(long)]
+    pub wait: bool,
+    /// Seconds between consent checks with --wait
+    #[arg(long, default_value_t = 60)]
+    pub poll_interval: u64,
+    /// Minutes to wait for consent before giving up
+    #[arg(long, default_value_t = 60)]
+    pub timeout: u64,
+}
+
+/// A secret given directly, or else read from a file.
+pub fn secret(
+    value: &Option<SecretString>,
+    file: &Option<PathBuf>,
+) -> io::Result<Option<SecretString>> {
+    match (value, file) {
+        (Some(value), _) => Ok(Some(value.clone())),
+        (None, Some(path)) => SecretString::read_from(path).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Basic auth when a username is given, else the token given directly or in a file.
+pub fn influx_auth(
+    token: &Option<SecretString>,
+    token_file: &Option<PathBuf>,
+    username: &Option<String>,
+    password: &Option<SecretString>,
+) -> io::Result<Option<InfluxAuth>> {
+    if let (Some(username), Some(password)) = (username, password) {
+        return Ok(Some(InfluxAuth::Basic {
+            username: username.clone(),
+            password: password.clone(),
+        }));
+    }
+    Ok(secret(token, token_file)?.map(InfluxAuth::Token))
+}
+
+fn parse_tag(value: &str) -> Result<(String, Template), String> {
+    let Some((key, tag_value)) = value.split_once('=') else {
+        return Err(format!("{} is not a KEY=VALUE tag", value));
+    };
+    if key.is_empty() || tag_value.is_empty() {
+        return Err(format!("{} needs both a tag key and a value", value));
+    }
+    if RESERVED_TAGS.contains(&key) {
+        return Err(format!("{} is set on every point already", key));
+    }
+    Ok((key.to_string(), tag_value.parse()?))
+}
+
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, header_value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), header_value.trim().to_string()))
+        }
+        _ => Err(format!("{} is not a NAME: VALUE header", value)),
+    }
+}
+
+#[cfg(feature = "s3")]
+fn parse_s3_key(value: &str) -> Result<Template, String> {
+    Template::parse(value, &archive::KEY_VARIABLES)
+}
+
+fn parse_graphite_path(value: &str) -> Result<Template, String> {
+    Template::parse(value, &graphite::PATH_VARIABLES)
+}
+
+fn parse_delay(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Ok(seconds),
+        _ => Err(format!("{} is not a non-negative number of seconds", value)),
+    }
+}
+
+fn parse_dt(value: String) -> Result<chrono::DateTime<Local>, String> {
     if let Ok(dt) = value.parse::<chrono::DateTime<Local>>() {
-        Ok(dt)
-    } else {
-        let naive_date = value.parse::<chrono::NaiveDate>().unwrap();
-        Ok(naive_date
-            .and_hms_opt(0, 0, 0)
+        return Ok(dt);
+    }
+    let naive_date = value.parse::<chrono::NaiveDate>().map_err(|_| {
+        format!(
+            "{} is not a date (2024-01-31) or date and time (2024-01-31T00:00:00Z)",
+            value
+        )
+    })?;
+    naive_date
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_local_timezone(Local)
+        .earliest()
+        .ok_or_else(|| format!("midnight on {} does not exist locally", naive_date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    /// The default window ending at `end`, as UTC instants.
+    fn window_ending(end: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        let range = RangeArgs {
+            start: None,
+            end: Some(end.with_timezone(&Local)),
+        };
+        let (start, end) = range.window().unwrap();
+        (start.to_utc(), end.to_utc())
+    }
+
+    #[test]
+    fn default_window_covers_a_whole_uk_day_across_clock_changes() {
+        let utc = |month, day, hour| Utc.with_ymd_and_hms(2024, month, day, hour, 0, 0).unwrap();
+        // 31 March is 23 hours long, starting in GMT and ending in BST
+        assert_eq!(
+            window_ending(utc(3, 31, 23)),
+            (utc(3, 31, 0), utc(3, 31, 23))
+        );
+        // 27 October is 25 hours long, starting in BST and ending in GMT
+        assert_eq!(
+            window_ending(utc(10, 28, 0)),
+            (utc(10, 26, 23), utc(10, 28, 0))
+        );
+    }
+
+    /// The `sync` options `line` parses to under the profile `home` in a
+    /// config file holding `profiles`, or clap's error.
+    fn sync_with_profile(profiles: &str, line: &str) -> Result<SyncArgs, String> {
+        let test = std::thread::current().name().unwrap().replace("::", "-");
+        let path = env::temp_dir().join(format!("n3rgy-{}-{}.toml", test, process::id()));
+        std::fs::write(&path, profiles).unwrap();
+        let args: Vec<String> = format!(
+            "n3rgy-rs --config {} --profile home sync electricity consumption {}",
+            path.display(),
+            line
+        )
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+        let matches = command_from(&args)?.try_get_matches_from(&args);
+        std::fs::remove_file(&path).unwrap();
+        match Cli::from_arg_matches(&matches.map_err(|e| e.kind().to_string())?)
             .unwrap()
-            .and_local_timezone(Local)
-            .unwrap())
+            .command
+        {
+            Some(Command::Sync(args)) => Ok(args),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn profile_supplies_options_the_command_line_does_not() {
+        let profiles = r#"
+            [profiles.home]
+            influx_uri = "http://influx:8086"
+            influx_database = "energy"
+            influx_token = "secret"
+        "#;
+        let args = sync_with_profile(profiles, "").unwrap();
+        assert_eq!(args.influx.uri(), Some("http://influx:8086"));
+        assert_eq!(args.influx.database(), Some("energy"));
+        assert!(matches!(
+            args.influx.auth().unwrap(),
+            Some(InfluxAuth::Token(token)) if token.expose() == "secret"
+        ));
+        assert!(env::var_os("N3RGY_INFLUX_TOKEN").is_none());
+
+        // flags win, and one credential given replaces all of the profile's
+        let args = sync_with_profile(
+            profiles,
+            "--influx-database other --influx-username me --influx-password pw",
+        )
+        .unwrap();
+        assert_eq!(args.influx.uri(), Some("http://influx:8086"));
+        assert_eq!(args.influx.database(), Some("other"));
+        assert!(matches!(
+            args.influx.auth().unwrap(),
+            Some(InfluxAuth::Basic { username, .. }) if username == "me"
+        ));
+    }
+
+    #[test]
+    fn profile_is_read_afresh_each_parse() {
+        let profiles = r#"
+            [profiles.home]
+            influx_uri = "http://influx:8086"
+            influx_database = "energy"
+            influx_token = "secret"
+        "#;
+        let args = sync_with_profile(profiles, "").unwrap();
+        assert_eq!(args.influx.database(), Some("energy"));
+
+        // as when `serve` reloads after the config file is edited
+        let edited = profiles.replace("\"energy\"", "\"energy_v2\"");
+        let args = sync_with_profile(&edited, "").unwrap();
+        assert_eq!(args.influx.database(), Some("energy_v2"));
+        let removed = profiles.replace("influx_database = \"energy\"", "");
+        assert_eq!(
+            sync_with_profile(&removed, "").err(),
+            Some(clap::error::ErrorKind::MissingRequiredArgument.to_string())
+        );
+        assert!(env::var_os("N3RGY_INFLUX_DATABASE").is_none());
     }
 }
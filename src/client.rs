@@ -0,0 +1,654 @@
+use std::ops::{Bound, RangeBounds};
+use std::time::{Duration as StdDuration, Instant};
+
+use chrono::{DateTime, Local};
+use futures::future;
+use futures::stream::{self, Stream, StreamExt};
+use log::{debug, warn};
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+
+pub use crate::batching::{Window, MAX_WINDOW_DAYS};
+
+use crate::batching::date_windows;
+use crate::cache::{meter_key, ResponseCache};
+use crate::error::Error;
+use crate::models::{
+    AvailableCacheRange, ConsumptionOrTariff, ConsumptionReading, DataSource, ElementInfo,
+    EnergyType, Entries, Granularity, RequestType, Response, TariffPrice,
+};
+use crate::recording::Recording;
+use crate::secret::SecretString;
+
+pub const N3RGY_BASE_URL: &str = "https://consumer-api.data.n3rgy.com/";
+/// n3rgy's sandbox environment, serving sample data for testing integrations.
+pub const N3RGY_SANDBOX_URL: &str = "https://sandboxapi.data.n3rgy.com/";
+
+/// How long to keep polling a window n3rgy is still retrieving from the DCC.
+pub const DEFAULT_PENDING_DEADLINE: StdDuration = StdDuration::from_secs(300);
+const INITIAL_PENDING_BACKOFF: StdDuration = StdDuration::from_secs(2);
+const MAX_PENDING_BACKOFF: StdDuration = StdDuration::from_secs(60);
+
+/// How many times to retry a request rate limited or failed by n3rgy's servers.
+const MAX_RETRIES: u32 = 4;
+const INITIAL_RETRY_BACKOFF: StdDuration = StdDuration::from_secs(1);
+
+/// Client for the n3rgy consumer API.
+///
+/// Ranges longer than the API allows are split into windows internally, so
+/// callers can simply ask for `client.consumption(EnergyType::Electricity, start..end)`.
+#[derive(Clone)]
+pub struct N3rgyClient {
+    http: reqwest::Client,
+    api_token: SecretString,
+    base_url: String,
+    element: u8,
+    granularity: Granularity,
+    pending_deadline: StdDuration,
+    chunk_days: i64,
+    recording: Option<Recording>,
+    cache: Option<ResponseCache>,
+}
+
+impl N3rgyClient {
+    pub fn new(api_token: impl Into<SecretString>) -> N3rgyClient {
+        N3rgyClient {
+            http: reqwest::Client::new(),
+            api_token: api_token.into(),
+            base_url: N3RGY_BASE_URL.to_string(),
+            element: 1,
+            granularity: Granularity::default(),
+            pending_deadline: DEFAULT_PENDING_DEADLINE,
+            chunk_days: MAX_WINDOW_DAYS,
+            recording: None,
+            cache: None,
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> N3rgyClient {
+        self.http = http;
+        self
+    }
+
+    /// Point the client at another deployment of the API, such as [`N3RGY_SANDBOX_URL`].
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> N3rgyClient {
+        let mut base_url = base_url.into();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        self.base_url = base_url;
+        self
+    }
+
+    /// Read from another meter register, e.g. the second element of a twin-element
+    /// (Economy 7) electricity meter. Defaults to 1.
+    pub fn with_element(mut self, element: u8) -> N3rgyClient {
+        self.element = element;
+        self
+    }
+
+    pub fn element(&self) -> u8 {
+        self.element
+    }
+
+    pub fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    /// Request consumption at a coarser interval than half-hourly.
+    pub fn with_granularity(mut self, granularity: Granularity) -> N3rgyClient {
+        self.granularity = granularity;
+        self
+    }
+
+    /// Set how long [`fetch`](Self::fetch) polls a `202 Accepted` response before
+    /// giving up with [`Error::Pending`].
+    pub fn with_pending_deadline(mut self, deadline: StdDuration) -> N3rgyClient {
+        self.pending_deadline = deadline;
+        self
+    }
+
+    /// Save response bodies to disk, or answer requests from ones saved earlier.
+    pub fn with_recording(mut self, recording: Recording) -> N3rgyClient {
+        self.recording = Some(recording);
+        self
+    }
+
+    /// Serve data requests from responses cached on disk, saving new ones there.
+    pub fn with_cache(mut self, cache: ResponseCache) -> N3rgyClient {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The recorded body for `request` when replaying, `None` otherwise.
+    fn replayed(&self, request: &str) -> Result<Option<String>, Error> {
+        match &self.recording {
+            Some(recording) if recording.is_replay() => {
+                recording
+                    .load(request)
+                    .map(Some)
+                    .map_err(|source| Error::Recording {
+                        path: recording.path(request),
+                        source,
+                    })
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Save the body of a successful response to `request` when recording.
+    fn record(&self, request: &str, body: &str) -> Result<(), Error> {
+        match &self.recording {
+            Some(recording) => recording
+                .save(request, body)
+                .map_err(|source| Error::Recording {
+                    path: recording.path(request),
+                    source,
+                }),
+            None => Ok(()),
+        }
+    }
+
+    /// The token as a header value marked sensitive, so reqwest never prints it.
+    fn authorization(&self) -> Result<HeaderValue, Error> {
+        let mut value =
+            HeaderValue::from_str(self.api_token.expose()).map_err(|_| Error::InvalidToken)?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+
+    /// Split ranges into windows of `days` days rather than the API's maximum
+    /// of [`MAX_WINDOW_DAYS`], which also caps it.
+    pub fn with_chunk_days(mut self, days: i64) -> N3rgyClient {
+        self.chunk_days = days;
+        self
+    }
+
+    /// The windows `start..end` is requested in.
+    pub fn windows(&self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<Window> {
+        date_windows(start, end, self.chunk_days)
+    }
+
+    /// Stream half-hourly consumption readings for every window in `range`.
+    pub fn consumption<R>(
+        &self,
+        energy_type: EnergyType,
+        range: R,
+    ) -> impl Stream<Item = Result<ConsumptionReading, Error>> + '_
+    where
+        R: RangeBounds<DateTime<Local>>,
+    {
+        self.readings(
+            energy_type,
+            RequestType::Consumption,
+            range,
+            |response| match response {
+                ConsumptionOrTariff::Consumption(consumption) => {
+                    consumption.into_readings().collect()
+                }
+                ConsumptionOrTariff::Tariff(_) => Vec::new(),
+            },
+        )
+    }
+
+    /// Stream unit prices and standing charges for every window in `range`.
+    pub fn tariff<R>(
+        &self,
+        energy_type: EnergyType,
+        range: R,
+    ) -> impl Stream<Item = Result<TariffPrice, Error>> + '_
+    where
+        R: RangeBounds<DateTime<Local>>,
+    {
+        self.readings(
+            energy_type,
+            RequestType::Tariff,
+            range,
+            |response| match response {
+                ConsumptionOrTariff::Tariff(tariff) => tariff.prices().collect(),
+                ConsumptionOrTariff::Consumption(_) => Vec::new(),
+            },
+        )
+    }
+
+    fn readings<R, T>(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        range: R,
+        extract: fn(ConsumptionOrTariff) -> Vec<T>,
+    ) -> impl Stream<Item = Result<T, Error>> + '_
+    where
+        R: RangeBounds<DateTime<Local>>,
+        T: 'static,
+    {
+        let windows: Vec<Result<Window, Error>> = match resolve_range(range) {
+            Ok((start, end)) => self.windows(start, end).into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        };
+
+        stream::iter(windows)
+            .then(move |window| async move {
+                let (start, end) = window?;
+                let response = self.fetch(energy_type, request_type, start, end).await?;
+                Ok(extract(response))
+            })
+            .flat_map(|batch| match batch {
+                Ok(readings) => stream::iter(readings.into_iter().map(Ok)).left_stream(),
+                Err(e) => stream::once(future::ready(Err(e))).right_stream(),
+            })
+    }
+
+    /// Whether the token is currently authorised to read data.
+    ///
+    /// Returns `Ok(false)` while consent is missing or has lapsed.
+    pub async fn check_access(&self) -> Result<bool, Error> {
+        if self.recording.as_ref().is_some_and(Recording::is_replay) {
+            return Ok(true);
+        }
+        let res = self
+            .http
+            .get(&self.base_url)
+            .header(AUTHORIZATION, self.authorization()?)
+            .send()
+            .await?;
+        match res.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Ok(false),
+            status => Err(Error::UnexpectedStatus(status)),
+        }
+    }
+
+    /// List the child resources under `path`, e.g. `""` for the fuels or
+    /// `"electricity/consumption"` for the meter elements.
+    pub async fn entries(&self, path: &str) -> Result<Vec<String>, Error> {
+        let entries: Entries = self.get_json(path).await?;
+        Ok(entries.entries)
+    }
+
+    /// Walk the API's entry points to find every fuel, data type and element the
+    /// token can read, along with the range of data n3rgy holds for each.
+    pub async fn discover(&self) -> Result<Vec<DataSource>, Error> {
+        let mut sources = Vec::new();
+        for fuel in self.entries("").await? {
+            for data_type in self.entries(&fuel).await? {
+                let path = format!("{}/{}", fuel, data_type);
+                for element in self.entries(&path).await? {
+                    let info: ElementInfo = self.get_json(&format!("{}/{}", path, element)).await?;
+                    sources.push(DataSource {
+                        fuel: fuel.clone(),
+                        data_type: data_type.clone(),
+                        element,
+                        range: info.available_cache_range,
+                    });
+                }
+            }
+        }
+        Ok(sources)
+    }
+
+    /// The meter elements available for a fuel and data type.
+    pub async fn elements(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+    ) -> Result<Vec<u8>, Error> {
+        let path = resource_path(energy_type, request_type);
+        let entries = self.entries(&path).await?;
+        Ok(entries.iter().filter_map(|e| e.parse().ok()).collect())
+    }
+
+    /// The range of data n3rgy holds for a fuel and data type, if it reports one.
+    pub async fn available_range(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+    ) -> Result<Option<AvailableCacheRange>, Error> {
+        let info: ElementInfo = self
+            .get_json(&format!(
+                "{}/{}",
+                resource_path(energy_type, request_type),
+                self.element
+            ))
+            .await?;
+        Ok(info.available_cache_range)
+    }
+
+    /// Narrow `start..end` to the data n3rgy actually holds, so no requests are
+    /// made for dates the DCC has nothing for.
+    ///
+    /// Returns `None` when the range lies entirely outside the available data.
+    pub async fn clamp_to_available(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<Option<Window>, Error> {
+        let Some(available) = self.available_range(energy_type, request_type).await? else {
+            return Ok(Some((start, end)));
+        };
+        let start = std::cmp::max(start, available.start.with_timezone(&Local));
+        let end = std::cmp::min(end, available.end.with_timezone(&Local));
+        if start >= end {
+            return Ok(None);
+        }
+        Ok(Some((start, end)))
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        if let Some(body) = self.replayed(path)? {
+            return Ok(serde_json::from_str(&body)?);
+        }
+        let res = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .header(AUTHORIZATION, self.authorization()?)
+            .send()
+            .await?;
+        match res.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                return Err(Error::Unauthorized(res.status()))
+            }
+            status if !status.is_success() => return Err(Error::UnexpectedStatus(status)),
+            _ => {}
+        }
+        let body = res.text().await?;
+        self.record(path, &body)?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// The series responses are cached under, e.g.
+    /// `electricity_consumption_1_halfhour`, in a directory of its own per
+    /// API and token, so meters sharing a cache never answer for each other.
+    fn cache_series(&self, energy_type: EnergyType, request_type: RequestType) -> String {
+        let series = format!(
+            "{}_{}_{}_{}",
+            energy_type,
+            request_type,
+            self.element,
+            self.granularity.as_param()
+        )
+        .to_lowercase();
+        format!(
+            "{}/{}",
+            meter_key(&self.base_url, self.api_token.expose()),
+            series
+        )
+    }
+
+    /// Fetch a single window from the API, which must not exceed [`MAX_WINDOW_DAYS`].
+    ///
+    /// n3rgy answers `202 Accepted` while it is still retrieving data from the DCC;
+    /// the request is retried with backoff until the pending deadline passes.
+    ///
+    /// Rate limiting and server errors are retried a few times; a rejected token
+    /// is returned as [`Error::Unauthorized`] and n3rgy's error payloads as
+    /// [`Error::Api`].
+    pub async fn fetch(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start_date: DateTime<Local>,
+        end_date: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        // n3rgy reads the range in UTC, as it stamps readings, so a local time
+        // must not be sent as is or BST ranges shift by an hour
+        let request_start = format!("{}", start_date.to_utc().format("%Y%m%d%H%M"));
+        let request_end = format!("{}", end_date.to_utc().format("%Y%m%d%H%M"));
+
+        debug!(
+            "requesting: {} {} element {} for dates {} {}",
+            energy_type, request_type, self.element, start_date, end_date
+        );
+
+        let url = self.build_request_url(
+            request_start,
+            request_end,
+            "JSON".to_string(),
+            energy_type,
+            request_type,
+        );
+        // the request relative to the base URL, which names its recording
+        let request = url.as_str()[self.base_url.len()..].to_string();
+        if let Some(body) = self.replayed(&request)? {
+            return parse_data(&url, start_date, end_date, StatusCode::OK, &body);
+        }
+        let series = self.cache_series(energy_type, request_type);
+        let (start_utc, end_utc) = (start_date.to_utc(), end_date.to_utc());
+        if let Some(cache) = &self.cache {
+            match cache.lookup(&series, start_utc, end_utc) {
+                Ok(Some((body, exact))) => {
+                    debug!(
+                        "serving {} {} for {} {} from the cache",
+                        energy_type, request_type, start_date, end_date
+                    );
+                    let mut data = parse_data(&url, start_date, end_date, StatusCode::OK, &body)?;
+                    if !exact {
+                        data.retain_within(start_utc, end_utc);
+                    }
+                    return Ok(data);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("could not read the response cache: {}", e),
+            }
+        }
+
+        let started = Instant::now();
+        let mut backoff = INITIAL_PENDING_BACKOFF;
+        let mut retries = 0;
+        let mut retry_backoff = INITIAL_RETRY_BACKOFF;
+        let res = loop {
+            let res = self
+                .http
+                .get(url.clone())
+                .header(AUTHORIZATION, self.authorization()?)
+                .send()
+                .await?;
+            let status = res.status();
+            if status == StatusCode::ACCEPTED {
+                if started.elapsed() + backoff > self.pending_deadline {
+                    return Err(Error::Pending {
+                        start: start_date,
+                        end: end_date,
+                    });
+                }
+                debug!(
+                    "n3rgy is still retrieving {} {} for {} {}, retrying in {:?}",
+                    energy_type, request_type, start_date, end_date, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, MAX_PENDING_BACKOFF);
+            } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+                return Err(Error::Unauthorized(status));
+            } else if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                if retries >= MAX_RETRIES {
+                    return Err(Error::UnexpectedStatus(status));
+                }
+                let delay = retry_after(&res).unwrap_or(retry_backoff);
+                warn!(
+                    "n3rgy responded {} for {} {}, retrying in {:?}",
+                    status, energy_type, request_type, delay
+                );
+                tokio::time::sleep(delay).await;
+                retries += 1;
+                retry_backoff *= 2;
+            } else {
+                break res;
+            }
+        };
+
+        let status = res.status();
+        let body = res.text().await?;
+        if status.is_success() {
+            self.record(&request, &body)?;
+        }
+        let data = parse_data(&url, start_date, end_date, status, &body)?;
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.store(&series, start_utc, end_utc, &body) {
+                warn!("could not write to the response cache: {}", e);
+            }
+        }
+        Ok(data)
+    }
+
+    fn build_request_url(
+        &self,
+        start: String,
+        end: String,
+        output: String,
+        energy_type: EnergyType,
+        request_type: RequestType,
+    ) -> Url {
+        let mut parameters = vec![("start", start), ("end", end), ("output", output)];
+        if let RequestType::Consumption = request_type {
+            parameters.push(("granularity", self.granularity.as_param().to_string()));
+        }
+
+        let request_url = format!(
+            "{}{}/{}",
+            self.base_url,
+            resource_path(energy_type, request_type),
+            self.element
+        );
+
+        reqwest::Url::parse_with_params(&request_url, parameters).unwrap()
+    }
+}
+
+/// Parse a data response body, which may be n3rgy's error payload instead.
+fn parse_data(
+    url: &Url,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    status: StatusCode,
+    body: &str,
+) -> Result<ConsumptionOrTariff, Error> {
+    match serde_json::from_str(body) {
+        Ok(Response::Error(error)) => Err(Error::Api {
+            url: url.to_string(),
+            start,
+            end,
+            errors: error.into_errors(),
+        }),
+        _ if !status.is_success() => Err(Error::UnexpectedStatus(status)),
+        Ok(Response::Data(data)) => Ok(data),
+        Err(source) => Err(Error::UnexpectedBody {
+            url: url.to_string(),
+            source,
+        }),
+    }
+}
+
+/// The delay requested by a `Retry-After` header given in seconds.
+fn retry_after(res: &reqwest::Response) -> Option<StdDuration> {
+    let seconds = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    Some(StdDuration::from_secs(seconds))
+}
+
+fn resolve_range<R>(range: R) -> Result<Window, Error>
+where
+    R: RangeBounds<DateTime<Local>>,
+{
+    let start = match range.start_bound() {
+        Bound::Included(start) | Bound::Excluded(start) => *start,
+        Bound::Unbounded => {
+            return Err(Error::InvalidRange("a start date is required".to_string()))
+        }
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) | Bound::Excluded(end) => *end,
+        Bound::Unbounded => Local::now(),
+    };
+    if end <= start {
+        return Err(Error::InvalidRange(format!(
+            "end {} is not after start {}",
+            end, start
+        )));
+    }
+    Ok((start, end))
+}
+
+/// The path listing the elements for a fuel and data type, e.g. `electricity/consumption`.
+fn resource_path(energy_type: EnergyType, request_type: RequestType) -> String {
+    let request_url = match energy_type {
+        EnergyType::Electricity => "electricity/".to_owned(),
+        EnergyType::Gas => "gas/".to_owned(),
+    };
+
+    match request_type {
+        RequestType::Consumption => request_url + "consumption",
+        RequestType::Tariff => request_url + "tariff",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    const BODY: &str = r#"{
+        "resource": "/electricity/consumption/1",
+        "responseTimestamp": "2024-06-02T00:00:00Z",
+        "start": "202406010000",
+        "end": "202406020000",
+        "granularity": "halfhour",
+        "unit": "kWh",
+        "values": [{"timestamp": "2024-06-01 00:30", "value": 0.25, "status": "valid"}]
+    }"#;
+
+    async fn fetch(
+        client: &N3rgyClient,
+        (start, end): Window,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        client
+            .fetch(
+                EnergyType::Electricity,
+                RequestType::Consumption,
+                start,
+                end,
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn cache_entries_are_not_shared_between_tokens() {
+        let dir = std::env::temp_dir().join(format!("n3rgy-cache-test-{}", std::process::id()));
+        let cache = ResponseCache::new(&dir, StdDuration::from_secs(3600));
+        // nothing listens here, so only a cached response can be served
+        let client = |token: &str| {
+            N3rgyClient::new(token)
+                .with_base_url("http://127.0.0.1:9/")
+                .with_cache(cache.clone())
+        };
+        let (first, second) = (client("first-token"), client("second-token"));
+
+        let start = Local.with_ymd_and_hms(2024, 6, 1, 1, 0, 0).unwrap();
+        let end = Local.with_ymd_and_hms(2024, 6, 2, 1, 0, 0).unwrap();
+        let series = first.cache_series(EnergyType::Electricity, RequestType::Consumption);
+        cache
+            .store(&series, start.to_utc(), end.to_utc(), BODY)
+            .unwrap();
+
+        let cached = fetch(&first, (start, end)).await;
+        let uncached = fetch(&second, (start, end)).await;
+        let elsewhere = fetch(
+            &first.clone().with_base_url("http://127.0.0.1:10/"),
+            (start, end),
+        )
+        .await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(cached.is_ok());
+        assert!(matches!(uncached, Err(Error::Http(_))));
+        assert!(matches!(elsewhere, Err(Error::Http(_))));
+    }
+}
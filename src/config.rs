@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Europe::London;
+use clap::ValueEnum;
+use n3rgy_rs::ev::DEFAULT_MIN_MINUTES;
+use n3rgy_rs::models::{Consumption, EnergyType, Granularity};
+use n3rgy_rs::secret::SecretString;
+use n3rgy_rs::solar::{PvArray, DEFAULT_PERFORMANCE_RATIO};
+use n3rgy_rs::weather::DEFAULT_BASE_TEMPERATURE;
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub meters: Vec<Meter>,
+    /// Named bundles of settings, selected with `--profile`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Time-of-use bands to tag half-hourly consumption and cost with.
+    #[serde(default)]
+    pub bands: Vec<Band>,
+    /// Price cap rates to write a `cap_cost` series at, for comparison with
+    /// actual spend.
+    pub price_cap: Option<PriceCap>,
+    /// Monthly spend `report budget` compares the projection with.
+    pub monthly_budget: Option<f64>,
+    /// Bounds half-hourly readings are checked against as they are loaded.
+    pub anomalies: Option<Anomalies>,
+    /// Where to fetch daily temperatures for, to write gas consumption
+    /// against heating degree days.
+    pub degree_days: Option<DegreeDays>,
+    /// Panels to estimate generation and self-consumption for, with
+    /// `--export-element`.
+    pub solar: Option<Solar>,
+    /// Draw half-hourly electricity is taken to be an EV charging at.
+    pub ev_charging: Option<EvCharging>,
+    /// Rules `serve` checks stored totals against after each sync.
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+    /// Where `report email` sends summaries.
+    #[cfg(feature = "email")]
+    pub email: Option<Email>,
+}
+
+/// Tags every point already carries, which a meter's extra tags may not reuse.
+pub const RESERVED_TAGS: [&str; 12] = [
+    "property",
+    "fuel",
+    "mpxn",
+    "element",
+    "type",
+    "status",
+    "unit",
+    "granularity",
+    "price_type",
+    "settlement_period",
+    "band",
+    "anomaly",
+];
+
+/// A single property's consent token, as listed under `[[meters]]`.
+#[derive(Deserialize)]
+pub struct Meter {
+    /// Tagged onto the property's points as `property`.
+    pub label: String,
+    pub api_token: SecretString,
+    /// The property's MPAN or MPRN, tagged as `mpxn`.
+    pub mpxn: Option<String>,
+    /// Date the consent behind `api_token` lapses, e.g. `"2025-06-01"`.
+    pub consent_expires: Option<NaiveDate>,
+    /// Extra tags for the property's points, e.g. `{ region = "north" }`.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// The property's own time-of-use bands, in place of the top-level ones.
+    pub bands: Option<Vec<Band>>,
+    /// The property's own location for degree days, in place of the top-level one.
+    pub degree_days: Option<DegreeDays>,
+    /// The property's own panels, in place of the top-level ones.
+    pub solar: Option<Solar>,
+}
+
+/// A named time-of-use band, as listed under `[[bands]]`, e.g. `night` from
+/// `"00:30"` to `"07:30"` UK local time. A band without times covers every
+/// half hour no other band does.
+#[derive(Clone, Deserialize)]
+pub struct Band {
+    pub name: String,
+    #[serde(default, deserialize_with = "clock_time")]
+    pub start: Option<NaiveTime>,
+    #[serde(default, deserialize_with = "clock_time")]
+    pub end: Option<NaiveTime>,
+}
+
+impl Band {
+    /// Whether the band covers the half hour starting at `time`, wrapping past
+    /// midnight when it ends earlier than it starts.
+    fn covers(&self, time: NaiveTime) -> bool {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start <= end => start <= time && time < end,
+            (Some(start), Some(end)) => start <= time || time < end,
+            _ => false,
+        }
+    }
+}
+
+/// The band the half hour ending at `end` falls in, as readings are stamped.
+pub fn band_for(bands: &[Band], end: DateTime<Utc>) -> Option<&str> {
+    let time = (end - Duration::minutes(30)).with_timezone(&London).time();
+    bands
+        .iter()
+        .find(|band| band.covers(time))
+        .or_else(|| bands.iter().find(|band| band.start.is_none()))
+        .map(|band| band.name.as_str())
+}
+
+/// The current Ofgem price cap, as set under `[price_cap]`, in the same unit
+/// stored prices are in.
+#[derive(Clone, Copy, Deserialize)]
+pub struct PriceCap {
+    pub electricity: Option<CapRates>,
+    pub gas: Option<CapRates>,
+}
+
+impl PriceCap {
+    pub fn rates(&self, energy_type: EnergyType) -> Option<CapRates> {
+        match energy_type {
+            EnergyType::Electricity => self.electricity,
+            EnergyType::Gas => self.gas,
+        }
+    }
+}
+
+/// A fuel's capped unit rate, per kWh, and daily standing charge, e.g.
+/// `electricity = { unit_rate = 24.5, standing_charge = 60.1 }`.
+#[derive(Clone, Copy, Deserialize)]
+pub struct CapRates {
+    pub unit_rate: f64,
+    pub standing_charge: f64,
+}
+
+/// Sanity checks on half-hourly readings, as set under `[anomalies]`. Readings
+/// that fail them are tagged `anomaly=true`, or with `quarantine` written to
+/// a separate measurement, rather than skewing dashboards. Negative readings
+/// always fail.
+#[derive(Clone, Copy, Deserialize)]
+pub struct Anomalies {
+    /// Most electricity a half hour may plausibly use, in kWh.
+    pub electricity_max: Option<f64>,
+    /// Most gas a half hour may plausibly use, in the meter's unit.
+    pub gas_max: Option<f64>,
+    /// Flag readings over this many times the median of their window.
+    pub spike_factor: Option<f64>,
+    #[serde(default)]
+    pub quarantine: bool,
+}
+
+impl Anomalies {
+    /// End times of the half-hourly readings in `consumption` out of bounds.
+    pub fn detect(
+        &self,
+        energy_type: EnergyType,
+        consumption: &Consumption,
+    ) -> HashSet<DateTime<Utc>> {
+        if consumption.granularity != Granularity::HalfHour.as_param() {
+            return HashSet::new();
+        }
+        let max = match energy_type {
+            EnergyType::Electricity => self.electricity_max,
+            EnergyType::Gas => self.gas_max,
+        };
+        let mut values: Vec<f64> = consumption.values.iter().map(|v| v.value).collect();
+        values.sort_by(f64::total_cmp);
+        let median = values.get(values.len() / 2).copied().unwrap_or(0.0);
+        let spike = self
+            .spike_factor
+            .filter(|_| median > 0.0)
+            .map(|factor| median * factor);
+
+        consumption
+            .values
+            .iter()
+            .filter(|v| {
+                max.is_some_and(|max| v.value > max)
+                    || spike.is_some_and(|spike| v.value > spike)
+                    || v.value < 0.0
+            })
+            .map(|v| v.timestamp)
+            .collect()
+    }
+}
+
+/// A location's daily mean temperature, as set under `[degree_days]`, e.g.
+/// `latitude = 51.45`, `longitude = -0.97`. Each complete UK day of half-hourly
+/// gas is written to a `degree_days` measurement with the day's temperature,
+/// heating degree days and consumption per degree day.
+#[derive(Clone, Copy, Deserialize)]
+pub struct DegreeDays {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Mean outdoor temperature, in °C, above which a day needs no heating.
+    #[serde(default = "default_base_temperature")]
+    pub base_temperature: f64,
+}
+
+fn default_base_temperature() -> f64 {
+    DEFAULT_BASE_TEMPERATURE
+}
+
+/// A property's solar panels, as set under `[solar]`, e.g. `latitude = 51.45`,
+/// `longitude = -0.97`, `capacity_kwp = 4.0`. Their half-hourly generation is
+/// roughly estimated from Open-Meteo's irradiance to tell how much of it was
+/// used at home rather than exported.
+#[derive(Clone, Copy, Deserialize)]
+pub struct Solar {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub capacity_kwp: f64,
+    /// Share of the rated output delivered after losses.
+    #[serde(default = "default_performance_ratio")]
+    pub performance_ratio: f64,
+}
+
+impl Solar {
+    pub fn array(&self) -> PvArray {
+        PvArray {
+            capacity: self.capacity_kwp,
+            performance_ratio: self.performance_ratio,
+        }
+    }
+}
+
+fn default_performance_ratio() -> f64 {
+    DEFAULT_PERFORMANCE_RATIO
+}
+
+/// Sustained draw taken to be an EV charging, as set under `[ev_charging]`,
+/// e.g. `threshold_kw = 3.0`. Each run of half hours averaging at least
+/// `threshold_kw`, lasting `min_minutes` or more, is written to an
+/// `ev_session` measurement.
+#[derive(Clone, Copy, Deserialize)]
+pub struct EvCharging {
+    pub threshold_kw: f64,
+    #[serde(default = "default_min_minutes")]
+    pub min_minutes: u32,
+}
+
+fn default_min_minutes() -> u32 {
+    DEFAULT_MIN_MINUTES
+}
+
+/// A daily threshold, as listed under `[[alerts]]`, e.g. `name = "high usage"`,
+/// `metric = "consumption"`, `daily_above = 20.0`.
+#[derive(Clone, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: AlertMetric,
+    /// Raise the alert once the UK day's total goes over this.
+    pub daily_above: f64,
+    /// Only check this fuel, rather than any the daemon loads.
+    #[serde(default, deserialize_with = "fuel")]
+    pub fuel: Option<EnergyType>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertMetric {
+    /// In the meter's unit.
+    Consumption,
+    /// From the `cost` measurement, so needs `--compute-cost`.
+    Cost,
+}
+
+impl fmt::Display for AlertMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AlertMetric::Consumption => "consumption",
+            AlertMetric::Cost => "cost",
+        })
+    }
+}
+
+/// An optional `"electricity"` or `"gas"`.
+fn fuel<'de, D>(deserializer: D) -> Result<Option<EnergyType>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    EnergyType::from_str(&s, true)
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("{} is not electricity or gas", s)))
+}
+
+/// SMTP settings for `report email`, as set under `[email]`.
+#[cfg(feature = "email")]
+#[derive(Deserialize)]
+pub struct Email {
+    pub host: String,
+    /// Defaults to the usual port for `tls`.
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+#[cfg(feature = "email")]
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade a plain connection, usually on port 587.
+    #[default]
+    Starttls,
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// No encryption, e.g. for a relay on localhost.
+    None,
+}
+
+/// An optional `"HH:MM"` time of day.
+fn clock_time<'de, D>(deserializer: D) -> Result<Option<NaiveTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H:%M")
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("{} is not an HH:MM time", s)))
+}
+
+/// Token, API and sink settings for one property or environment, as listed
+/// under `[profiles.<name>]`.
+#[derive(Deserialize)]
+pub struct Profile {
+    pub api_token: Option<SecretString>,
+    pub api_token_file: Option<PathBuf>,
+    pub base_url: Option<String>,
+    pub sandbox: Option<bool>,
+    pub consent_expires: Option<NaiveDate>,
+    pub influx_uri: Option<String>,
+    pub influx_database: Option<String>,
+    pub influx_token: Option<SecretString>,
+    pub influx_token_file: Option<PathBuf>,
+    pub influx_username: Option<String>,
+    pub influx_password: Option<SecretString>,
+    /// Cron expression `serve` syncs on, as for `--schedule`.
+    pub schedule: Option<String>,
+    /// Extra tags for every point loaded under the profile.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Profile {
+    /// The profile's settings, by the `N3RGY_` environment variable each
+    /// stands in for.
+    pub fn settings(&self) -> Vec<(&'static str, String)> {
+        let path = |p: &Option<PathBuf>| p.as_ref().map(|p| p.display().to_string());
+        let secret = |s: &Option<SecretString>| s.as_ref().map(|s| s.expose().to_string());
+        [
+            ("N3RGY_API_TOKEN", secret(&self.api_token)),
+            ("N3RGY_API_TOKEN_FILE", path(&self.api_token_file)),
+            ("N3RGY_BASE_URL", self.base_url.clone()),
+            ("N3RGY_SANDBOX", self.sandbox.map(|s| s.to_string())),
+            (
+                "N3RGY_CONSENT_EXPIRES",
+                self.consent_expires.map(|d| d.to_string()),
+            ),
+            ("N3RGY_INFLUX_URI", self.influx_uri.clone()),
+            ("N3RGY_INFLUX_DATABASE", self.influx_database.clone()),
+            ("N3RGY_INFLUX_TOKEN", secret(&self.influx_token)),
+            ("N3RGY_INFLUX_TOKEN_FILE", path(&self.influx_token_file)),
+            ("N3RGY_INFLUX_USERNAME", self.influx_username.clone()),
+            ("N3RGY_INFLUX_PASSWORD", secret(&self.influx_password)),
+            ("N3RGY_SCHEDULE", self.schedule.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(e) => write!(f, "could not read config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse config file: {}", e),
+            ConfigError::Invalid(e) => write!(f, "invalid config file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(ConfigError::Read)?;
+        let config: Config = toml::from_str(&contents).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut labels = HashSet::new();
+        for meter in &self.meters {
+            if !labels.insert(meter.label.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "meter label {} is used more than once",
+                    meter.label
+                )));
+            }
+            check_tags(&format!("meter {}", meter.label), &meter.tags)?;
+        }
+        for (name, profile) in &self.profiles {
+            check_tags(&format!("profile {}", name), &profile.tags)?;
+        }
+        check_bands("bands", &self.bands)?;
+        for meter in &self.meters {
+            if let Some(bands) = &meter.bands {
+                check_bands(&format!("meter {} bands", meter.label), bands)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_bands(owner: &str, bands: &[Band]) -> Result<(), ConfigError> {
+    let invalid = |e: String| Err(ConfigError::Invalid(format!("{}: {}", owner, e)));
+    for band in bands {
+        if band.start.is_some() != band.end.is_some() {
+            return invalid(format!(
+                "band {} needs both start and end, or neither",
+                band.name
+            ));
+        }
+    }
+    if bands.iter().filter(|band| band.start.is_none()).count() > 1 {
+        return invalid("only one band may leave out start and end".to_string());
+    }
+    Ok(())
+}
+
+fn check_tags(owner: &str, tags: &BTreeMap<String, String>) -> Result<(), ConfigError> {
+    match tags
+        .keys()
+        .find(|tag| RESERVED_TAGS.contains(&tag.as_str()))
+    {
+        Some(tag) => Err(ConfigError::Invalid(format!(
+            "{} sets tag {}, which is reserved",
+            owner, tag
+        ))),
+        None => Ok(()),
+    }
+}
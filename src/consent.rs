@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveDate};
+use log::{error, info, warn};
+use n3rgy_rs::N3rgyClient;
+
+use crate::cli::ConsentArgs;
+use crate::load::Target;
+use crate::metrics;
+
+/// Where consumers grant n3rgy access to their smart meter data.
+const N3RGY_CONSENT_URL: &str = "https://data.n3rgy.com/";
+
+/// Start warning this many days before consent lapses.
+pub const WARN_DAYS: i64 = 30;
+
+pub fn days_remaining(expires: NaiveDate) -> i64 {
+    (expires - Local::now().date_naive()).num_days()
+}
+
+pub fn describe(expires: NaiveDate) -> String {
+    let days = days_remaining(expires);
+    if days < 0 {
+        format!("consent expired {} days ago ({})", -days, expires)
+    } else {
+        format!("consent expires in {} days ({})", days, expires)
+    }
+}
+
+/// Log how long is left on a property's consent, louder as the expiry date
+/// approaches.
+pub fn report(label: Option<&str>, expires: Option<NaiveDate>) {
+    let Some(expires) = expires else {
+        return;
+    };
+    metrics::CONSENT_EXPIRY
+        .with_label_values(&[label.unwrap_or_default()])
+        .set(expires.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp());
+    let message = match label {
+        Some(label) => format!("{}: {}", label, describe(expires)),
+        None => describe(expires),
+    };
+    let days = days_remaining(expires);
+    if days < 0 {
+        error!("{}, renew it to keep collecting data", message);
+    } else if days <= WARN_DAYS {
+        warn!("{}", message);
+    } else {
+        info!("{}", message);
+    }
+}
+
+/// Report each property's consent once, however many elements it has.
+pub fn report_targets(targets: &[Target]) {
+    let mut seen = HashSet::new();
+    for target in targets {
+        if seen.insert(&target.label) {
+            report(target.label.as_deref(), target.consent_expires);
+        }
+    }
+}
+
+/// Check whether consent has been granted for an in-home display, pointing at
+/// n3rgy's web form when not, and with `wait` poll until it has.
+///
+/// n3rgy's consumer API authenticates with the in-home display's MAC address, so
+/// once consent has been granted the normalised MAC is the API token. n3rgy has
+/// no API for granting consent, only the form.
+pub async fn check(
+    http_client: &reqwest::Client,
+    base_url: &str,
+    args: ConsentArgs,
+) -> Result<(), String> {
+    let token = normalise_mac(&args.ihd_mac)?;
+    let client = N3rgyClient::new(token.clone())
+        .with_http_client(http_client.clone())
+        .with_base_url(base_url);
+
+    if client.check_access().await.map_err(|e| e.to_string())? {
+        println!("Consent is active. Use {} as your API_TOKEN.", token);
+        return Ok(());
+    }
+
+    println!("No consent found for this in-home display yet.");
+    println!(
+        "Complete n3rgy's consumer consent form at {}",
+        N3RGY_CONSENT_URL
+    );
+    println!("with your MPAN or MPRN and this IHD MAC: {}", token);
+    if !args.wait {
+        println!("Re-run with --wait to poll until consent is granted.");
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let timeout = Duration::from_secs(args.timeout * 60);
+    loop {
+        tokio::time::sleep(Duration::from_secs(args.poll_interval)).await;
+        if client.check_access().await.map_err(|e| e.to_string())? {
+            println!("Consent is active. Use {} as your API_TOKEN.", token);
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            return Err(format!(
+                "consent was not granted within {} minutes",
+                args.timeout
+            ));
+        }
+        info!("consent not granted yet, checking again");
+    }
+}
+
+fn normalise_mac(mac: &str) -> Result<String, String> {
+    let hex: String = mac
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':' | ' '))
+        .collect::<String>()
+        .to_uppercase();
+    if hex.len() == 16 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hex)
+    } else {
+        Err(format!(
+            "{} is not a 16 character IHD MAC address, e.g. 00-11-22-33-44-55-66-77",
+            mac
+        ))
+    }
+}
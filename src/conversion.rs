@@ -0,0 +1,39 @@
+/// Typical calorific value of GB mains gas, in MJ/m³.
+pub const DEFAULT_CALORIFIC_VALUE: f64 = 39.5;
+/// Standard correction for gas temperature and pressure used on GB bills.
+pub const DEFAULT_VOLUME_CORRECTION: f64 = 1.02264;
+
+const MJ_PER_KWH: f64 = 3.6;
+
+/// Converts gas volumes to energy the way suppliers do on bills:
+/// `kWh = m³ × volume correction × calorific value / 3.6`.
+#[derive(Clone, Copy, Debug)]
+pub struct GasConversion {
+    pub calorific_value: f64,
+    pub volume_correction: f64,
+}
+
+impl Default for GasConversion {
+    fn default() -> Self {
+        GasConversion {
+            calorific_value: DEFAULT_CALORIFIC_VALUE,
+            volume_correction: DEFAULT_VOLUME_CORRECTION,
+        }
+    }
+}
+
+impl GasConversion {
+    pub fn m3_to_kwh(&self, m3: f64) -> f64 {
+        m3 * self.volume_correction * self.calorific_value / MJ_PER_KWH
+    }
+
+    pub fn kwh_to_m3(&self, kwh: f64) -> f64 {
+        kwh * MJ_PER_KWH / (self.volume_correction * self.calorific_value)
+    }
+}
+
+/// Whether an n3rgy `unit` string denotes cubic metres.
+pub fn is_cubic_metres(unit: &str) -> bool {
+    let unit = unit.to_lowercase();
+    unit == "m3" || unit == "m³"
+}
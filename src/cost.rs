@@ -0,0 +1,123 @@
+use std::borrow::Borrow;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use influxdb::InfluxDbWriteable;
+
+use crate::conversion::{is_cubic_metres, GasConversion};
+use crate::models::{ConsumptionReading, TariffPrice, PRICE, STANDING_CHARGE};
+
+const MINUTES_PER_DAY: f64 = 1440.0;
+
+/// The cost of a single reading, in the tariff's currency units.
+#[derive(InfluxDbWriteable, Clone, Debug)]
+pub struct Cost {
+    pub time: DateTime<Utc>,
+    /// kWh priced, converted from the reading when it was in m³.
+    pub consumption: f64,
+    pub unit_rate: f64,
+    pub energy_cost: f64,
+    pub standing_charge: f64,
+    pub total: f64,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    ///
+    /// [`Resource::add_tags`]: crate::models::Resource::add_tags
+    #[influxdb(ignore)]
+    pub resource: String,
+}
+
+/// Price each reading at the unit rate in force at its timestamp, adding the
+/// day's standing charge prorated over the interval the reading covers. Gas
+/// readings in m³ are converted to kWh with `conversion` first, as unit rates
+/// are per kWh.
+///
+/// Readings before the first known unit rate are skipped.
+pub fn price_consumption<C, T>(consumption: C, tariff: T, conversion: GasConversion) -> Vec<Cost>
+where
+    C: IntoIterator,
+    C::Item: Borrow<ConsumptionReading>,
+    T: IntoIterator,
+    T::Item: Borrow<TariffPrice>,
+{
+    let mut rates: Vec<(DateTime<Utc>, f64)> = Vec::new();
+    let mut standing_charges: Vec<(NaiveDate, f64)> = Vec::new();
+    for price in tariff {
+        let price = price.borrow();
+        if price.price_type == PRICE {
+            rates.push((price.time, price.price));
+        } else if price.price_type == STANDING_CHARGE {
+            standing_charges.push((price.time.date_naive(), price.price));
+        }
+    }
+    rates.sort_by_key(|(time, _)| *time);
+    standing_charges.sort_by_key(|(date, _)| *date);
+
+    let mut costs = Vec::new();
+    for reading in consumption {
+        let reading = reading.borrow();
+        let Some(unit_rate) = in_force(&rates, &reading.time) else {
+            continue;
+        };
+        let standing_charge =
+            in_force(&standing_charges, &reading.time.date_naive()).unwrap_or(0.0);
+        costs.push(cost(reading, unit_rate, standing_charge, conversion));
+    }
+    costs
+}
+
+/// Price each reading at a single unit rate and daily standing charge, such as
+/// the price cap's, converting m³ as [`price_consumption`] does.
+pub fn price_flat<C>(
+    consumption: C,
+    unit_rate: f64,
+    standing_charge: f64,
+    conversion: GasConversion,
+) -> Vec<Cost>
+where
+    C: IntoIterator,
+    C::Item: Borrow<ConsumptionReading>,
+{
+    consumption
+        .into_iter()
+        .map(|reading| cost(reading.borrow(), unit_rate, standing_charge, conversion))
+        .collect()
+}
+
+/// Minutes a reading covers, from its `granularity`.
+fn interval_minutes(granularity: &str) -> f64 {
+    match granularity {
+        "day" => MINUTES_PER_DAY,
+        "hour" => 60.0,
+        _ => 30.0,
+    }
+}
+
+fn cost(
+    reading: &ConsumptionReading,
+    unit_rate: f64,
+    daily_standing_charge: f64,
+    conversion: GasConversion,
+) -> Cost {
+    let standing_charge =
+        daily_standing_charge * interval_minutes(&reading.granularity) / MINUTES_PER_DAY;
+    let kwh = if is_cubic_metres(&reading.unit) {
+        conversion.m3_to_kwh(reading.consumption)
+    } else {
+        reading.consumption
+    };
+    let energy_cost = kwh * unit_rate;
+    Cost {
+        time: reading.time,
+        consumption: kwh,
+        unit_rate,
+        energy_cost,
+        standing_charge,
+        total: energy_cost + standing_charge,
+        resource: reading.resource.clone(),
+    }
+}
+
+/// The latest value that took effect at or before `at`, from a list sorted by key.
+fn in_force<K: Ord>(values: &[(K, f64)], at: &K) -> Option<f64> {
+    let idx = values.partition_point(|(key, _)| key <= at);
+    idx.checked_sub(1).map(|i| values[i].1)
+}
@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::time::Duration;
+
+use n3rgy_rs::client::Window;
+use n3rgy_rs::models::{EnergyType, RequestType};
+use sentry::{ClientInitGuard, ClientOptions, Hub};
+
+use crate::exit::Exit;
+use crate::load::Target;
+
+/// Send panics, and failures passed to [`sync_failure`], to the Sentry DSN.
+/// Reporting stops once the guard is dropped.
+pub fn init(dsn: &str) -> Result<ClientInitGuard, Box<dyn Error>> {
+    let options = ClientOptions {
+        dsn: Some(dsn.parse()?),
+        release: sentry::release_name!(),
+        ..ClientOptions::default()
+    };
+    Ok(sentry::init(options))
+}
+
+/// Report a window that failed to load, tagged with what was being loaded and
+/// where to.
+pub fn sync_failure(
+    target: &Target,
+    (start, end): Window,
+    energy_type: EnergyType,
+    request_type: RequestType,
+    sink: &str,
+    error: &(dyn Error + 'static),
+) {
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("fuel", energy_type.to_string().to_lowercase());
+            scope.set_tag("type", request_type.to_string().to_lowercase());
+            scope.set_tag("element", target.source.element());
+            scope.set_tag("sink", sink);
+            scope.set_tag("exit_code", Exit::of(error) as i32);
+            if let Some(label) = &target.label {
+                scope.set_tag("property", label);
+            }
+            scope.set_extra("window.start", start.to_rfc3339().into());
+            scope.set_extra("window.end", end.to_rfc3339().into());
+        },
+        || sentry::capture_error(error),
+    );
+}
+
+/// Send queued reports before the process exits, since `process::exit`
+/// skips the guard's destructor.
+pub fn flush() {
+    if let Some(client) = Hub::main().client() {
+        client.flush(Some(Duration::from_secs(2)));
+    }
+}
@@ -0,0 +1,331 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration as StdDuration, SystemTime};
+
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Duration, Local};
+use croner::Cron;
+use log::{error, info};
+use n3rgy_rs::client::Window;
+use n3rgy_rs::models::{EnergyType, RequestType};
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::alerts::Alerts;
+use crate::consent;
+#[cfg(feature = "sentry")]
+use crate::crash_report;
+use crate::exit::Exit;
+use crate::load::{Loader, Target};
+use crate::metrics;
+use crate::notify;
+use crate::shutdown;
+use crate::systemd;
+
+/// When the daemon syncs.
+pub enum Schedule {
+    Every(StdDuration),
+    /// A cron expression, in local time.
+    Cron(Box<Cron>),
+}
+
+impl Schedule {
+    /// When the next sync after `now` is due.
+    fn next(&self, now: DateTime<Local>) -> DateTime<Local> {
+        let next = match self {
+            Schedule::Every(interval) => Duration::from_std(*interval)
+                .ok()
+                .and_then(|interval| now.checked_add_signed(interval)),
+            Schedule::Cron(cron) => cron.find_next_occurrence(&now, false).ok(),
+        };
+        // an expression that never matches again only waits as long as it can
+        next.unwrap_or(DateTime::<Local>::MAX_UTC.into())
+    }
+}
+
+pub struct Settings {
+    pub schedule: Schedule,
+    /// Up to this much random delay is added to each sync, so deployments
+    /// sharing a schedule don't all hit n3rgy and the sink at once.
+    pub jitter: StdDuration,
+    /// How much recent data each sync re-requests.
+    pub lookback: Duration,
+    /// Consecutive failed syncs before a notification is sent.
+    pub notify_after: u32,
+    /// Checked after each sync.
+    pub alerts: Alerts,
+}
+
+/// Where the daemon is up to, dumped on SIGUSR1 and served on `/status`.
+#[derive(Clone, Serialize)]
+pub struct Status {
+    pub next_sync: Option<DateTime<Local>>,
+    pub last_sync: Option<DateTime<Local>>,
+    /// `ok`, or what went wrong with the last sync.
+    pub last_result: Option<String>,
+    /// Syncs failed in a row.
+    pub failing: u32,
+    /// Windows waiting to be re-pulled because n3rgy was still retrieving them.
+    pub deferred: usize,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = |time: Option<DateTime<Local>>| {
+            time.map_or("never".to_string(), |time| time.to_rfc3339())
+        };
+        write!(
+            f,
+            "next sync {}, last sync {} ({}), {} failed in a row, {} deferred window(s)",
+            time(self.next_sync),
+            time(self.last_sync),
+            self.last_result.as_deref().unwrap_or("none yet"),
+            self.failing,
+            self.deferred
+        )
+    }
+}
+
+static STATUS: Mutex<Status> = Mutex::new(Status {
+    next_sync: None,
+    last_sync: None,
+    last_result: None,
+    failing: 0,
+    deferred: 0,
+});
+
+/// Wakes the daemon for a sync ahead of schedule.
+static SYNC_NOW: Notify = Notify::const_new();
+
+/// Wakes the daemon to re-read its config.
+static RELOAD: Notify = Notify::const_new();
+
+/// Why [`run`] returned.
+pub enum Stop {
+    Shutdown,
+    /// The config should be re-read and the daemon restarted with it, carrying
+    /// over the windows still to be re-pulled.
+    Reload(Vec<(Target, Window)>),
+}
+
+pub fn status() -> Status {
+    STATUS.lock().unwrap().clone()
+}
+
+/// Start a sync now, or straight after the one in progress.
+pub fn trigger_sync() {
+    SYNC_NOW.notify_one();
+}
+
+/// Reload the config once the sync in progress, if any, finishes.
+pub fn request_reload() {
+    RELOAD.notify_one();
+}
+
+/// Listen for the signals the daemon answers to and, given `config`, reload
+/// whenever that file changes. Call once, however often the daemon reloads.
+pub fn listen(config: Option<PathBuf>) {
+    #[cfg(unix)]
+    tokio::spawn(listen_signals());
+    if let Some(path) = config {
+        tokio::spawn(watch_config(path));
+    }
+}
+
+/// Poll `path` for changes, since a config edited in place, replaced by an
+/// editor or swapped under a mounted ConfigMap all show up as a new mtime.
+async fn watch_config(path: PathBuf) {
+    let modified = |path: &PathBuf| -> Option<SystemTime> { path.metadata().ok()?.modified().ok() };
+    let mut last = modified(&path);
+    let mut ticks = tokio::time::interval(StdDuration::from_secs(5));
+    loop {
+        ticks.tick().await;
+        let current = modified(&path);
+        // a file briefly missing mid-replace isn't a change worth reloading for
+        if current.is_some() && current != last {
+            info!("{} changed, reloading", path.display());
+            request_reload();
+        }
+        if current.is_some() {
+            last = current;
+        }
+    }
+}
+
+/// `GET /status` and `POST /sync`, the HTTP equivalents of SIGUSR1 and SIGUSR2.
+pub fn router() -> Router {
+    Router::new()
+        .route("/status", get(|| async { Json(status()) }))
+        .route(
+            "/sync",
+            post(|| async {
+                info!("sync requested over HTTP");
+                trigger_sync();
+                StatusCode::ACCEPTED
+            }),
+        )
+}
+
+/// Log the status on SIGUSR1, sync on SIGUSR2 and reload the config on SIGHUP.
+#[cfg(unix)]
+async fn listen_signals() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (Ok(mut usr1), Ok(mut usr2), Ok(mut hup)) = (
+        signal(SignalKind::user_defined1()),
+        signal(SignalKind::user_defined2()),
+        signal(SignalKind::hangup()),
+    ) else {
+        error!("could not listen for SIGUSR1, SIGUSR2 and SIGHUP");
+        return;
+    };
+    loop {
+        tokio::select! {
+            _ = usr1.recv() => info!("daemon status: {}", status()),
+            _ = usr2.recv() => {
+                info!("sync requested by SIGUSR2");
+                trigger_sync();
+            }
+            _ = hup.recv() => {
+                info!("reload requested by SIGHUP");
+                request_reload();
+            }
+        }
+    }
+}
+
+/// Re-sync the trailing `lookback` of data on the schedule until shutdown is
+/// requested, or sooner when [`trigger_sync`] is called. Returns early between
+/// syncs when [`request_reload`] is called.
+///
+/// Windows n3rgy was still retrieving are re-pulled at the start of the next cycle.
+pub async fn run(
+    targets: &[Target],
+    loader: &Loader,
+    energy_type: EnergyType,
+    request_type: RequestType,
+    settings: &mut Settings,
+    mut deferred: Vec<(Target, Window)>,
+) -> Stop {
+    let mut failing = 0;
+    let mut notified = false;
+    while !shutdown::requested() {
+        let jitter = settings.jitter.mul_f64(fastrand::f64());
+        let next = settings.schedule.next(Local::now()) + jitter;
+        {
+            let mut status = STATUS.lock().unwrap();
+            status.next_sync = Some(next);
+            status.deferred = deferred.len();
+        }
+        let wait = (next - Local::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = SYNC_NOW.notified() => {}
+            _ = RELOAD.notified() => return Stop::Reload(deferred),
+            _ = shutdown::wait() => break,
+        }
+
+        consent::report_targets(targets);
+        loader.retry_spooled().await;
+
+        let end = Local::now();
+        let start = end - settings.lookback;
+        let mut windows = std::mem::take(&mut deferred);
+        windows.extend(targets.iter().map(|t| (t.clone(), (start, end))));
+
+        let mut failure = None;
+        // every window written, not deferred, skipped or spooled
+        let mut complete = true;
+        for (target, (start, end)) in windows {
+            info!(
+                "daemon sync of {} {} {} from {} to {}",
+                energy_type,
+                request_type,
+                target.describe(),
+                start,
+                end
+            );
+            match loader
+                .sync(&target, start, end, energy_type, request_type)
+                .await
+            {
+                Ok(outcome) => {
+                    complete &= outcome.is_complete();
+                    if !outcome.spooled.is_empty() {
+                        // kept for a later run, but InfluxDB is down
+                        Exit::record(&mut failure, Exit::Sink);
+                    }
+                    for (_, e) in &outcome.failed {
+                        Exit::record(&mut failure, Exit::of(e.as_ref()));
+                    }
+                    #[cfg(feature = "sentry")]
+                    for (window, e) in &outcome.failed {
+                        crash_report::sync_failure(
+                            &target,
+                            *window,
+                            energy_type,
+                            request_type,
+                            loader.sink.kind(),
+                            e.as_ref(),
+                        );
+                    }
+                    deferred.extend(outcome.deferred.into_iter().map(|w| (target.clone(), w)));
+                }
+                Err(e) => {
+                    error!("daemon sync failed: {}", e);
+                    #[cfg(feature = "sentry")]
+                    crash_report::sync_failure(
+                        &target,
+                        (start, end),
+                        energy_type,
+                        request_type,
+                        loader.sink.kind(),
+                        e.as_ref(),
+                    );
+                    Exit::record(&mut failure, Exit::of(e.as_ref()));
+                }
+            }
+        }
+        match failure {
+            None => {
+                if complete {
+                    metrics::LAST_SUCCESSFUL_SYNC.set(end.timestamp());
+                }
+                systemd::status(&format!("last synced {}", end.format("%Y-%m-%d %H:%M")));
+                if notified {
+                    notify::recovered("serve").await;
+                }
+                failing = 0;
+                notified = false;
+            }
+            Some(code) => {
+                failing += 1;
+                systemd::status(&format!(
+                    "{} failed sync(s) in a row: {}",
+                    failing,
+                    code.describe()
+                ));
+                // a rejected token won't fix itself, so don't wait to say so
+                if !notified && (failing >= settings.notify_after || code == Exit::Auth) {
+                    notify::failure("serve", code).await;
+                    notified = true;
+                }
+            }
+        }
+        {
+            let mut status = STATUS.lock().unwrap();
+            status.last_sync = Some(end);
+            status.last_result = Some(failure.map_or("ok", |code| code.describe()).to_string());
+            status.failing = failing;
+        }
+        if request_type == RequestType::Consumption {
+            settings.alerts.evaluate(loader, targets, energy_type).await;
+        }
+    }
+    systemd::stopping();
+    info!("daemon stopped");
+    Stop::Shutdown
+}
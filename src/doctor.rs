@@ -0,0 +1,199 @@
+use std::fmt;
+use std::path::Path;
+
+use chrono::Utc;
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use n3rgy_rs::secret::SecretString;
+use n3rgy_rs::N3rgyClient;
+
+use crate::auth;
+use crate::cli::{self, DoctorArgs};
+use crate::config::Config;
+use crate::consent;
+
+/// Measurement the write check's test point is written to.
+const TEST_MEASUREMENT: &str = "n3rgy_doctor";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+            Status::Skip => "SKIP",
+        };
+        f.pad(label)
+    }
+}
+
+pub struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    pub fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(InfluxDbWriteable)]
+struct TestPoint {
+    time: chrono::DateTime<Utc>,
+    ok: bool,
+}
+
+/// Check the config file, n3rgy token, consent and InfluxDB, printing a report.
+///
+/// Returns whether every check passed (warnings and skipped checks included).
+pub async fn run(
+    http_client: &reqwest::Client,
+    influx_http: Option<&influx_reqwest::Client>,
+    base_url: &str,
+    config: Option<&Path>,
+    args: DoctorArgs,
+) -> bool {
+    let mut checks = vec![check_config(config)];
+    let api_token = match cli::secret(&args.api_token, &args.api_token_file) {
+        Ok(Some(token)) => Ok(Some(token)),
+        Ok(None) => auth::stored_token(),
+        Err(e) => Err(e.to_string()),
+    };
+    checks.push(match api_token {
+        Ok(api_token) => check_token(http_client, base_url, api_token.as_ref()).await,
+        Err(e) => Check::new("n3rgy token", Status::Fail, e),
+    });
+    checks.push(check_consent(&args));
+    checks.extend(check_influx(influx_http, &args).await);
+    report(&checks)
+}
+
+/// Print one line per check, returning whether none failed.
+pub fn report(checks: &[Check]) -> bool {
+    for check in checks {
+        println!("{:<5} {:<18} {}", check.status, check.name, check.detail);
+    }
+    checks.iter().all(|c| c.status != Status::Fail)
+}
+
+pub fn check_config(path: Option<&Path>) -> Check {
+    let Some(path) = path else {
+        return Check::new("config", Status::Skip, "no config file given");
+    };
+    match Config::load(path) {
+        Ok(config) => Check::new(
+            "config",
+            Status::Pass,
+            format!("{} ({} meters)", path.display(), config.meters.len()),
+        ),
+        Err(e) => Check::new("config", Status::Fail, e.to_string()),
+    }
+}
+
+async fn check_token(
+    http_client: &reqwest::Client,
+    base_url: &str,
+    api_token: Option<&SecretString>,
+) -> Check {
+    let Some(api_token) = api_token else {
+        return Check::new(
+            "n3rgy token",
+            Status::Fail,
+            "N3RGY_API_TOKEN is not set and no token is saved",
+        );
+    };
+    let client = N3rgyClient::new(api_token.clone())
+        .with_http_client(http_client.clone())
+        .with_base_url(base_url);
+    match client.check_access().await {
+        Ok(true) => Check::new(
+            "n3rgy token",
+            Status::Pass,
+            format!("accepted by {}", base_url),
+        ),
+        Ok(false) => Check::new(
+            "n3rgy token",
+            Status::Fail,
+            "rejected: the token is invalid or consent has not been granted",
+        ),
+        Err(e) => Check::new("n3rgy token", Status::Fail, e.to_string()),
+    }
+}
+
+fn check_consent(args: &DoctorArgs) -> Check {
+    let Some(expires) = args.consent_expires else {
+        return Check::new("consent", Status::Skip, "--consent-expires not given");
+    };
+    let days = consent::days_remaining(expires);
+    let status = if days < 0 {
+        Status::Fail
+    } else if days <= consent::WARN_DAYS {
+        Status::Warn
+    } else {
+        Status::Pass
+    };
+    Check::new("consent", status, consent::describe(expires))
+}
+
+async fn check_influx(http: Option<&influx_reqwest::Client>, args: &DoctorArgs) -> Vec<Check> {
+    let auth = match cli::influx_auth(
+        &args.influx_token,
+        &args.influx_token_file,
+        &args.influx_username,
+        &args.influx_password,
+    ) {
+        Ok(auth) => auth,
+        Err(e) => return vec![Check::new("influxdb", Status::Fail, e.to_string())],
+    };
+    let (Some(uri), Some(database), Some(auth)) = (&args.influx_uri, &args.influx_database, auth)
+    else {
+        return vec![Check::new(
+            "influxdb",
+            Status::Skip,
+            "N3RGY_INFLUX_URI, N3RGY_INFLUX_DATABASE and a token or username are not all set",
+        )];
+    };
+    let client = auth.client(uri, database, http);
+
+    let connection = match client.ping().await {
+        Ok((build, version)) => Check::new(
+            "influxdb",
+            Status::Pass,
+            format!("{} {} at {}", build, version, uri),
+        ),
+        Err(e) => {
+            return vec![
+                Check::new("influxdb", Status::Fail, e.to_string()),
+                Check::new("influxdb write", Status::Skip, "no connection"),
+            ]
+        }
+    };
+
+    let point: WriteQuery = TestPoint {
+        time: Utc::now(),
+        ok: true,
+    }
+    .into_query(TEST_MEASUREMENT);
+    let write = match client.query(point).await {
+        Ok(_) => Check::new(
+            "influxdb write",
+            Status::Pass,
+            format!("wrote a test point to {}.{}", database, TEST_MEASUREMENT),
+        ),
+        Err(e) => Check::new("influxdb write", Status::Fail, e.to_string()),
+    };
+    vec![connection, write]
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::line_protocol;
+
+/// Appends points to a local DuckDB database through the `duckdb` CLI, one
+/// table per measurement with a `time` column, a `VARCHAR` column per tag and
+/// a typed column per field. Columns are added as new tags and fields appear.
+///
+/// As in InfluxDB, a point replaces the fields it carries of any row with the
+/// same time and tags, so overlapping windows and re-runs don't duplicate
+/// rows. The primary key is `time` and the tags of a measurement's first
+/// points; tags first seen later are plain columns.
+pub struct DuckDb {
+    pub path: PathBuf,
+    /// The `duckdb` executable.
+    pub cli: PathBuf,
+}
+
+/// Column type of tags, which default to '' so points without one still have
+/// a key.
+const TAG_TYPE: &str = "VARCHAR DEFAULT ''";
+
+/// Rows for one table sharing the same columns, by their time and tag values
+/// so the last of several points for the same key wins.
+type Rows = BTreeMap<(String, Vec<String>), BTreeMap<Vec<String>, Vec<String>>>;
+
+impl DuckDb {
+    /// Send points given as line protocol, returning how many rows were
+    /// inserted or replaced.
+    pub async fn append(&self, lines: &[String]) -> Result<usize, Box<dyn Error>> {
+        let mut columns: BTreeMap<String, BTreeMap<String, &'static str>> = BTreeMap::new();
+        let mut rows = Rows::new();
+        for line in lines {
+            let point = line_protocol::parse(line)?;
+            let table = columns.entry(point.measurement.clone()).or_default();
+            let mut names = vec!["time".to_string()];
+            let mut values = vec![format!("TIMESTAMPTZ '{}'", point.time.to_rfc3339())];
+            for (tag, value) in point.tags {
+                table.insert(tag.clone(), TAG_TYPE);
+                names.push(tag);
+                values.push(string(&value));
+            }
+            let key = values.clone();
+            for (field, value) in point.fields {
+                let (column_type, value) = match value {
+                    Value::Bool(flag) => ("BOOLEAN", flag.to_string()),
+                    Value::Number(number) if number.is_i64() => ("BIGINT", number.to_string()),
+                    Value::Number(number) => ("DOUBLE", number.to_string()),
+                    Value::String(text) => ("VARCHAR", string(&text)),
+                    _ => continue,
+                };
+                table.insert(field.clone(), column_type);
+                names.push(field);
+                values.push(value);
+            }
+            rows.entry((point.measurement, names))
+                .or_default()
+                .insert(key, values);
+        }
+
+        let mut sql = String::from("BEGIN TRANSACTION;\n");
+        for (table, table_columns) in &columns {
+            let key_tags = table_columns
+                .iter()
+                .filter(|(_, column_type)| **column_type == TAG_TYPE)
+                .map(|(tag, _)| tag);
+            let mut definitions = vec!["\"time\" TIMESTAMPTZ NOT NULL".to_string()];
+            let mut key = vec!["\"time\"".to_string()];
+            for tag in key_tags {
+                // key columns can't be NULL, so points without the tag get ''
+                definitions.push(format!("{} {} NOT NULL", identifier(tag), TAG_TYPE));
+                key.push(identifier(tag));
+            }
+            definitions.push(format!("PRIMARY KEY ({})", key.join(", ")));
+            sql.push_str(&format!(
+                "CREATE TABLE IF NOT EXISTS {} ({});\n",
+                identifier(table),
+                definitions.join(", ")
+            ));
+            for (column, column_type) in table_columns {
+                sql.push_str(&format!(
+                    "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {};\n",
+                    identifier(table),
+                    identifier(column),
+                    column_type
+                ));
+            }
+        }
+        let mut inserted = 0;
+        for ((table, names), values) in &rows {
+            let names: Vec<String> = names.iter().map(|name| identifier(name)).collect();
+            let values: Vec<String> = values
+                .values()
+                .map(|row| format!("({})", row.join(", ")))
+                .collect();
+            sql.push_str(&format!(
+                "INSERT OR REPLACE INTO {} ({}) VALUES\n{};\n",
+                identifier(table),
+                names.join(", "),
+                values.join(",\n")
+            ));
+            inserted += values.len();
+        }
+        sql.push_str("COMMIT;\n");
+
+        self.run(sql).await?;
+        Ok(inserted)
+    }
+
+    async fn run(&self, sql: String) -> io::Result<()> {
+        let mut child = Command::new(&self.cli)
+            .arg("-bail")
+            .arg(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("could not run {}: {}", self.cli.display(), e),
+                )
+            })?;
+        // dropped once written, closing stdin so the CLI exits
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(sql.as_bytes()).await?;
+        drop(stdin);
+
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "could not write to {}: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
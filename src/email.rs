@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use n3rgy_rs::aggregate::Period;
+use n3rgy_rs::models::{EnergyType, RequestType, Resource};
+use n3rgy_rs::settlement::uk_midnight;
+
+use crate::config::{Email, SmtpTls};
+use crate::load::COST_MEASUREMENT;
+use crate::sink::Sink;
+
+/// Totals for the last complete period and the one before it.
+struct Comparison {
+    label: String,
+    current: f64,
+    previous: f64,
+}
+
+/// First days of the period before the last complete one, the last complete
+/// one and the current one, on the UK date `today`.
+fn first_days(period: Period, today: NaiveDate) -> [NaiveDate; 3] {
+    let current_end = period.first_day(today);
+    let current = period.first_day(current_end.pred_opt().unwrap());
+    let previous = period.first_day(current.pred_opt().unwrap());
+    [previous, current, current_end]
+}
+
+/// Summarise the last complete `period` of stored consumption and cost against
+/// the one before, and email it, or print it when `print` is set.
+pub async fn run(
+    sink: &Sink,
+    measurement: &str,
+    tags: &BTreeMap<String, String>,
+    period: Period,
+    settings: Option<&Email>,
+    print: bool,
+) -> Result<(), Box<dyn Error>> {
+    // periods run between UK midnights, whatever the host's timezone
+    let today = Utc::now().with_timezone(&London).date_naive();
+    let [previous_date, current_date, current_end_date] = first_days(period, today);
+    let current_end = uk_midnight(current_end_date);
+    let current_start = uk_midnight(current_date);
+    let previous_start = uk_midnight(previous_date);
+
+    let mut rows = Vec::new();
+    for energy_type in [EnergyType::Electricity, EnergyType::Gas] {
+        let resource = Resource {
+            fuel: Some(energy_type.to_string().to_lowercase()),
+            data_type: Some(RequestType::Consumption.to_string().to_lowercase()),
+            ..Resource::default()
+        };
+        for (what, measurement, field) in [
+            ("consumption", measurement, "consumption"),
+            ("cost", COST_MEASUREMENT, "total"),
+        ] {
+            let days = sink
+                .daily_sums(measurement, field, &resource, tags, previous_start)
+                .await?;
+            let total = |from: DateTime<Utc>, to: DateTime<Utc>| {
+                days.iter()
+                    .filter(|(time, _)| *time >= from && *time < to)
+                    .map(|(_, sum)| sum)
+                    .sum::<f64>()
+            };
+            rows.push(Comparison {
+                label: format!("{} {}", energy_type, what),
+                current: total(current_start, current_end),
+                previous: total(previous_start, current_start),
+            });
+        }
+    }
+
+    let subject = format!(
+        "Energy summary for the {} from {}",
+        format!("{:?}", period).to_lowercase(),
+        current_date.format("%Y-%m-%d")
+    );
+    let body = render(&rows);
+    if print {
+        println!("{}\n\n{}", subject, body);
+        return Ok(());
+    }
+    let Some(settings) = settings else {
+        return Err("report email needs an [email] section in the config file".into());
+    };
+    send(settings, &subject, body).await
+}
+
+fn render(rows: &[Comparison]) -> String {
+    let mut body = format!(
+        "{:<24} {:>12} {:>12} {:>8}\n",
+        "", "This period", "Previous", "Change"
+    );
+    for row in rows {
+        let change = if row.previous > 0.0 {
+            format!(
+                "{:+.1}%",
+                (row.current - row.previous) / row.previous * 100.0
+            )
+        } else {
+            "-".to_string()
+        };
+        body.push_str(&format!(
+            "{:<24} {:>12.2} {:>12.2} {:>8}\n",
+            row.label, row.current, row.previous, change
+        ));
+    }
+    body
+}
+
+async fn send(settings: &Email, subject: &str, body: String) -> Result<(), Box<dyn Error>> {
+    let mut message = Message::builder()
+        .from(settings.from.parse()?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN);
+    for to in &settings.to {
+        message = message.to(to.parse()?);
+    }
+    let message = message.body(body)?;
+
+    let mut transport = match settings.tls {
+        SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?,
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host),
+    };
+    if let Some(port) = settings.port {
+        transport = transport.port(port);
+    }
+    if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+        transport = transport.credentials(Credentials::new(
+            username.clone(),
+            password.expose().to_string(),
+        ));
+    }
+    transport.build().send(message).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Duration, TimeZone};
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// The UK-local bounds of the periods reported on `today`, as UTC instants.
+    fn bounds(period: Period, today: NaiveDate) -> [DateTime<Utc>; 3] {
+        first_days(period, today).map(uk_midnight)
+    }
+
+    #[test]
+    fn periods_follow_the_clock_changes() {
+        let utc = |month, day, hour| Utc.with_ymd_and_hms(2024, month, day, hour, 0, 0).unwrap();
+        // 31 March, reported on 1 April, is 23 hours long
+        assert_eq!(
+            bounds(Period::Day, date(2024, 4, 1)),
+            [utc(3, 30, 0), utc(3, 31, 0), utc(3, 31, 23)]
+        );
+        // the week to 27 October, reported on the Monday after, is an hour
+        // longer than the one before, which ran in BST throughout
+        let [previous, current, end] = bounds(Period::Week, date(2024, 10, 28));
+        assert_eq!(
+            [previous, current, end],
+            [utc(10, 13, 23), utc(10, 20, 23), utc(10, 28, 0)]
+        );
+        assert_eq!(end - current, Duration::days(7) + Duration::hours(1));
+        assert_eq!(current - previous, Duration::days(7));
+        // March loses an hour and October gains one
+        let [previous, current, end] = bounds(Period::Month, date(2024, 11, 5));
+        assert_eq!(
+            [previous, current, end],
+            [utc(8, 31, 23), utc(9, 30, 23), utc(11, 1, 0)]
+        );
+        assert_eq!(end - current, Duration::days(31) + Duration::hours(1));
+        let [previous, current, end] = bounds(Period::Month, date(2024, 4, 1));
+        assert_eq!(
+            [previous, current, end],
+            [utc(2, 1, 0), utc(3, 1, 0), utc(3, 31, 23)]
+        );
+        assert_eq!(end - current, Duration::days(31) - Duration::hours(1));
+        assert_eq!(current - previous, Duration::days(29));
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+
+use crate::models::ApiError;
+use crate::source::Provider;
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    Parse(serde_json::Error),
+    /// n3rgy answered a data request with its error payload.
+    Api {
+        url: String,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        errors: Vec<ApiError>,
+    },
+    /// A data request's body was neither data nor an n3rgy error payload.
+    UnexpectedBody {
+        url: String,
+        source: serde_json::Error,
+    },
+    InvalidRange(String),
+    UnexpectedStatus(reqwest::StatusCode),
+    /// The API token contains characters that can't be sent in a header.
+    InvalidToken,
+    /// n3rgy refused the API token with `401` or `403`.
+    Unauthorized(reqwest::StatusCode),
+    Pending {
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    },
+    /// A provider other than n3rgy refused the credentials it was given.
+    Rejected {
+        provider: Provider,
+        reason: String,
+    },
+    /// A request to a provider other than n3rgy failed or was answered with
+    /// an error.
+    Provider {
+        provider: Provider,
+        url: String,
+        reason: String,
+    },
+    /// A request to the Carbon Intensity API failed or was answered with an
+    /// error.
+    CarbonIntensity {
+        url: String,
+        reason: String,
+    },
+    /// A request to Open-Meteo failed or was answered with an error.
+    Weather {
+        url: String,
+        reason: String,
+    },
+    /// A response could not be saved to, or replayed from, a recording.
+    Recording {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request to n3rgy failed: {}", e),
+            Error::Parse(e) => write!(f, "could not parse n3rgy response: {}", e),
+            Error::Api {
+                url,
+                start,
+                end,
+                errors,
+            } => {
+                let errors: Vec<String> = errors.iter().map(ApiError::to_string).collect();
+                write!(
+                    f,
+                    "n3rgy returned an error for {} to {} ({}): {}",
+                    start,
+                    end,
+                    url,
+                    errors.join(", ")
+                )
+            }
+            Error::UnexpectedBody { url, source } => {
+                write!(f, "could not parse the response from {}: {}", url, source)
+            }
+            Error::InvalidRange(msg) => write!(f, "invalid date range: {}", msg),
+            Error::UnexpectedStatus(status) => {
+                write!(f, "n3rgy responded with unexpected status {}", status)
+            }
+            Error::InvalidToken => write!(f, "the API token is not a valid header value"),
+            Error::Unauthorized(status) => write!(
+                f,
+                "n3rgy rejected the API token ({}): the token is invalid or consent has expired",
+                status
+            ),
+            Error::Pending { start, end } => {
+                write!(f, "n3rgy is still retrieving data for {} to {}", start, end)
+            }
+            Error::Rejected { provider, reason } => {
+                write!(f, "{} refused {}", provider, reason)
+            }
+            Error::Provider {
+                provider,
+                url,
+                reason,
+            } => write!(f, "{} request to {} failed: {}", provider, url, reason),
+            Error::CarbonIntensity { url, reason } => {
+                write!(f, "carbon intensity request to {} failed: {}", url, reason)
+            }
+            Error::Weather { url, reason } => {
+                write!(f, "weather request to {} failed: {}", url, reason)
+            }
+            Error::Recording { path, source } => {
+                write!(f, "recorded response {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e)
+    }
+}
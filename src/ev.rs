@@ -0,0 +1,103 @@
+//! Heuristic detection of EV charging in half-hourly electricity readings: a
+//! run of half hours drawing at least a threshold power for long enough.
+
+use chrono::{DateTime, Duration, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+
+use crate::models::{ConsumptionReading, Resource};
+
+/// Shortest run of high draw taken as a charge by default.
+pub const DEFAULT_MIN_MINUTES: u32 = 60;
+
+const HALF_HOUR: i64 = 30;
+
+/// A run of half hours taken to be a car charging.
+#[derive(Clone, Debug)]
+pub struct ChargingSession {
+    /// Start of the first half hour.
+    pub start: DateTime<Utc>,
+    /// End of the last half hour.
+    pub end: DateTime<Utc>,
+    /// kWh drawn over the session, including whatever else was running.
+    pub consumption: f64,
+    /// What the session's kWh cost at the tariff, when every half hour was
+    /// priced.
+    pub cost: Option<f64>,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    pub resource: String,
+}
+
+impl InfluxDbWriteable for ChargingSession {
+    /// Written at the session's start.
+    fn into_query<I: Into<String>>(self, name: I) -> WriteQuery {
+        let minutes = (self.end - self.start).num_minutes();
+        let mut query = WriteQuery::new(self.start, name)
+            .add_field("end", self.end.to_rfc3339())
+            .add_field("duration_minutes", minutes)
+            .add_field("consumption", self.consumption)
+            .add_field("average_kw", self.consumption * 60.0 / minutes as f64);
+        if let Some(cost) = self.cost {
+            query = query.add_field("cost", cost);
+        }
+        Resource::parse(&self.resource).add_tags(query)
+    }
+}
+
+/// Follows half-hourly readings in time order, ignoring repeats of one already
+/// seen (e.g. at window seams), and yields each session once it ends. A
+/// missing half hour ends a session.
+pub struct ChargingDetector {
+    threshold_kw: f64,
+    min_duration: Duration,
+    last: Option<DateTime<Utc>>,
+    open: Option<ChargingSession>,
+}
+
+impl ChargingDetector {
+    pub fn new(threshold_kw: f64, min_minutes: u32) -> ChargingDetector {
+        ChargingDetector {
+            threshold_kw,
+            min_duration: Duration::minutes(min_minutes.into()),
+            last: None,
+            open: None,
+        }
+    }
+
+    /// Add the next reading, with the energy cost of the half hour when known,
+    /// returning the session it ended, if any.
+    pub fn add(
+        &mut self,
+        reading: &ConsumptionReading,
+        cost: Option<f64>,
+    ) -> Option<ChargingSession> {
+        let half_hour = Duration::minutes(HALF_HOUR);
+        if self.last.is_some_and(|last| reading.time <= last) {
+            return None;
+        }
+        let contiguous = self.last == Some(reading.time - half_hour);
+        self.last = Some(reading.time);
+
+        let charging = reading.consumption * 2.0 >= self.threshold_kw;
+        match &mut self.open {
+            Some(session) if charging && contiguous => {
+                session.end = reading.time;
+                session.consumption += reading.consumption;
+                session.cost = session.cost.zip(cost).map(|(total, cost)| total + cost);
+                None
+            }
+            _ => {
+                let ended = self.open.take();
+                if charging {
+                    self.open = Some(ChargingSession {
+                        start: reading.time - half_hour,
+                        end: reading.time,
+                        consumption: reading.consumption,
+                        cost,
+                        resource: reading.resource.clone(),
+                    });
+                }
+                ended.filter(|session| session.end - session.start >= self.min_duration)
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::error::Error;
+use std::process;
+
+use crate::http_sink::PostError;
+
+/// Listed under `--help`, so wrapper scripts and systemd units can tell
+/// failures apart.
+pub const HELP: &str = "\
+Exit codes:
+  0    success
+  1    any other failure, e.g. an unreadable config or checkpoint file
+  2    invalid arguments
+  3    the API token is missing or was rejected by n3rgy
+  4    n3rgy could not be reached or answered with an unexpected status
+  5    n3rgy's response could not be parsed
+  6    points could not be written to the sink, e.g. InfluxDB or the output file
+  7    partial success: some windows were deferred or skipped, re-run them later
+  8    another run holds the --lock-file
+  130  interrupted";
+
+/// Why the process is exiting. The discriminant is the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    Failure = 1,
+    Auth = 3,
+    ApiUnavailable = 4,
+    Parse = 5,
+    Sink = 6,
+    Partial = 7,
+    Locked = 8,
+}
+
+impl Exit {
+    /// The failure class of an error returned while loading.
+    pub fn of(error: &(dyn Error + 'static)) -> Exit {
+        if let Some(error) = error.downcast_ref::<n3rgy_rs::Error>() {
+            return match error {
+                n3rgy_rs::Error::InvalidToken
+                | n3rgy_rs::Error::Unauthorized(_)
+                | n3rgy_rs::Error::Rejected { .. } => Exit::Auth,
+                n3rgy_rs::Error::Http(_)
+                | n3rgy_rs::Error::Provider { .. }
+                | n3rgy_rs::Error::CarbonIntensity { .. }
+                | n3rgy_rs::Error::Weather { .. }
+                | n3rgy_rs::Error::UnexpectedStatus(_)
+                | n3rgy_rs::Error::Api { .. }
+                | n3rgy_rs::Error::Pending { .. } => Exit::ApiUnavailable,
+                n3rgy_rs::Error::Parse(_) | n3rgy_rs::Error::UnexpectedBody { .. } => Exit::Parse,
+                n3rgy_rs::Error::InvalidRange(_) | n3rgy_rs::Error::Recording { .. } => {
+                    Exit::Failure
+                }
+            };
+        }
+        if error.is::<influxdb::Error>() || error.is::<std::io::Error>() || error.is::<PostError>()
+        {
+            return Exit::Sink;
+        }
+        Exit::Failure
+    }
+
+    /// Note another failure in a run, keeping the first hard failure over a
+    /// partial success.
+    pub fn record(failure: &mut Option<Exit>, code: Exit) {
+        if matches!(failure, None | Some(Exit::Partial)) {
+            *failure = Some(code);
+        }
+    }
+
+    /// What went wrong, as listed in [`HELP`].
+    pub fn describe(self) -> &'static str {
+        match self {
+            Exit::Failure => "an unexpected failure, see the log",
+            Exit::Auth => "the API token is missing or was rejected by n3rgy",
+            Exit::ApiUnavailable => {
+                "n3rgy could not be reached or answered with an unexpected status"
+            }
+            Exit::Parse => "n3rgy's response could not be parsed",
+            Exit::Sink => {
+                "points could not be written to the sink, e.g. InfluxDB or the output file"
+            }
+            Exit::Partial => "some windows were deferred or skipped",
+            Exit::Locked => "another run holds the lock file",
+        }
+    }
+
+    pub fn exit(self) -> ! {
+        #[cfg(feature = "otel")]
+        crate::telemetry::shutdown();
+        #[cfg(feature = "sentry")]
+        crate::crash_report::flush();
+        process::exit(self as i32)
+    }
+}
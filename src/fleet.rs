@@ -0,0 +1,163 @@
+use chrono::{DateTime, Local};
+use futures::TryStreamExt;
+use log::warn;
+use n3rgy_rs::client::Window;
+use n3rgy_rs::conversion::GasConversion;
+use n3rgy_rs::cost::price_consumption;
+use n3rgy_rs::models::{ConsumptionReading, EnergyType, Pricing, TariffPrice};
+use n3rgy_rs::N3rgyClient;
+use serde::Serialize;
+
+use crate::config::Meter;
+use crate::consent;
+
+/// A property is flagged when its cost exceeds the fleet average by this factor.
+const OUTLIER_FACTOR: f64 = 1.5;
+
+#[derive(Serialize)]
+pub struct PropertySummary {
+    pub label: String,
+    pub electricity: f64,
+    pub gas: f64,
+    pub cost: f64,
+    pub consent_expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct FleetReport {
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub properties: Vec<PropertySummary>,
+    pub total_electricity: f64,
+    pub total_gas: f64,
+    pub total_cost: f64,
+    pub outliers: Vec<String>,
+}
+
+pub async fn run(
+    http_client: &reqwest::Client,
+    base_url: &str,
+    meters: &[Meter],
+    (start, end): Window,
+    pricing: Pricing,
+    json: bool,
+) {
+    let report = build_report(http_client, base_url, meters, (start, end), pricing).await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print_table(&report);
+    }
+}
+
+async fn build_report(
+    http_client: &reqwest::Client,
+    base_url: &str,
+    meters: &[Meter],
+    (start, end): Window,
+    pricing: Pricing,
+) -> FleetReport {
+    let mut properties = Vec::new();
+    for meter in meters {
+        let client = N3rgyClient::new(meter.api_token.clone())
+            .with_http_client(http_client.clone())
+            .with_base_url(base_url);
+        let mut summary = PropertySummary {
+            label: meter.label.clone(),
+            electricity: 0.0,
+            gas: 0.0,
+            cost: 0.0,
+            consent_expires_in_days: meter.consent_expires.map(consent::days_remaining),
+        };
+        for energy_type in [EnergyType::Electricity, EnergyType::Gas] {
+            match summarise_fuel(&client, energy_type, (start, end), pricing).await {
+                Ok((consumption, cost)) => {
+                    match energy_type {
+                        EnergyType::Electricity => summary.electricity = consumption,
+                        EnergyType::Gas => summary.gas = consumption,
+                    }
+                    summary.cost += cost;
+                }
+                Err(e) => warn!("skipping {} for {}: {}", energy_type, meter.label, e),
+            }
+        }
+        properties.push(summary);
+    }
+
+    let average_cost = if properties.is_empty() {
+        0.0
+    } else {
+        properties.iter().map(|p| p.cost).sum::<f64>() / properties.len() as f64
+    };
+    let outliers = properties
+        .iter()
+        .filter(|p| properties.len() > 1 && p.cost > average_cost * OUTLIER_FACTOR)
+        .map(|p| p.label.clone())
+        .collect();
+
+    FleetReport {
+        start,
+        end,
+        total_electricity: properties.iter().map(|p| p.electricity).sum(),
+        total_gas: properties.iter().map(|p| p.gas).sum(),
+        total_cost: properties.iter().map(|p| p.cost).sum(),
+        properties,
+        outliers,
+    }
+}
+
+async fn summarise_fuel(
+    client: &N3rgyClient,
+    energy_type: EnergyType,
+    (start, end): Window,
+    pricing: Pricing,
+) -> Result<(f64, f64), n3rgy_rs::Error> {
+    let consumption: Vec<ConsumptionReading> = client
+        .consumption(energy_type, start..end)
+        .try_collect()
+        .await?;
+    let mut tariff: Vec<TariffPrice> = client.tariff(energy_type, start..end).try_collect().await?;
+    for price in &mut tariff {
+        price.price = pricing.apply(price.price);
+    }
+
+    let total = consumption.iter().map(|r| r.consumption).sum();
+    let cost = price_consumption(&consumption, &tariff, GasConversion::default())
+        .iter()
+        .map(|c| c.total)
+        .sum();
+    Ok((total, cost))
+}
+
+fn print_table(report: &FleetReport) {
+    println!("Fleet report {} to {}", report.start, report.end);
+    println!(
+        "{:<20} {:>14} {:>14} {:>12}",
+        "Property", "Electricity", "Gas", "Cost"
+    );
+    for p in &report.properties {
+        println!(
+            "{:<20} {:>14.2} {:>14.2} {:>12.2}",
+            p.label, p.electricity, p.gas, p.cost
+        );
+    }
+    println!(
+        "{:<20} {:>14.2} {:>14.2} {:>12.2}",
+        "Total", report.total_electricity, report.total_gas, report.total_cost
+    );
+    for p in &report.properties {
+        if let Some(days) = p.consent_expires_in_days {
+            if days <= consent::WARN_DAYS {
+                println!("{}: consent expires in {} days", p.label, days);
+            }
+        }
+    }
+    if !report.outliers.is_empty() {
+        println!(
+            "Outliers (cost over {}x the fleet average): {}",
+            OUTLIER_FACTOR,
+            report.outliers.join(", ")
+        );
+    }
+}
@@ -0,0 +1,560 @@
+//! Client for the Glowmarkt API behind Hildebrand's Bright app and Glow CAD,
+//! which reads the same DCC data as n3rgy on its own schedule.
+
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Local, Utc};
+use log::debug;
+use reqwest::header::HeaderValue;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::batching::{date_windows, Window};
+use crate::error::Error;
+use crate::models::{
+    AvailableCacheRange, Consumption, ConsumptionOrTariff, DataSource, EnergyType, Granularity,
+    Price, RequestType, StandingCharge, Tariff, TariffValues, Value,
+};
+use crate::secret::SecretString;
+use crate::source::{resource_path, Provider, Source};
+
+pub const GLOWMARKT_BASE_URL: &str = "https://api.glowmarkt.com/api/v0-1/";
+/// The Bright app's application id, which Glowmarkt accepts from other clients.
+pub const BRIGHT_APPLICATION_ID: &str = "b0f1b774-a586-4f72-9edd-27ead8aa7a8d";
+
+/// Most days of half-hourly readings Glowmarkt returns for one request.
+const MAX_HALF_HOUR_DAYS: i64 = 10;
+/// Most days of daily readings Glowmarkt returns for one request.
+const MAX_DAY_DAYS: i64 = 31;
+/// Log in again this long before the session token expires.
+const SESSION_MARGIN: StdDuration = StdDuration::from_secs(3600);
+
+/// A logged-in session's token.
+struct Session {
+    token: SecretString,
+    expires: DateTime<Utc>,
+}
+
+/// A Glowmarkt resource: one fuel's consumption, cost or the like.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Resource {
+    resource_id: String,
+    /// E.g. `electricity.consumption` or `gas.consumption.cost`.
+    classifier: String,
+}
+
+impl Resource {
+    /// The fuel whose consumption in kWh this is, skipping costs and the rest.
+    fn consumption_of(&self) -> Option<EnergyType> {
+        match self.classifier.as_str() {
+            "electricity.consumption" => Some(EnergyType::Electricity),
+            "gas.consumption" => Some(EnergyType::Gas),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Auth {
+    valid: bool,
+    token: Option<String>,
+    /// Unix time the token expires at.
+    exp: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct Readings {
+    /// `[unix time the period starts, kWh]` pairs.
+    data: Vec<(i64, f64)>,
+    units: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TariffResponse {
+    data: Vec<TariffPlan>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TariffPlan {
+    /// When the plan started, e.g. `2024-04-01 00:00:00`.
+    from: Option<String>,
+    current_rates: Option<Rates>,
+}
+
+/// Unit rate in pence per kWh and standing charge in pence per day.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rates {
+    rate: f64,
+    standing_charge: f64,
+}
+
+#[derive(Deserialize)]
+struct FirstTime {
+    data: FirstTs,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FirstTs {
+    first_ts: i64,
+}
+
+#[derive(Deserialize)]
+struct LastTime {
+    data: LastTs,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LastTs {
+    last_ts: i64,
+}
+
+/// Client for a Bright account's meters, logging in with its username and
+/// password.
+///
+/// Glowmarkt lists one consumption resource per fuel, so only element 1 is
+/// readable. It reports just the tariff in force, so tariff requests only
+/// price the part of a window since that tariff started.
+#[derive(Clone)]
+pub struct GlowmarktClient {
+    http: reqwest::Client,
+    base_url: String,
+    application_id: String,
+    username: String,
+    password: SecretString,
+    element: u8,
+    granularity: Granularity,
+    /// Shared between clones, so every element and fuel logs in once.
+    session: Arc<Mutex<Option<Session>>>,
+    resources: Arc<Mutex<Option<Vec<Resource>>>>,
+}
+
+impl GlowmarktClient {
+    pub fn new(username: impl Into<String>, password: impl Into<SecretString>) -> GlowmarktClient {
+        GlowmarktClient {
+            http: reqwest::Client::new(),
+            base_url: GLOWMARKT_BASE_URL.to_string(),
+            application_id: BRIGHT_APPLICATION_ID.to_string(),
+            username: username.into(),
+            password: password.into(),
+            element: 1,
+            granularity: Granularity::default(),
+            session: Arc::new(Mutex::new(None)),
+            resources: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> GlowmarktClient {
+        self.http = http;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> GlowmarktClient {
+        let mut base_url = base_url.into();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        self.base_url = base_url;
+        self
+    }
+
+    /// Log in as another Glowmarkt application than the Bright app.
+    pub fn with_application_id(mut self, application_id: impl Into<String>) -> GlowmarktClient {
+        self.application_id = application_id.into();
+        self
+    }
+
+    pub fn with_granularity(mut self, granularity: Granularity) -> GlowmarktClient {
+        self.granularity = granularity;
+        self
+    }
+
+    /// The session token, logging in when there's none or it's about to expire.
+    async fn token(&self) -> Result<SecretString, Error> {
+        let mut session = self.session.lock().await;
+        let margin = Duration::from_std(SESSION_MARGIN).unwrap_or_default();
+        if let Some(session) = session.as_ref().filter(|s| s.expires - margin > Utc::now()) {
+            return Ok(session.token.clone());
+        }
+        let url = format!("{}auth", self.base_url);
+        debug!("logging in to Glowmarkt as {}", self.username);
+        let res = self
+            .http
+            .post(&url)
+            .header("applicationId", &self.application_id)
+            .json(&json!({
+                "username": self.username,
+                "password": self.password.expose(),
+            }))
+            .send()
+            .await
+            .map_err(|e| glowmarkt_error(&url, e))?;
+        let status = res.status();
+        let body = res.text().await.map_err(|e| glowmarkt_error(&url, e))?;
+        let auth: Option<Auth> = serde_json::from_str(&body).ok();
+        match auth {
+            Some(Auth {
+                valid: true,
+                token: Some(token),
+                exp,
+            }) => {
+                let expires = exp
+                    .and_then(|exp| DateTime::from_timestamp(exp, 0))
+                    .unwrap_or_else(|| Utc::now() + Duration::days(1));
+                let token = SecretString::new(token);
+                *session = Some(Session {
+                    token: token.clone(),
+                    expires,
+                });
+                Ok(token)
+            }
+            _ if status.is_server_error() => Err(Error::Provider {
+                provider: Provider::Glowmarkt,
+                url,
+                reason: format!("responded {}", status),
+            }),
+            _ => Err(Error::Rejected {
+                provider: Provider::Glowmarkt,
+                reason: format!("the login for {} ({})", self.username, status),
+            }),
+        }
+    }
+
+    /// GET `path` as the logged-in user, logging in again once if the session
+    /// was revoked.
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, Error> {
+        let url = format!("{}{}", self.base_url, path);
+        let request = |token: SecretString| -> Result<RequestBuilder, Error> {
+            let mut token =
+                HeaderValue::from_str(token.expose()).map_err(|_| Error::InvalidToken)?;
+            token.set_sensitive(true);
+            Ok(self
+                .http
+                .get(&url)
+                .query(query)
+                .header("applicationId", &self.application_id)
+                .header("token", token))
+        };
+        let mut res = request(self.token().await?)?
+            .send()
+            .await
+            .map_err(|e| glowmarkt_error(&url, e))?;
+        if res.status() == StatusCode::UNAUTHORIZED {
+            *self.session.lock().await = None;
+            res = request(self.token().await?)?
+                .send()
+                .await
+                .map_err(|e| glowmarkt_error(&url, e))?;
+        }
+        let status = res.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(Error::Rejected {
+                provider: Provider::Glowmarkt,
+                reason: format!("the session for {} ({})", self.username, status),
+            });
+        }
+        if !status.is_success() {
+            return Err(Error::Provider {
+                provider: Provider::Glowmarkt,
+                url,
+                reason: format!("responded {}", status),
+            });
+        }
+        let body = res.text().await.map_err(|e| glowmarkt_error(&url, e))?;
+        serde_json::from_str(&body).map_err(|source| Error::UnexpectedBody { url, source })
+    }
+
+    /// The account's resources, listed once and reused.
+    async fn resources(&self) -> Result<Vec<Resource>, Error> {
+        let mut resources = self.resources.lock().await;
+        if let Some(resources) = resources.as_ref() {
+            return Ok(resources.clone());
+        }
+        let listed: Vec<Resource> = self.get_json("resource", &[]).await?;
+        *resources = Some(listed.clone());
+        Ok(listed)
+    }
+
+    /// The consumption resource for a fuel, if the account has one.
+    async fn consumption_resource(&self, energy_type: EnergyType) -> Result<Resource, Error> {
+        self.resources()
+            .await?
+            .into_iter()
+            .find(|resource| resource.consumption_of() == Some(energy_type))
+            .ok_or_else(|| Error::Provider {
+                provider: Provider::Glowmarkt,
+                url: format!("{}resource", self.base_url),
+                reason: format!(
+                    "the account has no {} consumption resource",
+                    energy_type.to_string().to_lowercase()
+                ),
+            })
+    }
+
+    /// The span of readings Glowmarkt holds for a resource.
+    async fn range(&self, resource: &Resource) -> Result<Option<AvailableCacheRange>, Error> {
+        let path = format!("resource/{}", resource.resource_id);
+        let first: FirstTime = self.get_json(&format!("{}/first-time", path), &[]).await?;
+        let last: LastTime = self.get_json(&format!("{}/last-time", path), &[]).await?;
+        let (Some(start), Some(end)) = (
+            DateTime::from_timestamp(first.data.first_ts, 0),
+            DateTime::from_timestamp(last.data.last_ts, 0),
+        ) else {
+            return Ok(None);
+        };
+        // readings are stamped at the end of their period, as n3rgy's are
+        let interval = self.granularity.interval();
+        Ok(Some(AvailableCacheRange {
+            start: start + interval,
+            end: end + interval,
+        }))
+    }
+
+    async fn consumption(
+        &self,
+        energy_type: EnergyType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        let resource = self.consumption_resource(energy_type).await?;
+        // Glowmarkt stamps readings at the start of their period, so ask for the
+        // periods ending within the window and restamp them
+        let interval = self.granularity.interval();
+        let format = |time: DateTime<Utc>| time.format("%Y-%m-%dT%H:%M:%S").to_string();
+        let period = match self.granularity {
+            Granularity::HalfHour => "PT30M",
+            Granularity::Day => "P1D",
+        };
+        let query = [
+            ("from", format(start.to_utc() - interval)),
+            ("to", format(end.to_utc() - interval)),
+            ("period", period.to_string()),
+            ("offset", "0".to_string()),
+            ("function", "sum".to_string()),
+        ];
+        let readings: Readings = self
+            .get_json(
+                &format!("resource/{}/readings", resource.resource_id),
+                &query,
+            )
+            .await?;
+        let values = readings
+            .data
+            .into_iter()
+            .filter_map(|(time, value)| {
+                Some(Value {
+                    timestamp: DateTime::from_timestamp(time, 0)? + interval,
+                    value,
+                    status: None,
+                })
+            })
+            .collect();
+        let mut data = ConsumptionOrTariff::Consumption(Consumption {
+            resource: resource_path(energy_type, RequestType::Consumption, self.element),
+            response_timestamp: Utc::now().to_rfc3339(),
+            start: start.to_rfc3339(),
+            end: end.to_rfc3339(),
+            granularity: self.granularity.as_param().to_string(),
+            values,
+            message: None,
+            unit: readings.units.unwrap_or_else(|| "kWh".to_string()),
+        });
+        data.retain_within(start.to_utc(), end.to_utc());
+        Ok(data)
+    }
+
+    async fn tariff(
+        &self,
+        energy_type: EnergyType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        let resource = self.consumption_resource(energy_type).await?;
+        let tariff: TariffResponse = self
+            .get_json(&format!("resource/{}/tariff", resource.resource_id), &[])
+            .await?;
+        let mut values = Vec::new();
+        for plan in tariff.data {
+            let Some(rates) = plan.current_rates else {
+                continue;
+            };
+            let from = plan
+                .from
+                .and_then(|from| {
+                    chrono::NaiveDateTime::parse_from_str(&from, "%Y-%m-%d %H:%M:%S").ok()
+                })
+                .map(|from| from.and_utc());
+            // the rates are only known to apply since the plan started, or from
+            // now when Glowmarkt doesn't say
+            let first = std::cmp::max(start.to_utc(), from.unwrap_or_else(Utc::now));
+            let step = Duration::minutes(30);
+            let misaligned = first.timestamp().rem_euclid(step.num_seconds());
+            let first = match misaligned {
+                0 => first,
+                seconds => first + Duration::seconds(step.num_seconds() - seconds),
+            };
+            let mut prices = Vec::new();
+            let mut time = first;
+            while time <= end.to_utc() {
+                prices.push(Price {
+                    timestamp: time,
+                    value: rates.rate,
+                });
+                time += step;
+            }
+            let standing_charges = first
+                .date_naive()
+                .iter_days()
+                .take_while(|day| *day <= end.to_utc().date_naive())
+                .map(|day| StandingCharge {
+                    start_date: day,
+                    value: rates.standing_charge,
+                })
+                .collect();
+            values.push(TariffValues {
+                standing_charges,
+                prices,
+            });
+        }
+        Ok(ConsumptionOrTariff::Tariff(Tariff {
+            resource: resource_path(energy_type, RequestType::Tariff, self.element),
+            response_timestamp: Utc::now().to_rfc3339(),
+            start: start.to_rfc3339(),
+            end: end.to_rfc3339(),
+            values,
+            unit: Default::default(),
+        }))
+    }
+}
+
+fn glowmarkt_error(url: &str, e: reqwest::Error) -> Error {
+    Error::Provider {
+        provider: Provider::Glowmarkt,
+        url: url.to_string(),
+        reason: e.to_string(),
+    }
+}
+
+#[async_trait]
+impl Source for GlowmarktClient {
+    fn provider(&self) -> Provider {
+        Provider::Glowmarkt
+    }
+
+    fn element(&self) -> u8 {
+        self.element
+    }
+
+    fn with_element(&self, element: u8) -> Arc<dyn Source> {
+        Arc::new(GlowmarktClient {
+            element,
+            ..self.clone()
+        })
+    }
+
+    fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    fn windows(&self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<Window> {
+        let days = match self.granularity {
+            Granularity::HalfHour => MAX_HALF_HOUR_DAYS,
+            Granularity::Day => MAX_DAY_DAYS,
+        };
+        date_windows(start, end, days)
+    }
+
+    async fn fetch(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        if self.element != 1 {
+            return Err(Error::Provider {
+                provider: Provider::Glowmarkt,
+                url: self.base_url.clone(),
+                reason: format!("there is no element {}, only 1", self.element),
+            });
+        }
+        debug!(
+            "requesting {} {} from Glowmarkt for {} {}",
+            energy_type, request_type, start, end
+        );
+        match request_type {
+            RequestType::Consumption => self.consumption(energy_type, start, end).await,
+            RequestType::Tariff => self.tariff(energy_type, start, end).await,
+        }
+    }
+
+    async fn discover(&self) -> Result<Vec<DataSource>, Error> {
+        let mut sources = Vec::new();
+        for resource in self.resources().await? {
+            let Some(energy_type) = resource.consumption_of() else {
+                continue;
+            };
+            let range = self.range(&resource).await?;
+            for request_type in [RequestType::Consumption, RequestType::Tariff] {
+                sources.push(DataSource {
+                    fuel: energy_type.to_string().to_lowercase(),
+                    data_type: request_type.to_string().to_lowercase(),
+                    element: "1".to_string(),
+                    range: match request_type {
+                        RequestType::Consumption => range,
+                        RequestType::Tariff => None,
+                    },
+                });
+            }
+        }
+        Ok(sources)
+    }
+
+    async fn elements(
+        &self,
+        energy_type: EnergyType,
+        _request_type: RequestType,
+    ) -> Result<Vec<u8>, Error> {
+        let found = self
+            .resources()
+            .await?
+            .iter()
+            .any(|resource| resource.consumption_of() == Some(energy_type));
+        Ok(if found { vec![1] } else { Vec::new() })
+    }
+
+    async fn clamp_to_available(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<Option<Window>, Error> {
+        if request_type == RequestType::Tariff {
+            return Ok(Some((start, end)));
+        }
+        let resource = self.consumption_resource(energy_type).await?;
+        let Some(available) = self.range(&resource).await? else {
+            return Ok(Some((start, end)));
+        };
+        let start = std::cmp::max(start, available.start.with_timezone(&Local));
+        let end = std::cmp::min(end, available.end.with_timezone(&Local));
+        if start >= end {
+            return Ok(None);
+        }
+        Ok(Some((start, end)))
+    }
+}
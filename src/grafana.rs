@@ -0,0 +1,183 @@
+use std::error::Error;
+
+use n3rgy_rs::models::PRICE;
+use n3rgy_rs::secret::SecretString;
+use serde_json::{json, Value};
+
+use crate::http_sink::PostError;
+use crate::load::COST_MEASUREMENT;
+
+/// Datasource input Grafana prompts for when the dashboard is imported by hand.
+const DATASOURCE_INPUT: &str = "DS_INFLUXDB";
+
+/// A Grafana dashboard charting what the loader writes: consumption per fuel,
+/// daily cost and unit rates, filterable by property.
+pub fn dashboard(title: &str, measurement: &str, datasource_uid: Option<&str>) -> Value {
+    let datasource = json!({
+        "type": "influxdb",
+        "uid": datasource_uid
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("${{{}}}", DATASOURCE_INPUT)),
+    });
+    let property_filter = "\"property\" =~ /^$property$/";
+    let panels = vec![
+        panel(
+            1,
+            "Consumption",
+            "kwatth",
+            "bars",
+            (0, 0, 24),
+            &datasource,
+            format!(
+                "SELECT sum(\"consumption\") FROM \"{}\" WHERE \"type\" = 'consumption' \
+                 AND \"fuel\" =~ /^$fuel$/ AND {} AND $timeFilter \
+                 GROUP BY time($__interval), \"fuel\" fill(none) tz('Europe/London')",
+                measurement, property_filter
+            ),
+        ),
+        panel(
+            2,
+            "Daily cost",
+            "currencyGBP",
+            "bars",
+            (0, 9, 12),
+            &datasource,
+            format!(
+                "SELECT sum(\"total\") FROM \"{}\" WHERE \"fuel\" =~ /^$fuel$/ AND {} \
+                 AND $timeFilter GROUP BY time(1d), \"fuel\" fill(none) tz('Europe/London')",
+                COST_MEASUREMENT, property_filter
+            ),
+        ),
+        panel(
+            3,
+            "Unit rate",
+            "none",
+            "line",
+            (12, 9, 12),
+            &datasource,
+            format!(
+                "SELECT last(\"price\") FROM \"{}\" WHERE \"type\" = 'tariff' \
+                 AND \"price_type\" = '{}' AND \"fuel\" =~ /^$fuel$/ AND {} AND $timeFilter \
+                 GROUP BY time($__interval), \"fuel\" fill(previous)",
+                measurement, PRICE, property_filter
+            ),
+        ),
+    ];
+
+    let mut dashboard = json!({
+        "title": title,
+        "uid": "n3rgy",
+        "tags": ["n3rgy", "energy"],
+        "timezone": "Europe/London",
+        "time": {"from": "now-7d", "to": "now"},
+        "schemaVersion": 39,
+        "templating": {
+            "list": [
+                variable("fuel", &datasource, &format!(
+                    "SHOW TAG VALUES FROM \"{}\" WITH KEY = \"fuel\"",
+                    measurement
+                )),
+                variable("property", &datasource, &format!(
+                    "SHOW TAG VALUES FROM \"{}\" WITH KEY = \"property\"",
+                    measurement
+                )),
+            ]
+        },
+        "panels": panels,
+    });
+    if datasource_uid.is_none() {
+        dashboard["__inputs"] = json!([{
+            "name": DATASOURCE_INPUT,
+            "label": "InfluxDB",
+            "type": "datasource",
+            "pluginId": "influxdb",
+            "pluginName": "InfluxDB",
+        }]);
+    }
+    dashboard
+}
+
+/// A time series panel plotting a raw InfluxQL query, `gridPos` given as x, y
+/// and width.
+fn panel(
+    id: u32,
+    title: &str,
+    unit: &str,
+    draw_style: &str,
+    (x, y, w): (u32, u32, u32),
+    datasource: &Value,
+    query: String,
+) -> Value {
+    json!({
+        "id": id,
+        "type": "timeseries",
+        "title": title,
+        "datasource": datasource,
+        "gridPos": {"x": x, "y": y, "w": w, "h": 9},
+        "fieldConfig": {
+            "defaults": {
+                "unit": unit,
+                "custom": {"drawStyle": draw_style, "fillOpacity": 20},
+            },
+            "overrides": [],
+        },
+        "targets": [{
+            "refId": "A",
+            "datasource": datasource,
+            "rawQuery": true,
+            "resultFormat": "time_series",
+            "query": query,
+            "alias": "$tag_fuel",
+        }],
+    })
+}
+
+/// A multi-value dashboard variable listing a tag's values.
+fn variable(name: &str, datasource: &Value, query: &str) -> Value {
+    json!({
+        "name": name,
+        "type": "query",
+        "datasource": datasource,
+        "query": query,
+        "refresh": 1,
+        "multi": true,
+        "includeAll": true,
+        "current": {"text": "All", "value": "$__all"},
+    })
+}
+
+/// Create or replace the dashboard through Grafana's HTTP API.
+pub async fn push(
+    http: &reqwest::Client,
+    url: &str,
+    token: &SecretString,
+    dashboard: Value,
+    folder_uid: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let url = format!("{}/api/dashboards/db", url.trim_end_matches('/'));
+    let body = json!({
+        "dashboard": dashboard,
+        "folderUid": folder_uid,
+        "overwrite": true,
+        "message": "Provisioned by n3rgy-rs",
+    });
+    let response = http
+        .post(&url)
+        .bearer_auth(token.expose())
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| PostError(format!("could not POST to {}: {}", url, e)))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(PostError(format!(
+            "{} responded with {}: {}",
+            url,
+            status,
+            body.trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
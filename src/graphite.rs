@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::line_protocol;
+use crate::template::{Template, VARIABLES};
+
+/// Placeholders a Graphite metric path may use: a point's tags, plus the
+/// measurement and field it came from.
+pub const PATH_VARIABLES: [&str; 8] = [
+    VARIABLES[0],
+    VARIABLES[1],
+    VARIABLES[2],
+    VARIABLES[3],
+    VARIABLES[4],
+    VARIABLES[5],
+    "measurement",
+    "field",
+];
+
+/// Metric path used when none is given.
+pub const DEFAULT_PATH: &str = "{measurement}.{fuel}.{type}.{element}";
+
+/// Writes points to Graphite (carbon) over the plaintext TCP protocol, one
+/// metric per numeric field.
+pub struct Graphite {
+    /// `host:port`, usually port 2003.
+    pub address: String,
+    /// Metric path per field, with `.{field}` added when it has no `{field}`.
+    pub path: Template,
+}
+
+impl Graphite {
+    /// Send points given as line protocol, returning how many metrics were
+    /// written.
+    pub async fn send(&self, lines: &[String]) -> Result<usize, Box<dyn Error>> {
+        let mut body = String::new();
+        let mut metrics = 0;
+        for line in lines {
+            let point = line_protocol::parse(line)?;
+            for (field, value) in &point.fields {
+                let value = match value {
+                    serde_json::Value::Number(number) => number.as_f64(),
+                    serde_json::Value::Bool(flag) => Some(if *flag { 1.0 } else { 0.0 }),
+                    _ => None,
+                };
+                // strings can't be stored as metrics
+                let Some(value) = value else {
+                    continue;
+                };
+                body.push_str(&format!(
+                    "{} {} {}\n",
+                    self.metric_path(&point, field),
+                    value,
+                    point.time.timestamp()
+                ));
+                metrics += 1;
+            }
+        }
+
+        let mut stream = TcpStream::connect(&self.address).await.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("could not connect to Graphite at {}: {}", self.address, e),
+            )
+        })?;
+        stream.write_all(body.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(metrics)
+    }
+
+    fn metric_path(&self, point: &line_protocol::Point, field: &str) -> String {
+        let mut values: BTreeMap<&str, String> = PATH_VARIABLES
+            .iter()
+            .filter_map(|name| Some((*name, segment(point.tags.get(*name)?))))
+            .collect();
+        values.insert("measurement", segment(&point.measurement));
+        values.insert("field", segment(field));
+
+        let mut path = self.path.render(&values);
+        if !self.path.uses("field") {
+            path = format!("{}.{}", path, values["field"]);
+        }
+        // placeholders a point has no tag for leave empty segments behind
+        path.split('.')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+/// A value made safe to use as one segment of a metric path.
+fn segment(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '.' | ' ' | '/' | '\t' => '_',
+            c => c,
+        })
+        .collect()
+}
@@ -0,0 +1,98 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use crate::metrics;
+use crate::sink::Sink;
+
+/// The InfluxDB client pinged for readiness; other sinks have no connection
+/// to check.
+static INFLUX: Mutex<Option<influxdb::Client>> = Mutex::new(None);
+
+/// Check `sink` for readiness from now on, replacing any sink set before
+/// the config was reloaded.
+pub fn set_sink(sink: &Sink) {
+    *INFLUX.lock().unwrap() = match sink {
+        Sink::InfluxDb(client) => Some(client.clone()),
+        _ => None,
+    };
+}
+
+/// What `/healthz` and `/readyz` report on, for liveness and readiness probes.
+pub struct Health {
+    /// How long without a successful sync before `/healthz` fails.
+    stale_after: Option<Duration>,
+    started: DateTime<Utc>,
+}
+
+impl Health {
+    pub fn new(stale_after: Option<Duration>) -> Health {
+        Health {
+            stale_after,
+            started: Utc::now(),
+        }
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .with_state(Arc::new(self))
+    }
+}
+
+/// When a sync last completed without errors, if one has.
+fn last_successful_sync() -> Option<DateTime<Utc>> {
+    match metrics::LAST_SUCCESSFUL_SYNC.get() {
+        0 => None,
+        timestamp => DateTime::from_timestamp(timestamp, 0),
+    }
+}
+
+/// Fails once no sync has succeeded within `stale_after`, counting from start-up
+/// until the first one does.
+async fn healthz(State(health): State<Arc<Health>>) -> (StatusCode, Json<Value>) {
+    let last = last_successful_sync();
+    let since = (Utc::now() - last.unwrap_or(health.started))
+        .to_std()
+        .unwrap_or_default();
+    let stale = health.stale_after.is_some_and(|limit| since > limit);
+    let status = if stale {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    let body = json!({
+        "status": if stale { "stale" } else { "ok" },
+        "last_successful_sync": last.map(|time| time.to_rfc3339()),
+    });
+    (status, Json(body))
+}
+
+/// Ready once a sync has succeeded and the sink answers.
+async fn readyz() -> (StatusCode, Json<Value>) {
+    let last = last_successful_sync();
+    let influx = INFLUX.lock().unwrap().clone();
+    let sink = match influx {
+        Some(client) => client.ping().await.map(|_| ()).map_err(|e| e.to_string()),
+        None => Ok(()),
+    };
+    let ready = last.is_some() && sink.is_ok();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "status": if ready { "ready" } else { "not ready" },
+        "last_successful_sync": last.map(|time| time.to_rfc3339()),
+        "sink": sink.err().unwrap_or_else(|| "ok".to_string()),
+    });
+    (status, Json(body))
+}
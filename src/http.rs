@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Args;
+use n3rgy_rs::secret::SecretString;
+use reqwest::{Certificate, Client, Identity, Proxy};
+
+#[derive(Args)]
+pub struct HttpArgs {
+    /// Proxy to send n3rgy requests through, e.g. http://proxy.lan:3128
+    #[arg(long, env = "HTTPS_PROXY", global = true)]
+    pub proxy: Option<String>,
+    /// Username for proxies that require basic auth
+    #[arg(long, env = "N3RGY_PROXY_USERNAME", global = true, requires = "proxy")]
+    pub proxy_username: Option<String>,
+    #[arg(
+        long,
+        env = "N3RGY_PROXY_PASSWORD",
+        global = true,
+        requires = "proxy_username",
+        hide_env_values = true
+    )]
+    pub proxy_password: Option<SecretString>,
+    /// Seconds to wait for a connection to n3rgy
+    #[arg(
+        long,
+        env = "N3RGY_CONNECT_TIMEOUT",
+        global = true,
+        default_value_t = 10
+    )]
+    pub connect_timeout: u64,
+    /// Seconds to wait for each read of a response before giving up
+    #[arg(long, env = "N3RGY_READ_TIMEOUT", global = true, default_value_t = 60)]
+    pub read_timeout: u64,
+    /// Seconds an entire request may take, unlimited by default
+    #[arg(long, env = "N3RGY_REQUEST_TIMEOUT", global = true)]
+    pub request_timeout: Option<u64>,
+    /// Seconds between TCP keep-alive probes, 0 to disable
+    #[arg(long, env = "N3RGY_TCP_KEEPALIVE", global = true, default_value_t = 60)]
+    pub tcp_keepalive: u64,
+    /// Seconds an idle pooled connection is kept open
+    #[arg(
+        long,
+        env = "N3RGY_POOL_IDLE_TIMEOUT",
+        global = true,
+        default_value_t = 90
+    )]
+    pub pool_idle_timeout: u64,
+    /// Maximum idle pooled connections kept per host
+    #[arg(long, env = "N3RGY_POOL_MAX_IDLE", global = true)]
+    pub pool_max_idle: Option<usize>,
+    /// PEM CA certificate to trust, e.g. for InfluxDB behind a self-signed proxy
+    #[arg(long, env = "N3RGY_CA_CERT", global = true)]
+    pub ca_cert: Option<PathBuf>,
+    /// Accept any TLS certificate, for testing only
+    #[arg(long, env = "N3RGY_INSECURE_SKIP_VERIFY", global = true)]
+    pub insecure_skip_verify: bool,
+    /// PEM client certificate for mutual TLS
+    #[arg(
+        long,
+        env = "N3RGY_CLIENT_CERT",
+        global = true,
+        requires = "client_key"
+    )]
+    pub client_cert: Option<PathBuf>,
+    /// PEM (PKCS#8) private key for --client-cert
+    #[arg(
+        long,
+        env = "N3RGY_CLIENT_KEY",
+        global = true,
+        requires = "client_cert"
+    )]
+    pub client_key: Option<PathBuf>,
+}
+
+impl HttpArgs {
+    fn has_tls_options(&self) -> bool {
+        self.ca_cert.is_some() || self.insecure_skip_verify || self.client_cert.is_some()
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))
+}
+
+/// Build the HTTP client used for n3rgy requests.
+pub fn build_client(args: &HttpArgs) -> Result<Client, Box<dyn Error>> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(args.connect_timeout))
+        .read_timeout(Duration::from_secs(args.read_timeout))
+        .pool_idle_timeout(Duration::from_secs(args.pool_idle_timeout))
+        .tcp_keepalive((args.tcp_keepalive > 0).then(|| Duration::from_secs(args.tcp_keepalive)));
+    if let Some(timeout) = args.request_timeout {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(max) = args.pool_max_idle {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if let Some(proxy_url) = &args.proxy {
+        let mut proxy = Proxy::all(proxy_url)?;
+        if let Some(username) = &args.proxy_username {
+            proxy = proxy.basic_auth(
+                username,
+                args.proxy_password
+                    .as_ref()
+                    .map_or("", SecretString::expose),
+            );
+        }
+        builder = builder.proxy(proxy);
+    }
+    if let Some(path) = &args.ca_cert {
+        builder = builder.add_root_certificate(Certificate::from_pem(&read_pem(path)?)?);
+    }
+    if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+        builder = builder.identity(Identity::from_pkcs8_pem(&read_pem(cert)?, &read_pem(key)?)?);
+    }
+    Ok(builder
+        .danger_accept_invalid_certs(args.insecure_skip_verify)
+        .build()?)
+}
+
+/// Build the HTTP client for InfluxDB when any TLS options are given, leaving
+/// the influxdb crate's default client otherwise.
+pub fn build_influx_client(
+    args: &HttpArgs,
+) -> Result<Option<influx_reqwest::Client>, Box<dyn Error>> {
+    if !args.has_tls_options() {
+        return Ok(None);
+    }
+    let mut builder =
+        influx_reqwest::Client::builder().danger_accept_invalid_certs(args.insecure_skip_verify);
+    if let Some(path) = &args.ca_cert {
+        builder =
+            builder.add_root_certificate(influx_reqwest::Certificate::from_pem(&read_pem(path)?)?);
+    }
+    if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+        // rustls takes the key and certificate chain as one PEM bundle
+        let mut pem = read_pem(key)?;
+        pem.extend(read_pem(cert)?);
+        builder = builder.identity(influx_reqwest::Identity::from_pem(&pem)?);
+    }
+    Ok(Some(builder.build()?))
+}
@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::line_protocol;
+
+/// Body sent when no template is given: the batch as a JSON array.
+const DEFAULT_TEMPLATE: &str = "{points}";
+
+/// POSTs each batch of points as JSON to an arbitrary endpoint, e.g. a home
+/// automation webhook or a serverless function.
+pub struct HttpSink {
+    pub http: reqwest::Client,
+    pub url: String,
+    /// Sent with every request, e.g. `Authorization`.
+    pub headers: Vec<(String, String)>,
+    /// Request body, with `{points}` replaced by the batch as a JSON array and
+    /// `{count}` by its length.
+    pub template: Option<String>,
+}
+
+/// The endpoint refused a batch or couldn't be reached.
+#[derive(Debug)]
+pub struct PostError(pub String);
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for PostError {}
+
+impl HttpSink {
+    /// Send points given as line protocol.
+    pub async fn post(&self, lines: &[String]) -> Result<(), Box<dyn Error>> {
+        let points = lines
+            .iter()
+            .map(|line| line_protocol::parse(line).map(|point| point.to_json()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let body = self
+            .template
+            .as_deref()
+            .unwrap_or(DEFAULT_TEMPLATE)
+            .replace("{points}", &Value::Array(points).to_string())
+            .replace("{count}", &lines.len().to_string());
+
+        let mut request = self
+            .http
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(body);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| PostError(format!("could not POST to {}: {}", self.url, e)))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(PostError(format!(
+                "{} responded with {}: {}",
+                self.url,
+                status,
+                body.trim()
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
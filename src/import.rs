@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use log::info;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::line_protocol::{self, Point};
+#[cfg(feature = "parquet")]
+use crate::parquet_file;
+use crate::sink::Sink;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImportFormat {
+    /// InfluxDB line protocol, as written by --output
+    Lp,
+    /// `{measurement, time, tags, fields}` objects, as POSTed by
+    /// --http-sink-url or uploaded by --s3-format ndjson
+    Ndjson,
+    /// A `time` and `measurement` column then a column per tag and field, as
+    /// uploaded by --s3-format csv
+    Csv,
+    /// Columns as for CSV, as uploaded by --s3-format parquet
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl ImportFormat {
+    /// The format a file's extension names, ignoring `.gz` or `.zst`.
+    fn of(path: &Path) -> Option<ImportFormat> {
+        let name = path.file_name()?.to_string_lossy();
+        let name = name
+            .strip_suffix(".gz")
+            .or_else(|| name.strip_suffix(".zst"))
+            .unwrap_or(&name);
+        let (_, extension) = name.rsplit_once('.')?;
+        match extension {
+            "lp" | "line" | "txt" => Some(ImportFormat::Lp),
+            "ndjson" | "jsonl" => Some(ImportFormat::Ndjson),
+            "csv" => Some(ImportFormat::Csv),
+            #[cfg(feature = "parquet")]
+            "parquet" => Some(ImportFormat::Parquet),
+            _ => None,
+        }
+    }
+}
+
+/// Write the points in archived files, or every file under a directory,
+/// through `sink` in batches of `batch_size`. Returns how many were written.
+pub async fn run(
+    sink: &Sink,
+    paths: &[PathBuf],
+    format: Option<ImportFormat>,
+    batch_size: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect(path, &mut files)?;
+    }
+
+    let mut written = 0;
+    for file in &files {
+        let Some(format) = format.or_else(|| ImportFormat::of(file)) else {
+            return Err(
+                format!("can't tell the format of {}, pass --format", file.display()).into(),
+            );
+        };
+        let points =
+            read(file, format).map_err(|e| format!("could not read {}: {}", file.display(), e))?;
+        let count = points.len();
+        let mut points = points.into_iter().map(Point::into_query).peekable();
+        while points.peek().is_some() {
+            sink.write(points.by_ref().take(batch_size).collect())
+                .await?;
+        }
+        info!("imported {} points from {}", count, file.display());
+        written += count;
+    }
+    Ok(written)
+}
+
+/// `path`, or the files under it in name order, skipping any still being
+/// written by a partitioned --output.
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.extension().is_some_and(|ext| ext == "partial") {
+            continue;
+        }
+        collect(&entry, files)?;
+    }
+    Ok(())
+}
+
+fn read(path: &Path, format: ImportFormat) -> Result<Vec<Point>, Box<dyn Error>> {
+    let file = File::open(path)?;
+    match format {
+        ImportFormat::Lp => lines(path, file)?
+            .map(|line| Ok(line_protocol::parse(&line?)?))
+            .collect(),
+        ImportFormat::Ndjson => lines(path, file)?
+            .map(|line| ndjson_point(&line?))
+            .collect(),
+        ImportFormat::Csv => csv_points(lines(path, file)?.collect::<io::Result<Vec<_>>>()?),
+        // compressed within the file, and read by seeking around it
+        #[cfg(feature = "parquet")]
+        ImportFormat::Parquet => parquet_file::read(file),
+    }
+}
+
+/// The non-blank lines of a text file, decompressed by its extension.
+fn lines(path: &Path, file: File) -> io::Result<impl Iterator<Item = io::Result<String>>> {
+    let reader: Box<dyn Read> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(file)),
+        Some("zst") => Box::new(zstd::Decoder::new(file)?),
+        _ => Box::new(file),
+    };
+    Ok(BufReader::new(reader)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty())))
+}
+
+fn ndjson_point(line: &str) -> Result<Point, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct Json {
+        measurement: String,
+        time: DateTime<Utc>,
+        #[serde(default)]
+        tags: BTreeMap<String, String>,
+        fields: Map<String, Value>,
+    }
+    let json: Json = serde_json::from_str(line)?;
+    Ok(Point {
+        measurement: json.measurement,
+        tags: json.tags,
+        fields: json.fields.into_iter().collect(),
+        time: json.time,
+    })
+}
+
+/// Points from CSV with `time` and `measurement` columns. A column is read as
+/// a field when every value in it is a number or boolean, and as a tag
+/// otherwise.
+fn csv_points(lines: Vec<String>) -> Result<Vec<Point>, Box<dyn Error>> {
+    let mut rows = lines.iter().map(|line| csv_row(line));
+    let header = rows.next().ok_or("the file is empty")?;
+    let rows: Vec<Vec<String>> = rows.collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| format!("there is no {} column", name))
+    };
+    let (time, measurement) = (column("time")?, column("measurement")?);
+
+    let is_field: Vec<bool> = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .filter_map(|row| row.get(i).filter(|value| !value.is_empty()))
+                .all(|value| csv_value(value).is_some())
+        })
+        .collect();
+
+    let mut points = Vec::new();
+    for row in &rows {
+        let mut point = Point {
+            measurement: row.get(measurement).cloned().unwrap_or_default(),
+            tags: BTreeMap::new(),
+            fields: Vec::new(),
+            time: row.get(time).ok_or("a row has no time")?.parse()?,
+        };
+        for (i, name) in header.iter().enumerate() {
+            let Some(value) = row.get(i).filter(|value| !value.is_empty()) else {
+                continue;
+            };
+            if i == time || i == measurement {
+                continue;
+            }
+            match csv_value(value).filter(|_| is_field[i]) {
+                Some(value) => point.fields.push((name.clone(), value)),
+                None => {
+                    point.tags.insert(name.clone(), value.clone());
+                }
+            }
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
+
+fn csv_value(value: &str) -> Option<Value> {
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => serde_json::from_str::<serde_json::Number>(value)
+            .ok()
+            .map(Value::Number),
+    }
+}
+
+/// One row of RFC 4180 CSV, without embedded newlines.
+fn csv_row(line: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(std::mem::take(&mut value)),
+            c => value.push(c),
+        }
+    }
+    values.push(value);
+    values
+}
@@ -0,0 +1,24 @@
+//! Client library for the n3rgy consumer smart meter API, and the [`source::Source`]
+//! trait other smart meter data providers are loaded through.
+
+pub mod aggregate;
+pub mod batching;
+pub mod cache;
+pub mod carbon;
+pub mod client;
+pub mod conversion;
+pub mod cost;
+pub mod error;
+pub mod ev;
+pub mod glowmarkt;
+pub mod models;
+pub mod octopus;
+pub mod recording;
+pub mod secret;
+pub mod settlement;
+pub mod solar;
+pub mod source;
+pub mod weather;
+
+pub use client::N3rgyClient;
+pub use error::Error;
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::London;
+use influxdb::WriteQuery;
+use n3rgy_rs::settlement::settlement_date;
+use serde_json::{json, Map, Number, Value};
+
+/// A point read back from the line protocol the sinks build, for sinks that
+/// need something other than InfluxDB's format.
+pub struct Point {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    /// Numbers, booleans or strings, in line order.
+    pub fields: Vec<(String, Value)>,
+    pub time: DateTime<Utc>,
+}
+
+impl Point {
+    /// The point as `{"measurement", "time", "tags", "fields"}`.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "measurement": self.measurement,
+            "time": self.time.to_rfc3339(),
+            "tags": self.tags,
+            "fields": self.fields.iter().cloned().collect::<Map<_, _>>(),
+        })
+    }
+
+    /// The point as a write, e.g. to replay it through another sink.
+    pub fn into_query(self) -> WriteQuery {
+        let mut query = WriteQuery::new(self.time, self.measurement);
+        for (tag, value) in self.tags {
+            query = query.add_tag(tag, value);
+        }
+        for (field, value) in self.fields {
+            query = match value {
+                Value::Bool(flag) => query.add_field(field, flag),
+                Value::Number(number) => match number.as_i64() {
+                    Some(integer) => query.add_field(field, integer),
+                    None => query.add_field(field, number.as_f64().unwrap_or_default()),
+                },
+                Value::String(text) => query.add_field(field, text),
+                value => query.add_field(field, value.to_string()),
+            };
+        }
+        query
+    }
+
+    /// UK local day the point belongs to. Readings are stamped at the end of
+    /// their half hour, hour or day, so the one at midnight closes the day before.
+    pub fn uk_date(&self) -> NaiveDate {
+        match self.tags.get("granularity").map(String::as_str) {
+            Some("halfhour" | "hour" | "day") => settlement_date(self.time),
+            _ => self.time.with_timezone(&London).date_naive(),
+        }
+    }
+}
+
+/// Read a line of line protocol with a nanosecond timestamp.
+pub fn parse(line: &str) -> Result<Point, String> {
+    let invalid = || format!("could not read line protocol {}", line);
+    let (series, rest) = split_first(line, ' ', false).ok_or_else(invalid)?;
+    let (fields, timestamp) = split_first(rest, ' ', true).ok_or_else(invalid)?;
+
+    let mut series = split(series, ',', false).into_iter();
+    let measurement = unescape(series.next().ok_or_else(invalid)?);
+    let mut tags = BTreeMap::new();
+    for tag in series {
+        let (key, value) = split_first(tag, '=', false).ok_or_else(invalid)?;
+        tags.insert(unescape(key), unescape(value));
+    }
+    let mut values = Vec::new();
+    for field in split(fields, ',', true) {
+        let (key, value) = split_first(field, '=', false).ok_or_else(invalid)?;
+        values.push((unescape(key), field_value(value).ok_or_else(invalid)?));
+    }
+    let nanos = timestamp.parse::<i64>().map_err(|_| invalid())?;
+
+    Ok(Point {
+        measurement,
+        tags,
+        fields: values,
+        time: Utc.timestamp_nanos(nanos),
+    })
+}
+
+fn field_value(value: &str) -> Option<Value> {
+    if let Some(quoted) = value.strip_prefix('"') {
+        return Some(Value::String(unescape(quoted.strip_suffix('"')?)));
+    }
+    if let Some(integer) = value.strip_suffix('i') {
+        return integer.parse::<i64>().ok().map(Value::from);
+    }
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => value
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number),
+    }
+}
+
+/// Split at each `separator` not escaped with a backslash, nor inside double
+/// quotes when `quoted` strings are allowed (field values only).
+fn split(s: &str, separator: char, quoted: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut start, mut in_quotes, mut escaped) = (0, false, false);
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' && quoted {
+            in_quotes = !in_quotes;
+        } else if c == separator && !in_quotes {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn split_first(s: &str, separator: char, quoted: bool) -> Option<(&str, &str)> {
+    let first = split(s, separator, quoted).into_iter().next()?;
+    let rest = s.get(first.len() + separator.len_utf8()..)?;
+    Some((first, rest))
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ (',' | '=' | ' ' | '"' | '\\'))) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            (c, _) => unescaped.push(c),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,24 @@
+use n3rgy_rs::N3rgyClient;
+
+/// Print every fuel, data type and element the token can read with its cached range.
+pub async fn run(client: &N3rgyClient) -> Result<(), n3rgy_rs::Error> {
+    let sources = client.discover().await?;
+    println!(
+        "{:<12} {:<12} {:<8} {:<17} {:<17}",
+        "Fuel", "Type", "Element", "Available from", "Available to"
+    );
+    for source in sources {
+        let (from, to) = match source.range {
+            Some(range) => (
+                range.start.format("%Y-%m-%d %H:%M").to_string(),
+                range.end.format("%Y-%m-%d %H:%M").to_string(),
+            ),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        println!(
+            "{:<12} {:<12} {:<8} {:<17} {:<17}",
+            source.fuel, source.data_type, source.element, from, to
+        );
+    }
+    Ok(())
+}
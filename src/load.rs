@@ -0,0 +1,1209 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+#[cfg(feature = "otel")]
+use std::time::SystemTime;
+
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use influxdb::{InfluxDbWriteable, Query, WriteQuery};
+use log::{debug, error, info, warn};
+use n3rgy_rs::aggregate::{Aggregator, Period, Resample, Resampled, Resampler};
+use n3rgy_rs::carbon::{carbon_emissions, CarbonIntensityClient};
+use n3rgy_rs::client::Window;
+use n3rgy_rs::conversion::{is_cubic_metres, GasConversion};
+use n3rgy_rs::cost::{price_consumption, price_flat, Cost};
+use n3rgy_rs::ev::ChargingDetector;
+use n3rgy_rs::models::{
+    Consumption, ConsumptionOrTariff, ConsumptionReading, EnergyType, Granularity, Pricing,
+    RequestType, Resource, PRICE, STANDING_CHARGE,
+};
+use n3rgy_rs::settlement::{mismatched_days, settlement_period, uk_midnight};
+use n3rgy_rs::solar::solar_readings;
+use n3rgy_rs::source::Source;
+use n3rgy_rs::weather::{DailyConsumption, WeatherClient};
+
+use crate::checkpoint::Checkpoint;
+use crate::config::{band_for, Anomalies, Band, DegreeDays, EvCharging, PriceCap, Solar};
+use crate::metrics;
+use crate::shutdown;
+use crate::sink::Sink;
+use crate::spool::{self, Spool};
+use crate::tariff_change::{self, ChangeDetector};
+#[cfg(feature = "otel")]
+use crate::telemetry;
+use crate::template::Template;
+use crate::webhook::Webhook;
+
+/// Measurement raw readings and tariff prices are written to by default.
+pub const DEFAULT_MEASUREMENT: &str = "energy";
+
+/// Measurement consumption priced at the tariff is written to.
+pub const COST_MEASUREMENT: &str = "cost";
+
+/// Measurement consumption priced at the price cap is written to.
+pub const CAP_COST_MEASUREMENT: &str = "cap_cost";
+/// Measurement the carbon footprint of electricity readings is written to.
+pub const CARBON_MEASUREMENT: &str = "carbon";
+
+/// Measurement daily gas consumption against heating degree days is written to.
+pub const DEGREE_DAYS_MEASUREMENT: &str = "degree_days";
+
+/// Measurement net usage and self-consumption are written to with an export
+/// element.
+pub const SOLAR_MEASUREMENT: &str = "solar";
+
+/// Measurement detected EV charging sessions are written to.
+pub const EV_SESSION_MEASUREMENT: &str = "ev_session";
+
+/// How far back a sync detecting EV charging starts, so a session already
+/// under way at the start of the range is seen whole.
+const EV_SESSION_LOOKBACK: Duration = Duration::hours(12);
+
+/// Measurement readings failing the `[anomalies]` checks are written to when
+/// quarantining.
+pub const QUARANTINE_MEASUREMENT: &str = "quarantine";
+
+/// Measurement daily standing charges are written to with `standing_charge_series`.
+pub const STANDING_CHARGE_MEASUREMENT: &str = "standing_charge";
+
+/// Measurement prices published ahead are written to, kept apart from the
+/// prices already charged.
+pub const UPCOMING_MEASUREMENT: &str = "upcoming_price";
+
+/// Measurements holding points derived from the readings and prices, for
+/// `admin delete` to clear along with them.
+pub const DERIVED_MEASUREMENTS: [&str; 13] = [
+    COST_MEASUREMENT,
+    CAP_COST_MEASUREMENT,
+    CARBON_MEASUREMENT,
+    QUARANTINE_MEASUREMENT,
+    STANDING_CHARGE_MEASUREMENT,
+    UPCOMING_MEASUREMENT,
+    DEGREE_DAYS_MEASUREMENT,
+    SOLAR_MEASUREMENT,
+    EV_SESSION_MEASUREMENT,
+    tariff_change::MEASUREMENT,
+    Period::Day.measurement(),
+    Period::Week.measurement(),
+    Period::Month.measurement(),
+];
+
+/// A meter element to load, and how to tell its points apart from other properties'.
+#[derive(Clone)]
+pub struct Target {
+    /// The provider the meter's data is read from.
+    pub source: Arc<dyn Source>,
+    /// Property label from the config file, tagged onto every point as `property`.
+    pub label: Option<String>,
+    /// MPAN or MPRN to tag points with when n3rgy's resource path doesn't include one.
+    pub mpxn: Option<String>,
+    /// Extra tags from the config file.
+    pub tags: BTreeMap<String, String>,
+    pub consent_expires: Option<NaiveDate>,
+    /// Time-of-use bands to tag half-hourly consumption and cost with.
+    pub bands: Vec<Band>,
+    /// Price cap to write consumption's `cap_cost` at.
+    pub price_cap: Option<PriceCap>,
+    /// Checks to flag implausible half-hourly readings with.
+    pub anomalies: Option<Anomalies>,
+    /// Location to write gas consumption against heating degree days for.
+    pub degree_days: Option<DegreeDays>,
+    /// Panels to estimate generation and self-consumption for.
+    pub solar: Option<Solar>,
+    /// Draw to detect EV charging sessions at.
+    pub ev_charging: Option<EvCharging>,
+}
+
+impl Target {
+    /// An unlabelled target, for a token given on the command line.
+    pub fn new(source: Arc<dyn Source>) -> Target {
+        Target {
+            source,
+            label: None,
+            mpxn: None,
+            tags: BTreeMap::new(),
+            consent_expires: None,
+            bands: Vec::new(),
+            price_cap: None,
+            anomalies: None,
+            degree_days: None,
+            solar: None,
+            ev_charging: None,
+        }
+    }
+
+    pub fn with_element(&self, element: u8) -> Target {
+        Target {
+            source: self.source.with_element(element),
+            ..self.clone()
+        }
+    }
+
+    /// E.g. `home element 1`, or `element 1` when unlabelled.
+    pub fn describe(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{} element {}", label, self.source.element()),
+            None => format!("element {}", self.source.element()),
+        }
+    }
+}
+
+/// Windows fetched while the sink is still writing earlier ones.
+const FETCH_AHEAD: usize = 2;
+
+/// Points from one window on their way from the fetcher to the sink.
+struct Batch {
+    window: Window,
+    points: Vec<WriteQuery>,
+    /// No earlier window was deferred, skipped or failed to fetch.
+    contiguous: bool,
+}
+
+/// What a sync carries from one window to the next.
+#[derive(Default)]
+struct Carried {
+    /// Period totals, when aggregating consumption.
+    aggregator: Option<Aggregator>,
+    /// Buckets still waiting for readings, when resampling consumption.
+    resampler: Option<Resampler>,
+    /// Last prices seen, when writing tariff changes.
+    changes: Option<ChangeDetector>,
+    /// Daily gas totals, when writing degree days.
+    degree_days: Option<DailyConsumption>,
+    /// The charging session under way, when detecting EV charging.
+    charging: Option<ChargingDetector>,
+    /// Last time the windows so far returned; readings at or before it are
+    /// repeats from where windows meet.
+    seam: Option<DateTime<Utc>>,
+}
+
+/// Windows a sync left unloaded.
+#[derive(Default)]
+pub struct Outcome {
+    /// n3rgy was still retrieving these, re-pull them later.
+    pub deferred: Vec<Window>,
+    /// n3rgy answered these with its error payload.
+    pub skipped: Vec<Window>,
+    /// These failed to load and were passed over with `keep_going`.
+    pub failed: Vec<(Window, Box<dyn Error>)>,
+    /// InfluxDB couldn't be reached, so these were spooled for a later run to
+    /// write.
+    pub spooled: Vec<Window>,
+}
+
+impl Outcome {
+    pub fn is_complete(&self) -> bool {
+        self.deferred.is_empty()
+            && self.skipped.is_empty()
+            && self.failed.is_empty()
+            && self.spooled.is_empty()
+    }
+}
+
+/// Where a batch of points ended up.
+#[derive(PartialEq)]
+enum Written {
+    Sink,
+    /// Kept in the spool until InfluxDB can be reached.
+    Spooled,
+}
+
+/// Summarise the windows passed over with `keep_going`, once the run is done.
+pub fn report_failed(failed: &[(Target, Window)]) {
+    if failed.is_empty() {
+        return;
+    }
+    error!("{} window(s) failed to load:", failed.len());
+    for (target, (start, end)) in failed {
+        error!("  {} from {} to {}", target.describe(), start, end);
+    }
+}
+
+/// Where fetched readings are written and how they are transformed on the way.
+pub struct Loader {
+    /// Shared with the live readings listener under `serve`.
+    pub sink: Arc<Sink>,
+    /// Measurement raw readings and tariff prices are written to.
+    pub measurement: Template,
+    /// Tags added to every point, e.g. to tell deployments apart.
+    pub tags: BTreeMap<String, Template>,
+    /// Profile the run was started with, for `{profile}` in templates.
+    pub profile: Option<String>,
+    /// Also write gas readings converted between m³ and kWh.
+    pub gas_conversion: Option<GasConversion>,
+    /// Fetch the tariff alongside consumption and write a `cost` measurement.
+    pub compute_cost: bool,
+    /// Calendar periods to roll consumption (and cost) up into.
+    pub aggregate: Vec<Period>,
+    /// Sum consumption into coarser buckets instead of writing each reading.
+    pub resample: Option<Resample>,
+    /// Tag half-hourly electricity readings with their settlement period.
+    pub settlement_period: bool,
+    /// Also write each reading's UK local time, for reports across clock changes.
+    pub local_time: bool,
+    /// Resume from, and record, the last window loaded for each element.
+    pub checkpoint: Option<Checkpoint>,
+    /// Start from the newest point already in the sink, when there is one.
+    pub since_last: bool,
+    /// Skip readings and prices whose timestamp the sink already holds, for
+    /// sinks that don't overwrite points.
+    pub skip_existing: bool,
+    /// Pause between windows to go easy on the API during long runs.
+    pub request_delay: StdDuration,
+    /// Log a window that fails to load and carry on with the next one.
+    pub keep_going: bool,
+    /// Keep batches the sink refuses here for a later run.
+    pub spool: Option<Spool>,
+    /// Windows written to the sink at once.
+    pub write_concurrency: usize,
+    /// Write a `tariff_change` point wherever the unit rate or standing
+    /// charge moves.
+    pub tariff_changes: bool,
+    /// Also POST each tariff change here.
+    pub webhook: Option<Webhook>,
+    /// Write standing charges to [`STANDING_CHARGE_MEASUREMENT`] rather than
+    /// with the unit rates.
+    pub standing_charge_series: bool,
+    /// Conversion applied to tariff prices, and so to costs, as they are fetched.
+    pub pricing: Pricing,
+    /// Fetch prices beyond the range n3rgy reports as available and write
+    /// them to [`UPCOMING_MEASUREMENT`].
+    pub upcoming: bool,
+    /// Write each electricity reading's grid carbon intensity and emissions
+    /// to [`CARBON_MEASUREMENT`].
+    pub carbon: Option<CarbonIntensityClient>,
+    /// Where temperatures come from for targets writing degree days, and
+    /// irradiance for those with solar panels.
+    pub weather: WeatherClient,
+    /// Electricity element holding exported generation, to write the other
+    /// elements' net usage against to [`SOLAR_MEASUREMENT`].
+    pub export_element: Option<u8>,
+}
+
+impl Loader {
+    /// A loader writing raw readings to `sink` with no other transformation.
+    pub fn new(sink: Sink) -> Loader {
+        Loader {
+            sink: Arc::new(sink),
+            measurement: Template::literal(DEFAULT_MEASUREMENT),
+            tags: BTreeMap::new(),
+            profile: None,
+            gas_conversion: None,
+            compute_cost: false,
+            aggregate: Vec::new(),
+            resample: None,
+            settlement_period: false,
+            local_time: false,
+            checkpoint: None,
+            since_last: false,
+            skip_existing: false,
+            request_delay: StdDuration::ZERO,
+            keep_going: false,
+            spool: None,
+            write_concurrency: 1,
+            tariff_changes: false,
+            webhook: None,
+            standing_charge_series: false,
+            pricing: Pricing::default(),
+            upcoming: false,
+            carbon: None,
+            weather: WeatherClient::new(),
+            export_element: None,
+        }
+    }
+
+    /// Daily totals of `target`'s stored consumption, or its cost, from the UK
+    /// day containing `since`.
+    pub async fn daily_totals(
+        &self,
+        target: &Target,
+        energy_type: EnergyType,
+        cost: bool,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, f64)>, Box<dyn Error>> {
+        let resource = Resource {
+            mpxn: None,
+            fuel: Some(energy_type.to_string().to_lowercase()),
+            data_type: Some(RequestType::Consumption.to_string().to_lowercase()),
+            element: Some(target.source.element().to_string()),
+        };
+        let (measurement, field) = if cost {
+            (COST_MEASUREMENT.to_string(), "total")
+        } else {
+            (self.price_measurement(target, &resource), "consumption")
+        };
+        let tags = self.point_tags(target, &resource);
+        let since = uk_midnight(since.with_timezone(&London).date_naive());
+        self.sink
+            .daily_sums(&measurement, field, &resource, &tags, since)
+            .await
+    }
+
+    /// Resend batches spooled after an earlier write failure.
+    pub async fn retry_spooled(&self) {
+        let Some(spool) = &self.spool else {
+            return;
+        };
+        match spool.retry().await {
+            Ok(0) => {}
+            Ok(count) => info!("wrote {} spooled batch(es)", count),
+            Err(e) => warn!(
+                "could not write spooled batches, keeping them for the next run: {}",
+                e
+            ),
+        }
+    }
+
+    /// Write `points` to the sink, spooling them instead when InfluxDB can't be
+    /// reached. Other failures, such as points InfluxDB rejects, are returned.
+    async fn write(&self, points: Vec<WriteQuery>) -> Result<Written, Box<dyn Error>> {
+        let Some(spool) = &self.spool else {
+            self.sink.write(points).await?;
+            return Ok(Written::Sink);
+        };
+        let lines = points
+            .iter()
+            .map(|point| point.build().map(|query| query.get()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match self.sink.write(points).await {
+            Ok(()) => Ok(Written::Sink),
+            Err(e) if spool::is_transient(e.as_ref()) => {
+                let path = spool.save(&lines)?;
+                warn!(
+                    "{}, spooled {} point(s) to {} for the next run",
+                    e,
+                    lines.len(),
+                    path.display()
+                );
+                Ok(Written::Spooled)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Load `start..end` window by window, returning the windows n3rgy was still
+    /// retrieving, so they can be re-pulled later, and those it refused.
+    ///
+    /// Windows are fetched ahead of the sink and written `write_concurrency` at
+    /// a time.
+    ///
+    /// Stops between windows once shutdown has been requested.
+    ///
+    /// When aggregating, the range is widened back to the start of the longest
+    /// period so every period total covers all of its readings to date, and
+    /// likewise to the start of the UK day when writing degree days. Detecting
+    /// EV charging starts 12 hours earlier still.
+    pub async fn sync(
+        &self,
+        target: &Target,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        energy_type: EnergyType,
+        request_type: RequestType,
+    ) -> Result<Outcome, Box<dyn Error>> {
+        let source = &*target.source;
+        let mut key =
+            format!("{}/{}/{}", energy_type, request_type, source.element()).to_lowercase();
+        if let Some(label) = &target.label {
+            key = format!("{}/{}", label, key);
+        }
+        let field = match request_type {
+            RequestType::Consumption => "consumption",
+            RequestType::Tariff => "price",
+        };
+        let resource = Resource {
+            mpxn: None,
+            fuel: Some(energy_type.to_string().to_lowercase()),
+            data_type: Some(request_type.to_string().to_lowercase()),
+            element: Some(source.element().to_string()),
+        };
+        let measurement = self.price_measurement(target, &resource);
+        let tags = self.point_tags(target, &resource);
+
+        let mut start = start;
+        if self.since_last {
+            if let Some(latest) = self
+                .sink
+                .latest(&measurement, field, &resource, &tags)
+                .await?
+            {
+                start = (latest + source.granularity().interval()).with_timezone(&Local);
+                info!("{} has data up to {}, loading from {}", key, latest, start);
+            }
+        }
+        if let Some(loaded) = self.checkpoint.as_ref().and_then(|c| c.get(&key)) {
+            start = std::cmp::max(start, loaded.with_timezone(&Local));
+        }
+        if start >= end {
+            info!("{} is already loaded up to {}", key, end);
+            return Ok(Outcome::default());
+        }
+
+        let mut carried = Carried::default();
+        if request_type == RequestType::Consumption {
+            if let Some(period) = self.aggregate.iter().max() {
+                start = period.bucket_start(start.to_utc()).with_timezone(&Local);
+                let aggregator = Aggregator::new(self.aggregate.clone()).since(start.to_utc());
+                carried.aggregator = Some(aggregator);
+            }
+            if let Some(resample) = self.resample {
+                start = resample.bucket_start(start.to_utc()).with_timezone(&Local);
+                carried.resampler = Some(Resampler::new(resample).since(start.to_utc()));
+            }
+            if energy_type == EnergyType::Gas && target.degree_days.is_some() {
+                carried.degree_days = Some(DailyConsumption::default());
+                let day = start.with_timezone(&London).date_naive();
+                start = uk_midnight(day).with_timezone(&Local);
+            }
+            let export = self.export_element == Some(source.element());
+            if let (Some(ev), EnergyType::Electricity, false) =
+                (target.ev_charging, energy_type, export)
+            {
+                carried.charging = Some(ChargingDetector::new(ev.threshold_kw, ev.min_minutes));
+                start -= EV_SESSION_LOOKBACK;
+            }
+        }
+
+        // the available range ends now, so it would trim away the prices ahead
+        let available = if self.upcoming {
+            Ok(Some((start, end)))
+        } else {
+            source
+                .clamp_to_available(energy_type, request_type, start, end)
+                .await
+        };
+        let (start, end) = match available {
+            Ok(Some((available_start, available_end))) => {
+                if available_start > start {
+                    warn!(
+                        "n3rgy only holds {} {} data from {}, skipping the range before it",
+                        energy_type, request_type, available_start
+                    );
+                }
+                (available_start, available_end)
+            }
+            Ok(None) => {
+                warn!(
+                    "n3rgy holds no {} {} data between {} and {}",
+                    energy_type, request_type, start, end
+                );
+                return Ok(Outcome::default());
+            }
+            Err(e) => {
+                warn!("could not check the available data range: {}", e);
+                (start, end)
+            }
+        };
+        if request_type == RequestType::Tariff && self.tariff_changes {
+            let mut changes = ChangeDetector::default();
+            let before = start.to_utc();
+            let mut tags = tags.clone();
+            tags.insert("price_type".to_string(), STANDING_CHARGE.to_string());
+            let stored = self
+                .sink
+                .value_before(&measurement, field, &resource, &tags, before)
+                .await?;
+            if let Some(value) = stored {
+                changes.seed(STANDING_CHARGE, before, value);
+            }
+            // a day of unit rates, to compare each half hour with
+            tags.insert("price_type".to_string(), PRICE.to_string());
+            let day = (before - Duration::days(1), before);
+            for (time, value) in self
+                .sink
+                .values_between(&measurement, field, &resource, &tags, day)
+                .await?
+            {
+                changes.seed(PRICE, time, value);
+            }
+            carried.changes = Some(changes);
+        }
+
+        // fetch windows ahead of the sink, so a slow API and a slow database
+        // overlap and the sink holds the fetcher back when it falls behind
+        let (sender, receiver) = mpsc::channel(FETCH_AHEAD);
+        let (measurement, tags, resource) = (&measurement, &tags, &resource);
+        let fetching = async move {
+            let mut sender = sender;
+            let mut outcome = Outcome::default();
+            let mut contiguous = true;
+            for (i, (start, end)) in source.windows(start, end).into_iter().enumerate() {
+                if i > 0 && !self.request_delay.is_zero() {
+                    tokio::select! {
+                        _ = tokio::time::sleep(self.request_delay) => {}
+                        _ = shutdown::wait() => {}
+                    }
+                }
+                if shutdown::requested() {
+                    warn!(
+                        "stopping before {}, re-run from there to load the rest",
+                        start
+                    );
+                    break;
+                }
+                let pulled = async {
+                    let existing = if self.skip_existing {
+                        self.sink
+                            .existing_times(measurement, field, resource, tags, (start, end))
+                            .await?
+                    } else {
+                        HashSet::new()
+                    };
+                    self.pull_window(
+                        target,
+                        (start, end),
+                        energy_type,
+                        request_type,
+                        &mut carried,
+                        &existing,
+                    )
+                    .await
+                };
+                match pulled.await {
+                    Ok(points) => {
+                        let batch = Batch {
+                            window: (start, end),
+                            points,
+                            contiguous,
+                        };
+                        if sender.send(batch).await.is_err() {
+                            // the writer stopped on an error, which it reports
+                            break;
+                        }
+                    }
+                    Err(e) if matches!(e.downcast_ref(), Some(n3rgy_rs::Error::Pending { .. })) => {
+                        warn!("{}, deferring window", e);
+                        outcome.deferred.push((start, end));
+                        contiguous = false;
+                    }
+                    Err(e) if matches!(e.downcast_ref(), Some(n3rgy_rs::Error::Api { .. })) => {
+                        error!("{}, skipping window", e);
+                        outcome.skipped.push((start, end));
+                        contiguous = false;
+                    }
+                    Err(e) if self.keep_going => {
+                        error!("{}, carrying on with the next window", e);
+                        outcome.failed.push(((start, end), e));
+                        contiguous = false;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok((outcome, carried))
+        };
+        let writing = async {
+            // buffered keeps completions in window order for the checkpoint
+            let mut writes = receiver
+                .map(|batch: Batch| async move {
+                    #[cfg(feature = "otel")]
+                    let (started, count) = (SystemTime::now(), batch.points.len());
+                    let result = self.write(batch.points).await;
+                    #[cfg(feature = "otel")]
+                    telemetry::batch(
+                        (batch.window.0.to_rfc3339(), batch.window.1.to_rfc3339()),
+                        count,
+                        started,
+                        result.as_ref().err().map(|e| e.as_ref()),
+                    );
+                    (batch.window, batch.contiguous, result)
+                })
+                .buffered(self.write_concurrency);
+            let (mut failed, mut spooled) = (Vec::new(), Vec::new());
+            while let Some(((start, end), contiguous, result)) = writes.next().await {
+                match result {
+                    Ok(Written::Spooled) => spooled.push((start, end)),
+                    Ok(Written::Sink) => {
+                        let checkpoint = self
+                            .checkpoint
+                            .as_ref()
+                            .filter(|_| contiguous && failed.is_empty() && spooled.is_empty());
+                        if let Some(checkpoint) = checkpoint {
+                            checkpoint.record(&key, end.to_utc());
+                        }
+                    }
+                    Err(e) if self.keep_going => {
+                        error!("{}, carrying on with the next window", e);
+                        failed.push(((start, end), e));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok((failed, spooled))
+        };
+        let (fetched, written) = futures::join!(fetching, writing);
+        let (failed, spooled) = written?;
+        let (mut outcome, carried) = fetched?;
+        outcome.failed.extend(failed);
+        outcome.spooled.extend(spooled);
+        // totals written after the windows cover the whole range
+        let mut spooled_totals = false;
+
+        if let Some(resampler) = carried.resampler {
+            let points = resampler
+                .finish()
+                .into_iter()
+                .map(|bucket| self.resampled_point(target, energy_type, bucket))
+                .collect();
+            spooled_totals |= self.write(points).await? == Written::Spooled;
+        }
+        if let Some(aggregator) = carried.aggregator {
+            let totals = aggregator
+                .finish()
+                .into_iter()
+                .map(|a| {
+                    let measurement = a.period.measurement();
+                    let resource = Resource::parse(&a.resource);
+                    self.add_tags(target, &resource, a.into_query(measurement))
+                })
+                .collect();
+            spooled_totals |= self.write(totals).await? == Written::Spooled;
+        }
+        if let (Some(days), Some(location)) = (carried.degree_days, target.degree_days) {
+            if let Some((first, last)) = days.complete_range() {
+                // a missing temperature shouldn't fail the sync
+                match self
+                    .weather
+                    .daily_temperatures(location.latitude, location.longitude, first, last)
+                    .await
+                {
+                    Ok(temperatures) => {
+                        let points = days
+                            .degree_days(&temperatures, location.base_temperature)
+                            .into_iter()
+                            .map(|day| {
+                                let resource = Resource::parse(&day.resource);
+                                self.add_tags(
+                                    target,
+                                    &resource,
+                                    day.into_query(DEGREE_DAYS_MEASUREMENT),
+                                )
+                            })
+                            .collect();
+                        spooled_totals |= self.write(points).await? == Written::Spooled;
+                    }
+                    Err(e) => warn!("{}, skipping degree days", e),
+                }
+            }
+        }
+        if spooled_totals && outcome.spooled.is_empty() {
+            outcome.spooled.push((start, end));
+        }
+        Ok(outcome)
+    }
+
+    /// Fetch a window and turn it into points, leaving out raw points at the
+    /// `existing` timestamps.
+    async fn pull_window(
+        &self,
+        target: &Target,
+        (start, end): Window,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        carried: &mut Carried,
+        existing: &HashSet<DateTime<Utc>>,
+    ) -> Result<Vec<WriteQuery>, Box<dyn Error>> {
+        let source = &*target.source;
+        let mut measurements = fetch(source, start, end, energy_type, request_type).await?;
+        if let ConsumptionOrTariff::Tariff(tariff) = &mut measurements {
+            tariff.apply_pricing(self.pricing);
+        }
+        if let Some(seam) = carried.seam {
+            let dropped = measurements.retain_after(seam);
+            if dropped > 0 {
+                debug!("dropped {} readings or prices already loaded", dropped);
+            }
+        }
+        carried.seam = measurements.last_time().or(carried.seam);
+
+        let mut readings = Vec::new();
+        if let ConsumptionOrTariff::Consumption(consumption) = &measurements {
+            if consumption.granularity == Granularity::HalfHour.as_param() {
+                let ends = consumption.values.iter().map(|value| value.timestamp);
+                for (date, found, expected) in mismatched_days(ends, start.to_utc(), end.to_utc()) {
+                    warn!(
+                        "{} returned {} half-hourly readings for {} on {}, expected {}",
+                        source.provider(),
+                        found,
+                        target.describe(),
+                        date,
+                        expected
+                    );
+                }
+            }
+        }
+        let mut anomalous = HashSet::new();
+        if let (ConsumptionOrTariff::Consumption(consumption), Some(anomalies)) =
+            (&mut measurements, target.anomalies)
+        {
+            anomalous = anomalies.detect(energy_type, consumption);
+            if anomalies.quarantine {
+                // left out of costs, totals and resampled sums as well
+                readings.extend(
+                    consumption
+                        .readings()
+                        .filter(|reading| anomalous.contains(&reading.time))
+                        .map(|reading| self.consumption_point(target, energy_type, reading, true)),
+                );
+                consumption
+                    .values
+                    .retain(|value| !anomalous.contains(&value.timestamp));
+            }
+            if !anomalous.is_empty() {
+                warn!(
+                    "{} had {} implausible reading(s) between {} and {}",
+                    target.describe(),
+                    anomalous.len(),
+                    start,
+                    end
+                );
+            }
+        }
+        if let ConsumptionOrTariff::Consumption(consumption) = &measurements {
+            let costs = if self.compute_cost {
+                fetch_costs(
+                    source,
+                    start,
+                    end,
+                    energy_type,
+                    consumption,
+                    self.pricing,
+                    self.gas_conversion.unwrap_or_default(),
+                )
+                .await?
+            } else {
+                Vec::new()
+            };
+            if let Some(aggregator) = &mut carried.aggregator {
+                for reading in consumption.readings() {
+                    aggregator.add_reading(&reading);
+                }
+                for cost in &costs {
+                    aggregator.add_cost(cost);
+                }
+            }
+            let half_hourly = consumption.granularity == Granularity::HalfHour.as_param();
+            if let (Some(days), true) = (&mut carried.degree_days, half_hourly) {
+                for reading in consumption.readings() {
+                    days.add_reading(&reading);
+                }
+            }
+            if let (Some(detector), true) = (&mut carried.charging, half_hourly) {
+                let energy_costs: HashMap<_, _> = costs
+                    .iter()
+                    .map(|cost| (cost.time, cost.energy_cost))
+                    .collect();
+                let mut in_order: Vec<_> = consumption.readings().collect();
+                in_order.sort_by_key(|reading| reading.time);
+                for reading in in_order {
+                    let cost = energy_costs.get(&reading.time).copied();
+                    if let Some(session) = detector.add(&reading, cost) {
+                        info!(
+                            "{} looks to have charged an EV from {} to {}, {:.1}kWh",
+                            target.describe(),
+                            session.start,
+                            session.end,
+                            session.consumption
+                        );
+                        let resource = Resource::parse(&session.resource);
+                        readings.push(self.add_tags(
+                            target,
+                            &resource,
+                            session.into_query(EV_SESSION_MEASUREMENT),
+                        ));
+                    }
+                }
+            }
+            let cap_costs = target
+                .price_cap
+                .and_then(|cap| cap.rates(energy_type))
+                .map(|rates| {
+                    price_flat(
+                        consumption.readings(),
+                        rates.unit_rate,
+                        rates.standing_charge,
+                        self.gas_conversion.unwrap_or_default(),
+                    )
+                })
+                .unwrap_or_default();
+            let costs = costs
+                .into_iter()
+                .map(|cost| (cost, COST_MEASUREMENT))
+                .chain(
+                    cap_costs
+                        .into_iter()
+                        .map(|cost| (cost, CAP_COST_MEASUREMENT)),
+                );
+            if let (Some(carbon), EnergyType::Electricity, true) =
+                (&self.carbon, energy_type, half_hourly)
+            {
+                // a missing footprint shouldn't hold back the readings
+                match carbon.intensity(start.to_utc(), end.to_utc()).await {
+                    Ok(intensities) => {
+                        let emissions = carbon_emissions(consumption.readings(), &intensities);
+                        readings.extend(emissions.into_iter().map(|emissions| {
+                            let resource = Resource::parse(&emissions.resource);
+                            self.add_tags(
+                                target,
+                                &resource,
+                                resource.add_tags(emissions.into_query(CARBON_MEASUREMENT)),
+                            )
+                        }));
+                    }
+                    Err(e) => warn!("{}, skipping carbon intensity", e),
+                }
+            }
+            if let (Some(export), EnergyType::Electricity) = (self.export_element, energy_type) {
+                if source.element() != export {
+                    // nor should a missing export
+                    match self
+                        .solar_points(target, (start, end), export, consumption)
+                        .await
+                    {
+                        Ok(points) => readings.extend(points),
+                        Err(e) => warn!("{}, skipping net usage", e),
+                    }
+                }
+            }
+            readings.extend(costs.map(|(cost, measurement)| {
+                let resource = Resource::parse(&cost.resource);
+                let band = band_for(&target.bands, cost.time);
+                let mut query = self.add_tags(
+                    target,
+                    &resource,
+                    resource.add_tags(cost.into_query(measurement)),
+                );
+                if let Some(band) = band {
+                    query = query.add_tag("band", band);
+                }
+                query
+            }));
+        }
+        if let (ConsumptionOrTariff::Tariff(tariff), Some(changes)) =
+            (&measurements, carried.changes.as_mut())
+        {
+            for change in changes.changes(tariff.prices()) {
+                info!(
+                    "{} {} changed from {} to {} at {}",
+                    target.describe(),
+                    change.price_type,
+                    change.previous,
+                    change.value,
+                    change.time
+                );
+                if let Some(webhook) = &self.webhook {
+                    webhook.post(&change.to_json(target.label.as_deref())).await;
+                }
+                let resource = Resource::parse(&change.resource);
+                readings.push(self.add_tags(
+                    target,
+                    &resource,
+                    change.into_query(tariff_change::MEASUREMENT),
+                ));
+            }
+        }
+        match (measurements, carried.resampler.as_mut()) {
+            (ConsumptionOrTariff::Consumption(consumption), Some(resampler)) => {
+                for reading in consumption.into_readings() {
+                    let anomaly = anomalous.contains(&reading.time);
+                    resampler.add(reading, anomaly);
+                }
+                readings.extend(
+                    resampler
+                        .complete(end.to_utc())
+                        .into_iter()
+                        .filter(|bucket| !existing.contains(&bucket.reading.time))
+                        .map(|bucket| self.resampled_point(target, energy_type, bucket)),
+                );
+            }
+            (measurements, _) => readings.extend(self.construct_influx_measurements(
+                target,
+                energy_type,
+                measurements,
+                existing,
+                &anomalous,
+            )),
+        }
+        Ok(readings)
+    }
+
+    /// Net usage of the window's import against the export element's readings
+    /// over the same window, with generation and self-consumption estimated
+    /// when the target has panels.
+    async fn solar_points(
+        &self,
+        target: &Target,
+        (start, end): Window,
+        export: u8,
+        import: &Consumption,
+    ) -> Result<Vec<WriteQuery>, n3rgy_rs::Error> {
+        let source = target.source.with_element(export);
+        let exported = fetch(
+            &*source,
+            start,
+            end,
+            EnergyType::Electricity,
+            RequestType::Consumption,
+        )
+        .await?;
+        let ConsumptionOrTariff::Consumption(exported) = exported else {
+            return Ok(Vec::new());
+        };
+        let mut irradiance = None;
+        if let Some(solar) = target.solar {
+            let (first, last) = (start.to_utc().date_naive(), end.to_utc().date_naive());
+            match self
+                .weather
+                .solar_radiation(solar.latitude, solar.longitude, first, last)
+                .await
+            {
+                Ok(hourly) => irradiance = Some((solar.array(), hourly)),
+                Err(e) => warn!("{}, writing net usage without generation", e),
+            }
+        }
+        let array = irradiance.as_ref().map(|(array, hourly)| (*array, hourly));
+        Ok(
+            solar_readings(import.readings(), exported.readings(), array)
+                .into_iter()
+                .map(|reading| {
+                    let resource = Resource::parse(&reading.resource);
+                    self.add_tags(target, &resource, reading.into_query(SOLAR_MEASUREMENT))
+                })
+                .collect(),
+        )
+    }
+
+    /// What `{name}` placeholders stand for in a point from `resource`.
+    fn template_values(&self, target: &Target, resource: &Resource) -> BTreeMap<&str, String> {
+        let values = [
+            ("fuel", resource.fuel.clone()),
+            ("type", resource.data_type.clone()),
+            ("element", resource.element.clone()),
+            (
+                "mpxn",
+                resource.mpxn.clone().or_else(|| target.mpxn.clone()),
+            ),
+            ("property", target.label.clone()),
+            ("profile", self.profile.clone()),
+        ];
+        values
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?)))
+            .collect()
+    }
+
+    /// Measurement raw readings and prices from `resource` go to.
+    fn price_measurement(&self, target: &Target, resource: &Resource) -> String {
+        if self.upcoming {
+            UPCOMING_MEASUREMENT.to_string()
+        } else {
+            self.measurement
+                .render(&self.template_values(target, resource))
+        }
+    }
+
+    /// Tags singling out `target`'s points: the `--tag`s, overridden by the
+    /// property's own, plus its `property` label. Tags whose template renders
+    /// empty are left off.
+    fn point_tags(&self, target: &Target, resource: &Resource) -> BTreeMap<String, String> {
+        let values = self.template_values(target, resource);
+        let mut tags: BTreeMap<String, String> = self
+            .tags
+            .iter()
+            .map(|(tag, template)| (tag.clone(), template.render(&values)))
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        tags.extend(target.tags.clone());
+        if let Some(label) = &target.label {
+            tags.insert("property".to_string(), label.clone());
+        }
+        tags
+    }
+
+    /// Add the point tags to a point already tagged from `resource`.
+    fn add_tags(&self, target: &Target, resource: &Resource, mut query: WriteQuery) -> WriteQuery {
+        if let (None, Some(mpxn)) = (&resource.mpxn, &target.mpxn) {
+            query = query.add_tag("mpxn", mpxn.clone());
+        }
+        for (tag, value) in self.point_tags(target, resource) {
+            query = query.add_tag(tag, value);
+        }
+        query
+    }
+
+    /// A consumption reading as a point, with the gas conversion when enabled,
+    /// written to [`QUARANTINE_MEASUREMENT`] when `quarantined`.
+    fn consumption_point(
+        &self,
+        target: &Target,
+        energy_type: EnergyType,
+        reading: ConsumptionReading,
+        quarantined: bool,
+    ) -> WriteQuery {
+        let resource = Resource::parse(&reading.resource);
+        let in_m3 = is_cubic_metres(&reading.unit);
+        let value = reading.consumption;
+        let local_time = self
+            .local_time
+            .then(|| reading.time.with_timezone(&London).to_rfc3339());
+        let half_hourly = reading.granularity == Granularity::HalfHour.as_param();
+        let settlement_period =
+            (self.settlement_period && energy_type == EnergyType::Electricity && half_hourly)
+                .then(|| settlement_period(reading.time));
+        let band = half_hourly
+            .then(|| band_for(&target.bands, reading.time))
+            .flatten();
+        let measurement = if quarantined {
+            QUARANTINE_MEASUREMENT.to_string()
+        } else {
+            self.measurement
+                .render(&self.template_values(target, &resource))
+        };
+        let mut query = self.add_tags(
+            target,
+            &resource,
+            resource.add_tags(reading.into_query(measurement)),
+        );
+        if let (EnergyType::Gas, Some(conversion)) = (energy_type, self.gas_conversion) {
+            query = if in_m3 {
+                query.add_field("consumption_kwh", conversion.m3_to_kwh(value))
+            } else {
+                query.add_field("consumption_m3", conversion.kwh_to_m3(value))
+            };
+        }
+        if let Some(period) = settlement_period {
+            query = query.add_tag("settlement_period", period);
+        }
+        if let Some(band) = band {
+            query = query.add_tag("band", band);
+        }
+        if let Some(local_time) = local_time {
+            query = query.add_field("local_time", local_time);
+        }
+        query
+    }
+
+    /// A resampled bucket as a point, tagged `anomaly=true` when any of its readings was.
+    fn resampled_point(
+        &self,
+        target: &Target,
+        energy_type: EnergyType,
+        bucket: Resampled,
+    ) -> WriteQuery {
+        let point = self.consumption_point(target, energy_type, bucket.reading, false);
+        if bucket.anomaly {
+            point.add_tag("anomaly", true)
+        } else {
+            point
+        }
+    }
+
+    fn construct_influx_measurements(
+        &self,
+        target: &Target,
+        energy_type: EnergyType,
+        parsed_messages: ConsumptionOrTariff,
+        existing: &HashSet<DateTime<Utc>>,
+        anomalous: &HashSet<DateTime<Utc>>,
+    ) -> Vec<WriteQuery> {
+        let mut readings = Vec::new();
+        if let ConsumptionOrTariff::Consumption(consumption) = parsed_messages {
+            for m in consumption.into_readings() {
+                if existing.contains(&m.time) {
+                    continue;
+                }
+                let anomaly = anomalous.contains(&m.time);
+                let mut point = self.consumption_point(target, energy_type, m, false);
+                if anomaly {
+                    point = point.add_tag("anomaly", true);
+                }
+                readings.push(point);
+            }
+        } else if let ConsumptionOrTariff::Tariff(tariff) = parsed_messages {
+            let resource = Resource::parse(&tariff.resource);
+            for m in tariff.prices() {
+                if existing.contains(&m.time)
+                    || (self.standing_charge_series && m.price_type == STANDING_CHARGE)
+                {
+                    continue;
+                }
+                readings.push(self.add_tags(
+                    target,
+                    &resource,
+                    resource.add_tags(m.into_query(self.price_measurement(target, &resource))),
+                ));
+            }
+            if self.standing_charge_series {
+                readings.extend(tariff.daily_standing_charges().map(|charge| {
+                    self.add_tags(
+                        target,
+                        &resource,
+                        resource.add_tags(charge.into_query(STANDING_CHARGE_MEASUREMENT)),
+                    )
+                }));
+            }
+        }
+        readings
+    }
+}
+
+/// Fetch a window from the source, keeping the request and failure counters up to date.
+async fn fetch(
+    source: &dyn Source,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    energy_type: EnergyType,
+    request_type: RequestType,
+) -> Result<ConsumptionOrTariff, n3rgy_rs::Error> {
+    let labels = [energy_type.to_string(), request_type.to_string()];
+    metrics::API_REQUESTS.with_label_values(&labels).inc();
+    #[cfg(feature = "otel")]
+    let started = SystemTime::now();
+    let result = source.fetch(energy_type, request_type, start, end).await;
+    #[cfg(feature = "otel")]
+    telemetry::api_call(
+        &labels[0],
+        &labels[1],
+        started,
+        result.as_ref().err().map(|e| e as &dyn Error),
+    );
+    match result {
+        Ok(measurements) => Ok(measurements),
+        Err(e) => {
+            if !matches!(e, n3rgy_rs::Error::Pending { .. }) {
+                metrics::API_FAILURES.with_label_values(&labels).inc();
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Price the window's consumption against the tariff for the same window.
+async fn fetch_costs(
+    source: &dyn Source,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    energy_type: EnergyType,
+    consumption: &Consumption,
+    pricing: Pricing,
+    conversion: GasConversion,
+) -> Result<Vec<Cost>, n3rgy_rs::Error> {
+    let mut tariff = match fetch(source, start, end, energy_type, RequestType::Tariff).await {
+        Ok(ConsumptionOrTariff::Tariff(tariff)) => tariff,
+        Ok(ConsumptionOrTariff::Consumption(_)) => return Ok(Vec::new()),
+        Err(e @ n3rgy_rs::Error::Api { .. }) => {
+            error!("{}, skipping cost", e);
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e),
+    };
+    tariff.apply_pricing(pricing);
+    Ok(price_consumption(
+        consumption.readings(),
+        tariff.prices(),
+        conversion,
+    ))
+}
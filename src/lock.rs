@@ -0,0 +1,50 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+use log::info;
+
+/// Why the lock couldn't be taken.
+pub enum LockError {
+    /// Another run holds it, with the pid it wrote if readable.
+    Held(Option<String>),
+    Io(io::Error),
+}
+
+/// Take an exclusive lock on `path`, so overlapping runs can't double-write
+/// or trip n3rgy's rate limits. With `wait`, block until the holder finishes
+/// rather than failing.
+///
+/// The lock lasts as long as the returned file is open, and the OS releases
+/// it however the process ends, so a crashed run never leaves it stuck.
+pub fn acquire(path: &Path, wait: bool) -> Result<File, LockError> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(LockError::Io)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) if wait => {
+            info!("waiting for the run holding {} to finish", path.display());
+            tokio::task::block_in_place(|| file.lock()).map_err(LockError::Io)?;
+        }
+        Err(TryLockError::WouldBlock) => {
+            let mut holder = String::new();
+            let holder = file
+                .read_to_string(&mut holder)
+                .ok()
+                .map(|_| holder.trim().to_string())
+                .filter(|holder| !holder.is_empty());
+            return Err(LockError::Held(holder));
+        }
+        Err(TryLockError::Error(e)) => return Err(LockError::Io(e)),
+    }
+    // record who holds it, for the message a blocked run prints
+    file.set_len(0).map_err(LockError::Io)?;
+    file.rewind().map_err(LockError::Io)?;
+    writeln!(file, "{}", std::process::id()).map_err(LockError::Io)?;
+    Ok(file)
+}
@@ -0,0 +1,143 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+
+use crate::cli::LogArgs;
+
+/// How often a new log file is started, whatever its size.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Rotation {
+    Hourly,
+    Daily,
+}
+
+impl Rotation {
+    /// The period `time` falls in, to tell when one has passed.
+    fn period(self, time: DateTime<Local>) -> String {
+        match self {
+            Rotation::Hourly => time.format("%Y%m%d%H").to_string(),
+            Rotation::Daily => time.format("%Y%m%d").to_string(),
+        }
+    }
+}
+
+/// A log file moved aside to `path.1`, `path.2` and so on once it grows past
+/// `max_bytes` or the rotation period ends, keeping the newest `keep`.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: Option<u64>,
+    rotation: Option<Rotation>,
+    keep: usize,
+    file: File,
+    size: u64,
+    /// Rotation period the current file was started in.
+    period: Option<String>,
+}
+
+impl RotatingFile {
+    pub fn open(
+        path: &Path,
+        max_bytes: Option<u64>,
+        rotation: Option<Rotation>,
+        keep: usize,
+    ) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        // an existing file belongs to the period it was last written in, so a
+        // restart the next day still rotates it
+        let modified: DateTime<Local> = metadata.modified()?.into();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_bytes,
+            rotation,
+            keep,
+            file,
+            size: metadata.len(),
+            period: rotation.map(|rotation| rotation.period(modified)),
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match fs::rename(numbered(n), numbered(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let period = self.rotation.map(|rotation| rotation.period(Local::now()));
+        let too_big = self
+            .max_bytes
+            .is_some_and(|max| self.size > 0 && self.size + buf.len() as u64 > max);
+        if too_big || period != self.period {
+            self.rotate()?;
+            self.period = period;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Copies log output to stderr as well as the file.
+struct Tee(RotatingFile);
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(buf)?;
+        self.0.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()?;
+        self.0.flush()
+    }
+}
+
+/// Set up logging to stderr, and to `--log-file` when one is given.
+pub fn init(args: &LogArgs) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(path) = &args.log_file {
+        let max_bytes = args.log_max_size.map(|megabytes| megabytes * 1024 * 1024);
+        match RotatingFile::open(path, max_bytes, args.log_rotate, args.log_keep) {
+            Ok(file) => {
+                builder.target(env_logger::Target::Pipe(Box::new(Tee(file))));
+            }
+            Err(e) => {
+                eprintln!("could not open log file {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+    builder.init();
+}
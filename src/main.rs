@@ -1,175 +1,1360 @@
-use std::borrow::Borrow;
-
-use chrono::{DateTime, Duration, Local, TimeZone};
-use clap::Parser;
-use env_logger;
-use influxdb::InfluxDbWriteable;
-use log::debug;
-use reqwest::{Client, Url};
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Local};
+use clap::CommandFactory;
+use log::{error, info, warn};
+use n3rgy_rs::cache::ResponseCache;
+use n3rgy_rs::carbon::CarbonIntensityClient;
+use n3rgy_rs::client::Window;
+use n3rgy_rs::conversion::GasConversion;
+use n3rgy_rs::glowmarkt::GlowmarktClient;
+use n3rgy_rs::models::{EnergyType, Granularity, RequestType};
+use n3rgy_rs::octopus::OctopusClient;
+use n3rgy_rs::recording::Recording;
+use n3rgy_rs::secret::SecretString;
+use n3rgy_rs::source::{Provider, Source};
+use n3rgy_rs::weather::WeatherClient;
+use n3rgy_rs::N3rgyClient;
+mod admin;
+mod alerts;
+#[cfg(feature = "s3")]
+mod archive;
+mod auth;
+mod backfill;
+mod budget;
+mod checkpoint;
 mod cli;
-mod models;
+mod config;
+mod consent;
+#[cfg(feature = "sentry")]
+mod crash_report;
+mod daemon;
+mod doctor;
+mod duckdb;
+#[cfg(feature = "email")]
+mod email;
+mod exit;
+mod fleet;
+mod grafana;
+mod graphite;
+mod health;
+mod http;
+mod http_sink;
+mod import;
+mod line_protocol;
+mod list;
+mod load;
+mod lock;
+mod log_file;
+mod metrics;
+mod migrate;
+#[cfg(feature = "mock-server")]
+mod mock;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod notify;
+#[cfg(feature = "parquet")]
+mod parquet_file;
+mod partition;
+mod plot;
+mod quality;
+mod reconcile;
+mod retag;
+mod shutdown;
+mod sink;
+mod spool;
+mod stats;
+mod systemd;
+mod tariff_change;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod template;
+mod validate;
+mod victoria;
+mod webhook;
 
-use crate::cli::Cli;
-use crate::models::{ConsumptionOrTariff, EnergyType, RequestType};
-const N3RGY_BASE_URL: &str = "https://consumer-api.data.n3rgy.com/";
+use crate::alerts::Alerts;
+#[cfg(feature = "s3")]
+use crate::archive::Archive;
+use crate::checkpoint::Checkpoint;
+use crate::cli::{
+    AdminCommand, ApiArgs, AuthCommand, Cli, Command, ConfigCommand, ElementSelection,
+    GrafanaCommand, InfluxArgs, LoadArgs, LogArgs, ReportCommand, ServeArgs, TokenArgs,
+};
+use crate::config::Config;
+use crate::duckdb::DuckDb;
+use crate::exit::Exit;
+use crate::graphite::Graphite;
+use crate::health::Health;
+use crate::http_sink::HttpSink;
+use crate::load::{Loader, Target};
+use crate::lock::LockError;
+use crate::partition::PartitionedFiles;
+use crate::reconcile::Reconciled;
+use crate::sink::{InfluxAuth, Sink};
+use crate::spool::Spool;
+use crate::template::Template;
+use crate::victoria::VictoriaMetrics;
+use crate::webhook::Webhook;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    log_file::init(&LogArgs::early());
+    shutdown::listen();
+
+    let mut cli = cli::parse();
+    let http_client = http::build_client(&cli.http).unwrap_or_else(|e| {
+        error!("could not build HTTP client: {}", e);
+        process::exit(1);
+    });
+    let influx_http = http::build_influx_client(&cli.http).unwrap_or_else(|e| {
+        error!("could not build the InfluxDB HTTP client: {}", e);
+        process::exit(1);
+    });
 
-    let cli = Cli::parse();
-    let api_token: &str = cli.api_token.borrow();
-    let client = reqwest::Client::new();
-    let influx_client =
-        influxdb::Client::new(cli.influx_uri, cli.influx_database).with_token(cli.influx_token);
+    if let Some(url) = &cli.notify_webhook {
+        notify::configure(Webhook {
+            http: http_client.clone(),
+            url: url.clone(),
+        });
+    }
+    #[cfg(feature = "sentry")]
+    let _crash_report = cli.sentry_dsn.as_deref().map(|dsn| {
+        crash_report::init(dsn).unwrap_or_else(|e| {
+            error!("invalid --sentry-dsn: {}", e);
+            process::exit(2);
+        })
+    });
+    #[cfg(feature = "otel")]
+    if let Some(endpoint) = &cli.otlp_endpoint {
+        if let Err(e) = telemetry::init(endpoint) {
+            error!("could not set up OpenTelemetry export: {}", e);
+            process::exit(1);
+        }
+    }
 
-    if (cli.end_date - cli.start_date).num_days() > 90 {
-        debug!("requested more than 90 days of data, chunking requests");
-        let mut start_date = cli.start_date;
-        let mut end_date = start_date + Duration::days(90);
-        let mut date_batches = Vec::new();
+    let base_url = cli.api_base_url().to_string();
+    let recording = cli.recording();
 
-        date_batches.push((start_date, end_date));
+    if cli.generate_man {
+        if let Err(e) = clap_mangen::Man::new(Cli::command()).render(&mut io::stdout()) {
+            error!("could not write the man page: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let Some(command) = cli.command.take() else {
+        // arg_required_else_help only covers a bare invocation
+        Cli::command().print_help().ok();
+        process::exit(2);
+    };
+    let _lock = match &cli.lock_file {
+        Some(path) if command.writes() => match lock::acquire(path, cli.wait_for_lock) {
+            Ok(file) => Some(file),
+            Err(LockError::Held(holder)) => {
+                let holder = holder.map_or("another run".to_string(), |pid| format!("pid {}", pid));
+                warn!("{} is held by {}, not starting", path.display(), holder);
+                Exit::Locked.exit();
+            }
+            Err(LockError::Io(e)) => {
+                error!("could not lock {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        _ => None,
+    };
 
-        while cli.end_date > end_date {
-            start_date = start_date + Duration::days(90);
-            end_date = min_dates(start_date + Duration::days(90), cli.end_date);
-            date_batches.push((start_date, end_date));
+    match command {
+        Command::Fetch(args) => {
+            let targets = targets(
+                &http_client,
+                &base_url,
+                recording.as_ref(),
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+                &args.api,
+                Some(args.load.granularity),
+            );
+            let loader = loader(
+                cli.profile.as_deref(),
+                &http_client,
+                influx_http.as_ref(),
+                &args.api,
+                args.load,
+                args.influx,
+            );
+            let targets = element_targets(
+                targets,
+                args.api.element,
+                args.energy_type,
+                args.request_type,
+            )
+            .await;
+            consent::report_targets(&targets);
+            let window = args.range.window().unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+            load_once(
+                &targets,
+                &loader,
+                window,
+                args.energy_type,
+                args.request_type,
+            )
+            .await;
+        }
+        Command::Tariff(args) => {
+            let targets = targets(
+                &http_client,
+                &base_url,
+                recording.as_ref(),
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+                &args.api,
+                None,
+            );
+            let loader = Loader {
+                request_delay: StdDuration::from_secs_f64(args.api.request_delay),
+                keep_going: args.api.keep_going,
+                upcoming: args.upcoming,
+                ..base_loader(
+                    cli.profile.as_deref(),
+                    &http_client,
+                    influx_http.as_ref(),
+                    args.influx,
+                )
+            };
+            let targets = element_targets(
+                targets,
+                args.api.element,
+                args.energy_type,
+                RequestType::Tariff,
+            )
+            .await;
+            consent::report_targets(&targets);
+            let window = if args.upcoming {
+                args.range.upcoming_window()
+            } else {
+                args.range.window()
+            };
+            let window = window.unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+            load_once(
+                &targets,
+                &loader,
+                window,
+                args.energy_type,
+                RequestType::Tariff,
+            )
+            .await;
+        }
+        Command::Backfill(args) => {
+            let targets = targets(
+                &http_client,
+                &base_url,
+                recording.as_ref(),
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+                &args.api,
+                Some(args.load.granularity),
+            );
+            let checkpoint = Checkpoint::open(args.checkpoint.clone()).unwrap_or_else(|e| {
+                error!(
+                    "could not read checkpoint {}: {}",
+                    args.checkpoint.display(),
+                    e
+                );
+                process::exit(1);
+            });
+            let loader = Loader {
+                checkpoint: Some(checkpoint),
+                ..loader(
+                    cli.profile.as_deref(),
+                    &http_client,
+                    influx_http.as_ref(),
+                    &args.api,
+                    args.load,
+                    args.influx,
+                )
+            };
+            consent::report_targets(&targets);
+            if let Some(code) = backfill::run(
+                &targets,
+                &loader,
+                args.fuel,
+                args.request_type,
+                args.api.element,
+            )
+            .await
+            {
+                notify::failure("backfill", code).await;
+                code.exit();
+            }
+        }
+        Command::Sync(args) => {
+            let targets = targets(
+                &http_client,
+                &base_url,
+                recording.as_ref(),
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+                &args.api,
+                Some(args.load.granularity),
+            );
+            let loader = Loader {
+                since_last: args.since_last,
+                ..loader(
+                    cli.profile.as_deref(),
+                    &http_client,
+                    influx_http.as_ref(),
+                    &args.api,
+                    args.load,
+                    args.influx,
+                )
+            };
+            let targets = element_targets(
+                targets,
+                args.api.element,
+                args.energy_type,
+                args.request_type,
+            )
+            .await;
+            consent::report_targets(&targets);
+            let end = Local::now();
+            let window = (end - Duration::hours(args.lookback_hours), end);
+            load_once(
+                &targets,
+                &loader,
+                window,
+                args.energy_type,
+                args.request_type,
+            )
+            .await;
+        }
+        Command::Serve(args) => {
+            let metrics_addr = args.metrics_addr;
+            let stale_after = args.health_stale_after.map(StdDuration::from_secs);
+            let watch = args.watch_config.then(|| cli.config.clone()).flatten();
+            if args.watch_config && watch.is_none() {
+                warn!("--watch-config has no --config file to watch");
+            }
+            #[cfg(feature = "mqtt")]
+            let mqtt_args = args.mqtt.clone();
+            let mut daemon = daemon_setup(&cli, *args, &http_client, influx_http.as_ref()).await;
+            health::set_sink(&daemon.loader.sink);
+            #[cfg(feature = "mqtt")]
+            {
+                mqtt::set_sink(&daemon.loader.sink);
+                mqtt::spawn(mqtt_args);
+            }
+            tokio::spawn(metrics::serve(metrics_addr, Health::new(stale_after)));
+            daemon::listen(watch);
+            systemd::spawn_watchdog();
+            systemd::ready();
+            consent::report_targets(&daemon.targets);
+
+            let end = Local::now();
+            let (mut deferred, _) = sync_all(
+                &daemon.targets,
+                &daemon.loader,
+                (end - daemon.settings.lookback, end),
+                daemon.energy_type,
+                daemon.request_type,
+            )
+            .await;
+            while let daemon::Stop::Reload(carried) = daemon::run(
+                &daemon.targets,
+                &daemon.loader,
+                daemon.energy_type,
+                daemon.request_type,
+                &mut daemon.settings,
+                deferred,
+            )
+            .await
+            {
+                deferred = carried;
+                systemd::reloading();
+                if let Some(reloaded) = reload_daemon(&http_client, influx_http.as_ref()).await {
+                    daemon = reloaded;
+                    health::set_sink(&daemon.loader.sink);
+                    #[cfg(feature = "mqtt")]
+                    mqtt::set_sink(&daemon.loader.sink);
+                    consent::report_targets(&daemon.targets);
+                    info!(
+                        "reloaded the config, syncing {} target(s)",
+                        daemon.targets.len()
+                    );
+                }
+                systemd::ready();
+            }
+        }
+        Command::List(args) => {
+            let mut client = N3rgyClient::new(api_token(&args))
+                .with_http_client(http_client)
+                .with_base_url(base_url);
+            if let Some(recording) = recording {
+                client = client.with_recording(recording);
+            }
+            if let Err(e) = list::run(&client).await {
+                error!("{}", e);
+                Exit::of(&e).exit();
+            }
+        }
+        Command::Auth { command } => {
+            let result = match command {
+                AuthCommand::Login => auth::login(&http_client, &base_url).await,
+                AuthCommand::Logout => auth::logout(),
+            };
+            if let Err(e) = result {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Command::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                env!("CARGO_PKG_NAME"),
+                &mut io::stdout(),
+            );
         }
-        for batch in date_batches {
-            pull_and_load(
+        Command::SystemdUnit(args) => {
+            let exe = args
+                .exec
+                .or_else(|| env::current_exe().ok())
+                .unwrap_or_else(|| PathBuf::from(env!("CARGO_PKG_NAME")));
+            print!(
+                "{}",
+                systemd::unit(&exe, &args.serve_args, &args.env_file, args.watchdog_sec)
+            );
+        }
+        Command::Config {
+            command: ConfigCommand::Validate(args),
+        } => {
+            if !validate::run(
+                cli.config.as_deref(),
+                cli.profile.as_deref(),
+                influx_http.as_ref(),
+                args,
+            )
+            .await
+            {
+                process::exit(1);
+            }
+        }
+        Command::Doctor(args) => {
+            if !doctor::run(
+                &http_client,
+                influx_http.as_ref(),
+                &base_url,
+                cli.config.as_deref(),
+                args,
+            )
+            .await
+            {
+                process::exit(1);
+            }
+        }
+        Command::Stats(args) => {
+            let window = args.window().unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+            let mut client = N3rgyClient::new(api_token(&args.token))
+                .with_http_client(http_client)
+                .with_base_url(base_url);
+            if let Some(recording) = recording {
+                client = client.with_recording(recording);
+            }
+            if let Err(e) = stats::run(
+                &client,
+                args.energy_type,
+                window,
+                args.period,
+                args.cost,
+                args.pricing.pricing(),
+            )
+            .await
+            {
+                error!("{}", e);
+                Exit::of(&e).exit();
+            }
+        }
+        Command::Plot(args) => {
+            let window = args.window().unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+            let mut client = N3rgyClient::new(api_token(&args.token))
+                .with_http_client(http_client)
+                .with_base_url(base_url);
+            if let Some(recording) = recording {
+                client = client.with_recording(recording);
+            }
+            if let Err(e) =
+                plot::run(&client, args.energy_type, window, args.style, args.ascii).await
+            {
+                error!("{}", e);
+                Exit::of(&e).exit();
+            }
+        }
+        Command::Report {
+            command: ReportCommand::Budget(args),
+        } => {
+            let (sink, _) = sink(&args.influx, &http_client, influx_http.as_ref());
+            if !matches!(sink, Sink::InfluxDb(_)) {
+                error!("report budget reads stored cost from InfluxDB, pass --influx-uri");
+                process::exit(2);
+            }
+            let budget = args
+                .budget
+                .or(load_config(cli.config.as_deref()).monthly_budget);
+            let tags = args
+                .property
+                .map(|label| BTreeMap::from([("property".to_string(), label)]))
+                .unwrap_or_default();
+            if let Err(e) = budget::run(&sink, &tags, budget, args.recent_days, args.json).await {
+                error!("could not build the budget report: {}", e);
+                Exit::of(e.as_ref()).exit();
+            }
+        }
+        #[cfg(feature = "email")]
+        Command::Report {
+            command: ReportCommand::Email(args),
+        } => {
+            let (sink, _) = sink(&args.influx, &http_client, influx_http.as_ref());
+            if !matches!(sink, Sink::InfluxDb(_)) {
+                error!("report email reads stored data from InfluxDB, pass --influx-uri");
+                process::exit(2);
+            }
+            let config = load_config(cli.config.as_deref());
+            let tags = args
+                .property
+                .map(|label| BTreeMap::from([("property".to_string(), label)]))
+                .unwrap_or_default();
+            let result = email::run(
+                &sink,
+                &args.influx.measurement,
+                &tags,
+                args.period,
+                config.email.as_ref(),
+                args.print,
+            )
+            .await;
+            if let Err(e) = result {
+                error!("could not send the summary email: {}", e);
+                Exit::of(e.as_ref()).exit();
+            }
+        }
+        Command::Report {
+            command: ReportCommand::Quality(args),
+        } => {
+            let window = args.window().unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+            let mut client = N3rgyClient::new(api_token(&args.token))
+                .with_http_client(http_client)
+                .with_base_url(base_url);
+            if let Some(recording) = recording {
+                client = client.with_recording(recording);
+            }
+            let result = quality::run(
                 &client,
-                api_token,
-                &influx_client,
-                batch.0,
-                batch.1,
-                cli.energy_type,
-                cli.request_type,
+                args.energy_type,
+                window,
+                args.min_zero_run,
+                args.spike_factor,
+                args.json,
             )
             .await;
+            if let Err(e) = result {
+                error!("{}", e);
+                Exit::of(&e).exit();
+            }
+        }
+        Command::FleetReport(args) => {
+            let config = load_config(cli.config.as_deref());
+            if config.meters.is_empty() {
+                error!("fleet-report needs at least one [[meters]] entry in the config file");
+                process::exit(1);
+            }
+            let window = args.window().unwrap_or_else(|e| {
+                error!("{}", e);
+                process::exit(1);
+            });
+            fleet::run(
+                &http_client,
+                &base_url,
+                &config.meters,
+                window,
+                args.pricing.pricing(),
+                args.json,
+            )
+            .await;
+        }
+        Command::Consent(args) => {
+            if let Err(e) = consent::check(&http_client, &base_url, args).await {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Command::Import(args) => {
+            let (sink, _) = sink(&args.influx, &http_client, influx_http.as_ref());
+            match import::run(&sink, &args.paths, args.format, args.batch_size.get()).await {
+                Ok(count) => info!("imported {} points", count),
+                Err(e) => {
+                    error!("could not import: {}", e);
+                    Exit::of(e.as_ref()).exit();
+                }
+            }
+        }
+        Command::Migrate(args) => {
+            let (sink, _) = sink(&args.influx, &http_client, influx_http.as_ref());
+            let auth = args
+                .from_username
+                .zip(args.from_password)
+                .map(|(username, password)| InfluxAuth::Basic { username, password });
+            let source = match auth {
+                Some(auth) => {
+                    auth.client(&args.from_uri, &args.from_database, influx_http.as_ref())
+                }
+                None => {
+                    let client = influxdb::Client::new(&args.from_uri, &args.from_database);
+                    match &influx_http {
+                        Some(http) => client.with_http_client(http.clone()),
+                        None => client,
+                    }
+                }
+            };
+            let result = migrate::run(
+                &source,
+                &args.from_measurements,
+                &sink,
+                args.new_tag_schema,
+                Duration::days(args.chunk_days),
+            )
+            .await;
+            match result {
+                Ok(count) => info!("copied {} points", count),
+                Err(e) => {
+                    error!("could not migrate: {}", e);
+                    Exit::of(e.as_ref()).exit();
+                }
+            }
+        }
+        Command::Retag(args) => {
+            let (Sink::InfluxDb(client), _) =
+                sink(&args.influx, &http_client, influx_http.as_ref())
+            else {
+                error!("retag rewrites points stored in InfluxDB, pass --influx-uri");
+                process::exit(2);
+            };
+            let result = retag::run(
+                &client,
+                &args.from_measurement,
+                args.to_measurement.as_deref(),
+                args.delete_old,
+                Duration::days(args.chunk_days),
+            )
+            .await;
+            match result {
+                Ok(count) => info!("rewrote {} points", count),
+                Err(e) => {
+                    error!("could not retag: {}", e);
+                    Exit::of(e.as_ref()).exit();
+                }
+            }
+        }
+        Command::Admin {
+            command: AdminCommand::Delete(args),
+        } => {
+            if args.end <= args.start {
+                error!("end {} is not after start {}", args.end, args.start);
+                process::exit(2);
+            }
+            let (sink, _) = sink(&args.influx, &http_client, influx_http.as_ref());
+            if !matches!(sink, Sink::InfluxDb(_) | Sink::DryRun) {
+                error!("admin delete removes points stored in InfluxDB, pass --influx-uri");
+                process::exit(2);
+            }
+            let deletion = admin::Deletion {
+                measurement: &args.influx.measurement,
+                start: args.start,
+                end: args.end,
+                fuel: args.fuel,
+                request_type: args.request_type,
+                property: args.property.as_deref(),
+            };
+            if let Err(e) = admin::delete(&sink, &deletion).await {
+                error!("could not delete: {}", e);
+                Exit::of(e.as_ref()).exit();
+            }
+        }
+        Command::Grafana {
+            command: GrafanaCommand::ExportDashboard(args),
+        } => {
+            let dashboard = grafana::dashboard(
+                &args.title,
+                &args.measurement,
+                args.datasource_uid.as_deref(),
+            );
+            let (Some(url), Some(token)) = (&args.grafana_url, &args.grafana_token) else {
+                println!("{:#}", dashboard);
+                return;
+            };
+            if let Err(e) = grafana::push(
+                &http_client,
+                url,
+                token,
+                dashboard,
+                args.folder_uid.as_deref(),
+            )
+            .await
+            {
+                error!("could not push the dashboard: {}", e);
+                Exit::of(e.as_ref()).exit();
+            }
+            info!("pushed dashboard {:?} to {}", args.title, url);
+        }
+        #[cfg(feature = "mock-server")]
+        Command::MockServer(args) => {
+            if let Err(e) = mock::run(args).await {
+                error!("{}", e);
+                process::exit(1);
+            }
         }
-    } else {
-        pull_and_load(
-            &client,
-            api_token,
-            &influx_client,
-            cli.start_date,
-            cli.end_date,
-            cli.energy_type,
-            cli.request_type,
-        )
-        .await;
     }
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
 }
 
-fn min_dates<Tz: TimeZone>(d1: DateTime<Tz>, d2: DateTime<Tz>) -> DateTime<Tz> {
-    let d1_unix = d1.timestamp();
-    let d2_unix = d2.timestamp();
-    if d1_unix < d2_unix {
-        return d1;
-    } else if d1_unix > d2_unix {
-        return d2;
-    } else {
-        return d1;
+fn load_config(path: Option<&Path>) -> Config {
+    match path {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            process::exit(1);
+        }),
+        None => Config::default(),
     }
 }
 
-async fn pull_and_load(
-    api_client: &reqwest::Client,
-    api_token: &str,
-    influx_client: &influxdb::Client,
-    start: DateTime<Local>,
-    end: DateTime<Local>,
-    energy_type: EnergyType,
-    request_type: RequestType,
-) {
-    let measurements = pull_usage(api_client, start, end, energy_type, request_type, api_token)
-        .await
-        .unwrap();
-
-    let readings = match measurements {
-        ConsumptionOrTariff::Error(_) => construct_influx_measurements(measurements),
-        ConsumptionOrTariff::Consumption(_) => construct_influx_measurements(measurements),
-        ConsumptionOrTariff::Tariff(_) => construct_influx_measurements(measurements),
+/// The meters to load, each merged with the --reconcile-with sources when
+/// given.
+fn targets(
+    http_client: &reqwest::Client,
+    base_url: &str,
+    recording: Option<&Recording>,
+    config: Option<&Path>,
+    profile: Option<&str>,
+    args: &ApiArgs,
+    granularity: Option<Granularity>,
+) -> Vec<Target> {
+    let targets = source_targets(
+        http_client,
+        base_url,
+        recording,
+        config,
+        profile,
+        args,
+        granularity,
+    );
+    if args.reconcile_with.is_empty() {
+        return targets;
+    }
+    if targets.len() > 1 {
+        error!(
+            "--reconcile-with compares a single meter, but the config file lists {}",
+            targets.len()
+        );
+        process::exit(2);
+    }
+    let others: Vec<Arc<dyn Source>> = args
+        .reconcile_with
+        .iter()
+        .map(|provider| match provider {
+            Provider::N3rgy => {
+                let client = api_client(
+                    http_client,
+                    base_url,
+                    recording,
+                    args,
+                    api_token(&args.token),
+                );
+                Arc::new(match granularity {
+                    Some(granularity) => client.with_granularity(granularity),
+                    None => client,
+                }) as Arc<dyn Source>
+            }
+            Provider::Glowmarkt => glowmarkt(http_client, args, granularity),
+            Provider::Octopus => octopus(http_client, args, granularity),
+        })
+        .collect();
+    targets
+        .into_iter()
+        .map(|target| {
+            let mut sources = vec![target.source.clone()];
+            sources.extend(others.iter().cloned());
+            Target {
+                source: Arc::new(Reconciled::new(
+                    sources,
+                    args.reconcile_tolerance,
+                    args.reconcile_report.clone(),
+                )),
+                ..target
+            }
+        })
+        .collect()
+}
+
+/// The Glowmarkt or Octopus account, or for n3rgy the token given on the
+/// command line or by the profile, else every meter in the config file, else
+/// the token saved by `auth login`.
+fn source_targets(
+    http_client: &reqwest::Client,
+    base_url: &str,
+    recording: Option<&Recording>,
+    config: Option<&Path>,
+    profile: Option<&str>,
+    args: &ApiArgs,
+    granularity: Option<Granularity>,
+) -> Vec<Target> {
+    let client = |token| -> Arc<dyn Source> {
+        let client = api_client(http_client, base_url, recording, args, token);
+        Arc::new(match granularity {
+            Some(granularity) => client.with_granularity(granularity),
+            None => client,
+        })
+    };
+    let mut config = load_config(config);
+    let bands = config.bands;
+    let price_cap = config.price_cap;
+    let anomalies = config.anomalies;
+    let degree_days = config.degree_days;
+    let solar = config.solar;
+    let ev_charging = config.ev_charging;
+    // cli::parse has already checked the profile exists
+    let tags = profile
+        .and_then(|name| config.profiles.remove(name))
+        .map(|profile| profile.tags)
+        .unwrap_or_default();
+    let supplier = match args.source {
+        Provider::N3rgy => None,
+        Provider::Glowmarkt => Some(glowmarkt(http_client, args, granularity)),
+        Provider::Octopus => Some(octopus(http_client, args, granularity)),
+    };
+    if let Some(source) = supplier {
+        return vec![Target {
+            tags,
+            consent_expires: args.consent_expires,
+            bands,
+            price_cap,
+            anomalies,
+            degree_days,
+            solar,
+            ev_charging,
+            ..Target::new(source)
+        }];
+    }
+    if !args.token.given() && !config.meters.is_empty() {
+        return config
+            .meters
+            .into_iter()
+            .map(|meter| {
+                let mut meter_tags = tags.clone();
+                meter_tags.extend(meter.tags);
+                Target {
+                    source: client(meter.api_token),
+                    label: Some(meter.label),
+                    mpxn: meter.mpxn,
+                    tags: meter_tags,
+                    consent_expires: meter.consent_expires,
+                    bands: meter.bands.unwrap_or_else(|| bands.clone()),
+                    price_cap,
+                    anomalies,
+                    degree_days: meter.degree_days.or(degree_days),
+                    solar: meter.solar.or(solar),
+                    ev_charging,
+                }
+            })
+            .collect();
+    }
+    vec![Target {
+        tags,
+        consent_expires: args.consent_expires,
+        bands,
+        price_cap,
+        anomalies,
+        degree_days,
+        solar,
+        ev_charging,
+        ..Target::new(client(api_token(&args.token)))
+    }]
+}
+
+/// A Glowmarkt client logged in as the Bright account given.
+fn glowmarkt(
+    http_client: &reqwest::Client,
+    args: &ApiArgs,
+    granularity: Option<Granularity>,
+) -> Arc<dyn Source> {
+    let (Some(username), Some(password)) = (&args.glowmarkt_username, &args.glowmarkt_password)
+    else {
+        error!("--source glowmarkt needs --glowmarkt-username and --glowmarkt-password");
+        process::exit(2);
     };
+    let client = GlowmarktClient::new(username.clone(), password.clone())
+        .with_http_client(http_client.clone())
+        .with_base_url(args.glowmarkt_url.clone());
+    Arc::new(match granularity {
+        Some(granularity) => client.with_granularity(granularity),
+        None => client,
+    })
+}
+
+/// An Octopus Energy client for the account given.
+fn octopus(
+    http_client: &reqwest::Client,
+    args: &ApiArgs,
+    granularity: Option<Granularity>,
+) -> Arc<dyn Source> {
+    let (Some(api_key), Some(account)) = (&args.octopus_api_key, &args.octopus_account) else {
+        error!("--source octopus needs --octopus-api-key and --octopus-account");
+        process::exit(2);
+    };
+    let client = OctopusClient::new(api_key.clone(), account.clone())
+        .with_http_client(http_client.clone())
+        .with_base_url(args.octopus_url.clone())
+        .with_gas_kwh(args.octopus_gas_kwh);
+    Arc::new(match granularity {
+        Some(granularity) => client.with_granularity(granularity),
+        None => client,
+    })
+}
 
-    if readings.len() > 0 {
-        influx_client.query(readings).await.unwrap();
+fn api_client(
+    http_client: &reqwest::Client,
+    base_url: &str,
+    recording: Option<&Recording>,
+    args: &ApiArgs,
+    token: SecretString,
+) -> N3rgyClient {
+    let mut client = N3rgyClient::new(token)
+        .with_http_client(http_client.clone())
+        .with_base_url(base_url)
+        .with_pending_deadline(StdDuration::from_secs(args.pending_deadline))
+        .with_chunk_days(args.chunk_days);
+    if let Some(dir) = &args.cache_dir {
+        let cache = ResponseCache::new(dir, StdDuration::from_secs(args.cache_ttl * 3600));
+        client = client.with_cache(if args.force_refresh {
+            cache.refreshing()
+        } else {
+            cache
+        });
+    }
+    match recording {
+        Some(recording) => client.with_recording(recording.clone()),
+        None => client,
     }
 }
 
-fn construct_influx_measurements(
-    parsed_messages: ConsumptionOrTariff,
-) -> Vec<influxdb::WriteQuery> {
-    let mut readings = Vec::new();
-    if let ConsumptionOrTariff::Consumption(consumption) = parsed_messages {
-        for m in consumption.influx_format() {
-            readings.push(m.into_query("energy"));
-        }
-    } else if let ConsumptionOrTariff::Tariff(tariff) = parsed_messages {
-        for m in tariff.influx_format() {
-            readings.push(m.into_query("energy"));
+/// The sink, and the spool for batches it refuses when `--spool-dir` is given.
+fn sink(
+    args: &InfluxArgs,
+    http_client: &reqwest::Client,
+    http: Option<&influx_reqwest::Client>,
+) -> (Sink, Option<Spool>) {
+    if args.dry_run {
+        return (Sink::DryRun, None);
+    }
+    if let Some(url) = &args.http_sink_url {
+        let sink = Sink::Http(HttpSink {
+            http: http_client.clone(),
+            url: url.clone(),
+            headers: args.http_sink_header.clone(),
+            template: args.http_sink_template.clone(),
+        });
+        return (sink, None);
+    }
+    if let Some(path) = &args.output {
+        let templated = path.to_string_lossy().contains('{');
+        if args.output_compression.is_some() || args.output_partition.is_some() || templated {
+            if path.as_os_str() == "-" {
+                error!("--output-compression and --output-partition need a file, not stdout");
+                process::exit(2);
+            }
+            let files = PartitionedFiles::new(
+                &path.to_string_lossy(),
+                args.output_partition,
+                args.output_compression,
+            )
+            .unwrap_or_else(|e| {
+                error!("invalid --output: {}", e);
+                process::exit(2);
+            });
+            return (Sink::Files(files), None);
         }
-    } else if let ConsumptionOrTariff::Error(error) = parsed_messages {
-        error.log_out();
+        let sink = Sink::file(path).unwrap_or_else(|e| {
+            error!("could not open {}: {}", path.display(), e);
+            process::exit(1);
+        });
+        return (sink, None);
+    }
+    #[cfg(feature = "s3")]
+    if let Some(bucket) = &args.s3_bucket {
+        let archive = Archive::new(
+            bucket,
+            args.s3_endpoint.as_deref(),
+            args.s3_key.clone(),
+            args.s3_format,
+        )
+        .unwrap_or_else(|e| {
+            error!("could not set up uploads to {}: {}", bucket, e);
+            process::exit(1);
+        });
+        return (Sink::Archive(archive), None);
+    }
+    if let Some(path) = &args.duckdb {
+        let sink = Sink::DuckDb(DuckDb {
+            path: path.clone(),
+            cli: args.duckdb_cli.clone(),
+        });
+        return (sink, None);
     }
-    readings
+    if let Some(address) = &args.graphite {
+        let sink = Sink::Graphite(Graphite {
+            address: address.clone(),
+            path: args.graphite_path.clone(),
+        });
+        return (sink, None);
+    }
+    if let Some(url) = &args.victoria_metrics_url {
+        let sink = Sink::VictoriaMetrics(VictoriaMetrics::new(http_client.clone(), url));
+        return (sink, None);
+    }
+    let auth = args.auth().unwrap_or_else(|e| {
+        error!("could not read the InfluxDB token: {}", e);
+        process::exit(1);
+    });
+    // clap enforces these unless another sink is chosen
+    let auth = auth.unwrap();
+    let uri = args.uri().unwrap();
+    let database = args.database().unwrap();
+    let spool = args
+        .spool_dir
+        .as_deref()
+        .map(|dir| Spool::new(dir, uri, database, auth.clone(), http));
+    (Sink::InfluxDb(auth.client(uri, database, http)), spool)
 }
 
-async fn pull_usage(
-    client: &Client,
-    start_date: DateTime<Local>,
-    end_date: DateTime<Local>,
-    energy_type: EnergyType,
-    request_type: RequestType,
-    api_token: &str,
-) -> Result<ConsumptionOrTariff, serde_json::Error> {
-    let request_start = format!("{}", start_date.format("%Y%m%d%H%M"));
-    let request_end = format!("{}", end_date.format("%Y%m%d%H%M"));
-
-    debug!(
-        "requesting: {} {} for dates {} {}",
-        energy_type, request_type, start_date, end_date
-    );
+/// The token given on the command line, or else the one saved by `auth login`.
+fn api_token(args: &TokenArgs) -> SecretString {
+    let token = args
+        .api_token()
+        .map_err(|e| format!("could not read the API token: {}", e))
+        .and_then(|token| match token {
+            Some(token) => Ok(Some(token)),
+            None => auth::stored_token(),
+        });
+    match token {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            error!("no API token given, pass --api-token or --api-token-file or run `auth login`");
+            Exit::Auth.exit();
+        }
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+}
 
-    let url = build_request_url(
-        request_start,
-        request_end,
-        "JSON".to_string(),
-        energy_type,
-        request_type.clone(),
-    );
+fn loader(
+    profile: Option<&str>,
+    http_client: &reqwest::Client,
+    influx_http: Option<&influx_reqwest::Client>,
+    api: &ApiArgs,
+    load: LoadArgs,
+    influx: InfluxArgs,
+) -> Loader {
+    if load.resample.is_some() && matches!(load.granularity, Granularity::Day) {
+        error!("--resample sums half-hourly readings and can't be used with --granularity day");
+        process::exit(2);
+    }
+    Loader {
+        gas_conversion: load.convert_gas.then_some(GasConversion {
+            calorific_value: load.calorific_value,
+            volume_correction: load.volume_correction,
+        }),
+        compute_cost: load.compute_cost,
+        aggregate: load.aggregate,
+        resample: load.resample,
+        settlement_period: load.settlement_period,
+        local_time: load.local_time,
+        carbon: load.carbon_intensity.then(|| {
+            let client = CarbonIntensityClient::new()
+                .with_http_client(http_client.clone())
+                .with_base_url(load.carbon_intensity_url.clone());
+            match &load.carbon_postcode {
+                Some(postcode) => client.with_postcode(postcode),
+                None => client,
+            }
+        }),
+        weather: WeatherClient::new()
+            .with_http_client(http_client.clone())
+            .with_archive_url(load.open_meteo_archive_url.clone())
+            .with_forecast_url(load.open_meteo_url.clone()),
+        export_element: load.export_element,
+        request_delay: StdDuration::from_secs_f64(api.request_delay),
+        keep_going: api.keep_going,
+        ..base_loader(profile, http_client, influx_http, influx)
+    }
+}
 
-    let res = client
-        .get(url)
-        .header("Authorization", api_token)
-        .send()
-        .await
-        .unwrap();
+/// A loader writing raw readings to the sink, named and tagged as configured,
+/// and tariff changes when asked for.
+fn base_loader(
+    profile: Option<&str>,
+    http_client: &reqwest::Client,
+    influx_http: Option<&influx_reqwest::Client>,
+    influx: InfluxArgs,
+) -> Loader {
+    let (sink, spool) = sink(&influx, http_client, influx_http);
+    Loader {
+        profile: profile.map(str::to_string),
+        measurement: influx
+            .measurement_template
+            .clone()
+            .unwrap_or_else(|| Template::literal(&influx.measurement)),
+        tags: influx.tags.iter().cloned().collect(),
+        skip_existing: influx.skip_existing,
+        spool,
+        write_concurrency: influx.write_concurrency.get(),
+        tariff_changes: influx.tariff_changes,
+        standing_charge_series: influx.standing_charge_series,
+        pricing: influx.pricing.pricing(),
+        webhook: influx.tariff_change_webhook.map(|url| Webhook {
+            http: http_client.clone(),
+            url,
+        }),
+        ..Loader::new(sink)
+    }
+}
 
-    let body = res.text().await.unwrap();
-    let measurement: ConsumptionOrTariff = serde_json::from_str(&body).unwrap();
-    Ok(measurement)
+/// One target per meter element selected, discovering them for `all`.
+async fn element_targets(
+    targets: Vec<Target>,
+    selection: ElementSelection,
+    energy_type: EnergyType,
+    request_type: RequestType,
+) -> Vec<Target> {
+    let mut selected = Vec::new();
+    for target in targets {
+        let elements = match selection {
+            ElementSelection::Number(element) => vec![element],
+            ElementSelection::All => target
+                .source
+                .elements(energy_type, request_type)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("could not list meter elements: {}", e);
+                    Exit::of(&e).exit();
+                }),
+        };
+        selected.extend(
+            elements
+                .into_iter()
+                .map(|element| target.with_element(element)),
+        );
+    }
+    selected
 }
 
-fn build_request_url(
-    start: String,
-    end: String,
-    output: String,
+/// What each `serve` cycle syncs and how, rebuilt when the config is reloaded.
+struct Daemon {
+    targets: Vec<Target>,
+    loader: Loader,
     energy_type: EnergyType,
     request_type: RequestType,
-) -> Url {
-    let parameters = [("start", start), ("end", end), ("output", output)];
+    settings: daemon::Settings,
+}
 
-    let request_url = match energy_type {
-        EnergyType::Electricity => N3RGY_BASE_URL.to_owned() + "electricity/",
-        EnergyType::Gas => N3RGY_BASE_URL.to_owned() + "gas/",
+async fn daemon_setup(
+    cli: &Cli,
+    args: ServeArgs,
+    http_client: &reqwest::Client,
+    influx_http: Option<&influx_reqwest::Client>,
+) -> Daemon {
+    let targets = targets(
+        http_client,
+        cli.api_base_url(),
+        cli.recording().as_ref(),
+        cli.config.as_deref(),
+        cli.profile.as_deref(),
+        &args.api,
+        Some(args.load.granularity),
+    );
+    let loader = loader(
+        cli.profile.as_deref(),
+        http_client,
+        influx_http,
+        &args.api,
+        args.load,
+        args.influx,
+    );
+    let targets = element_targets(
+        targets,
+        args.api.element,
+        args.energy_type,
+        args.request_type,
+    )
+    .await;
+    let settings = daemon::Settings {
+        schedule: match args.schedule {
+            Some(cron) => daemon::Schedule::Cron(Box::new(cron)),
+            None => daemon::Schedule::Every(StdDuration::from_secs(args.interval)),
+        },
+        jitter: StdDuration::from_secs(args.jitter),
+        lookback: Duration::hours(args.lookback_hours),
+        notify_after: args.notify_after,
+        alerts: Alerts::new(
+            load_config(cli.config.as_deref()).alerts,
+            args.alert_webhook.map(|url| Webhook {
+                http: http_client.clone(),
+                url,
+            }),
+        ),
     };
+    Daemon {
+        targets,
+        loader,
+        energy_type: args.energy_type,
+        request_type: args.request_type,
+        settings,
+    }
+}
 
-    let request_url = match request_type {
-        RequestType::Consumption => request_url + "consumption/1",
-        RequestType::Tariff => request_url + "tariff/1",
+/// Parse the command line again for `serve`, with the profile and config file
+/// as they now stand, or `None` to carry on as before when they no longer make
+/// sense. The new settings replace the old wholesale; nothing is carried over
+/// through the environment.
+async fn reload_daemon(
+    http_client: &reqwest::Client,
+    influx_http: Option<&influx_reqwest::Client>,
+) -> Option<Daemon> {
+    let mut cli = match cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            error!("not reloading: {}", e);
+            return None;
+        }
     };
+    // checked here so a typo doesn't take the daemon down with it
+    if let Some(path) = &cli.config {
+        if let Err(e) = Config::load(path) {
+            error!("not reloading: {}", e);
+            return None;
+        }
+    }
+    let Some(Command::Serve(args)) = cli.command.take() else {
+        return None;
+    };
+    Some(daemon_setup(&cli, *args, http_client, influx_http).await)
+}
+
+/// Sync `window` for every target, returning the windows n3rgy was still
+/// retrieving and whether any sync failed.
+async fn sync_all(
+    targets: &[Target],
+    loader: &Loader,
+    (start, end): Window,
+    energy_type: EnergyType,
+    request_type: RequestType,
+) -> (Vec<(Target, Window)>, Option<Exit>) {
+    loader.retry_spooled().await;
+    let mut deferred = Vec::new();
+    let mut failed = Vec::new();
+    let mut failure = None;
+    for target in targets {
+        match loader
+            .sync(target, start, end, energy_type, request_type)
+            .await
+        {
+            Ok(outcome) => {
+                if !outcome.is_complete() {
+                    Exit::record(&mut failure, Exit::Partial);
+                }
+                if !outcome.spooled.is_empty() {
+                    // kept for a later run, but InfluxDB is down
+                    Exit::record(&mut failure, Exit::Sink);
+                }
+                for (window, e) in outcome.failed {
+                    #[cfg(feature = "sentry")]
+                    crash_report::sync_failure(
+                        target,
+                        window,
+                        energy_type,
+                        request_type,
+                        loader.sink.kind(),
+                        e.as_ref(),
+                    );
+                    Exit::record(&mut failure, Exit::of(e.as_ref()));
+                    failed.push((target.clone(), window));
+                }
+                deferred.extend(outcome.deferred.into_iter().map(|w| (target.clone(), w)));
+            }
+            Err(e) => {
+                error!("{}", e);
+                #[cfg(feature = "sentry")]
+                crash_report::sync_failure(
+                    target,
+                    (start, end),
+                    energy_type,
+                    request_type,
+                    loader.sink.kind(),
+                    e.as_ref(),
+                );
+                Exit::record(&mut failure, Exit::of(e.as_ref()));
+            }
+        }
+    }
+    load::report_failed(&failed);
+    // deferred, skipped or spooled windows aren't written yet
+    if failure.is_none() {
+        metrics::LAST_SUCCESSFUL_SYNC.set(Local::now().timestamp());
+    }
+    (deferred, failure)
+}
 
-    reqwest::Url::parse_with_params(&request_url, parameters).unwrap()
+/// Sync `window` once, exiting with the code for the worst failure, if any.
+async fn load_once(
+    targets: &[Target],
+    loader: &Loader,
+    window: Window,
+    energy_type: EnergyType,
+    request_type: RequestType,
+) {
+    let (deferred, failure) = sync_all(targets, loader, window, energy_type, request_type).await;
+    for (target, (start, end)) in deferred {
+        warn!(
+            "n3rgy was still retrieving {} data for {} to {}, re-run this window later",
+            target.describe(),
+            start,
+            end
+        );
+    }
+    if let Some(code) = failure {
+        let what = format!("{} {} load", energy_type, request_type).to_lowercase();
+        notify::failure(&what, code).await;
+        code.exit();
+    }
 }
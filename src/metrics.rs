@@ -0,0 +1,98 @@
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+
+use axum::routing::get;
+use axum::Router;
+use log::{error, info};
+use prometheus::{
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, Encoder, IntCounterVec,
+    IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use crate::daemon;
+use crate::health::Health;
+use crate::shutdown;
+
+pub static API_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "n3rgy_api_requests_total",
+        "Requests made to the n3rgy API",
+        &["energy_type", "request_type"]
+    )
+    .unwrap()
+});
+
+pub static API_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "n3rgy_api_failures_total",
+        "Requests to the n3rgy API that failed or returned an error response",
+        &["energy_type", "request_type"]
+    )
+    .unwrap()
+});
+
+pub static POINTS_WRITTEN: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "n3rgy_points_written_total",
+        "Points successfully written, per sink",
+        &["sink"]
+    )
+    .unwrap()
+});
+
+pub static LAST_SUCCESSFUL_SYNC: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "n3rgy_last_successful_sync_timestamp_seconds",
+        "Unix timestamp of the last sync that completed without errors"
+    )
+    .unwrap()
+});
+
+pub static CONSENT_EXPIRY: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "n3rgy_consent_expiry_timestamp_seconds",
+        "Unix timestamp at which the data consent for each property lapses",
+        &["property"]
+    )
+    .unwrap()
+});
+
+/// Serve `/metrics`, alongside the `/healthz` and `/readyz` probes and the
+/// daemon's `/status` and `/sync`.
+pub async fn serve(addr: SocketAddr, health: Health) {
+    // Register every metric up front so the first scrape has the full set.
+    LazyLock::force(&API_REQUESTS);
+    LazyLock::force(&API_FAILURES);
+    LazyLock::force(&POINTS_WRITTEN);
+    LazyLock::force(&LAST_SUCCESSFUL_SYNC);
+
+    let app = Router::new()
+        .route("/metrics", get(render))
+        .merge(health.router())
+        .merge(daemon::router());
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("could not bind metrics endpoint to {}: {}", addr, e);
+            return;
+        }
+    };
+    info!(
+        "serving metrics on http://{}/metrics, probes on /healthz and /readyz",
+        addr
+    );
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown::wait())
+        .await
+    {
+        error!("metrics endpoint stopped: {}", e);
+    }
+}
+
+async fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use chrono::{DateTime, Duration, Utc};
+use influxdb::ReadQuery;
+use log::info;
+use n3rgy_rs::models::Resource;
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+
+use crate::line_protocol::Point;
+use crate::sink::Sink;
+
+/// Tag older releases wrote the n3rgy resource path to, before it was split
+/// into `fuel`, `mpxn`, `element` and `type`.
+pub const LEGACY_RESOURCE_TAG: &str = "measurement";
+
+/// Copy `measurements` from an InfluxDB 1.x database to `sink`, e.g. an
+/// InfluxDB 2.x bucket, `chunk` of time at a time. With `new_tag_schema`,
+/// points still carrying the legacy resource path tag are tagged the way
+/// current releases write them. Returns how many points were copied.
+pub async fn run(
+    source: &influxdb::Client,
+    measurements: &[String],
+    sink: &Sink,
+    new_tag_schema: bool,
+    chunk: Duration,
+) -> Result<usize, Box<dyn Error>> {
+    let mut copied = 0;
+    for measurement in measurements {
+        let Some(mut chunks) = Chunks::new(source, measurement, chunk).await? else {
+            info!("{} is empty", measurement);
+            continue;
+        };
+        while let Some(mut points) = chunks.next().await? {
+            if new_tag_schema {
+                for point in &mut points {
+                    retag(&mut point.tags);
+                }
+            }
+            copied += points.len();
+            sink.write(points.into_iter().map(Point::into_query).collect())
+                .await?;
+        }
+    }
+    Ok(copied)
+}
+
+/// Reads every point of a measurement back, a chunk of time at a time.
+pub struct Chunks<'a> {
+    source: &'a influxdb::Client,
+    measurement: &'a str,
+    /// The type InfluxDB stores each field as, so floats that happen to be
+    /// whole aren't read back as integers.
+    types: BTreeMap<String, String>,
+    next: DateTime<Utc>,
+    last: DateTime<Utc>,
+    chunk: Duration,
+}
+
+impl<'a> Chunks<'a> {
+    /// `None` when the measurement holds no points.
+    pub async fn new(
+        source: &'a influxdb::Client,
+        measurement: &'a str,
+        chunk: Duration,
+    ) -> Result<Option<Chunks<'a>>, Box<dyn Error>> {
+        let Some((first, last)) = time_range(source, measurement).await? else {
+            return Ok(None);
+        };
+        Ok(Some(Chunks {
+            source,
+            measurement,
+            types: field_types(source, measurement).await?,
+            next: first,
+            last,
+            chunk,
+        }))
+    }
+
+    /// The points of the next chunk, `None` once past the newest.
+    pub async fn next(&mut self) -> Result<Option<Vec<Point>>, Box<dyn Error>> {
+        if self.next > self.last {
+            return Ok(None);
+        }
+        let (start, end) = (self.next, self.next + self.chunk);
+        self.next = end;
+
+        let query = format!(
+            "SELECT * FROM \"{}\" WHERE time >= '{}' AND time < '{}' GROUP BY *",
+            self.measurement,
+            start.to_rfc3339(),
+            end.to_rfc3339()
+        );
+        let mut result = self.source.json_query(ReadQuery::new(query)).await?;
+        let rows =
+            result.deserialize_next_tagged::<BTreeMap<String, String>, Map<String, Value>>()?;
+
+        let mut points = Vec::new();
+        for series in rows.series {
+            for row in series.values {
+                points.push(self.point(series.tags.clone(), row)?);
+            }
+        }
+        info!(
+            "read {} {} points from {} to {}",
+            points.len(),
+            self.measurement,
+            start,
+            end
+        );
+        Ok(Some(points))
+    }
+
+    fn point(
+        &self,
+        tags: BTreeMap<String, String>,
+        mut row: Map<String, Value>,
+    ) -> Result<Point, Box<dyn Error>> {
+        let time = row.remove("time").ok_or("a row has no time")?;
+        let time: DateTime<Utc> = serde_json::from_value(time)?;
+        let fields = row
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(field, value)| {
+                let value = match (self.types.get(&field).map(String::as_str), &value) {
+                    (Some("float"), Value::Number(number)) => number
+                        .as_f64()
+                        .and_then(Number::from_f64)
+                        .map_or(value, Value::Number),
+                    _ => value,
+                };
+                (field, value)
+            })
+            .collect();
+        Ok(Point {
+            measurement: self.measurement.to_string(),
+            tags,
+            fields,
+            time,
+        })
+    }
+}
+
+async fn field_types(
+    source: &influxdb::Client,
+    measurement: &str,
+) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct FieldKey {
+        #[serde(rename = "fieldKey")]
+        key: String,
+        #[serde(rename = "fieldType")]
+        field_type: String,
+    }
+    let query = format!("SHOW FIELD KEYS FROM \"{}\"", measurement);
+    let mut result = source.json_query(ReadQuery::new(query)).await?;
+    Ok(result
+        .deserialize_next::<FieldKey>()?
+        .series
+        .into_iter()
+        .flat_map(|series| series.values)
+        .map(|field| (field.key, field.field_type))
+        .collect())
+}
+
+/// Times of the oldest and newest points in `measurement`.
+async fn time_range(
+    source: &influxdb::Client,
+    measurement: &str,
+) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct Row {
+        time: DateTime<Utc>,
+    }
+    let mut times = Vec::new();
+    for order in ["ASC", "DESC"] {
+        let query = format!(
+            "SELECT * FROM \"{}\" ORDER BY time {} LIMIT 1",
+            measurement, order
+        );
+        let mut result = source.json_query(ReadQuery::new(query)).await?;
+        times.extend(
+            result
+                .deserialize_next::<Row>()?
+                .series
+                .into_iter()
+                .flat_map(|series| series.values)
+                .map(|row| row.time),
+        );
+    }
+    Ok(times.iter().min().copied().zip(times.iter().max().copied()))
+}
+
+/// Swap the legacy resource path tag for the tags parsed from it, keeping any
+/// already set. Returns whether the tags changed.
+pub fn retag(tags: &mut BTreeMap<String, String>) -> bool {
+    let Some(resource) = tags.remove(LEGACY_RESOURCE_TAG) else {
+        return false;
+    };
+    let resource = Resource::parse(&resource);
+    let parsed = [
+        ("fuel", resource.fuel),
+        ("mpxn", resource.mpxn),
+        ("element", resource.element),
+        ("type", resource.data_type),
+    ];
+    for (tag, value) in parsed {
+        if let Some(value) = value {
+            tags.entry(tag.to_string()).or_insert(value);
+        }
+    }
+    true
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Duration, DurationRound, NaiveDateTime, Timelike, Utc};
+use log::info;
+use serde_json::{json, Value};
+
+use crate::cli::MockServerArgs;
+use crate::shutdown;
+
+const FUELS: [&str; 2] = ["electricity", "gas"];
+const DATA_TYPES: [&str; 2] = ["consumption", "tariff"];
+const RANGE_FORMAT: &str = "%Y%m%d%H%M";
+const READING_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// n3rgy's error payload and the status it comes with.
+type ApiError = (StatusCode, Json<Value>);
+type Response = Result<Json<Value>, ApiError>;
+
+/// Serve canned data in the shapes the n3rgy consumer API uses, for exercising
+/// fetch, transform and sink end to end without a consent token.
+///
+/// Any non-empty `Authorization` header is accepted. Data is generated for the
+/// last `history_days` days, ending at the most recent half hour, with tariff
+/// prices running on to the end of tomorrow.
+pub async fn run(args: MockServerArgs) -> Result<(), String> {
+    let app = Router::new()
+        .route("/", get(fuels))
+        .route("/{fuel}", get(data_types))
+        .route("/{fuel}/{data_type}", get(elements))
+        .route("/{fuel}/{data_type}/{element}", get(element))
+        .with_state(args.history_days);
+    let listener = tokio::net::TcpListener::bind(args.addr)
+        .await
+        .map_err(|e| format!("could not bind {}: {}", args.addr, e))?;
+    info!(
+        "serving mock n3rgy API on http://{}/, point --base-url at it",
+        args.addr
+    );
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown::wait())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn fuels(headers: HeaderMap) -> Response {
+    authorised(&headers)?;
+    ok(json!({ "resource": "/", "entries": FUELS }))
+}
+
+async fn data_types(headers: HeaderMap, Path(fuel): Path<String>) -> Response {
+    authorised(&headers)?;
+    known(&fuel, &FUELS)?;
+    ok(json!({ "resource": format!("/{}", fuel), "entries": DATA_TYPES }))
+}
+
+async fn elements(headers: HeaderMap, Path((fuel, data_type)): Path<(String, String)>) -> Response {
+    authorised(&headers)?;
+    known(&fuel, &FUELS)?;
+    known(&data_type, &DATA_TYPES)?;
+    ok(json!({ "resource": format!("/{}/{}", fuel, data_type), "entries": ["1"] }))
+}
+
+async fn element(
+    headers: HeaderMap,
+    State(history_days): State<i64>,
+    Path((fuel, data_type, element)): Path<(String, String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    authorised(&headers)?;
+    known(&fuel, &FUELS)?;
+    known(&data_type, &DATA_TYPES)?;
+    known(&element, &["1"])?;
+    let resource = format!("/{}/{}/{}", fuel, data_type, element);
+
+    let available_end = Utc::now().duration_trunc(Duration::minutes(30)).unwrap();
+    let available_start = available_end - Duration::days(history_days);
+    let (Some(start), Some(end)) = (params.get("start"), params.get("end")) else {
+        return ok(json!({
+            "resource": resource,
+            "availableCacheRange": {
+                "start": available_start.format(RANGE_FORMAT).to_string(),
+                "end": available_end.format(RANGE_FORMAT).to_string(),
+            },
+        }));
+    };
+    let (start, end) = (parse_range(start)?, parse_range(end)?);
+    if end <= start {
+        return Err(error(StatusCode::BAD_REQUEST, "end must be after start"));
+    }
+    if end - start > Duration::days(n3rgy_rs::client::MAX_WINDOW_DAYS) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "the requested range exceeds the maximum of 90 days",
+        ));
+    }
+    let from = start.max(available_start);
+    let to = end.min(available_end);
+    let gas = fuel == "gas";
+
+    let mut body = json!({
+        "resource": resource,
+        "responseTimestamp": Utc::now().to_rfc3339(),
+        "start": start.format(RANGE_FORMAT).to_string(),
+        "end": end.format(RANGE_FORMAT).to_string(),
+    });
+    if data_type == "consumption" {
+        let daily = params.get("granularity").map(String::as_str) == Some("day");
+        body["granularity"] = json!(if daily { "day" } else { "halfhour" });
+        body["unit"] = json!(if gas { "m3" } else { "kWh" });
+        body["values"] = json!(consumption(from, to, gas, daily));
+    } else {
+        // suppliers publish prices ahead, so serve them to the end of tomorrow
+        let published =
+            available_end.duration_trunc(Duration::days(1)).unwrap() + Duration::days(2);
+        body["values"] = json!([tariff(from, end.min(published), gas)]);
+    }
+    ok(body)
+}
+
+/// Readings stamped at the end of each interval between `from` and `to`
+/// inclusive, following a daily curve that peaks in the early evening.
+fn consumption(from: DateTime<Utc>, to: DateTime<Utc>, gas: bool, daily: bool) -> Vec<Value> {
+    let half_hour = |time: DateTime<Utc>| {
+        let hour = time.hour() as f64 + time.minute() as f64 / 60.0;
+        let curve = 0.5 - 0.5 * ((hour - 6.0) / 24.0 * 2.0 * PI).cos();
+        let kwh = 0.1 + 0.4 * curve;
+        if gas {
+            kwh / 11.2
+        } else {
+            kwh
+        }
+    };
+    let round = |value: f64| (value * 1000.0).round() / 1000.0;
+
+    let mut values = Vec::new();
+    let step = Duration::minutes(30);
+    if daily {
+        let mut day = from.duration_round_up(Duration::days(1)).unwrap();
+        while day <= to {
+            // each day totals the half hours ending after its start and up to its end
+            let mut time = day - Duration::days(1) + step;
+            let mut total = 0.0;
+            while time <= day {
+                total += half_hour(time);
+                time += step;
+            }
+            values.push(reading(day, round(total)));
+            day += Duration::days(1);
+        }
+    } else {
+        let mut time = from.duration_round_up(step).unwrap();
+        while time <= to {
+            values.push(reading(time, round(half_hour(time))));
+            time += step;
+        }
+    }
+    values
+}
+
+fn reading(time: DateTime<Utc>, value: f64) -> Value {
+    json!({
+        "timestamp": time.format(READING_FORMAT).to_string(),
+        "value": value,
+        "status": "valid",
+    })
+}
+
+/// A flat unit rate with a 16:00-19:00 peak for electricity, and a daily
+/// standing charge.
+fn tariff(from: DateTime<Utc>, to: DateTime<Utc>, gas: bool) -> Value {
+    let step = Duration::minutes(30);
+    let mut prices = Vec::new();
+    let mut time = from.duration_round_up(step).unwrap();
+    while time <= to {
+        let price = match (gas, time.hour()) {
+            (true, _) => 0.0632,
+            (false, 16..=18) => 0.3512,
+            (false, _) => 0.2462,
+        };
+        prices.push(json!({
+            "timestamp": time.format(READING_FORMAT).to_string(),
+            "value": price,
+        }));
+        time += step;
+    }
+
+    let mut standing_charges = Vec::new();
+    let mut day = from.date_naive();
+    while day <= to.date_naive() {
+        standing_charges.push(json!({
+            "startDate": day.to_string(),
+            "value": if gas { 0.3143 } else { 0.6036 },
+        }));
+        day = day.succ_opt().unwrap();
+    }
+    json!({ "standingCharges": standing_charges, "prices": prices })
+}
+
+fn authorised(headers: &HeaderMap) -> Result<(), ApiError> {
+    match headers.get("authorization") {
+        Some(token) if !token.is_empty() => Ok(()),
+        _ => Err(error(
+            StatusCode::UNAUTHORIZED,
+            "missing Authorization header",
+        )),
+    }
+}
+
+fn known(value: &str, options: &[&str]) -> Result<(), ApiError> {
+    if options.contains(&value) {
+        Ok(())
+    } else {
+        Err(error(
+            StatusCode::NOT_FOUND,
+            &format!("no such resource: {}", value),
+        ))
+    }
+}
+
+fn parse_range(value: &str) -> Result<DateTime<Utc>, ApiError> {
+    NaiveDateTime::parse_from_str(value, RANGE_FORMAT)
+        .map(|dt| dt.and_utc())
+        .map_err(|_| {
+            error(
+                StatusCode::BAD_REQUEST,
+                &format!("{} is not a YYYYMMDDHHMM date", value),
+            )
+        })
+}
+
+fn ok(body: Value) -> Response {
+    Ok(Json(body))
+}
+
+fn error(status: StatusCode, message: &str) -> ApiError {
+    (
+        status,
+        Json(json!({ "errors": [{ "code": status.as_u16(), "message": message }] })),
+    )
+}
@@ -2,11 +2,19 @@ use chrono::DateTime;
 use chrono::NaiveDate;
 use chrono::Utc;
 use clap::ValueEnum;
-use influxdb::InfluxDbWriteable;
-use log::error;
+use influxdb::{InfluxDbWriteable, WriteQuery};
 use serde::Deserialize;
 use std::fmt;
 
+use crate::settlement::uk_midnight;
+
+/// `price_type` tag of per-kWh unit rates.
+pub const PRICE: &str = "Price";
+/// `price_type` tag of daily standing charges.
+pub const STANDING_CHARGE: &str = "StandingCharge";
+/// `status` tag of readings n3rgy returned without a status.
+pub const UNKNOWN_STATUS: &str = "unknown";
+
 mod n3rgy_date_format {
     use chrono::{DateTime, NaiveDateTime, Utc};
     use serde::{Deserialize, Deserializer};
@@ -23,7 +31,23 @@ mod n3rgy_date_format {
     }
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+mod n3rgy_range_format {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use serde::{Deserialize, Deserializer};
+
+    const FORMAT: &str = "%Y%m%d%H%M";
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let dt = NaiveDateTime::parse_from_str(&s, FORMAT).map_err(serde::de::Error::custom)?;
+        Ok(DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
 pub enum EnergyType {
     Electricity,
     Gas,
@@ -35,7 +59,7 @@ impl fmt::Display for EnergyType {
     }
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
 pub enum RequestType {
     Consumption,
     Tariff,
@@ -46,144 +70,402 @@ impl fmt::Display for RequestType {
         fmt::Debug::fmt(self, f)
     }
 }
+
+/// Currency unit tariff prices are stored in. n3rgy quotes them in pence.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PriceUnit {
+    #[default]
+    Pence,
+    Pounds,
+}
+
+/// How prices from n3rgy are converted to match the way the supplier bills them.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Pricing {
+    pub unit: PriceUnit,
+    /// VAT to add, as a percentage, e.g. 5.
+    pub vat: f64,
+}
+
+impl Pricing {
+    pub fn apply(&self, price: f64) -> f64 {
+        let price = price * (1.0 + self.vat / 100.0);
+        match self.unit {
+            PriceUnit::Pence => price,
+            PriceUnit::Pounds => price / 100.0,
+        }
+    }
+}
+
+/// Interval of consumption readings requested from n3rgy.
+#[derive(Copy, Clone, Debug, Default, ValueEnum)]
+pub enum Granularity {
+    #[default]
+    HalfHour,
+    Day,
+}
+
+impl Granularity {
+    /// The value of the API's `granularity` query parameter.
+    pub fn as_param(&self) -> &'static str {
+        match self {
+            Granularity::HalfHour => "halfhour",
+            Granularity::Day => "day",
+        }
+    }
+
+    /// The time between consecutive readings.
+    pub fn interval(&self) -> chrono::Duration {
+        match self {
+            Granularity::HalfHour => chrono::Duration::minutes(30),
+            Granularity::Day => chrono::Duration::days(1),
+        }
+    }
+}
+
+/// The child resources listed under an API path, e.g. the fuels under `/`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entries {
+    pub resource: String,
+    pub entries: Vec<String>,
+}
+
+/// The response for a meter element requested without a date range.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ElementInfo {
+    pub resource: String,
+    pub available_cache_range: Option<AvailableCacheRange>,
+}
+
+/// The span of data n3rgy currently holds for an element.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct AvailableCacheRange {
+    #[serde(with = "n3rgy_range_format")]
+    pub start: DateTime<Utc>,
+    #[serde(with = "n3rgy_range_format")]
+    pub end: DateTime<Utc>,
+}
+
+/// A fuel, data type and meter element readable with the token.
+#[derive(Clone, Debug)]
+pub struct DataSource {
+    pub fuel: String,
+    pub data_type: String,
+    pub element: String,
+    pub range: Option<AvailableCacheRange>,
+}
+
+/// The parts of an n3rgy resource path such as `/electricity/consumption/1`,
+/// which may be prefixed with the meter's MPAN/MPRN.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Resource {
+    pub mpxn: Option<String>,
+    pub fuel: Option<String>,
+    pub data_type: Option<String>,
+    pub element: Option<String>,
+}
+
+impl Resource {
+    pub fn parse(resource: &str) -> Resource {
+        let mut segments = resource.split('/').filter(|s| !s.is_empty()).peekable();
+        let mpxn = segments
+            .next_if(|s| s.chars().all(|c| c.is_ascii_digit()))
+            .map(str::to_string);
+        Resource {
+            mpxn,
+            fuel: segments.next().map(str::to_string),
+            data_type: segments.next().map(str::to_string),
+            element: segments.next().map(str::to_string),
+        }
+    }
+
+    /// Tag a point with `fuel`, `mpxn`, `element` and `type`, skipping any the
+    /// resource path didn't include.
+    pub fn add_tags(&self, mut query: WriteQuery) -> WriteQuery {
+        let tags = [
+            ("fuel", &self.fuel),
+            ("mpxn", &self.mpxn),
+            ("element", &self.element),
+            ("type", &self.data_type),
+        ];
+        for (tag, value) in tags {
+            if let Some(value) = value {
+                query = query.add_tag(tag, value.clone());
+            }
+        }
+        query
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(untagged)]
 pub enum ConsumptionOrTariff {
     Consumption(Consumption),
     Tariff(Tariff),
+}
+
+impl ConsumptionOrTariff {
+    /// Keep only readings and unit rates stamped within `start..=end`, for a
+    /// response covering a wider range. Standing charges are kept, as each
+    /// applies from its start date on.
+    pub(crate) fn retain_within(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) {
+        let within = |time: &DateTime<Utc>| (start..=end).contains(time);
+        match self {
+            ConsumptionOrTariff::Consumption(consumption) => {
+                consumption.values.retain(|value| within(&value.timestamp));
+            }
+            ConsumptionOrTariff::Tariff(tariff) => {
+                for values in &mut tariff.values {
+                    values.prices.retain(|price| within(&price.timestamp));
+                }
+            }
+        }
+    }
+
+    /// Drop readings, unit rates and standing charges stamped at or before
+    /// `seam`, the last time an earlier window returned, as repeats of what it
+    /// already loaded. Returns how many were dropped.
+    pub fn retain_after(&mut self, seam: DateTime<Utc>) -> usize {
+        let mut dropped = 0;
+        let mut tally = |count: usize, kept: usize| dropped += count - kept;
+        match self {
+            ConsumptionOrTariff::Consumption(consumption) => {
+                let count = consumption.values.len();
+                consumption.values.retain(|value| value.timestamp > seam);
+                tally(count, consumption.values.len());
+            }
+            ConsumptionOrTariff::Tariff(tariff) => {
+                for values in &mut tariff.values {
+                    let count = values.prices.len() + values.standing_charges.len();
+                    values.prices.retain(|price| price.timestamp > seam);
+                    values.standing_charges.retain(|stdcharge| {
+                        stdcharge.start_date.and_hms_opt(0, 0, 0).unwrap().and_utc() > seam
+                    });
+                    tally(count, values.prices.len() + values.standing_charges.len());
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Latest time a reading or unit rate is stamped at.
+    pub fn last_time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            ConsumptionOrTariff::Consumption(consumption) => {
+                consumption.values.iter().map(|value| value.timestamp).max()
+            }
+            ConsumptionOrTariff::Tariff(tariff) => tariff
+                .values
+                .iter()
+                .flat_map(|values| &values.prices)
+                .map(|price| price.timestamp)
+                .max(),
+        }
+    }
+}
+
+/// A data response, or the error payload n3rgy sends in its place.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Response {
+    Data(ConsumptionOrTariff),
     Error(ErrorResponse),
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Consumption {
-    resource: String,
-    response_timestamp: String,
-    start: String,
-    end: String,
-    granularity: String,
-    values: Vec<Value>,
-    message: Option<String>,
-    unit: String,
+    pub resource: String,
+    pub response_timestamp: String,
+    pub start: String,
+    pub end: String,
+    pub granularity: String,
+    pub values: Vec<Value>,
+    pub message: Option<String>,
+    pub unit: String,
 }
 
 impl Consumption {
-    pub fn influx_format(&self) -> Vec<ConsumptionReading> {
-        let mut readings = Vec::new();
-        let values = self.values.clone();
-        for value in values {
-            readings.push(
-                ConsumptionReading::new()
-                    .consumption(value.value)
-                    .time(value.timestamp)
-                    .measurement(self.resource.clone())
-                    .build(),
-            );
-        }
-        readings
+    /// A reading per value, built as it is iterated rather than all at once.
+    pub fn readings(&self) -> impl Iterator<Item = ConsumptionReading> + '_ {
+        self.values
+            .iter()
+            .map(|value| self.reading(value.timestamp, value.value, value.status.clone()))
+    }
+
+    /// Like [`Consumption::readings`], reusing each value's status instead of
+    /// copying it.
+    pub fn into_readings(mut self) -> impl Iterator<Item = ConsumptionReading> {
+        let values = std::mem::take(&mut self.values);
+        values
+            .into_iter()
+            .map(move |value| self.reading(value.timestamp, value.value, value.status))
+    }
+
+    fn reading(
+        &self,
+        time: DateTime<Utc>,
+        consumption: f64,
+        status: Option<String>,
+    ) -> ConsumptionReading {
+        ConsumptionReading::new()
+            .consumption(consumption)
+            .time(time)
+            .status(status.unwrap_or_else(|| UNKNOWN_STATUS.to_string()))
+            .unit(self.unit.clone())
+            .granularity(self.granularity.clone())
+            .resource(self.resource.clone())
+            .build()
     }
 }
 
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Value {
+pub struct Value {
     #[serde(with = "n3rgy_date_format")]
-    timestamp: DateTime<Utc>,
-    value: f64,
-    status: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+    pub status: Option<String>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Tariff {
-    resource: String,
-    response_timestamp: String,
-    start: String,
-    end: String,
-    values: Vec<TariffValues>,
+    pub resource: String,
+    pub response_timestamp: String,
+    pub start: String,
+    pub end: String,
+    pub values: Vec<TariffValues>,
+    /// Unit the prices are in, once [`Tariff::apply_pricing`] has converted them.
+    #[serde(skip)]
+    pub unit: PriceUnit,
 }
 
 impl Tariff {
-    pub fn influx_format(&self) -> Vec<TariffPrice> {
-        let mut readings = Vec::new();
-        let values = self.values.clone();
-        let resource = self.resource.clone();
-        for value in values {
-            for price in value.prices {
-                readings.push(
-                    TariffPrice::new()
-                        .price(price.value)
-                        .time(price.timestamp)
-                        .price_type("Price".to_string())
-                        .measurement(resource.clone())
-                        .build(),
-                );
+    /// Convert every unit rate and standing charge with `pricing`.
+    pub fn apply_pricing(&mut self, pricing: Pricing) {
+        for value in &mut self.values {
+            for price in &mut value.prices {
+                price.value = pricing.apply(price.value);
             }
-            for stdcharge in value.standing_charges {
-                let start_time = stdcharge.start_date.and_hms_opt(0, 0, 0).unwrap().and_utc();
-                readings.push(
-                    TariffPrice::new()
-                        .price(stdcharge.value)
-                        .time(start_time)
-                        .price_type("StandingCharge".to_string())
-                        .measurement(resource.clone())
-                        .build(),
-                )
+            for stdcharge in &mut value.standing_charges {
+                stdcharge.value = pricing.apply(stdcharge.value);
             }
         }
+        self.unit = pricing.unit;
+    }
+
+    /// Unit prices followed by standing charges, built as they are iterated
+    /// rather than all at once.
+    pub fn prices(&self) -> impl Iterator<Item = TariffPrice> + '_ {
+        self.values.iter().flat_map(move |value| {
+            let prices = value
+                .prices
+                .iter()
+                .map(move |price| self.price(price.timestamp, price.value, PRICE));
+            let standing_charges = value.standing_charges.iter().map(move |stdcharge| {
+                let start_time = stdcharge.start_date.and_hms_opt(0, 0, 0).unwrap().and_utc();
+                self.price(start_time, stdcharge.value, STANDING_CHARGE)
+            });
+            prices.chain(standing_charges)
+        })
+    }
+
+    /// Each day's standing charge, stamped at UK midnight.
+    pub fn daily_standing_charges(&self) -> impl Iterator<Item = DailyStandingCharge> + '_ {
+        self.values
+            .iter()
+            .flat_map(|value| &value.standing_charges)
+            .map(|stdcharge| DailyStandingCharge {
+                time: uk_midnight(stdcharge.start_date),
+                per_day: stdcharge.value,
+                unit: self.unit,
+                resource: self.resource.clone(),
+            })
+    }
 
-        readings
+    fn price(&self, time: DateTime<Utc>, price: f64, price_type: &str) -> TariffPrice {
+        TariffPrice::new()
+            .price(price)
+            .time(time)
+            .price_type(price_type.to_string())
+            .resource(self.resource.clone())
+            .build()
     }
 }
 
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct TariffValues {
-    standing_charges: Vec<StandingCharge>,
-    prices: Vec<Price>,
+pub struct TariffValues {
+    pub standing_charges: Vec<StandingCharge>,
+    pub prices: Vec<Price>,
 }
 
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct StandingCharge {
-    start_date: NaiveDate,
-    value: f64,
+pub struct StandingCharge {
+    pub start_date: NaiveDate,
+    pub value: f64,
 }
 
 #[derive(Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Price {
+pub struct Price {
     #[serde(with = "n3rgy_date_format")]
-    timestamp: DateTime<Utc>,
-    value: f64,
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
 }
+
+/// n3rgy's error payload, either a list of errors or a single bare error.
 #[derive(Clone, Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ErrorResponse {
-    errors: Vec<Error>,
+#[serde(untagged)]
+pub enum ErrorResponse {
+    List { errors: Vec<ApiError> },
+    Single(ApiError),
+}
+
+impl ErrorResponse {
+    pub fn into_errors(self) -> Vec<ApiError> {
+        match self {
+            ErrorResponse::List { errors } => errors,
+            ErrorResponse::Single(error) => vec![error],
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct Error {
-    code: u16,
-    message: String,
+pub struct ApiError {
+    #[serde(alias = "errorCode")]
+    pub code: u16,
+    pub message: String,
 }
 
-impl ErrorResponse {
-    pub fn log_out(self) -> Vec<Error> {
-        error!("n3rgy returned an error response: {:?}", self.errors);
-        Vec::new()
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
     }
 }
 
 #[derive(InfluxDbWriteable, Clone, Default)]
 pub struct ConsumptionReading {
-    time: DateTime<Utc>,
-    consumption: f64,
+    pub time: DateTime<Utc>,
+    pub consumption: f64,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    #[influxdb(ignore)]
+    pub resource: String,
+    /// Whether the reading is actual or estimated, as reported by n3rgy.
+    #[influxdb(tag)]
+    pub status: String,
+    /// Unit of `consumption`, e.g. `kWh` or `m3`.
+    #[influxdb(tag)]
+    pub unit: String,
+    /// Interval the reading covers, e.g. `halfhour` or `day`.
     #[influxdb(tag)]
-    measurement: String,
+    pub granularity: String,
 }
 
 impl ConsumptionReading {
@@ -191,7 +473,10 @@ impl ConsumptionReading {
         ConsumptionReading {
             time: Utc::now(),
             consumption: 0.0,
-            measurement: "default".to_string(),
+            resource: "default".to_string(),
+            status: UNKNOWN_STATUS.to_string(),
+            unit: "default".to_string(),
+            granularity: "default".to_string(),
         }
     }
 
@@ -205,8 +490,23 @@ impl ConsumptionReading {
         self
     }
 
-    fn measurement(&mut self, measurement: String) -> &mut ConsumptionReading {
-        self.measurement = measurement;
+    fn resource(&mut self, resource: String) -> &mut ConsumptionReading {
+        self.resource = resource;
+        self
+    }
+
+    fn status(&mut self, status: String) -> &mut ConsumptionReading {
+        self.status = status;
+        self
+    }
+
+    fn unit(&mut self, unit: String) -> &mut ConsumptionReading {
+        self.unit = unit;
+        self
+    }
+
+    fn granularity(&mut self, granularity: String) -> &mut ConsumptionReading {
+        self.granularity = granularity;
         self
     }
 
@@ -214,19 +514,45 @@ impl ConsumptionReading {
         ConsumptionReading {
             time: self.time,
             consumption: self.consumption,
-            measurement: self.measurement.clone(),
+            resource: self.resource.clone(),
+            status: self.status.clone(),
+            unit: self.unit.clone(),
+            granularity: self.granularity.clone(),
         }
     }
 }
 
 #[derive(InfluxDbWriteable, Clone, Default)]
 pub struct TariffPrice {
-    time: DateTime<Utc>,
-    price: f64,
-    #[influxdb(tag)]
-    measurement: String,
+    pub time: DateTime<Utc>,
+    pub price: f64,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    #[influxdb(ignore)]
+    pub resource: String,
     #[influxdb(tag)]
-    price_type: String,
+    pub price_type: String,
+}
+
+/// A day's standing charge as a point of its own, so daily cost doesn't need
+/// picking out of the price series.
+#[derive(Clone)]
+pub struct DailyStandingCharge {
+    pub time: DateTime<Utc>,
+    /// Written as `pence_per_day` or `pounds_per_day`, after `unit`.
+    pub per_day: f64,
+    pub unit: PriceUnit,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    pub resource: String,
+}
+
+impl InfluxDbWriteable for DailyStandingCharge {
+    fn into_query<I: Into<String>>(self, name: I) -> WriteQuery {
+        let field = match self.unit {
+            PriceUnit::Pence => "pence_per_day",
+            PriceUnit::Pounds => "pounds_per_day",
+        };
+        WriteQuery::new(self.time, name).add_field(field, self.per_day)
+    }
 }
 
 impl TariffPrice {
@@ -234,7 +560,7 @@ impl TariffPrice {
         TariffPrice {
             time: Utc::now(),
             price: 0.0,
-            measurement: "default".to_string(),
+            resource: "default".to_string(),
             price_type: "default".to_string(),
         }
     }
@@ -249,8 +575,8 @@ impl TariffPrice {
         self
     }
 
-    fn measurement(&mut self, measurement: String) -> &mut TariffPrice {
-        self.measurement = measurement;
+    fn resource(&mut self, resource: String) -> &mut TariffPrice {
+        self.resource = resource;
         self
     }
 
@@ -263,8 +589,97 @@ impl TariffPrice {
         TariffPrice {
             time: self.time,
             price: self.price,
-            measurement: self.measurement.clone(),
+            resource: self.resource.clone(),
             price_type: self.price_type.clone(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn consumption(hours: &[u32]) -> ConsumptionOrTariff {
+        ConsumptionOrTariff::Consumption(Consumption {
+            resource: "/electricity/consumption/1".to_string(),
+            response_timestamp: String::new(),
+            start: String::new(),
+            end: String::new(),
+            granularity: "halfhour".to_string(),
+            values: hours
+                .iter()
+                .map(|hour| Value {
+                    timestamp: Utc.with_ymd_and_hms(2024, 6, 1, *hour, 0, 0).unwrap(),
+                    value: 0.5,
+                    status: None,
+                })
+                .collect(),
+            message: None,
+            unit: "kWh".to_string(),
+        })
+    }
+
+    fn tariff(hours: &[u32], days: &[u32]) -> ConsumptionOrTariff {
+        ConsumptionOrTariff::Tariff(Tariff {
+            resource: "/electricity/tariff/1".to_string(),
+            response_timestamp: String::new(),
+            start: String::new(),
+            end: String::new(),
+            values: vec![TariffValues {
+                standing_charges: days
+                    .iter()
+                    .map(|day| StandingCharge {
+                        start_date: NaiveDate::from_ymd_opt(2024, 6, *day).unwrap(),
+                        value: 50.0,
+                    })
+                    .collect(),
+                prices: hours
+                    .iter()
+                    .map(|hour| Price {
+                        timestamp: Utc.with_ymd_and_hms(2024, 6, 1, *hour, 0, 0).unwrap(),
+                        value: 25.0,
+                    })
+                    .collect(),
+            }],
+            unit: PriceUnit::default(),
+        })
+    }
+
+    #[test]
+    fn retain_after_drops_readings_up_to_the_seam() {
+        let seam = Utc.with_ymd_and_hms(2024, 6, 1, 2, 0, 0).unwrap();
+        let mut response = consumption(&[1, 2, 3, 4]);
+        assert_eq!(response.retain_after(seam), 2);
+        assert_eq!(
+            response.last_time(),
+            Some(Utc.with_ymd_and_hms(2024, 6, 1, 4, 0, 0).unwrap())
+        );
+
+        let mut response = consumption(&[1, 2]);
+        assert_eq!(response.retain_after(seam), 2);
+        assert_eq!(response.last_time(), None);
+    }
+
+    #[test]
+    fn retain_after_drops_prices_and_standing_charges_up_to_the_seam() {
+        // the standing charge for 1 June is stamped at its midnight, before the seam
+        let seam = Utc.with_ymd_and_hms(2024, 6, 1, 2, 0, 0).unwrap();
+        let mut response = tariff(&[1, 2, 3], &[1, 2]);
+        assert_eq!(response.retain_after(seam), 3);
+        let ConsumptionOrTariff::Tariff(tariff) = &response else {
+            unreachable!();
+        };
+        assert_eq!(tariff.values[0].prices.len(), 1);
+        assert_eq!(
+            tariff.values[0].standing_charges[0].start_date,
+            NaiveDate::from_ymd_opt(2024, 6, 2).unwrap()
+        );
+        // standing charges don't count towards the last time
+        assert_eq!(
+            response.last_time(),
+            Some(Utc.with_ymd_and_hms(2024, 6, 1, 3, 0, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Utc};
+use influxdb::WriteQuery;
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, Transport};
+use serde::Deserialize;
+
+use crate::cli::MqttArgs;
+use crate::sink::Sink;
+
+/// Measurement live power readings are written to.
+pub const MEASUREMENT: &str = "power";
+
+/// How long to wait before reconnecting to a broker that dropped or refused
+/// the connection.
+const RECONNECT_DELAY: StdDuration = StdDuration::from_secs(10);
+
+/// The sink live readings are written to, replaced when the config is
+/// reloaded.
+static SINK: Mutex<Option<Arc<Sink>>> = Mutex::new(None);
+
+pub fn set_sink(sink: &Arc<Sink>) {
+    *SINK.lock().unwrap() = Some(sink.clone());
+}
+
+/// What a Glow CAD publishes on `glow/<device>/SENSOR/<meter>/state`, one
+/// meter per message.
+#[derive(Deserialize)]
+struct Message {
+    electricitymeter: Option<Meter>,
+    gasmeter: Option<Meter>,
+}
+
+#[derive(Deserialize)]
+struct Meter {
+    timestamp: Option<DateTime<Utc>>,
+    energy: Option<Energy>,
+    power: Option<Power>,
+}
+
+#[derive(Deserialize)]
+struct Energy {
+    import: Option<Import>,
+}
+
+#[derive(Deserialize)]
+struct Import {
+    /// Meter register, in kWh.
+    cumulative: Option<f64>,
+    mpan: Option<String>,
+    mprn: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Power {
+    value: f64,
+    units: Option<String>,
+}
+
+/// Subscribe to `args.mqtt_topic` for as long as the daemon runs, writing each
+/// Glow reading to the sink set by [`set_sink`]. Does nothing without a broker.
+pub fn spawn(args: MqttArgs) {
+    let Some(url) = args.mqtt_url else {
+        return;
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+    let (transport, default_port) = match url.scheme() {
+        "mqtts" => (Transport::tls_with_default_config(), 8883),
+        _ => (Transport::Tcp, 1883),
+    };
+    let client_id = format!("{}-{}", env!("CARGO_PKG_NAME"), std::process::id());
+    let mut options = MqttOptions::new(client_id, &host, url.port().unwrap_or(default_port));
+    options.set_transport(transport);
+    options.set_keep_alive(StdDuration::from_secs(30));
+    if let Some(username) = args.mqtt_username {
+        let password = args.mqtt_password.map(|p| p.expose().to_string());
+        options.set_credentials(username, password.unwrap_or_default());
+    }
+    tokio::spawn(listen(options, host, args.mqtt_topic));
+}
+
+async fn listen(options: MqttOptions, host: String, topic: String) {
+    let (client, mut events) = AsyncClient::new(options, 16);
+    loop {
+        match events.poll().await {
+            // subscriptions don't outlive a clean session, so renew them on
+            // every connect
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("subscribed to {} on {} for live readings", topic, host);
+                if let Err(e) = client.try_subscribe(&topic, QoS::AtMostOnce) {
+                    warn!("could not subscribe to {}: {}", topic, e);
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                let points = points(&publish.topic, &publish.payload);
+                if points.is_empty() {
+                    continue;
+                }
+                let sink = SINK.lock().unwrap().clone();
+                if let Some(sink) = sink {
+                    if let Err(e) = sink.write(points).await {
+                        warn!("could not write a live reading: {}", e);
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(
+                    "MQTT connection to {} failed, retrying in {}s: {}",
+                    host,
+                    RECONNECT_DELAY.as_secs(),
+                    e
+                );
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+/// The power and meter register in a Glow message, tagged with the fuel, the
+/// CAD it came through and the meter's MPAN or MPRN. Anything else published
+/// under the topic gives none.
+fn points(topic: &str, payload: &[u8]) -> Vec<WriteQuery> {
+    let message: Message = match serde_json::from_slice(payload) {
+        Ok(message) => message,
+        Err(e) => {
+            debug!("ignoring the message on {}: {}", topic, e);
+            return Vec::new();
+        }
+    };
+    // glow/<device>/...
+    let device = topic.split('/').nth(1).unwrap_or_default();
+    [
+        ("electricity", message.electricitymeter),
+        ("gas", message.gasmeter),
+    ]
+    .into_iter()
+    .filter_map(|(fuel, meter)| {
+        let meter = meter?;
+        let import = meter.energy.and_then(|energy| energy.import);
+        let power = meter.power.map(|power| match power.units.as_deref() {
+            Some("W") => power.value / 1000.0,
+            _ => power.value,
+        });
+        let cumulative = import.as_ref().and_then(|import| import.cumulative);
+        if power.is_none() && cumulative.is_none() {
+            return None;
+        }
+        let mut query = WriteQuery::new(meter.timestamp.unwrap_or_else(Utc::now), MEASUREMENT)
+            .add_tag("fuel", fuel)
+            .add_tag("device", device);
+        if let Some(mpxn) = import.and_then(|import| import.mpan.or(import.mprn)) {
+            query = query.add_tag("mpxn", mpxn);
+        }
+        if let Some(power) = power {
+            query = query.add_field("power", power);
+        }
+        if let Some(cumulative) = cumulative {
+            query = query.add_field("cumulative", cumulative);
+        }
+        Some(query)
+    })
+    .collect()
+}
@@ -0,0 +1,44 @@
+use std::sync::OnceLock;
+
+use serde_json::json;
+
+use crate::exit::Exit;
+use crate::webhook::Webhook;
+
+static WEBHOOK: OnceLock<Webhook> = OnceLock::new();
+
+/// Send failure notifications to `webhook` from now on.
+pub fn configure(webhook: Webhook) {
+    WEBHOOK.set(webhook).ok();
+}
+
+/// Tell the notification webhook, if one is configured, that `what` failed
+/// with `code`. Partial successes aren't worth waking anyone for.
+pub async fn failure(what: &str, code: Exit) {
+    if code == Exit::Partial {
+        return;
+    }
+    send(
+        &format!("n3rgy-rs {} failed: {}", what, code.describe()),
+        Some(code),
+    )
+    .await;
+}
+
+/// Tell the notification webhook, if one is configured, that loading works again.
+pub async fn recovered(what: &str) {
+    send(&format!("n3rgy-rs {} is working again", what), None).await;
+}
+
+async fn send(message: &str, code: Option<Exit>) {
+    let Some(webhook) = WEBHOOK.get() else {
+        return;
+    };
+    // Slack displays `text` and Discord `content`
+    let body = json!({
+        "text": message,
+        "content": message,
+        "exit_code": code.map(|code| code as i32),
+    });
+    webhook.post(&body).await;
+}
@@ -0,0 +1,538 @@
+//! Client for the Octopus Energy API, which serves an Octopus customer's
+//! smart meter readings and the exact rates they were charged, including
+//! Agile's half-hourly and Go's off-peak unit rates.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Local, Utc};
+use chrono_tz::Europe::London;
+use log::debug;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::batching::{date_windows, Window};
+use crate::error::Error;
+use crate::models::{
+    AvailableCacheRange, Consumption, ConsumptionOrTariff, DataSource, EnergyType, Granularity,
+    Price, RequestType, StandingCharge, Tariff, TariffValues, Value,
+};
+use crate::secret::SecretString;
+use crate::settlement::uk_midnight;
+use crate::source::{resource_path, Provider, Source};
+
+pub const OCTOPUS_BASE_URL: &str = "https://api.octopus.energy/v1/";
+
+/// Days of readings requested at once; Octopus pages them, so this only
+/// bounds how much a failed window has to re-request.
+const WINDOW_DAYS: i64 = 30;
+/// Rows asked for per page, Octopus's maximum.
+const PAGE_SIZE: &str = "25000";
+
+#[derive(Clone, Deserialize)]
+struct Account {
+    properties: Vec<Property>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Property {
+    #[serde(default)]
+    electricity_meter_points: Vec<ElectricityPoint>,
+    #[serde(default)]
+    gas_meter_points: Vec<GasPoint>,
+}
+
+#[derive(Clone, Deserialize)]
+struct ElectricityPoint {
+    mpan: String,
+    #[serde(default)]
+    is_export: bool,
+    meters: Vec<Meter>,
+    agreements: Vec<Agreement>,
+}
+
+#[derive(Clone, Deserialize)]
+struct GasPoint {
+    mprn: String,
+    meters: Vec<Meter>,
+    agreements: Vec<Agreement>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Meter {
+    serial_number: String,
+}
+
+/// A tariff the meter point was on, e.g. `E-1R-AGILE-FLEX-22-11-25-C`.
+#[derive(Clone, Deserialize)]
+struct Agreement {
+    tariff_code: String,
+    valid_from: DateTime<Utc>,
+    valid_to: Option<DateTime<Utc>>,
+}
+
+impl Agreement {
+    /// The product a tariff code belongs to, e.g. `AGILE-FLEX-22-11-25`, found
+    /// by dropping the fuel and register prefix and the region suffix.
+    fn product_code(&self) -> Option<String> {
+        let parts: Vec<&str> = self.tariff_code.split('-').collect();
+        (parts.len() > 3).then(|| parts[2..parts.len() - 1].join("-"))
+    }
+}
+
+/// A meter point of either fuel, with what the client needs of it.
+struct Point {
+    mpxn: String,
+    serials: Vec<String>,
+    agreements: Vec<Agreement>,
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+    next: Option<String>,
+    results: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct Reading {
+    consumption: f64,
+    interval_end: DateTime<Utc>,
+}
+
+/// A unit rate or standing charge, in pence, over the time it applied.
+#[derive(Deserialize)]
+struct Rate {
+    value_exc_vat: f64,
+    valid_from: DateTime<Utc>,
+    valid_to: Option<DateTime<Utc>>,
+}
+
+/// Client for an Octopus Energy account, authenticating with its API key.
+///
+/// Each meter point of a fuel is an element, numbered from 1 with import
+/// before export, so an export MPAN is usually electricity element 2.
+#[derive(Clone)]
+pub struct OctopusClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: SecretString,
+    account: String,
+    element: u8,
+    granularity: Granularity,
+    /// Whether gas readings are already in kWh, as from SMETS1 meters, rather
+    /// than the m³ SMETS2 meters report.
+    gas_kwh: bool,
+    /// Shared between clones, so the account is looked up once.
+    details: Arc<Mutex<Option<Account>>>,
+}
+
+impl OctopusClient {
+    pub fn new(api_key: impl Into<SecretString>, account: impl Into<String>) -> OctopusClient {
+        OctopusClient {
+            http: reqwest::Client::new(),
+            base_url: OCTOPUS_BASE_URL.to_string(),
+            api_key: api_key.into(),
+            account: account.into(),
+            element: 1,
+            granularity: Granularity::default(),
+            gas_kwh: false,
+            details: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> OctopusClient {
+        self.http = http;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> OctopusClient {
+        let mut base_url = base_url.into();
+        if !base_url.ends_with('/') {
+            base_url.push('/');
+        }
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn with_granularity(mut self, granularity: Granularity) -> OctopusClient {
+        self.granularity = granularity;
+        self
+    }
+
+    /// Treat gas readings as kWh rather than m³.
+    pub fn with_gas_kwh(mut self, gas_kwh: bool) -> OctopusClient {
+        self.gas_kwh = gas_kwh;
+        self
+    }
+
+    fn error(&self, url: &str, reason: impl Into<String>) -> Error {
+        Error::Provider {
+            provider: Provider::Octopus,
+            url: url.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, Error> {
+        let res = self
+            .http
+            .get(url)
+            .query(query)
+            .basic_auth(self.api_key.expose(), Some(""))
+            .send()
+            .await
+            .map_err(|e| self.error(url, e.to_string()))?;
+        let status = res.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(Error::Rejected {
+                provider: Provider::Octopus,
+                reason: format!("the API key for {} ({})", self.account, status),
+            });
+        }
+        if !status.is_success() {
+            return Err(self.error(url, format!("responded {}", status)));
+        }
+        let body = res
+            .text()
+            .await
+            .map_err(|e| self.error(url, e.to_string()))?;
+        serde_json::from_str(&body).map_err(|source| Error::UnexpectedBody {
+            url: url.to_string(),
+            source,
+        })
+    }
+
+    /// Every row of a paged list, following `next` links.
+    async fn get_pages<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<Vec<T>, Error> {
+        let mut rows = Vec::new();
+        let mut page: Page<T> = self
+            .get_json(&format!("{}{}", self.base_url, path), query)
+            .await?;
+        loop {
+            rows.append(&mut page.results);
+            let Some(next) = page.next else {
+                return Ok(rows);
+            };
+            // the link carries the query already
+            page = self.get_json(&next, &[]).await?;
+        }
+    }
+
+    /// The account's properties and meter points, looked up once and reused.
+    async fn account(&self) -> Result<Account, Error> {
+        let mut details = self.details.lock().await;
+        if let Some(account) = details.as_ref() {
+            return Ok(account.clone());
+        }
+        let url = format!("{}accounts/{}/", self.base_url, self.account);
+        let account: Account = self.get_json(&url, &[]).await?;
+        *details = Some(account.clone());
+        Ok(account)
+    }
+
+    /// The fuel's meter points, in element order.
+    async fn points(&self, energy_type: EnergyType) -> Result<Vec<Point>, Error> {
+        let account = self.account().await?;
+        let properties = account.properties.into_iter();
+        let serials = |meters: Vec<Meter>| meters.into_iter().map(|m| m.serial_number).collect();
+        Ok(match energy_type {
+            EnergyType::Electricity => {
+                let mut points: Vec<ElectricityPoint> = properties
+                    .flat_map(|property| property.electricity_meter_points)
+                    .collect();
+                points.sort_by_key(|point| point.is_export);
+                points
+                    .into_iter()
+                    .map(|point| Point {
+                        mpxn: point.mpan,
+                        serials: serials(point.meters),
+                        agreements: point.agreements,
+                    })
+                    .collect()
+            }
+            EnergyType::Gas => properties
+                .flat_map(|property| property.gas_meter_points)
+                .map(|point| Point {
+                    mpxn: point.mprn,
+                    serials: serials(point.meters),
+                    agreements: point.agreements,
+                })
+                .collect(),
+        })
+    }
+
+    /// The meter point read as this client's element.
+    async fn point(&self, energy_type: EnergyType) -> Result<Point, Error> {
+        let mut points = self.points(energy_type).await?;
+        let index = usize::from(self.element).checked_sub(1);
+        match index.filter(|index| *index < points.len()) {
+            Some(index) => Ok(points.swap_remove(index)),
+            None => Err(self.error(
+                &format!("{}accounts/{}/", self.base_url, self.account),
+                format!(
+                    "the account has no {} meter point {}",
+                    energy_type.to_string().to_lowercase(),
+                    self.element
+                ),
+            )),
+        }
+    }
+
+    async fn consumption(
+        &self,
+        energy_type: EnergyType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        let point = self.point(energy_type).await?;
+        let (collection, unit) = match energy_type {
+            EnergyType::Electricity => ("electricity-meter-points", "kWh"),
+            EnergyType::Gas if self.gas_kwh => ("gas-meter-points", "kWh"),
+            EnergyType::Gas => ("gas-meter-points", "m3"),
+        };
+        let mut query = vec![
+            // readings are stamped at the end of their period, as n3rgy's are
+            (
+                "period_from",
+                (start.to_utc() - self.granularity.interval()).to_rfc3339(),
+            ),
+            ("period_to", end.to_utc().to_rfc3339()),
+            ("page_size", PAGE_SIZE.to_string()),
+            ("order_by", "period".to_string()),
+        ];
+        if let Granularity::Day = self.granularity {
+            query.push(("group_by", "day".to_string()));
+        }
+        // a replaced meter's readings stay under its own serial
+        let mut values = Vec::new();
+        for serial in &point.serials {
+            let path = format!(
+                "{}/{}/meters/{}/consumption/",
+                collection, point.mpxn, serial
+            );
+            let readings: Vec<Reading> = self.get_pages(&path, &query).await?;
+            values.extend(readings.into_iter().map(|reading| Value {
+                timestamp: reading.interval_end,
+                value: reading.consumption,
+                status: None,
+            }));
+        }
+        values.sort_by_key(|value| value.timestamp);
+        values.dedup_by_key(|value| value.timestamp);
+        let mut data = ConsumptionOrTariff::Consumption(Consumption {
+            resource: resource_path(energy_type, RequestType::Consumption, self.element),
+            response_timestamp: Utc::now().to_rfc3339(),
+            start: start.to_rfc3339(),
+            end: end.to_rfc3339(),
+            granularity: self.granularity.as_param().to_string(),
+            values,
+            message: None,
+            unit: unit.to_string(),
+        });
+        data.retain_within(start.to_utc(), end.to_utc());
+        Ok(data)
+    }
+
+    /// The unit rates and standing charges of every agreement overlapping the
+    /// window, each unit rate repeated for every half hour it applied to.
+    async fn tariff(
+        &self,
+        energy_type: EnergyType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        let point = self.point(energy_type).await?;
+        let (start, end) = (start.to_utc(), end.to_utc());
+        let tariffs = match energy_type {
+            EnergyType::Electricity => "electricity-tariffs",
+            EnergyType::Gas => "gas-tariffs",
+        };
+        let mut values = Vec::new();
+        for agreement in &point.agreements {
+            let from = std::cmp::max(start, agreement.valid_from);
+            let to = agreement
+                .valid_to
+                .map_or(end, |valid_to| std::cmp::min(end, valid_to));
+            if from >= to {
+                continue;
+            }
+            let Some(product) = agreement.product_code() else {
+                debug!("skipping unrecognised tariff {}", agreement.tariff_code);
+                continue;
+            };
+            let path = format!("products/{}/{}/{}", product, tariffs, agreement.tariff_code);
+            let query = [
+                ("period_from", from.to_rfc3339()),
+                ("period_to", to.to_rfc3339()),
+                ("page_size", PAGE_SIZE.to_string()),
+            ];
+            let unit_rates: Vec<Rate> = self
+                .get_pages(&format!("{}/standard-unit-rates/", path), &query)
+                .await?;
+            let standing: Vec<Rate> = self
+                .get_pages(&format!("{}/standing-charges/", path), &query)
+                .await?;
+            values.push(TariffValues {
+                prices: half_hourly(&unit_rates, from, to),
+                standing_charges: daily(&standing, from, to),
+            });
+        }
+        Ok(ConsumptionOrTariff::Tariff(Tariff {
+            resource: resource_path(energy_type, RequestType::Tariff, self.element),
+            response_timestamp: Utc::now().to_rfc3339(),
+            start: start.to_rfc3339(),
+            end: end.to_rfc3339(),
+            values,
+            unit: Default::default(),
+        }))
+    }
+}
+
+/// A price per half hour between `from` and `to`, stamped at the end of the
+/// half hour as the reading it prices is.
+fn half_hourly(rates: &[Rate], from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Price> {
+    let step = Duration::minutes(30);
+    let mut prices = Vec::new();
+    for rate in rates {
+        let mut time = std::cmp::max(rate.valid_from, from);
+        let until = rate
+            .valid_to
+            .map_or(to, |valid_to| std::cmp::min(valid_to, to));
+        while time < until {
+            prices.push(Price {
+                timestamp: time + step,
+                value: rate.value_exc_vat,
+            });
+            time += step;
+        }
+    }
+    prices.sort_by_key(|price| price.timestamp);
+    prices
+}
+
+/// A standing charge for each UK day starting between `from` and `to`, plus
+/// the day `from` falls in.
+fn daily(rates: &[Rate], from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<StandingCharge> {
+    from.with_timezone(&London)
+        .date_naive()
+        .iter_days()
+        .map(|day| (day, std::cmp::max(uk_midnight(day), from)))
+        .take_while(|(_, time)| *time < to)
+        .filter_map(|(day, time)| {
+            let rate = rates.iter().find(|rate| {
+                rate.valid_from <= time && rate.valid_to.is_none_or(|until| time < until)
+            })?;
+            Some(StandingCharge {
+                start_date: day,
+                value: rate.value_exc_vat,
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Source for OctopusClient {
+    fn provider(&self) -> Provider {
+        Provider::Octopus
+    }
+
+    fn element(&self) -> u8 {
+        self.element
+    }
+
+    fn with_element(&self, element: u8) -> Arc<dyn Source> {
+        Arc::new(OctopusClient {
+            element,
+            ..self.clone()
+        })
+    }
+
+    fn granularity(&self) -> Granularity {
+        self.granularity
+    }
+
+    fn windows(&self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<Window> {
+        date_windows(start, end, WINDOW_DAYS)
+    }
+
+    async fn fetch(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        debug!(
+            "requesting {} {} element {} from Octopus for {} {}",
+            energy_type, request_type, self.element, start, end
+        );
+        match request_type {
+            RequestType::Consumption => self.consumption(energy_type, start, end).await,
+            RequestType::Tariff => self.tariff(energy_type, start, end).await,
+        }
+    }
+
+    async fn discover(&self) -> Result<Vec<DataSource>, Error> {
+        let mut sources = Vec::new();
+        for energy_type in [EnergyType::Electricity, EnergyType::Gas] {
+            for (i, point) in self.points(energy_type).await?.iter().enumerate() {
+                // readings go back as far as Octopus has supplied the point
+                let range = point
+                    .agreements
+                    .iter()
+                    .map(|agreement| agreement.valid_from)
+                    .min()
+                    .map(|start| AvailableCacheRange {
+                        start,
+                        end: Utc::now(),
+                    });
+                for request_type in [RequestType::Consumption, RequestType::Tariff] {
+                    sources.push(DataSource {
+                        fuel: energy_type.to_string().to_lowercase(),
+                        data_type: request_type.to_string().to_lowercase(),
+                        element: (i + 1).to_string(),
+                        range,
+                    });
+                }
+            }
+        }
+        Ok(sources)
+    }
+
+    async fn elements(
+        &self,
+        energy_type: EnergyType,
+        _request_type: RequestType,
+    ) -> Result<Vec<u8>, Error> {
+        let count = self.points(energy_type).await?.len();
+        Ok((1..=count).filter_map(|n| u8::try_from(n).ok()).collect())
+    }
+
+    async fn clamp_to_available(
+        &self,
+        energy_type: EnergyType,
+        _request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<Option<Window>, Error> {
+        let point = self.point(energy_type).await?;
+        let Some(supplied) = point.agreements.iter().map(|a| a.valid_from).min() else {
+            return Ok(Some((start, end)));
+        };
+        let start = std::cmp::max(start, supplied.with_timezone(&Local));
+        if start >= end {
+            return Ok(None);
+        }
+        Ok(Some((start, end)))
+    }
+}
@@ -0,0 +1,308 @@
+//! Points as Parquet, with the same columns as the CSV archives: `time`,
+//! `measurement`, then a column per tag and field.
+
+use std::collections::BTreeMap;
+#[cfg(any(feature = "s3", test))]
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+#[cfg(any(feature = "s3", test))]
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float64Type, Int64Type, TimestampMicrosecondType, TimestampMillisecondType,
+    TimestampNanosecondType, TimestampSecondType,
+};
+use arrow_array::{Array, ArrayRef};
+#[cfg(any(feature = "s3", test))]
+use arrow_array::{
+    BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray, TimestampNanosecondArray,
+};
+use arrow_schema::{DataType, TimeUnit};
+#[cfg(any(feature = "s3", test))]
+use arrow_schema::{Field, Schema};
+use chrono::{DateTime, Utc};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+#[cfg(any(feature = "s3", test))]
+use parquet::arrow::ArrowWriter;
+#[cfg(any(feature = "s3", test))]
+use parquet::basic::{Compression, ZstdLevel};
+#[cfg(any(feature = "s3", test))]
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::ChunkReader;
+use serde_json::{Number, Value};
+
+use crate::line_protocol::Point;
+
+/// Column metadata key saying whether a column holds a tag or a field, as
+/// string fields would otherwise read back as tags.
+const KIND: &str = "n3rgy.kind";
+
+#[cfg(any(feature = "s3", test))]
+/// Points as a Parquet file. Each field column takes the narrowest type its
+/// values fit: booleans, integers, floats, or strings for a mix.
+pub fn write(points: &[Point]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let tags: BTreeSet<&str> = points
+        .iter()
+        .flat_map(|point| point.tags.keys().map(String::as_str))
+        .collect();
+    let fields: BTreeSet<&str> = points
+        .iter()
+        .flat_map(|point| point.fields.iter().map(|(field, _)| field.as_str()))
+        .collect();
+    let values: Vec<BTreeMap<&str, &Value>> = points
+        .iter()
+        .map(|point| {
+            point
+                .fields
+                .iter()
+                .map(|(field, value)| (field.as_str(), value))
+                .collect()
+        })
+        .collect();
+
+    let time = TimestampNanosecondArray::from(
+        points
+            .iter()
+            .map(|point| point.time.timestamp_nanos_opt())
+            .collect::<Option<Vec<i64>>>()
+            .ok_or("a point's time is out of range")?,
+    )
+    .with_timezone("UTC");
+    let measurement = StringArray::from_iter_values(points.iter().map(|p| &p.measurement));
+    let mut schema = vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("measurement", DataType::Utf8, false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(time), Arc::new(measurement)];
+
+    for tag in &tags {
+        schema.push(Field::new(*tag, DataType::Utf8, true).with_metadata(kind("tag")));
+        columns.push(Arc::new(StringArray::from_iter(
+            points.iter().map(|point| point.tags.get(*tag)),
+        )));
+    }
+    for field in &fields {
+        let column: Vec<Option<&Value>> = values.iter().map(|v| v.get(field).copied()).collect();
+        let array = field_array(&column);
+        schema
+            .push(Field::new(*field, array.data_type().clone(), true).with_metadata(kind("field")));
+        columns.push(array);
+    }
+
+    let batch = RecordBatch::try_new(Arc::new(Schema::new(schema)), columns)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut body = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut body, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(body)
+}
+
+/// Points from a Parquet file with `time` and `measurement` columns. A string
+/// column is read as a tag unless it was written as a field, and any other
+/// column as a field.
+pub fn read(file: impl ChunkReader + 'static) -> Result<Vec<Point>, Box<dyn Error>> {
+    let mut points = Vec::new();
+    for batch in ParquetRecordBatchReaderBuilder::try_new(file)?.build()? {
+        let batch = batch?;
+        let schema = batch.schema();
+        let column = |name: &str| {
+            schema
+                .index_of(name)
+                .map_err(|_| format!("there is no {} column", name))
+        };
+        let times = times(batch.column(column("time")?))?;
+        let measurements = batch.column(column("measurement")?);
+        let measurements = measurements
+            .as_string_opt::<i32>()
+            .ok_or("the measurement column isn't text")?;
+
+        let start = points.len();
+        for (row, time) in times.into_iter().enumerate() {
+            points.push(Point {
+                measurement: measurements.value(row).to_string(),
+                tags: BTreeMap::new(),
+                fields: Vec::new(),
+                time: time.ok_or("a row has no time")?,
+            });
+        }
+        for (field, array) in schema.fields().iter().zip(batch.columns()) {
+            let name = field.name();
+            if name == "time" || name == "measurement" {
+                continue;
+            }
+            let is_tag = field.metadata().get(KIND).map(String::as_str) != Some("field");
+            for (row, point) in points[start..].iter_mut().enumerate() {
+                if array.is_null(row) {
+                    continue;
+                }
+                match (array.data_type(), is_tag) {
+                    (DataType::Utf8, true) => {
+                        let value = array.as_string::<i32>().value(row);
+                        point.tags.insert(name.clone(), value.to_string());
+                    }
+                    (DataType::Utf8, false) => {
+                        let value = array.as_string::<i32>().value(row);
+                        point.fields.push((name.clone(), Value::from(value)));
+                    }
+                    (DataType::Boolean, _) => {
+                        let value = array.as_boolean().value(row);
+                        point.fields.push((name.clone(), Value::Bool(value)));
+                    }
+                    (DataType::Int64, _) => {
+                        let value = array.as_primitive::<Int64Type>().value(row);
+                        point.fields.push((name.clone(), Value::from(value)));
+                    }
+                    (DataType::Float64, _) => {
+                        let value = array.as_primitive::<Float64Type>().value(row);
+                        if let Some(value) = Number::from_f64(value) {
+                            point.fields.push((name.clone(), Value::Number(value)));
+                        }
+                    }
+                    (data_type, _) => {
+                        return Err(format!("the {} column is {}", name, data_type).into());
+                    }
+                }
+            }
+        }
+    }
+    Ok(points)
+}
+
+/// A timestamp column's times, in whichever unit it was written.
+fn times(array: &ArrayRef) -> Result<Vec<Option<DateTime<Utc>>>, Box<dyn Error>> {
+    let nanos = |scale: i64| {
+        move |value: Option<i64>| value.map(|value| DateTime::from_timestamp_nanos(value * scale))
+    };
+    Ok(match array.data_type() {
+        DataType::Timestamp(TimeUnit::Second, _) => (array.as_primitive::<TimestampSecondType>())
+            .iter()
+            .map(nanos(1_000_000_000))
+            .collect(),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => array
+            .as_primitive::<TimestampMillisecondType>()
+            .iter()
+            .map(nanos(1_000_000))
+            .collect(),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => array
+            .as_primitive::<TimestampMicrosecondType>()
+            .iter()
+            .map(nanos(1_000))
+            .collect(),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => array
+            .as_primitive::<TimestampNanosecondType>()
+            .iter()
+            .map(nanos(1))
+            .collect(),
+        data_type => return Err(format!("the time column is {}", data_type).into()),
+    })
+}
+
+#[cfg(any(feature = "s3", test))]
+fn kind(kind: &str) -> HashMap<String, String> {
+    HashMap::from([(KIND.to_string(), kind.to_string())])
+}
+
+#[cfg(any(feature = "s3", test))]
+/// A field's values, `None` where a point doesn't have it.
+fn field_array(values: &[Option<&Value>]) -> ArrayRef {
+    let present = || values.iter().flatten();
+    if present().all(|value| value.is_boolean()) {
+        Arc::new(BooleanArray::from_iter(
+            values.iter().map(|value| value.and_then(Value::as_bool)),
+        ))
+    } else if present().all(|value| value.is_i64()) {
+        Arc::new(Int64Array::from_iter(
+            values.iter().map(|value| value.and_then(Value::as_i64)),
+        ))
+    } else if present().all(|value| value.is_number()) {
+        Arc::new(Float64Array::from_iter(
+            values.iter().map(|value| value.and_then(Value::as_f64)),
+        ))
+    } else {
+        Arc::new(StringArray::from_iter(values.iter().map(|value| {
+            value.map(|value| match value {
+                Value::String(text) => text.clone(),
+                value => value.to_string(),
+            })
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+    use chrono::TimeZone;
+
+    fn point(minute: u32, tags: &[(&str, &str)], fields: Vec<(&str, Value)>) -> Point {
+        Point {
+            measurement: "energy".to_string(),
+            tags: tags
+                .iter()
+                .map(|(tag, value)| (tag.to_string(), value.to_string()))
+                .collect(),
+            fields: fields
+                .into_iter()
+                .map(|(field, value)| (field.to_string(), value))
+                .collect(),
+            time: Utc.with_ymd_and_hms(2024, 6, 1, 0, minute, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn points_round_trip() {
+        let points = vec![
+            point(
+                0,
+                &[("fuel", "electricity"), ("status", "valid")],
+                vec![
+                    ("consumption", Value::from(0.25)),
+                    ("period", Value::from(1)),
+                ],
+            ),
+            point(
+                30,
+                &[("fuel", "electricity")],
+                vec![
+                    ("consumption", Value::from(1)),
+                    ("note", Value::from("estimated")),
+                    ("anomaly", Value::Bool(true)),
+                ],
+            ),
+        ];
+        let read = read(Bytes::from(write(&points).unwrap())).unwrap();
+        assert_eq!(read.len(), 2);
+
+        assert_eq!(read[0].time, points[0].time);
+        assert_eq!(read[0].measurement, "energy");
+        assert_eq!(read[0].tags, points[0].tags);
+        assert_eq!(
+            read[0].fields,
+            vec![
+                ("consumption".to_string(), Value::from(0.25)),
+                ("period".to_string(), Value::from(1)),
+            ]
+        );
+
+        // a column mixing integers and floats is read back as floats, and
+        // string fields stay fields
+        assert_eq!(read[1].tags, points[1].tags);
+        assert_eq!(
+            read[1].fields,
+            vec![
+                ("anomaly".to_string(), Value::Bool(true)),
+                ("consumption".to_string(), Value::from(1.0)),
+                ("note".to_string(), Value::from("estimated")),
+            ]
+        );
+    }
+}
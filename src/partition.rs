@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+
+use crate::line_protocol;
+use crate::template::{Template, VARIABLES};
+
+/// Placeholders a partitioned output path may use: a point's tags, plus its
+/// measurement and UK {date}.
+pub const PATH_VARIABLES: [&str; 8] = [
+    VARIABLES[0],
+    VARIABLES[1],
+    VARIABLES[2],
+    VARIABLES[3],
+    VARIABLES[4],
+    VARIABLES[5],
+    "measurement",
+    "date",
+];
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+/// How much of the UK calendar each output file covers.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Partition {
+    Day,
+    Month,
+}
+
+/// Line protocol files named from a path template, e.g. `energy-{date}.lp`,
+/// optionally compressed.
+///
+/// Each batch is appended to a `.partial` copy of its file that is renamed
+/// over it once written, so a file is never seen half-written and an
+/// interrupted run leaves the files it already finished intact. Gzip and zstd
+/// both read appended batches back as one stream.
+pub struct PartitionedFiles {
+    path: Template,
+    /// `{date}` is the month rather than the day for [`Partition::Month`].
+    partition: Option<Partition>,
+    compression: Option<Compression>,
+    /// Held while files are rewritten, so concurrent windows don't race on
+    /// the same `.partial` copy.
+    lock: Mutex<()>,
+}
+
+impl PartitionedFiles {
+    pub fn new(
+        path: &str,
+        partition: Option<Partition>,
+        compression: Option<Compression>,
+    ) -> Result<PartitionedFiles, String> {
+        let mut path = path.to_string();
+        if let Some(compression) = compression {
+            if !path.ends_with(compression.extension()) {
+                path.push_str(compression.extension());
+            }
+        }
+        let template = Template::parse(&path, &PATH_VARIABLES)?;
+        if partition.is_some() && !template.uses("date") {
+            return Err(format!("{} needs a {{date}} to partition by", path));
+        }
+        Ok(PartitionedFiles {
+            path: template,
+            partition,
+            compression,
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Append lines of line protocol to the files they belong in, returning
+    /// how many were written.
+    pub fn write(&self, lines: &[String]) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut files: BTreeMap<PathBuf, String> = BTreeMap::new();
+        for line in lines {
+            let point = line_protocol::parse(line)?;
+            let body = files.entry(self.file(&point)).or_default();
+            body.push_str(line);
+            body.push('\n');
+        }
+
+        let _lock = self.lock.lock().unwrap();
+        for (path, body) in &files {
+            self.append(path, body.as_bytes())?;
+        }
+        Ok(lines.len())
+    }
+
+    fn file(&self, point: &line_protocol::Point) -> PathBuf {
+        let date = point.uk_date();
+        let date = match self.partition {
+            Some(Partition::Month) => date.format("%Y-%m"),
+            _ => date.format("%Y-%m-%d"),
+        };
+        let mut values: BTreeMap<&str, String> = PATH_VARIABLES
+            .iter()
+            .filter_map(|name| Some((*name, point.tags.get(*name)?.replace('/', "_"))))
+            .collect();
+        values.insert("measurement", point.measurement.replace('/', "_"));
+        values.insert("date", date.to_string());
+        PathBuf::from(self.path.render(&values))
+    }
+
+    fn append(&self, path: &PathBuf, body: &[u8]) -> io::Result<()> {
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        match fs::copy(path, &partial) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::File::create(&partial)?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let file = OpenOptions::new().append(true).open(&partial)?;
+        match self.compression {
+            None => {
+                let mut file = file;
+                file.write_all(body)?;
+                file.sync_all()?;
+            }
+            Some(Compression::Gzip) => {
+                let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()?.sync_all()?;
+            }
+            Some(Compression::Zstd) => {
+                let mut encoder = zstd::Encoder::new(file, 0)?;
+                encoder.write_all(body)?;
+                encoder.finish()?.sync_all()?;
+            }
+        }
+        fs::rename(&partial, path)
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Europe::London;
+use clap::ValueEnum;
+use futures::TryStreamExt;
+use n3rgy_rs::aggregate::Period;
+use n3rgy_rs::client::Window;
+use n3rgy_rs::models::EnergyType;
+use n3rgy_rs::settlement::settlement_date;
+use n3rgy_rs::N3rgyClient;
+
+/// Columns the longest bar fills.
+const BAR_WIDTH: usize = 50;
+/// Partial blocks in eighths, for bars that don't end on a whole column.
+const EIGHTHS: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const ASCII_SPARKS: [char; 8] = ['_', '.', '-', ':', '=', '+', '*', '#'];
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+pub enum Style {
+    /// A bar per day, labelled with its date and total
+    Bars,
+    /// A single line with a character per day
+    Sparkline,
+}
+
+/// Chart daily consumption over `start..end` on stdout.
+pub async fn run(
+    client: &N3rgyClient,
+    energy_type: EnergyType,
+    (start, end): Window,
+    style: Style,
+    ascii: bool,
+) -> Result<(), n3rgy_rs::Error> {
+    let mut days: BTreeMap<DateTime<Utc>, f64> = BTreeMap::new();
+    let mut readings = Box::pin(client.consumption(energy_type, start..end));
+    while let Some(reading) = readings.try_next().await? {
+        *days
+            .entry(Period::Day.date_start(settlement_date(reading.time)))
+            .or_default() += reading.consumption;
+    }
+    if days.is_empty() {
+        println!("No {} readings in the range", energy_type);
+        return Ok(());
+    }
+
+    let max = days.values().copied().fold(0.0, f64::max);
+    match style {
+        Style::Bars => {
+            for (day, total) in &days {
+                println!(
+                    "{} {:>9.2} {}",
+                    day.with_timezone(&London).format("%Y-%m-%d"),
+                    total,
+                    bar(*total / max, ascii)
+                );
+            }
+        }
+        Style::Sparkline => {
+            let sparks = if ascii { ASCII_SPARKS } else { SPARKS };
+            let line: String = days
+                .values()
+                .map(|total| {
+                    let level = (total / max * (sparks.len() - 1) as f64).round() as usize;
+                    sparks[level.min(sparks.len() - 1)]
+                })
+                .collect();
+            let (first, last) = (days.keys().next().unwrap(), days.keys().last().unwrap());
+            println!(
+                "{} {} {} (max {:.2})",
+                first.with_timezone(&London).format("%Y-%m-%d"),
+                line,
+                last.with_timezone(&London).format("%Y-%m-%d"),
+                max
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A bar `fraction` of [`BAR_WIDTH`] long.
+fn bar(fraction: f64, ascii: bool) -> String {
+    let eighths = (fraction.clamp(0.0, 1.0) * (BAR_WIDTH * 8) as f64).round() as usize;
+    if ascii {
+        return "#".repeat((eighths + 4) / 8);
+    }
+    let mut bar = "█".repeat(eighths / 8);
+    let partial = EIGHTHS[eighths % 8];
+    if partial != ' ' {
+        bar.push(partial);
+    }
+    bar
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use futures::TryStreamExt;
+use n3rgy_rs::client::Window;
+use n3rgy_rs::models::{ConsumptionReading, EnergyType, UNKNOWN_STATUS};
+use n3rgy_rs::N3rgyClient;
+use serde::Serialize;
+
+/// Status of readings taken from the meter rather than estimated.
+pub const VALID_STATUS: &str = "valid";
+
+/// Consecutive half hours, by the end times readings are stamped with.
+#[derive(Serialize)]
+pub struct Run {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub half_hours: usize,
+}
+
+#[derive(Serialize)]
+pub struct Flagged {
+    pub time: DateTime<Utc>,
+    pub status: String,
+}
+
+#[derive(Serialize)]
+pub struct Spike {
+    pub time: DateTime<Utc>,
+    pub consumption: f64,
+    /// The range's median half-hourly consumption it is compared with.
+    pub median: f64,
+}
+
+#[derive(Serialize)]
+pub struct QualityReport {
+    pub readings: usize,
+    pub missing: Vec<Run>,
+    /// Readings n3rgy marked as anything but valid, e.g. estimated.
+    pub flagged: Vec<Flagged>,
+    pub zero_runs: Vec<Run>,
+    pub spikes: Vec<Spike>,
+}
+
+/// Fetch half-hourly consumption over `start..end` and list its gaps, flagged
+/// readings, runs of at least `min_zero_run` zeroes and readings over
+/// `spike_factor` times the median.
+pub async fn run(
+    client: &N3rgyClient,
+    energy_type: EnergyType,
+    (start, end): Window,
+    min_zero_run: usize,
+    spike_factor: f64,
+    json: bool,
+) -> Result<(), n3rgy_rs::Error> {
+    let readings: Vec<ConsumptionReading> = client
+        .consumption(energy_type, start..end)
+        .try_collect()
+        .await?;
+    let readings: BTreeMap<DateTime<Utc>, ConsumptionReading> = readings
+        .into_iter()
+        .map(|reading| (reading.time, reading))
+        .collect();
+
+    let step = Duration::minutes(30);
+    let first = start.to_utc().duration_trunc(step).unwrap() + step;
+    let expected = (0..)
+        .map(|i| first + step * i)
+        .take_while(|time| *time <= end.to_utc());
+    let missing = runs(expected.filter(|time| !readings.contains_key(time)));
+
+    let flagged = readings
+        .values()
+        .filter(|r| r.status != VALID_STATUS && r.status != UNKNOWN_STATUS)
+        .map(|r| Flagged {
+            time: r.time,
+            status: r.status.clone(),
+        })
+        .collect();
+    let zero_runs = runs(
+        readings
+            .values()
+            .filter(|r| r.consumption == 0.0)
+            .map(|r| r.time),
+    )
+    .into_iter()
+    .filter(|run| run.half_hours >= min_zero_run)
+    .collect();
+
+    let mut values: Vec<f64> = readings.values().map(|r| r.consumption).collect();
+    values.sort_by(f64::total_cmp);
+    let median = values.get(values.len() / 2).copied().unwrap_or(0.0);
+    let spikes = readings
+        .values()
+        .filter(|r| median > 0.0 && r.consumption > median * spike_factor)
+        .map(|r| Spike {
+            time: r.time,
+            consumption: r.consumption,
+            median,
+        })
+        .collect();
+
+    let report = QualityReport {
+        readings: readings.len(),
+        missing,
+        flagged,
+        zero_runs,
+        spikes,
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+/// Group ascending half-hour end times into runs of consecutive ones.
+fn runs(times: impl Iterator<Item = DateTime<Utc>>) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for time in times {
+        match runs.last_mut() {
+            Some(run) if time - run.to == Duration::minutes(30) => {
+                run.to = time;
+                run.half_hours += 1;
+            }
+            _ => runs.push(Run {
+                from: time,
+                to: time,
+                half_hours: 1,
+            }),
+        }
+    }
+    runs
+}
+
+fn print_report(report: &QualityReport) {
+    println!("{} readings", report.readings);
+    let print_runs = |title: &str, runs: &[Run]| {
+        println!("{}: {}", title, runs.len());
+        for run in runs {
+            println!(
+                "  {} to {} ({} half hours)",
+                run.from, run.to, run.half_hours
+            );
+        }
+    };
+    print_runs("Missing half hours", &report.missing);
+    println!("Flagged readings: {}", report.flagged.len());
+    for flagged in &report.flagged {
+        println!("  {} {}", flagged.time, flagged.status);
+    }
+    print_runs("Runs of zero readings", &report.zero_runs);
+    println!("Spikes: {}", report.spikes.len());
+    for spike in &report.spikes {
+        println!(
+            "  {} {:.3} (median {:.3})",
+            spike.time, spike.consumption, spike.median
+        );
+    }
+}
@@ -0,0 +1,629 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local, Utc};
+use log::{info, warn};
+use n3rgy_rs::client::Window;
+use n3rgy_rs::conversion::{is_cubic_metres, GasConversion};
+use n3rgy_rs::models::{
+    Consumption, ConsumptionOrTariff, DataSource, EnergyType, Granularity, RequestType, Value,
+    UNKNOWN_STATUS,
+};
+use n3rgy_rs::source::{Provider, Source};
+use n3rgy_rs::Error;
+
+use crate::quality::VALID_STATUS;
+
+/// Columns of the `--reconcile-report` CSV.
+const REPORT_HEADER: &str = "time,fuel,element,source,value,chosen_source,chosen_value";
+
+/// A reading a source disagreed with, or was missing within the range it
+/// otherwise covers.
+struct Difference {
+    time: DateTime<Utc>,
+    source: Provider,
+    /// `None` when the source had no reading.
+    value: Option<f64>,
+    chosen_source: Provider,
+    chosen_value: f64,
+}
+
+/// One meter read from several sources, most trusted first, merged into a
+/// single series.
+///
+/// Each half hour takes the first actual reading among the sources, falling
+/// back to the first estimate when none has one. Readings further than
+/// `tolerance` from the one taken, and gaps inside another source's range,
+/// are logged and appended to `report`. Tariffs aren't merged: they come
+/// from the first source that returns them.
+#[derive(Clone)]
+pub struct Reconciled {
+    sources: Vec<Arc<dyn Source>>,
+    tolerance: f64,
+    report: Option<PathBuf>,
+}
+
+impl Reconciled {
+    pub fn new(
+        sources: Vec<Arc<dyn Source>>,
+        tolerance: f64,
+        report: Option<PathBuf>,
+    ) -> Reconciled {
+        Reconciled {
+            sources,
+            tolerance,
+            report,
+        }
+    }
+
+    fn primary(&self) -> &dyn Source {
+        &*self.sources[0]
+    }
+
+    /// Tariffs from the first source that has them.
+    async fn tariff(
+        &self,
+        energy_type: EnergyType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        let mut failure = None;
+        for source in &self.sources {
+            match source
+                .fetch(energy_type, RequestType::Tariff, start, end)
+                .await
+            {
+                Ok(data) => return Ok(data),
+                Err(e) => {
+                    warn!("{} has no {} tariff: {}", source.provider(), energy_type, e);
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        Err(failure.expect("reconciling needs at least one source"))
+    }
+
+    async fn consumption(
+        &self,
+        energy_type: EnergyType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        let mut fetched: Vec<(Provider, Consumption)> = Vec::new();
+        let mut failure = None;
+        for source in &self.sources {
+            match consumption(&**source, energy_type, start, end).await {
+                Ok(consumption) => fetched.push((source.provider(), consumption)),
+                Err(e) => {
+                    warn!(
+                        "leaving {} out of reconciling {} to {}: {}",
+                        source.provider(),
+                        start,
+                        end,
+                        e
+                    );
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        if fetched.is_empty() {
+            return Err(failure.expect("reconciling needs at least one source"));
+        }
+
+        // compare in the unit of the most trusted source that answered
+        let unit = fetched[0].1.unit.clone();
+        let conversion = GasConversion::default();
+        let mut readings: BTreeMap<DateTime<Utc>, Vec<(Provider, Value)>> = BTreeMap::new();
+        let mut covered = Vec::new();
+        for (provider, consumption) in &fetched {
+            let convert =
+                |value: f64| match (is_cubic_metres(&consumption.unit), is_cubic_metres(&unit)) {
+                    (true, false) => conversion.m3_to_kwh(value),
+                    (false, true) => conversion.kwh_to_m3(value),
+                    _ => value,
+                };
+            let times = consumption.values.iter().map(|value| value.timestamp);
+            covered.push((*provider, times.clone().min(), times.max()));
+            for value in &consumption.values {
+                readings.entry(value.timestamp).or_default().push((
+                    *provider,
+                    Value {
+                        value: convert(value.value),
+                        ..value.clone()
+                    },
+                ));
+            }
+        }
+
+        let mut differences = Vec::new();
+        let mut values = Vec::with_capacity(readings.len());
+        let mut substituted: Vec<(Provider, usize)> = Vec::new();
+        for (time, candidates) in readings {
+            let (chosen_source, chosen) = candidates
+                .iter()
+                .find(|(_, value)| !estimated(value))
+                .unwrap_or(&candidates[0])
+                .clone();
+            if chosen_source != fetched[0].0 {
+                match substituted.iter_mut().find(|(p, _)| *p == chosen_source) {
+                    Some((_, count)) => *count += 1,
+                    None => substituted.push((chosen_source, 1)),
+                }
+            }
+            for (source, first, last) in &covered {
+                let value = candidates
+                    .iter()
+                    .find(|(provider, _)| provider == source)
+                    .map(|(_, value)| value.value);
+                let differs = match value {
+                    Some(value) => (value - chosen.value).abs() > self.tolerance,
+                    // a source lagging behind the others isn't a gap
+                    None => {
+                        first.is_some_and(|first| first < time)
+                            && last.is_some_and(|last| time < last)
+                    }
+                };
+                if differs {
+                    differences.push(Difference {
+                        time,
+                        source: *source,
+                        value,
+                        chosen_source,
+                        chosen_value: chosen.value,
+                    });
+                }
+            }
+            values.push(chosen);
+        }
+
+        for (provider, count) in substituted {
+            info!(
+                "took {} {} reading(s) from {} where {} had none or an estimate",
+                count,
+                energy_type.to_string().to_lowercase(),
+                provider,
+                fetched[0].0
+            );
+        }
+        if !differences.is_empty() {
+            warn!(
+                "{} {} reading(s) from {} to {} differ between sources by more than {}{}",
+                differences.len(),
+                energy_type.to_string().to_lowercase(),
+                start,
+                end,
+                self.tolerance,
+                unit
+            );
+            if let Some(path) = &self.report {
+                if let Err(e) = self.append_report(path, energy_type, &differences) {
+                    warn!("could not write to {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        let (_, base) = fetched.swap_remove(0);
+        Ok(ConsumptionOrTariff::Consumption(Consumption {
+            values,
+            unit,
+            ..base
+        }))
+    }
+
+    fn append_report(
+        &self,
+        path: &Path,
+        energy_type: EnergyType,
+        differences: &[Difference],
+    ) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", REPORT_HEADER)?;
+        }
+        let fuel = energy_type.to_string().to_lowercase();
+        for difference in differences {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{}",
+                difference.time.to_rfc3339(),
+                fuel,
+                self.element(),
+                difference.source,
+                difference
+                    .value
+                    .map_or(String::new(), |value| value.to_string()),
+                difference.chosen_source,
+                difference.chosen_value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the source marked the reading as anything but actual. Readings from
+/// sources that don't say count as actual.
+fn estimated(value: &Value) -> bool {
+    value
+        .status
+        .as_deref()
+        .is_some_and(|status| status != VALID_STATUS && status != UNKNOWN_STATUS)
+}
+
+/// Consumption over `start..end` from one source, in as many requests as it
+/// needs.
+async fn consumption(
+    source: &dyn Source,
+    energy_type: EnergyType,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+) -> Result<Consumption, Error> {
+    let mut merged: Option<Consumption> = None;
+    for (start, end) in source.windows(start, end) {
+        let ConsumptionOrTariff::Consumption(consumption) = source
+            .fetch(energy_type, RequestType::Consumption, start, end)
+            .await?
+        else {
+            continue;
+        };
+        match &mut merged {
+            Some(merged) => merged.values.extend(consumption.values),
+            None => merged = Some(consumption),
+        }
+    }
+    Ok(merged.unwrap_or(Consumption {
+        resource: String::new(),
+        response_timestamp: Utc::now().to_rfc3339(),
+        start: start.to_rfc3339(),
+        end: end.to_rfc3339(),
+        granularity: source.granularity().as_param().to_string(),
+        values: Vec::new(),
+        message: None,
+        unit: String::new(),
+    }))
+}
+
+#[async_trait]
+impl Source for Reconciled {
+    fn provider(&self) -> Provider {
+        self.primary().provider()
+    }
+
+    fn element(&self) -> u8 {
+        self.primary().element()
+    }
+
+    /// Element numbers are each provider's own, so only sources from the
+    /// primary's provider follow it to another element.
+    fn with_element(&self, element: u8) -> Arc<dyn Source> {
+        let provider = self.provider();
+        Arc::new(Reconciled {
+            sources: self
+                .sources
+                .iter()
+                .map(|source| {
+                    if source.provider() == provider {
+                        source.with_element(element)
+                    } else {
+                        source.clone()
+                    }
+                })
+                .collect(),
+            ..self.clone()
+        })
+    }
+
+    fn granularity(&self) -> Granularity {
+        self.primary().granularity()
+    }
+
+    fn windows(&self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<Window> {
+        self.primary().windows(start, end)
+    }
+
+    async fn fetch(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        match request_type {
+            RequestType::Consumption => self.consumption(energy_type, start, end).await,
+            RequestType::Tariff => self.tariff(energy_type, start, end).await,
+        }
+    }
+
+    async fn discover(&self) -> Result<Vec<DataSource>, Error> {
+        self.primary().discover().await
+    }
+
+    async fn elements(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+    ) -> Result<Vec<u8>, Error> {
+        self.primary().elements(energy_type, request_type).await
+    }
+
+    /// The range any of the sources holds.
+    async fn clamp_to_available(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<Option<Window>, Error> {
+        let mut available: Option<Window> = None;
+        let mut failure = None;
+        for source in &self.sources {
+            match source
+                .clamp_to_available(energy_type, request_type, start, end)
+                .await
+            {
+                Ok(Some((from, to))) => {
+                    available = Some(match available {
+                        Some((first, last)) => (first.min(from), last.max(to)),
+                        None => (from, to),
+                    })
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        match (available, failure) {
+            (None, Some(e)) => Err(e),
+            (available, _) => Ok(available),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Duration, TimeZone};
+
+    /// A source holding fixed half-hourly readings for each element, as
+    /// `(half hours after midnight, kWh, status)`.
+    #[derive(Clone)]
+    struct Stub {
+        provider: Provider,
+        element: u8,
+        readings: BTreeMap<u8, Vec<(i64, f64, &'static str)>>,
+    }
+
+    impl Stub {
+        fn new(provider: Provider, readings: Vec<(i64, f64, &'static str)>) -> Stub {
+            Stub {
+                provider,
+                element: 1,
+                readings: BTreeMap::from([(1, readings)]),
+            }
+        }
+    }
+
+    fn midnight() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap()
+    }
+
+    fn time(half_hours: i64) -> DateTime<Utc> {
+        midnight() + Duration::minutes(30 * half_hours)
+    }
+
+    #[async_trait]
+    impl Source for Stub {
+        fn provider(&self) -> Provider {
+            self.provider
+        }
+
+        fn element(&self) -> u8 {
+            self.element
+        }
+
+        fn with_element(&self, element: u8) -> Arc<dyn Source> {
+            Arc::new(Stub {
+                element,
+                ..self.clone()
+            })
+        }
+
+        fn granularity(&self) -> Granularity {
+            Granularity::HalfHour
+        }
+
+        fn windows(&self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<Window> {
+            vec![(start, end)]
+        }
+
+        async fn fetch(
+            &self,
+            _: EnergyType,
+            _: RequestType,
+            start: DateTime<Local>,
+            end: DateTime<Local>,
+        ) -> Result<ConsumptionOrTariff, Error> {
+            let readings = self
+                .readings
+                .get(&self.element)
+                .cloned()
+                .unwrap_or_default();
+            Ok(ConsumptionOrTariff::Consumption(Consumption {
+                resource: format!("/electricity/consumption/{}", self.element),
+                response_timestamp: String::new(),
+                start: start.to_rfc3339(),
+                end: end.to_rfc3339(),
+                granularity: Granularity::HalfHour.as_param().to_string(),
+                values: readings
+                    .into_iter()
+                    .map(|(half_hours, value, status)| Value {
+                        timestamp: time(half_hours),
+                        value,
+                        status: Some(status.to_string()),
+                    })
+                    .collect(),
+                message: None,
+                unit: "kWh".to_string(),
+            }))
+        }
+
+        async fn discover(&self) -> Result<Vec<DataSource>, Error> {
+            Ok(Vec::new())
+        }
+
+        async fn elements(&self, _: EnergyType, _: RequestType) -> Result<Vec<u8>, Error> {
+            Ok(self.readings.keys().copied().collect())
+        }
+
+        async fn clamp_to_available(
+            &self,
+            _: EnergyType,
+            _: RequestType,
+            start: DateTime<Local>,
+            end: DateTime<Local>,
+        ) -> Result<Option<Window>, Error> {
+            Ok(Some((start, end)))
+        }
+    }
+
+    /// The merged readings as `(half hours after midnight, kWh)`, and the
+    /// `--reconcile-report` rows, tolerating 0.1 kWh of difference.
+    async fn reconcile(source: Arc<dyn Source>, report: &Path) -> (Vec<(i64, f64)>, Vec<String>) {
+        let start = midnight().with_timezone(&Local);
+        let ConsumptionOrTariff::Consumption(merged) = source
+            .fetch(
+                EnergyType::Electricity,
+                RequestType::Consumption,
+                start,
+                start + Duration::days(1),
+            )
+            .await
+            .unwrap()
+        else {
+            unreachable!();
+        };
+        let readings = merged
+            .values
+            .iter()
+            .map(|value| {
+                (
+                    (value.timestamp - midnight()).num_minutes() / 30,
+                    value.value,
+                )
+            })
+            .collect();
+        let rows = std::fs::read_to_string(report)
+            .unwrap_or_default()
+            .lines()
+            .skip(1)
+            .map(str::to_string)
+            .collect();
+        let _ = std::fs::remove_file(report);
+        (readings, rows)
+    }
+
+    fn report_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "n3rgy-reconcile-{}-{}.csv",
+            test,
+            std::process::id()
+        ))
+    }
+
+    fn reconciled(
+        n3rgy: Vec<(i64, f64, &'static str)>,
+        octopus: Vec<(i64, f64, &'static str)>,
+        report: &Path,
+    ) -> Reconciled {
+        Reconciled::new(
+            vec![
+                Arc::new(Stub::new(Provider::N3rgy, n3rgy)),
+                Arc::new(Stub::new(Provider::Octopus, octopus)),
+            ],
+            0.1,
+            Some(report.to_path_buf()),
+        )
+    }
+
+    #[tokio::test]
+    async fn actual_readings_beat_estimates() {
+        let report = report_path("actual");
+        let source = reconciled(
+            vec![(1, 1.0, "estimated"), (2, 2.0, "valid")],
+            vec![(1, 1.5, "valid"), (2, 2.5, "estimated")],
+            &report,
+        );
+        let (readings, _) = reconcile(Arc::new(source), &report).await;
+        assert_eq!(readings, vec![(1, 1.5), (2, 2.0)]);
+    }
+
+    #[tokio::test]
+    async fn differences_within_tolerance_are_not_reported() {
+        let report = report_path("within");
+        let source = reconciled(
+            vec![(1, 1.0, "valid"), (2, 2.0, "valid")],
+            vec![(1, 1.05, "valid"), (2, 1.95, "valid")],
+            &report,
+        );
+        let (readings, rows) = reconcile(Arc::new(source), &report).await;
+        assert_eq!(readings, vec![(1, 1.0), (2, 2.0)]);
+        assert!(rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn differences_beyond_tolerance_are_reported() {
+        let report = report_path("beyond");
+        let source = reconciled(
+            vec![(1, 1.0, "valid"), (2, 2.0, "valid")],
+            vec![(1, 1.05, "valid"), (2, 2.5, "valid")],
+            &report,
+        );
+        let (readings, rows) = reconcile(Arc::new(source), &report).await;
+        assert_eq!(readings, vec![(1, 1.0), (2, 2.0)]);
+        assert_eq!(
+            rows,
+            vec![format!(
+                "{},electricity,1,Octopus Energy,2.5,n3rgy,2",
+                time(2).to_rfc3339()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn readings_only_the_secondary_has_fill_gaps() {
+        let report = report_path("gap");
+        let source = reconciled(
+            vec![(1, 1.0, "valid"), (3, 3.0, "valid")],
+            vec![(1, 1.0, "valid"), (2, 2.0, "valid"), (3, 3.0, "valid")],
+            &report,
+        );
+        let (readings, rows) = reconcile(Arc::new(source), &report).await;
+        assert_eq!(readings, vec![(1, 1.0), (2, 2.0), (3, 3.0)]);
+        // the primary's gap is reported, with the reading taken in its place
+        assert_eq!(
+            rows,
+            vec![format!(
+                "{},electricity,1,n3rgy,,Octopus Energy,2",
+                time(2).to_rfc3339()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn other_providers_keep_their_own_element() {
+        let report = report_path("element");
+        let mut n3rgy = Stub::new(Provider::N3rgy, Vec::new());
+        n3rgy.readings.insert(2, vec![(1, 1.0, "estimated")]);
+        let octopus = Stub::new(Provider::Octopus, vec![(1, 1.5, "valid")]);
+        let source = Reconciled::new(vec![Arc::new(n3rgy), Arc::new(octopus)], 0.1, None);
+        let source = source.with_element(2);
+        assert_eq!(source.element(), 2);
+        let (readings, _) = reconcile(source, &report).await;
+        assert_eq!(readings, vec![(1, 1.5)]);
+    }
+}
@@ -0,0 +1,54 @@
+//! Saving raw n3rgy responses to disk and serving them back without the network,
+//! so parsing problems with a user's own data can be reproduced offline.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub enum Recording {
+    /// Save every successful response body under this directory.
+    Record(PathBuf),
+    /// Answer requests from bodies saved by [`Recording::Record`], without
+    /// touching the network.
+    Replay(PathBuf),
+}
+
+impl Recording {
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Recording::Replay(_))
+    }
+
+    /// Save the body of the response to `request`, when recording.
+    pub fn save(&self, request: &str, body: &str) -> io::Result<()> {
+        let Recording::Record(dir) = self else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)?;
+        fs::write(file_for(dir, request), body)
+    }
+
+    /// The body recorded for `request`.
+    pub fn load(&self, request: &str) -> io::Result<String> {
+        let (Recording::Record(dir) | Recording::Replay(dir)) = self;
+        fs::read_to_string(file_for(dir, request))
+    }
+
+    /// Where the body for `request` is kept.
+    pub fn path(&self, request: &str) -> PathBuf {
+        let (Recording::Record(dir) | Recording::Replay(dir)) = self;
+        file_for(dir, request)
+    }
+}
+
+/// One file per request path and query, e.g.
+/// `electricity_consumption_1_start_202401010000_end_...json`.
+fn file_for(dir: &Path, request: &str) -> PathBuf {
+    let name: String = request
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let name = name.trim_matches('_');
+    let name = if name.is_empty() { "index" } else { name };
+    dir.join(format!("{}.json", name))
+}
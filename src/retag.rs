@@ -0,0 +1,69 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+use chrono::Duration;
+use influxdb::ReadQuery;
+use log::info;
+
+use crate::line_protocol::Point;
+use crate::migrate::{self, Chunks};
+
+/// Rewrite the points in `measurement` under the current tag schema, and into
+/// `rename` when given, then with `delete_old` drop the series they were read
+/// from. Series already tagged the current way, and not renamed, are left
+/// alone. Returns how many points were rewritten.
+pub async fn run(
+    client: &influxdb::Client,
+    measurement: &str,
+    rename: Option<&str>,
+    delete_old: bool,
+    chunk: Duration,
+) -> Result<usize, Box<dyn Error>> {
+    let Some(mut chunks) = Chunks::new(client, measurement, chunk).await? else {
+        info!("{} is empty", measurement);
+        return Ok(0);
+    };
+
+    let mut rewritten = 0;
+    // tags of each series rewritten, to drop once they've all been copied
+    let mut old_series: BTreeSet<BTreeMap<String, String>> = BTreeSet::new();
+    while let Some(points) = chunks.next().await? {
+        let mut queries = Vec::new();
+        for mut point in points {
+            let old_tags = point.tags.clone();
+            let retagged = migrate::retag(&mut point.tags);
+            if !retagged && rename.is_none() {
+                continue;
+            }
+            if let Some(rename) = rename {
+                point.measurement = rename.to_string();
+            }
+            old_series.insert(old_tags);
+            queries.push(Point::into_query(point));
+        }
+        rewritten += queries.len();
+        if !queries.is_empty() {
+            client.query(queries).await?;
+        }
+    }
+
+    if delete_old {
+        for tags in &old_series {
+            let mut query = format!("DROP SERIES FROM \"{}\"", measurement);
+            if !tags.is_empty() {
+                let conditions: Vec<String> = tags
+                    .iter()
+                    .map(|(tag, value)| format!("\"{}\" = '{}'", tag, value.replace('\'', "\\'")))
+                    .collect();
+                query = format!("{} WHERE {}", query, conditions.join(" AND "));
+            }
+            client.query(ReadQuery::new(query)).await?;
+        }
+        info!(
+            "dropped {} old series from {}",
+            old_series.len(),
+            measurement
+        );
+    }
+    Ok(rewritten)
+}
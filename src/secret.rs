@@ -0,0 +1,63 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+/// A token or password that is redacted wherever it is formatted, so it can't
+/// leak into logs or error messages. Use [`expose`](Self::expose) at the one
+/// point the real value is needed.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: impl Into<String>) -> SecretString {
+        SecretString(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Read a secret mounted as a file, such as a Docker or Kubernetes secret,
+    /// ignoring surrounding whitespace.
+    pub fn read_from(path: &Path) -> io::Result<SecretString> {
+        Ok(SecretString::new(fs::read_to_string(path)?.trim()))
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SecretString(\"[redacted]\")")
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl FromStr for SecretString {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(SecretString::new(s))
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        SecretString(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        SecretString::new(secret)
+    }
+}
@@ -0,0 +1,159 @@
+//! GB settlement periods, the half hours of the UK local day that wholesale
+//! and Agile-style half-hourly prices are quoted against.
+
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::London;
+
+/// Length of a settlement period, the half hour each reading covers.
+pub const PERIOD_MINUTES: i64 = 30;
+
+/// Settlement period of the half hour ending at `end`, which is how n3rgy
+/// stamps half-hourly readings.
+///
+/// Periods count from 1 at UK local midnight, so a day has 48, or 46 and 50
+/// on the days the clocks go forward and back.
+pub fn settlement_period(end: DateTime<Utc>) -> u32 {
+    let start = end - Duration::minutes(PERIOD_MINUTES);
+    let midnight = uk_midnight(settlement_date(end));
+    ((start - midnight).num_minutes() / PERIOD_MINUTES + 1) as u32
+}
+
+/// UK local day the half hour ending at `end` belongs to.
+pub fn settlement_date(end: DateTime<Utc>) -> NaiveDate {
+    (end - Duration::minutes(PERIOD_MINUTES))
+        .with_timezone(&London)
+        .date_naive()
+}
+
+/// Half hours in the UK local day: 48, or 46 and 50 when the clocks change.
+pub fn periods_in_day(date: NaiveDate) -> u32 {
+    let next = date.succ_opt().unwrap();
+    ((uk_midnight(next) - uk_midnight(date)).num_minutes() / PERIOD_MINUTES) as u32
+}
+
+/// UK local days wholly inside `start..=end` whose half-hourly readings, given
+/// by the time each ends, don't number [`periods_in_day`], as
+/// `(date, found, expected)`.
+pub fn mismatched_days(
+    ends: impl IntoIterator<Item = DateTime<Utc>>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<(NaiveDate, u32, u32)> {
+    let mut found: BTreeMap<NaiveDate, HashSet<DateTime<Utc>>> = BTreeMap::new();
+    for time in ends {
+        found.entry(settlement_date(time)).or_default().insert(time);
+    }
+
+    let mut mismatched = Vec::new();
+    let mut date = start.with_timezone(&London).date_naive();
+    if uk_midnight(date) < start {
+        date = date.succ_opt().unwrap();
+    }
+    loop {
+        let next = date.succ_opt().unwrap();
+        if uk_midnight(next) > end {
+            break;
+        }
+        let expected = periods_in_day(date);
+        let count = found.get(&date).map_or(0, |times| times.len() as u32);
+        if count != expected {
+            mismatched.push((date, count, expected));
+        }
+        date = next;
+    }
+    mismatched
+}
+
+/// The start of `date` in UK local time. GB clocks change at 01:00 and 02:00,
+/// so midnight always exists and is never repeated.
+pub fn uk_midnight(date: NaiveDate) -> DateTime<Utc> {
+    London
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    /// Each half hour of the UK day `date`, by the time it ends.
+    fn half_hours(date: NaiveDate) -> Vec<DateTime<Utc>> {
+        (1..=periods_in_day(date) as i64)
+            .map(|period| uk_midnight(date) + Duration::minutes(period * PERIOD_MINUTES))
+            .collect()
+    }
+
+    #[test]
+    fn periods_in_day_follows_the_clocks() {
+        assert_eq!(periods_in_day(date(6, 12)), 48);
+        assert_eq!(periods_in_day(date(3, 31)), 46);
+        assert_eq!(periods_in_day(date(10, 27)), 50);
+    }
+
+    #[test]
+    fn settlement_period_counts_from_uk_midnight() {
+        // BST, so UK midnight is 23:00 UTC the day before
+        assert_eq!(settlement_period(utc(6, 11, 23, 30)), 1);
+        assert_eq!(settlement_date(utc(6, 11, 23, 30)), date(6, 12));
+        assert_eq!(settlement_period(utc(6, 12, 23, 0)), 48);
+        assert_eq!(settlement_date(utc(6, 12, 23, 0)), date(6, 12));
+    }
+
+    #[test]
+    fn settlement_period_across_clock_changes() {
+        // GMT at midnight, BST from 01:00 GMT
+        assert_eq!(settlement_period(utc(3, 31, 0, 30)), 1);
+        assert_eq!(settlement_period(utc(3, 31, 23, 0)), 46);
+        assert_eq!(settlement_date(utc(3, 31, 23, 0)), date(3, 31));
+        // BST at midnight, GMT from 01:00 GMT
+        assert_eq!(settlement_period(utc(10, 26, 23, 30)), 1);
+        assert_eq!(settlement_period(utc(10, 28, 0, 0)), 50);
+        assert_eq!(settlement_date(utc(10, 28, 0, 0)), date(10, 27));
+        for day in [date(3, 31), date(10, 27)] {
+            let periods: Vec<_> = half_hours(day).into_iter().map(settlement_period).collect();
+            assert_eq!(periods, (1..=periods_in_day(day)).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn mismatched_days_finds_short_and_long_days() {
+        let (start, end) = (uk_midnight(date(3, 30)), uk_midnight(date(4, 1)));
+        let ends = [date(3, 30), date(3, 31)].into_iter().flat_map(half_hours);
+        assert!(mismatched_days(ends, start, end).is_empty());
+
+        let mut ends = half_hours(date(10, 27));
+        ends.pop();
+        let (start, end) = (uk_midnight(date(10, 27)), uk_midnight(date(10, 28)));
+        assert_eq!(
+            mismatched_days(ends, start, end),
+            vec![(date(10, 27), 49, 50)]
+        );
+
+        // a normal day still needs all 48
+        let ends = half_hours(date(6, 12)).into_iter().take(46);
+        let (start, end) = (uk_midnight(date(6, 12)), uk_midnight(date(6, 13)));
+        assert_eq!(
+            mismatched_days(ends, start, end),
+            vec![(date(6, 12), 46, 48)]
+        );
+    }
+
+    #[test]
+    fn mismatched_days_skips_partial_days() {
+        let ends = half_hours(date(6, 12)).into_iter().take(10);
+        let start = uk_midnight(date(6, 12)) + Duration::hours(1);
+        assert!(mismatched_days(ends, start, uk_midnight(date(6, 13))).is_empty());
+    }
+}
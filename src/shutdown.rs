@@ -0,0 +1,54 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{info, warn};
+use tokio::sync::Notify;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static NOTIFY: Notify = Notify::const_new();
+
+/// Listen for SIGINT/SIGTERM in the background.
+///
+/// The first signal asks the run to stop once the in-flight batch has been
+/// written; a second one exits immediately.
+pub fn listen() {
+    tokio::spawn(async {
+        signal().await;
+        info!("shutdown requested, finishing the current batch");
+        REQUESTED.store(true, Ordering::SeqCst);
+        NOTIFY.notify_waiters();
+
+        signal().await;
+        warn!("second shutdown signal received, exiting immediately");
+        process::exit(130);
+    });
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Resolves once shutdown has been requested.
+pub async fn wait() {
+    let notified = NOTIFY.notified();
+    if requested() {
+        return;
+    }
+    notified.await;
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    tokio::signal::ctrl_c().await.unwrap();
+}
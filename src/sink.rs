@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+#[cfg(feature = "otel")]
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use influxdb::{Query, ReadQuery, WriteQuery};
+use n3rgy_rs::client::Window;
+use n3rgy_rs::models::Resource;
+use n3rgy_rs::secret::SecretString;
+use serde::Deserialize;
+
+#[cfg(feature = "s3")]
+use crate::archive::Archive;
+use crate::duckdb::DuckDb;
+use crate::graphite::Graphite;
+use crate::http_sink::HttpSink;
+use crate::metrics;
+use crate::partition::PartitionedFiles;
+#[cfg(feature = "otel")]
+use crate::telemetry;
+use crate::victoria::VictoriaMetrics;
+
+/// How to authenticate to InfluxDB.
+#[derive(Clone)]
+pub enum InfluxAuth {
+    Token(SecretString),
+    /// Username and password, for InfluxDB 1.x servers without tokens.
+    Basic {
+        username: String,
+        password: SecretString,
+    },
+}
+
+impl InfluxAuth {
+    pub fn client(
+        &self,
+        uri: &str,
+        database: &str,
+        http: Option<&influx_reqwest::Client>,
+    ) -> influxdb::Client {
+        let mut client = influxdb::Client::new(uri, database);
+        if let Some(http) = http {
+            client = client.with_http_client(http.clone());
+        }
+        match self {
+            InfluxAuth::Token(token) => client.with_token(token.expose()),
+            InfluxAuth::Basic { username, password } => {
+                client.with_auth(username, password.expose())
+            }
+        }
+    }
+}
+
+/// Where transformed points end up.
+pub enum Sink {
+    InfluxDb(influxdb::Client),
+    /// Print the line protocol to stdout instead of writing it anywhere.
+    DryRun,
+    /// Append the line protocol to a file, e.g. for `influx write` or Telegraf.
+    File(Mutex<Box<dyn Write + Send>>),
+    /// Line protocol files split by date or tags, optionally compressed.
+    Files(PartitionedFiles),
+    /// POST each batch as JSON to an HTTP endpoint.
+    Http(HttpSink),
+    VictoriaMetrics(VictoriaMetrics),
+    Graphite(Graphite),
+    DuckDb(DuckDb),
+    /// Upload each batch to S3-compatible object storage.
+    #[cfg(feature = "s3")]
+    Archive(Archive),
+}
+
+impl Sink {
+    /// A line protocol sink appending to `path`, or writing to stdout for `-`.
+    pub fn file(path: &Path) -> io::Result<Sink> {
+        let out: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            ))
+        };
+        Ok(Sink::File(Mutex::new(out)))
+    }
+
+    pub async fn write(&self, points: Vec<WriteQuery>) -> Result<(), Box<dyn Error>> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        #[cfg(feature = "otel")]
+        let (started, count) = (SystemTime::now(), points.len());
+        let result = self.write_points(points).await;
+        #[cfg(feature = "otel")]
+        telemetry::sink_write(
+            self.kind(),
+            count,
+            started,
+            result.as_ref().err().map(|e| e.as_ref()),
+        );
+        result
+    }
+
+    /// Name of the sink, as traced or reported.
+    #[cfg(any(feature = "otel", feature = "sentry"))]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Sink::InfluxDb(_) => "influxdb",
+            Sink::DryRun => "dry-run",
+            Sink::File(_) | Sink::Files(_) => "file",
+            Sink::Http(_) => "http",
+            Sink::VictoriaMetrics(_) => "victoriametrics",
+            Sink::Graphite(_) => "graphite",
+            Sink::DuckDb(_) => "duckdb",
+            #[cfg(feature = "s3")]
+            Sink::Archive(_) => "s3",
+        }
+    }
+
+    async fn write_points(&self, points: Vec<WriteQuery>) -> Result<(), Box<dyn Error>> {
+        match self {
+            Sink::InfluxDb(client) => {
+                let count = points.len() as u64;
+                client.query(points).await?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["influxdb"])
+                    .inc_by(count);
+            }
+            Sink::DryRun => {
+                for point in points {
+                    println!("{}", point.build()?.get());
+                }
+            }
+            Sink::File(out) => {
+                let count = points.len() as u64;
+                let mut out = out.lock().unwrap();
+                for point in points {
+                    writeln!(out, "{}", point.build()?.get())?;
+                }
+                out.flush()?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["file"])
+                    .inc_by(count);
+            }
+            Sink::Files(files) => {
+                let lines = points
+                    .iter()
+                    .map(|point| Ok(point.build()?.get()))
+                    .collect::<Result<Vec<_>, influxdb::Error>>()?;
+                let count = files.write(&lines)?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["file"])
+                    .inc_by(count as u64);
+            }
+            Sink::Http(http) => {
+                let lines = points
+                    .iter()
+                    .map(|point| Ok(point.build()?.get()))
+                    .collect::<Result<Vec<_>, influxdb::Error>>()?;
+                http.post(&lines).await?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["http"])
+                    .inc_by(lines.len() as u64);
+            }
+            Sink::VictoriaMetrics(victoria) => {
+                let lines = points
+                    .iter()
+                    .map(|point| Ok(point.build()?.get()))
+                    .collect::<Result<Vec<_>, influxdb::Error>>()?;
+                let samples = victoria.import(&lines).await?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["victoriametrics"])
+                    .inc_by(samples as u64);
+            }
+            Sink::Graphite(graphite) => {
+                let lines = points
+                    .iter()
+                    .map(|point| Ok(point.build()?.get()))
+                    .collect::<Result<Vec<_>, influxdb::Error>>()?;
+                let metrics = graphite.send(&lines).await?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["graphite"])
+                    .inc_by(metrics as u64);
+            }
+            Sink::DuckDb(duckdb) => {
+                let lines = points
+                    .iter()
+                    .map(|point| Ok(point.build()?.get()))
+                    .collect::<Result<Vec<_>, influxdb::Error>>()?;
+                let rows = duckdb.append(&lines).await?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["duckdb"])
+                    .inc_by(rows as u64);
+            }
+            #[cfg(feature = "s3")]
+            Sink::Archive(archive) => {
+                let lines = points
+                    .iter()
+                    .map(|point| Ok(point.build()?.get()))
+                    .collect::<Result<Vec<_>, influxdb::Error>>()?;
+                let count = archive.upload(&lines).await?;
+                metrics::POINTS_WRITTEN
+                    .with_label_values(&["s3"])
+                    .inc_by(count as u64);
+            }
+        }
+        Ok(())
+    }
+
+    /// Timestamp of the newest `field` value stored in `measurement` for a resource,
+    /// narrowed to points carrying `extra_tags`.
+    pub async fn latest(
+        &self,
+        measurement: &str,
+        field: &str,
+        resource: &Resource,
+        extra_tags: &BTreeMap<String, String>,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let Sink::InfluxDb(client) = self else {
+            return Ok(None);
+        };
+
+        let conditions = conditions(resource, extra_tags);
+        let mut query = format!("SELECT last(\"{}\") FROM \"{}\"", field, measurement);
+        if !conditions.is_empty() {
+            query = format!("{} WHERE {}", query, conditions.join(" AND "));
+        }
+
+        #[derive(Deserialize)]
+        struct Latest {
+            time: DateTime<Utc>,
+        }
+        let mut result = client.json_query(ReadQuery::new(query)).await?;
+        let latest = result.deserialize_next::<Latest>()?;
+        Ok(latest
+            .series
+            .into_iter()
+            .flat_map(|series| series.values)
+            .map(|value| value.time)
+            .max())
+    }
+
+    /// The newest `field` value stored in `measurement` for a resource and
+    /// `extra_tags` from before `before`.
+    pub async fn value_before(
+        &self,
+        measurement: &str,
+        field: &str,
+        resource: &Resource,
+        extra_tags: &BTreeMap<String, String>,
+        before: DateTime<Utc>,
+    ) -> Result<Option<f64>, Box<dyn Error>> {
+        let Sink::InfluxDb(client) = self else {
+            return Ok(None);
+        };
+
+        let mut conditions = conditions(resource, extra_tags);
+        conditions.push(format!("time < '{}'", before.to_rfc3339()));
+        let query = format!(
+            "SELECT last(\"{}\") FROM \"{}\" WHERE {}",
+            field,
+            measurement,
+            conditions.join(" AND ")
+        );
+
+        #[derive(Deserialize)]
+        struct Last {
+            last: f64,
+        }
+        let mut result = client.json_query(ReadQuery::new(query)).await?;
+        let last = result.deserialize_next::<Last>()?;
+        Ok(last
+            .series
+            .into_iter()
+            .flat_map(|series| series.values)
+            .map(|value| value.last)
+            .next())
+    }
+
+    /// Each `field` value stored in `measurement` for a resource and
+    /// `extra_tags` from `start` up to `end`, oldest first.
+    pub async fn values_between(
+        &self,
+        measurement: &str,
+        field: &str,
+        resource: &Resource,
+        extra_tags: &BTreeMap<String, String>,
+        (start, end): (DateTime<Utc>, DateTime<Utc>),
+    ) -> Result<Vec<(DateTime<Utc>, f64)>, Box<dyn Error>> {
+        let Sink::InfluxDb(client) = self else {
+            return Ok(Vec::new());
+        };
+
+        let mut conditions = conditions(resource, extra_tags);
+        conditions.push(format!(
+            "time >= '{}' AND time < '{}'",
+            start.to_rfc3339(),
+            end.to_rfc3339()
+        ));
+        let query = format!(
+            "SELECT \"{}\" AS \"value\" FROM \"{}\" WHERE {}",
+            field,
+            measurement,
+            conditions.join(" AND ")
+        );
+
+        #[derive(Deserialize)]
+        struct Point {
+            time: DateTime<Utc>,
+            value: f64,
+        }
+        let mut result = client.json_query(ReadQuery::new(query)).await?;
+        let points = result.deserialize_next::<Point>()?;
+        Ok(points
+            .series
+            .into_iter()
+            .flat_map(|series| series.values)
+            .map(|point| (point.time, point.value))
+            .collect())
+    }
+
+    /// The sum of `field` in `measurement` for each UK day from `start`,
+    /// narrowed to a resource and points carrying `extra_tags`. Days without
+    /// points are left out.
+    pub async fn daily_sums(
+        &self,
+        measurement: &str,
+        field: &str,
+        resource: &Resource,
+        extra_tags: &BTreeMap<String, String>,
+        start: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, f64)>, Box<dyn Error>> {
+        let Sink::InfluxDb(client) = self else {
+            return Ok(Vec::new());
+        };
+
+        let mut conditions = conditions(resource, extra_tags);
+        conditions.push(format!("time >= '{}'", start.to_rfc3339()));
+        let query = format!(
+            "SELECT sum(\"{}\") FROM \"{}\" WHERE {} GROUP BY time(1d) tz('Europe/London')",
+            field,
+            measurement,
+            conditions.join(" AND ")
+        );
+
+        #[derive(Deserialize)]
+        struct Day {
+            time: DateTime<Utc>,
+            sum: Option<f64>,
+        }
+        let mut result = client.json_query(ReadQuery::new(query)).await?;
+        let days = result.deserialize_next::<Day>()?;
+        Ok(days
+            .series
+            .into_iter()
+            .flat_map(|series| series.values)
+            .filter_map(|day| Some((day.time, day.sum?)))
+            .collect())
+    }
+
+    /// Timestamps at which `measurement` already holds a `field` value for a
+    /// resource and `extra_tags` within `window`.
+    pub async fn existing_times(
+        &self,
+        measurement: &str,
+        field: &str,
+        resource: &Resource,
+        extra_tags: &BTreeMap<String, String>,
+        (start, end): Window,
+    ) -> Result<HashSet<DateTime<Utc>>, Box<dyn Error>> {
+        let Sink::InfluxDb(client) = self else {
+            return Ok(HashSet::new());
+        };
+
+        let mut conditions = conditions(resource, extra_tags);
+        conditions.push(format!(
+            "time >= '{}' AND time <= '{}'",
+            start.to_utc().to_rfc3339(),
+            end.to_utc().to_rfc3339()
+        ));
+        let query = format!(
+            "SELECT \"{}\" FROM \"{}\" WHERE {}",
+            field,
+            measurement,
+            conditions.join(" AND ")
+        );
+
+        #[derive(Deserialize)]
+        struct Point {
+            time: DateTime<Utc>,
+        }
+        let mut result = client.json_query(ReadQuery::new(query)).await?;
+        let points = result.deserialize_next::<Point>()?;
+        Ok(points
+            .series
+            .into_iter()
+            .flat_map(|series| series.values)
+            .map(|value| value.time)
+            .collect())
+    }
+}
+
+/// InfluxQL conditions matching a resource's tags and `extra_tags`.
+fn conditions(resource: &Resource, extra_tags: &BTreeMap<String, String>) -> Vec<String> {
+    let tags = [
+        ("fuel", &resource.fuel),
+        ("element", &resource.element),
+        ("type", &resource.data_type),
+    ];
+    tags.iter()
+        .filter_map(|(tag, value)| Some((*tag, value.as_ref()?.as_str())))
+        .chain(
+            extra_tags
+                .iter()
+                .map(|(tag, value)| (tag.as_str(), value.as_str())),
+        )
+        .map(|(tag, value)| format!("\"{}\" = '{}'", tag, value.replace('\'', "\\'")))
+        .collect()
+}
@@ -0,0 +1,119 @@
+//! Net usage and self-consumption for homes with solar panels, from the
+//! import and export a smart meter records each half hour.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+
+use crate::models::{ConsumptionReading, Granularity, Resource};
+
+/// Share of their rated output panels typically deliver once inverter, wiring,
+/// temperature and soiling losses are taken off.
+pub const DEFAULT_PERFORMANCE_RATIO: f64 = 0.8;
+
+/// Irradiance panels are rated at, in W/m².
+const STANDARD_IRRADIANCE: f64 = 1000.0;
+
+/// A panel array's rough output, modelled from the irradiance on flat ground.
+#[derive(Clone, Copy, Debug)]
+pub struct PvArray {
+    /// Rated output, in kWp.
+    pub capacity: f64,
+    pub performance_ratio: f64,
+}
+
+impl PvArray {
+    /// kWh generated over the half hour ending at `end`, from the irradiance
+    /// of each hour keyed by the hour's end.
+    pub fn generation(
+        &self,
+        irradiance: &BTreeMap<DateTime<Utc>, f64>,
+        end: DateTime<Utc>,
+    ) -> Option<f64> {
+        let hour = end.duration_trunc(Duration::hours(1)).ok()?;
+        let hour_end = if hour == end {
+            end
+        } else {
+            hour + Duration::hours(1)
+        };
+        let irradiance = irradiance.get(&hour_end)?;
+        Some(self.capacity * irradiance / STANDARD_IRRADIANCE * self.performance_ratio * 0.5)
+    }
+}
+
+/// An interval's electricity flows at a home that exports.
+#[derive(Clone, Debug)]
+pub struct SolarReading {
+    pub time: DateTime<Utc>,
+    pub import: f64,
+    pub export: f64,
+    /// `import − export`, negative when more was exported than imported.
+    pub net: f64,
+    /// Estimated kWh the panels generated, for half hours with an irradiance.
+    pub generation: Option<f64>,
+    /// Estimated kWh of the generation used at home rather than exported.
+    pub self_consumption: Option<f64>,
+    /// n3rgy resource path of the import, written as the tags from
+    /// [`Resource::add_tags`].
+    pub resource: String,
+}
+
+impl InfluxDbWriteable for SolarReading {
+    fn into_query<I: Into<String>>(self, name: I) -> WriteQuery {
+        let mut query = WriteQuery::new(self.time, name)
+            .add_field("import", self.import)
+            .add_field("export", self.export)
+            .add_field("net", self.net);
+        if let Some(generation) = self.generation {
+            query = query.add_field("generation", generation);
+        }
+        if let Some(self_consumption) = self.self_consumption {
+            query = query.add_field("self_consumption", self_consumption);
+        }
+        Resource::parse(&self.resource).add_tags(query)
+    }
+}
+
+/// The flows of each interval with both an import and an export reading,
+/// estimating generation and self-consumption when an array is given with the
+/// hourly irradiance over the readings.
+pub fn solar_readings<I, E>(
+    import: I,
+    export: E,
+    array: Option<(PvArray, &BTreeMap<DateTime<Utc>, f64>)>,
+) -> Vec<SolarReading>
+where
+    I: IntoIterator,
+    I::Item: Borrow<ConsumptionReading>,
+    E: IntoIterator,
+    E::Item: Borrow<ConsumptionReading>,
+{
+    let exported: BTreeMap<DateTime<Utc>, f64> = export
+        .into_iter()
+        .map(|reading| {
+            let reading = reading.borrow();
+            (reading.time, reading.consumption)
+        })
+        .collect();
+    import
+        .into_iter()
+        .filter_map(|reading| {
+            let reading = reading.borrow();
+            let export = *exported.get(&reading.time)?;
+            let generation = array
+                .filter(|_| reading.granularity == Granularity::HalfHour.as_param())
+                .and_then(|(array, irradiance)| array.generation(irradiance, reading.time));
+            Some(SolarReading {
+                time: reading.time,
+                import: reading.consumption,
+                export,
+                net: reading.consumption - export,
+                generation,
+                self_consumption: generation.map(|generation| (generation - export).max(0.0)),
+                resource: reading.resource.clone(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,147 @@
+//! Where readings and tariffs are loaded from: n3rgy, Glowmarkt or Octopus
+//! Energy. Another UK smart meter data provider implements [`Source`] and is
+//! added to [`Provider`].
+
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+
+use crate::client::{N3rgyClient, Window};
+use crate::error::Error;
+use crate::models::{ConsumptionOrTariff, DataSource, EnergyType, Granularity, RequestType};
+
+/// The data providers `--source` can choose between.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Provider {
+    #[default]
+    N3rgy,
+    /// Hildebrand's Glowmarkt API, behind the Bright app
+    Glowmarkt,
+    /// The Octopus Energy API, for Octopus customers
+    Octopus,
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Provider::N3rgy => "n3rgy",
+            Provider::Glowmarkt => "Glowmarkt",
+            Provider::Octopus => "Octopus Energy",
+        })
+    }
+}
+
+/// One meter's readings and tariffs at a data provider, read for a single
+/// meter element.
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// The provider, for messages.
+    fn provider(&self) -> Provider;
+
+    fn element(&self) -> u8;
+
+    /// The same meter read for another element.
+    fn with_element(&self, element: u8) -> Arc<dyn Source>;
+
+    fn granularity(&self) -> Granularity;
+
+    /// The windows `start..end` is requested in, each small enough for one request.
+    fn windows(&self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<Window>;
+
+    /// Fetch a single window returned by [`Source::windows`].
+    async fn fetch(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error>;
+
+    /// Every fuel, data type and element readable, with the range held for each.
+    async fn discover(&self) -> Result<Vec<DataSource>, Error>;
+
+    /// The meter elements available for a fuel and data type.
+    async fn elements(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+    ) -> Result<Vec<u8>, Error>;
+
+    /// Narrow `start..end` to the data the provider holds, or `None` when it
+    /// holds none of it.
+    async fn clamp_to_available(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<Option<Window>, Error>;
+}
+
+#[async_trait]
+impl Source for N3rgyClient {
+    fn provider(&self) -> Provider {
+        Provider::N3rgy
+    }
+
+    fn element(&self) -> u8 {
+        N3rgyClient::element(self)
+    }
+
+    fn with_element(&self, element: u8) -> Arc<dyn Source> {
+        Arc::new(self.clone().with_element(element))
+    }
+
+    fn granularity(&self) -> Granularity {
+        N3rgyClient::granularity(self)
+    }
+
+    fn windows(&self, start: DateTime<Local>, end: DateTime<Local>) -> Vec<Window> {
+        N3rgyClient::windows(self, start, end)
+    }
+
+    async fn fetch(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<ConsumptionOrTariff, Error> {
+        N3rgyClient::fetch(self, energy_type, request_type, start, end).await
+    }
+
+    async fn discover(&self) -> Result<Vec<DataSource>, Error> {
+        N3rgyClient::discover(self).await
+    }
+
+    async fn elements(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+    ) -> Result<Vec<u8>, Error> {
+        N3rgyClient::elements(self, energy_type, request_type).await
+    }
+
+    async fn clamp_to_available(
+        &self,
+        energy_type: EnergyType,
+        request_type: RequestType,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<Option<Window>, Error> {
+        N3rgyClient::clamp_to_available(self, energy_type, request_type, start, end).await
+    }
+}
+
+/// The n3rgy-style resource path points are tagged from, for sources without
+/// one of their own.
+pub(crate) fn resource_path(
+    energy_type: EnergyType,
+    request_type: RequestType,
+    element: u8,
+) -> String {
+    format!("/{}/{}/{}", energy_type, request_type, element).to_lowercase()
+}
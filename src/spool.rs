@@ -0,0 +1,156 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use influx_reqwest::StatusCode;
+use log::{error, info};
+
+use crate::sink::InfluxAuth;
+
+/// File in the spool directory that batches InfluxDB rejects on retry are
+/// appended to, each after a `#` comment saying why, rather than being retried
+/// forever.
+pub const DEAD_LETTER: &str = "dead-letter";
+
+/// Batches InfluxDB couldn't be reached to write, kept on disk as line protocol
+/// until a later run gets them through, so an outage doesn't lose readings.
+pub struct Spool {
+    dir: PathBuf,
+    http: influx_reqwest::Client,
+    /// `/write` endpoint for the database, at the nanosecond precision points
+    /// are built with.
+    write_url: String,
+    auth: InfluxAuth,
+}
+
+impl Spool {
+    pub fn new(
+        dir: &Path,
+        uri: &str,
+        database: &str,
+        auth: InfluxAuth,
+        http: Option<&influx_reqwest::Client>,
+    ) -> Spool {
+        Spool {
+            dir: dir.to_path_buf(),
+            http: http.cloned().unwrap_or_default(),
+            write_url: format!(
+                "{}/write?db={}&precision=ns",
+                uri.trim_end_matches('/'),
+                database
+            ),
+            auth,
+        }
+    }
+
+    /// Keep `lines` for a later [`Spool::retry`], returning the file they went to.
+    pub fn save(&self, lines: &[String]) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        // sorts oldest first, and the pid keeps concurrent runs apart
+        let name = format!(
+            "{}-{}.lp",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            std::process::id()
+        );
+        let path = self.dir.join(name);
+        fs::write(&path, lines.join("\n") + "\n")?;
+        Ok(path)
+    }
+
+    /// Resend spooled batches oldest first, removing each once InfluxDB accepts
+    /// it and moving any it rejects to the [`DEAD_LETTER`] file, stopping only
+    /// while InfluxDB is still unreachable. Returns how many were sent.
+    pub async fn retry(&self) -> Result<usize, Box<dyn Error>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut batches: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "lp"))
+            .collect();
+        batches.sort();
+
+        let mut sent = 0;
+        for path in &batches {
+            let body = fs::read_to_string(path)?;
+            match self.send(&body).await {
+                Ok(()) => {
+                    fs::remove_file(path)?;
+                    info!("wrote spooled batch {}", path.display());
+                    sent += 1;
+                }
+                Err(SendError::Rejected(reason)) => {
+                    self.dead_letter(path, &body, &reason)?;
+                    error!(
+                        "InfluxDB rejected spooled batch {}, moved it to {}: {}",
+                        path.display(),
+                        DEAD_LETTER,
+                        reason
+                    );
+                }
+                Err(SendError::Unreachable(e)) => return Err(e),
+            }
+        }
+        Ok(sent)
+    }
+
+    fn dead_letter(&self, path: &Path, body: &str, reason: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(DEAD_LETTER))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        write!(file, "# {}: {}\n{}", name, reason.replace('\n', " "), body)?;
+        fs::remove_file(path)
+    }
+
+    async fn send(&self, body: &str) -> Result<(), SendError> {
+        let request = self.http.post(&self.write_url).body(body.to_string());
+        let request = match &self.auth {
+            InfluxAuth::Token(token) => {
+                request.header("Authorization", format!("Token {}", token.expose()))
+            }
+            InfluxAuth::Basic { username, password } => {
+                request.basic_auth(username, Some(password.expose()))
+            }
+        };
+        let response = request
+            .send()
+            .await
+            .map_err(|e| SendError::Unreachable(e.into()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let reason = format!("InfluxDB responded with {}: {}", status, body.trim());
+            // overloaded or restarting, rather than objecting to the points
+            return Err(
+                if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+                    SendError::Unreachable(reason.into())
+                } else {
+                    SendError::Rejected(reason)
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+enum SendError {
+    /// Worth trying again later.
+    Unreachable(Box<dyn Error>),
+    /// InfluxDB objected to the batch itself, so resending won't help.
+    Rejected(String),
+}
+
+/// Whether a sink write failed because InfluxDB couldn't be reached, rather
+/// than because it refused the points, so they're worth spooling.
+pub fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<influxdb::Error>(),
+        Some(influxdb::Error::ConnectionError { .. })
+    )
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Europe::London;
+use futures::TryStreamExt;
+use n3rgy_rs::aggregate::Period;
+use n3rgy_rs::client::Window;
+use n3rgy_rs::conversion::GasConversion;
+use n3rgy_rs::cost::price_consumption;
+use n3rgy_rs::models::{ConsumptionReading, EnergyType, Pricing, TariffPrice};
+use n3rgy_rs::settlement::settlement_date;
+use n3rgy_rs::N3rgyClient;
+
+/// Reduces the per-period values to one, `None` when there are none.
+type Summary = fn(&[f64]) -> Option<f64>;
+
+/// Consumption, and cost when priced, in one period.
+#[derive(Default)]
+struct Bucket {
+    consumption: f64,
+    cost: Option<f64>,
+}
+
+/// Print consumption (and cost, converted with `pricing`) per period over
+/// `start..end`, followed by the minimum, maximum, mean and total across
+/// periods.
+pub async fn run(
+    client: &N3rgyClient,
+    energy_type: EnergyType,
+    (start, end): Window,
+    period: Period,
+    with_cost: bool,
+    pricing: Pricing,
+) -> Result<(), n3rgy_rs::Error> {
+    let consumption: Vec<ConsumptionReading> = client
+        .consumption(energy_type, start..end)
+        .try_collect()
+        .await?;
+
+    let mut buckets: BTreeMap<DateTime<Utc>, Bucket> = BTreeMap::new();
+    for reading in &consumption {
+        buckets
+            .entry(period.date_start(settlement_date(reading.time)))
+            .or_default()
+            .consumption += reading.consumption;
+    }
+    if with_cost {
+        let mut tariff: Vec<TariffPrice> =
+            client.tariff(energy_type, start..end).try_collect().await?;
+        for price in &mut tariff {
+            price.price = pricing.apply(price.price);
+        }
+        for cost in price_consumption(&consumption, &tariff, GasConversion::default()) {
+            let bucket = buckets
+                .entry(period.date_start(settlement_date(cost.time)))
+                .or_default();
+            *bucket.cost.get_or_insert(0.0) += cost.total;
+        }
+    }
+
+    print_table(energy_type, period, &buckets);
+    Ok(())
+}
+
+fn print_table(energy_type: EnergyType, period: Period, buckets: &BTreeMap<DateTime<Utc>, Bucket>) {
+    println!("{} by {:?}", energy_type, period);
+    println!("{:<12} {:>14} {:>12}", "Period", "Consumption", "Cost");
+    let cost = |cost: Option<f64>| cost.map_or_else(|| "-".to_string(), |c| format!("{:.2}", c));
+    for (start, bucket) in buckets {
+        println!(
+            "{:<12} {:>14.3} {:>12}",
+            start.with_timezone(&London).format("%Y-%m-%d"),
+            bucket.consumption,
+            cost(bucket.cost)
+        );
+    }
+    if buckets.is_empty() {
+        println!("No readings in the range");
+        return;
+    }
+
+    let consumption: Vec<f64> = buckets.values().map(|b| b.consumption).collect();
+    let costs: Vec<f64> = buckets.values().filter_map(|b| b.cost).collect();
+    let summaries: [(&str, Summary); 4] = [
+        ("Min", |v| v.iter().copied().reduce(f64::min)),
+        ("Max", |v| v.iter().copied().reduce(f64::max)),
+        ("Mean", |v| {
+            (!v.is_empty()).then(|| v.iter().sum::<f64>() / v.len() as f64)
+        }),
+        ("Total", |v| (!v.is_empty()).then(|| v.iter().sum())),
+    ];
+    for (label, summarise) in summaries {
+        println!(
+            "{:<12} {:>14.3} {:>12}",
+            label,
+            summarise(&consumption).unwrap_or(0.0),
+            cost(summarise(&costs))
+        );
+    }
+}
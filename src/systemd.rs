@@ -0,0 +1,125 @@
+//! Supervision under systemd: `Type=notify` readiness, status and watchdog
+//! keep-alives. Each call does nothing unless systemd set `NOTIFY_SOCKET`.
+
+use std::path::Path;
+
+#[cfg(unix)]
+use log::{debug, info};
+#[cfg(unix)]
+use sd_notify::NotifyState;
+
+/// Tell systemd start-up has finished.
+pub fn ready() {
+    #[cfg(unix)]
+    send(&[NotifyState::Ready]);
+}
+
+/// The one-line status `systemctl status` shows.
+pub fn status(status: &str) {
+    #[cfg(unix)]
+    send(&[NotifyState::Status(status)]);
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+/// Tell systemd the daemon is reloading its config; [`ready`] says when it's done.
+pub fn reloading() {
+    #[cfg(unix)]
+    send(&[NotifyState::Reloading]);
+}
+
+/// Tell systemd the daemon is shutting down.
+pub fn stopping() {
+    #[cfg(unix)]
+    send(&[NotifyState::Stopping]);
+}
+
+/// Ping the watchdog at half its timeout in the background, when `WatchdogSec=`
+/// is set, so systemd restarts the daemon if it stops responding.
+pub fn spawn_watchdog() {
+    #[cfg(unix)]
+    {
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) {
+            return;
+        }
+        let period = std::time::Duration::from_micros(usec / 2);
+        info!("pinging the systemd watchdog every {:?}", period);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            loop {
+                ticks.tick().await;
+                send(&[NotifyState::Watchdog]);
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+fn send(states: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, states) {
+        debug!("could not notify systemd: {}", e);
+    }
+}
+
+/// A unit running `serve` with `serve_args`, restarted on failure or when the
+/// watchdog isn't pinged for `watchdog_sec` seconds.
+pub fn unit(exe: &Path, serve_args: &[String], env_file: &Path, watchdog_sec: u64) -> String {
+    let exec = std::iter::once(exe.display().to_string())
+        .chain(std::iter::once("serve".to_string()))
+        .chain(serve_args.iter().cloned())
+        .map(|arg| quote(&arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "\
+[Unit]
+Description=Sync smart meter data from n3rgy
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exec}
+ExecReload=/bin/kill -HUP $MAINPID
+# N3RGY_API_TOKEN, N3RGY_INFLUX_URI and other settings
+EnvironmentFile=-{env_file}
+Restart=on-failure
+RestartSec=30
+WatchdogSec={watchdog_sec}
+DynamicUser=yes
+StateDirectory=n3rgy-rs
+CacheDirectory=n3rgy-rs
+LogsDirectory=n3rgy-rs
+NoNewPrivileges=yes
+ProtectSystem=strict
+ProtectHome=yes
+PrivateTmp=yes
+
+[Install]
+WantedBy=multi-user.target
+",
+        exec = exec,
+        env_file = env_file.display(),
+        watchdog_sec = watchdog_sec,
+    )
+}
+
+/// `arg` quoted for `ExecStart=` when it holds anything systemd would split
+/// or expand.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '$' | '%' | ';'));
+    if plain {
+        return arg.to_string();
+    }
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "$$")
+        .replace('%', "%%");
+    format!("\"{}\"", escaped)
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Europe::London;
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use n3rgy_rs::models::{Resource, TariffPrice, PRICE};
+use serde_json::{json, Value};
+
+/// Measurement tariff change events are written to.
+pub const MEASUREMENT: &str = "tariff_change";
+
+/// A unit rate or standing charge that differs from the one before it.
+pub struct TariffChange {
+    pub time: DateTime<Utc>,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    pub resource: String,
+    pub price_type: String,
+    pub previous: f64,
+    pub value: f64,
+}
+
+impl InfluxDbWriteable for TariffChange {
+    fn into_query<I: Into<String>>(self, name: I) -> WriteQuery {
+        let query = WriteQuery::new(self.time, name)
+            .add_field("previous", self.previous)
+            .add_field("value", self.value)
+            .add_tag("price_type", self.price_type);
+        Resource::parse(&self.resource).add_tags(query)
+    }
+}
+
+/// Remembers the last unit rate and standing charge seen, so a sync spots
+/// where they move, including across windows.
+///
+/// Unit rates are compared with the same UK half hour the day before, so the
+/// daily pattern of a time-of-use tariff isn't taken for changes. A rate counts
+/// as changed where it first differs from that day's, not again for each later
+/// half hour at the new rate.
+#[derive(Default)]
+pub struct ChangeDetector {
+    /// Last value of each price type.
+    last: HashMap<String, f64>,
+    /// Last unit rate at each UK time of day.
+    slots: HashMap<NaiveTime, f64>,
+}
+
+impl ChangeDetector {
+    /// Start from a value already stored, oldest first, so a change at the
+    /// start of a run isn't missed.
+    pub fn seed(&mut self, price_type: &str, time: DateTime<Utc>, value: f64) {
+        self.record(price_type, time, value);
+    }
+
+    /// The changes among `prices`, in time order.
+    pub fn changes(&mut self, prices: impl IntoIterator<Item = TariffPrice>) -> Vec<TariffChange> {
+        let mut prices: Vec<TariffPrice> = prices.into_iter().collect();
+        prices.sort_by_key(|price| price.time);
+
+        let mut changes = Vec::new();
+        for price in prices {
+            let (last, slot) = self.record(&price.price_type, price.time, price.price);
+            let previous = match price.price_type.as_str() {
+                // only a move away from the rate just before, so a flat rate
+                // changing at 10:00 isn't reported again at 10:30
+                PRICE if last == Some(price.price) => None,
+                PRICE => slot,
+                _ => last,
+            };
+            match previous {
+                Some(previous) if previous != price.price => changes.push(TariffChange {
+                    time: price.time,
+                    resource: price.resource,
+                    price_type: price.price_type,
+                    previous,
+                    value: price.price,
+                }),
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    /// Note `value` as the latest of its type, returning the one before it and,
+    /// for unit rates, the one at the same time of day before.
+    fn record(
+        &mut self,
+        price_type: &str,
+        time: DateTime<Utc>,
+        value: f64,
+    ) -> (Option<f64>, Option<f64>) {
+        let last = self.last.insert(price_type.to_string(), value);
+        let slot = (price_type == PRICE)
+            .then(|| self.slots.insert(time.with_timezone(&London).time(), value))
+            .flatten();
+        (last, slot)
+    }
+}
+
+impl TariffChange {
+    /// The change as a webhook payload.
+    pub fn to_json(&self, property: Option<&str>) -> Value {
+        json!({
+            "property": property,
+            "resource": self.resource,
+            "price_type": self.price_type,
+            "time": self.time.to_rfc3339(),
+            "previous": self.previous,
+            "value": self.value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use n3rgy_rs::models::STANDING_CHARGE;
+
+    /// A GMT day of half-hourly unit rates, `night` from midnight to 07:00 and
+    /// `day` after.
+    fn economy_7(day: u32, night: f64, rate: f64) -> Vec<TariffPrice> {
+        let midnight = Utc.with_ymd_and_hms(2024, 2, day, 0, 0, 0).unwrap();
+        (0..48)
+            .map(|half_hour| TariffPrice {
+                time: midnight + Duration::minutes(30 * half_hour),
+                price: if half_hour < 14 { night } else { rate },
+                resource: "/electricity/tariff/1".to_string(),
+                price_type: PRICE.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn time_of_use_pattern_is_not_a_change() {
+        let mut detector = ChangeDetector::default();
+        for day in 1..=3 {
+            assert!(detector.changes(economy_7(day, 10.0, 30.0)).is_empty());
+        }
+    }
+
+    #[test]
+    fn time_of_use_rates_changing() {
+        let mut detector = ChangeDetector::default();
+        detector.changes(economy_7(1, 10.0, 30.0));
+        let changes = detector.changes(economy_7(2, 12.0, 35.0));
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.time.time().to_string(),
+                    change.previous,
+                    change.value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("00:00:00".to_string(), 10.0, 12.0),
+                ("07:00:00".to_string(), 30.0, 35.0)
+            ]
+        );
+        assert!(detector.changes(economy_7(3, 12.0, 35.0)).is_empty());
+    }
+
+    #[test]
+    fn flat_rate_change_is_reported_once() {
+        let mut detector = ChangeDetector::default();
+        detector.changes(economy_7(1, 25.0, 25.0));
+        let mut prices = economy_7(2, 25.0, 25.0);
+        for price in &mut prices[20..] {
+            price.price = 27.0;
+        }
+        let changes = detector.changes(prices);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].time.time().to_string(), "10:00:00");
+        assert!(detector.changes(economy_7(3, 27.0, 27.0)).is_empty());
+    }
+
+    #[test]
+    fn seeded_from_the_day_before() {
+        let mut detector = ChangeDetector::default();
+        for price in economy_7(1, 10.0, 30.0) {
+            detector.seed(PRICE, price.time, price.price);
+        }
+        assert!(detector.changes(economy_7(2, 10.0, 30.0)).is_empty());
+    }
+
+    #[test]
+    fn standing_charge_change() {
+        let mut detector = ChangeDetector::default();
+        let charge = |day, price| TariffPrice {
+            time: Utc.with_ymd_and_hms(2024, 2, day, 0, 0, 0).unwrap(),
+            price,
+            resource: "/electricity/tariff/1".to_string(),
+            price_type: STANDING_CHARGE.to_string(),
+        };
+        detector.seed(STANDING_CHARGE, charge(1, 0.0).time, 50.0);
+        assert!(detector.changes([charge(1, 50.0)]).is_empty());
+        let changes = detector.changes([charge(2, 55.0)]);
+        assert_eq!((changes[0].previous, changes[0].value), (50.0, 55.0));
+    }
+}
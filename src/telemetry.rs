@@ -0,0 +1,169 @@
+use std::error::Error;
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use opentelemetry::metrics::Histogram;
+use opentelemetry::trace::{Span, Status, Tracer};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+
+const NAME: &str = "n3rgy-rs";
+
+/// Providers set up by [`init`], kept to flush them on exit.
+static PROVIDERS: OnceLock<(SdkTracerProvider, SdkMeterProvider)> = OnceLock::new();
+
+static API_DURATION: LazyLock<Histogram<f64>> = LazyLock::new(|| {
+    global::meter(NAME)
+        .f64_histogram("n3rgy.api.duration")
+        .with_unit("s")
+        .with_description("Time taken by requests to the n3rgy API")
+        .build()
+});
+
+static BATCH_DURATION: LazyLock<Histogram<f64>> = LazyLock::new(|| {
+    global::meter(NAME)
+        .f64_histogram("n3rgy.batch.duration")
+        .with_unit("s")
+        .with_description("Time taken to write a window's batch of points, spooling included")
+        .build()
+});
+
+static SINK_DURATION: LazyLock<Histogram<f64>> = LazyLock::new(|| {
+    global::meter(NAME)
+        .f64_histogram("n3rgy.sink.write.duration")
+        .with_unit("s")
+        .with_description("Time taken by writes to the sink")
+        .build()
+});
+
+/// Export traces and metrics over OTLP/HTTP to the collector at `endpoint`,
+/// e.g. `http://localhost:4318`.
+pub fn init(endpoint: &str) -> Result<(), Box<dyn Error>> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let resource = Resource::builder().with_service_name(NAME).build();
+
+    let spans = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint))
+        .build()?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(spans)
+        .with_resource(resource.clone())
+        .build();
+
+    let metrics = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/metrics", endpoint))
+        .build()?;
+    let meter_provider = SdkMeterProvider::builder()
+        .with_periodic_exporter(metrics)
+        .with_resource(resource)
+        .build();
+
+    global::set_tracer_provider(tracer_provider.clone());
+    global::set_meter_provider(meter_provider.clone());
+    PROVIDERS.set((tracer_provider, meter_provider)).ok();
+    Ok(())
+}
+
+/// Export whatever is still buffered. Called before exiting, since the
+/// process may end through `process::exit` without running destructors.
+pub fn shutdown() {
+    if let Some((tracer_provider, meter_provider)) = PROVIDERS.get() {
+        tracer_provider.shutdown().ok();
+        meter_provider.shutdown().ok();
+    }
+}
+
+/// Record a finished request to the n3rgy API.
+pub fn api_call(
+    energy_type: &str,
+    request_type: &str,
+    started: SystemTime,
+    error: Option<&dyn Error>,
+) {
+    let attributes = vec![
+        KeyValue::new("energy_type", energy_type.to_string()),
+        KeyValue::new("request_type", request_type.to_string()),
+    ];
+    record(
+        "n3rgy.fetch",
+        &API_DURATION,
+        started,
+        attributes,
+        Vec::new(),
+        error,
+    );
+}
+
+/// Record a finished write of a window's batch.
+pub fn batch(
+    (start, end): (String, String),
+    points: usize,
+    started: SystemTime,
+    error: Option<&dyn Error>,
+) {
+    let details = vec![
+        KeyValue::new("window.start", start),
+        KeyValue::new("window.end", end),
+        KeyValue::new("points", points as i64),
+    ];
+    record(
+        "n3rgy.batch",
+        &BATCH_DURATION,
+        started,
+        Vec::new(),
+        details,
+        error,
+    );
+}
+
+/// Record a finished write to the sink.
+pub fn sink_write(
+    sink: &'static str,
+    points: usize,
+    started: SystemTime,
+    error: Option<&dyn Error>,
+) {
+    let attributes = vec![KeyValue::new("sink", sink)];
+    let details = vec![KeyValue::new("points", points as i64)];
+    record(
+        "n3rgy.sink.write",
+        &SINK_DURATION,
+        started,
+        attributes,
+        details,
+        error,
+    );
+}
+
+/// A span covering `started` until now, and its duration in `histogram`,
+/// with an `error` attribute so error rates can be derived from either.
+/// `details` only go on the span, keeping the metric's cardinality down.
+fn record(
+    name: &'static str,
+    histogram: &Histogram<f64>,
+    started: SystemTime,
+    mut attributes: Vec<KeyValue>,
+    details: Vec<KeyValue>,
+    error: Option<&dyn Error>,
+) {
+    let elapsed = started.elapsed().unwrap_or(Duration::ZERO);
+    attributes.push(KeyValue::new("error", error.is_some()));
+    histogram.record(elapsed.as_secs_f64(), &attributes);
+
+    attributes.extend(details);
+    let tracer = global::tracer(NAME);
+    let mut span = tracer
+        .span_builder(name)
+        .with_start_time(started)
+        .with_attributes(attributes)
+        .start(&tracer);
+    if let Some(error) = error {
+        span.set_status(Status::error(error.to_string()));
+    }
+    span.end();
+}
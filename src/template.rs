@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Placeholders a template may use.
+pub const VARIABLES: [&str; 6] = ["fuel", "type", "element", "mpxn", "property", "profile"];
+
+/// A measurement name or tag value with `{name}` placeholders filled in per
+/// point, e.g. `{fuel}_{type}`. `{{` and `}}` are literal braces.
+#[derive(Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone)]
+enum Part {
+    Literal(String),
+    Variable(&'static str),
+}
+
+impl Template {
+    /// A template with no placeholders.
+    pub fn literal(value: &str) -> Template {
+        Template {
+            parts: vec![Part::Literal(value.to_string())],
+        }
+    }
+
+    /// Parse a template allowing only `variables` as placeholders.
+    pub fn parse(value: &str, variables: &[&'static str]) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = value.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("unclosed {{ in {}", value));
+                    }
+                    let variable = variables.iter().find(|v| **v == name).ok_or_else(|| {
+                        format!(
+                            "{{{}}} in {} is not one of {{{}}}",
+                            name,
+                            value,
+                            variables.join("}, {")
+                        )
+                    })?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Variable(variable));
+                }
+                '}' => return Err(format!("unmatched }} in {}", value)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Whether the template has a `{name}` placeholder.
+    pub fn uses(&self, name: &str) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Variable(variable) if *variable == name))
+    }
+
+    /// Fill in the placeholders, leaving out any `values` has no entry for.
+    pub fn render(&self, values: &BTreeMap<&str, String>) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Variable(name) => {
+                    if let Some(value) = values.get(name) {
+                        rendered.push_str(value);
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Template::parse(value, &VARIABLES)
+    }
+}
@@ -0,0 +1,344 @@
+use std::fs;
+use std::path::Path;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use n3rgy_rs::models::Granularity;
+
+use crate::cli::{
+    self, AdminCommand, ApiArgs, Cli, Command, InfluxArgs, LoadArgs, ReportCommand, ValidateArgs,
+};
+use crate::config::Config;
+use crate::doctor::{self, Check, Status};
+
+/// Shown in place of tokens and passwords.
+const REDACTED: &str = "[redacted]";
+
+/// Check the config file and the command line in `args` without running it,
+/// then print the settings it would run with, secrets redacted.
+///
+/// Returns whether every check passed, as `doctor` does.
+pub async fn run(
+    config: Option<&Path>,
+    profile: Option<&str>,
+    influx_http: Option<&influx_reqwest::Client>,
+    args: ValidateArgs,
+) -> bool {
+    let mut checks = vec![doctor::check_config(config)];
+    let parsed = if args.command.is_empty() {
+        checks.push(Check::new(
+            "command line",
+            Status::Skip,
+            "none given, e.g. `config validate -- serve electricity consumption`",
+        ));
+        None
+    } else {
+        let argv = command_line(config, profile, &args.command);
+        match parse(&argv) {
+            Ok(parsed) => {
+                checks.push(Check::new(
+                    "command line",
+                    Status::Pass,
+                    args.command.join(" "),
+                ));
+                Some(parsed)
+            }
+            Err(e) => {
+                checks.push(Check::new("command line", Status::Fail, e));
+                None
+            }
+        }
+    };
+    if let Some((cli, matches)) = &parsed {
+        let config = config.and_then(|path| Config::load(path).ok());
+        checks.extend(conflicts(cli, config.as_ref()));
+        if args.connect {
+            checks.push(match cli.command.as_ref().and_then(influx_args) {
+                // InfluxDB is only written to when no other sink is chosen
+                Some(_) if leaf(matches).contains_id("sink") => Check::new(
+                    "sink",
+                    Status::Skip,
+                    "only InfluxDB connections are checked",
+                ),
+                Some(influx) => check_sink(influx, influx_http).await,
+                None => Check::new("sink", Status::Skip, "the command writes nowhere"),
+            });
+        }
+    }
+    let passed = doctor::report(&checks);
+
+    if let Some(path) = config {
+        if let Some(contents) = redacted_config(path) {
+            println!("\n# {}\n{}", path.display(), contents.trim_end());
+        }
+    }
+    if let Some((_, matches)) = &parsed {
+        println!("\n# effective settings");
+        print_settings(&Cli::command(), matches, true);
+    }
+    passed
+}
+
+/// The command line to parse, given the `--config` and `--profile` this run
+/// was given when it doesn't set its own.
+fn command_line(config: Option<&Path>, profile: Option<&str>, command: &[String]) -> Vec<String> {
+    let mut argv = vec![env!("CARGO_PKG_NAME").to_string()];
+    let sets = |flag: &str| {
+        command
+            .iter()
+            .any(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)))
+    };
+    if let (Some(path), false) = (config, sets("--config")) {
+        argv.extend(["--config".to_string(), path.display().to_string()]);
+    }
+    if let (Some(profile), false) = (profile, sets("--profile")) {
+        argv.extend(["--profile".to_string(), profile.to_string()]);
+    }
+    argv.extend(command.iter().cloned());
+    argv
+}
+
+/// Parse `argv` as `main` would, profile included, reporting the first line of
+/// clap's error when it doesn't.
+fn parse(argv: &[String]) -> Result<(Cli, ArgMatches), String> {
+    let matches = cli::command_from(argv)?
+        .try_get_matches_from(argv)
+        .map_err(|e| {
+            let message = e.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            first.trim_start_matches("error: ").to_string()
+        })?;
+    let cli = Cli::from_arg_matches(&matches).map_err(|e| e.to_string())?;
+    Ok((cli, matches))
+}
+
+/// The matches for the innermost subcommand, where its options are.
+fn leaf(mut matches: &ArgMatches) -> &ArgMatches {
+    while let Some((_, subcommand)) = matches.subcommand() {
+        matches = subcommand;
+    }
+    matches
+}
+
+/// Options clap accepts together but the command would reject or ignore.
+fn conflicts(cli: &Cli, config: Option<&Config>) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let Some(command) = &cli.command else {
+        return checks;
+    };
+    let meters = config.map_or(0, |config| config.meters.len());
+    if let Some(api) = api_args(command) {
+        if api.token.given() && meters > 0 {
+            checks.push(Check::new(
+                "meters",
+                Status::Warn,
+                format!(
+                    "the API token given overrides the {} meter(s) in the config file",
+                    meters
+                ),
+            ));
+        }
+        if api.reconcile_with.contains(&api.source) {
+            checks.push(Check::new(
+                "options",
+                Status::Warn,
+                format!(
+                    "--reconcile-with compares {} with itself, as it's also the --source",
+                    api.source
+                ),
+            ));
+        }
+    }
+    if let Some(load) = load_args(command) {
+        if load.resample.is_some() && matches!(load.granularity, Granularity::Day) {
+            checks.push(Check::new(
+                "options",
+                Status::Fail,
+                "--resample sums half-hourly readings and can't be used with --granularity day",
+            ));
+        }
+        let degree_days = config.is_some_and(|config| {
+            config.degree_days.is_some()
+                || config
+                    .meters
+                    .iter()
+                    .any(|meter| meter.degree_days.is_some())
+        });
+        let solar = config.is_some_and(|config| {
+            config.solar.is_some() || config.meters.iter().any(|meter| meter.solar.is_some())
+        });
+        if solar && load.export_element.is_none() {
+            checks.push(Check::new(
+                "options",
+                Status::Warn,
+                "[solar] estimates self-consumption against exports, so needs --export-element",
+            ));
+        }
+        if degree_days && matches!(load.granularity, Granularity::Day) {
+            checks.push(Check::new(
+                "options",
+                Status::Warn,
+                "[degree_days] needs half-hourly gas, so writes nothing with --granularity day",
+            ));
+        }
+        let ev_charging = config.is_some_and(|config| config.ev_charging.is_some());
+        if ev_charging && matches!(load.granularity, Granularity::Day) {
+            checks.push(Check::new(
+                "options",
+                Status::Warn,
+                "[ev_charging] needs half-hourly electricity, so finds no sessions with --granularity day",
+            ));
+        }
+    }
+    if let Command::Serve(args) = command {
+        if args.watch_config && cli.config.is_none() {
+            checks.push(Check::new(
+                "options",
+                Status::Warn,
+                "--watch-config has no --config file to watch",
+            ));
+        }
+    }
+    checks
+}
+
+fn api_args(command: &Command) -> Option<&ApiArgs> {
+    match command {
+        Command::Fetch(args) => Some(&args.api),
+        Command::Tariff(args) => Some(&args.api),
+        Command::Backfill(args) => Some(&args.api),
+        Command::Sync(args) => Some(&args.api),
+        Command::Serve(args) => Some(&args.api),
+        _ => None,
+    }
+}
+
+fn load_args(command: &Command) -> Option<&LoadArgs> {
+    match command {
+        Command::Fetch(args) => Some(&args.load),
+        Command::Backfill(args) => Some(&args.load),
+        Command::Sync(args) => Some(&args.load),
+        Command::Serve(args) => Some(&args.load),
+        _ => None,
+    }
+}
+
+fn influx_args(command: &Command) -> Option<&InfluxArgs> {
+    match command {
+        Command::Fetch(args) => Some(&args.influx),
+        Command::Tariff(args) => Some(&args.influx),
+        Command::Backfill(args) => Some(&args.influx),
+        Command::Sync(args) => Some(&args.influx),
+        Command::Serve(args) => Some(&args.influx),
+        Command::Import(args) => Some(&args.influx),
+        Command::Migrate(args) => Some(&args.influx),
+        Command::Retag(args) => Some(&args.influx),
+        Command::Admin {
+            command: AdminCommand::Delete(args),
+        } => Some(&args.influx),
+        Command::Report {
+            command: ReportCommand::Budget(args),
+        } => Some(&args.influx),
+        #[cfg(feature = "email")]
+        Command::Report {
+            command: ReportCommand::Email(args),
+        } => Some(&args.influx),
+        _ => None,
+    }
+}
+
+/// Ping InfluxDB when it's the sink; other sinks aren't checked.
+async fn check_sink(influx: &InfluxArgs, http: Option<&influx_reqwest::Client>) -> Check {
+    let auth = match influx.auth() {
+        Ok(auth) => auth,
+        Err(e) => return Check::new("influxdb", Status::Fail, e.to_string()),
+    };
+    let (Some(uri), Some(database), Some(auth)) = (influx.uri(), influx.database(), auth) else {
+        return Check::new(
+            "influxdb",
+            Status::Skip,
+            "N3RGY_INFLUX_URI, N3RGY_INFLUX_DATABASE and a token or username are not all set",
+        );
+    };
+    match auth.client(uri, database, http).ping().await {
+        Ok((build, version)) => Check::new(
+            "influxdb",
+            Status::Pass,
+            format!("{} {} at {}", build, version, uri),
+        ),
+        Err(e) => Check::new("influxdb", Status::Fail, e.to_string()),
+    }
+}
+
+/// The config file as written, with every token and password replaced.
+fn redacted_config(path: &Path) -> Option<String> {
+    let mut table: toml::Table = toml::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    redact(&mut table);
+    toml::to_string_pretty(&table).ok()
+}
+
+fn redact(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        let secret = key == "password" || key.ends_with("_token") || key.ends_with("_password");
+        match value {
+            toml::Value::String(value) if secret => *value = REDACTED.to_string(),
+            toml::Value::Table(table) => redact(table),
+            toml::Value::Array(values) => {
+                for value in values {
+                    if let toml::Value::Table(table) = value {
+                        redact(table);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Each option with a value, and whether it came from the command line, the
+/// environment, the profile or its default. Global options are listed once,
+/// then each subcommand's own.
+fn print_settings(command: &clap::Command, matches: &ArgMatches, top: bool) {
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        if arg.is_hide_set() || matches!(id, "help" | "version") {
+            continue;
+        }
+        if !top && arg.is_global_set() {
+            continue;
+        }
+        let Ok(Some(values)) = matches.try_get_raw(id) else {
+            continue;
+        };
+        let value = if arg.is_hide_env_values_set() {
+            REDACTED.to_string()
+        } else {
+            values
+                .map(|value| value.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let source = match matches.value_source(id) {
+            Some(ValueSource::DefaultValue)
+                if arg
+                    .get_env()
+                    .is_some_and(|name| cli::from_profile(&name.to_string_lossy())) =>
+            {
+                "profile"
+            }
+            Some(ValueSource::DefaultValue) => "default",
+            Some(ValueSource::EnvVariable) => "environment",
+            _ => "command line",
+        };
+        let name = arg
+            .get_long()
+            .map_or(id.to_string(), |long| format!("--{}", long));
+        println!("{} = {} ({})", name, value, source);
+    }
+    if let Some((name, matches)) = matches.subcommand() {
+        if let Some(subcommand) = command.find_subcommand(name) {
+            println!("\n# {}", name);
+            print_settings(subcommand, matches, false);
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Daily temperatures and hourly irradiance from Open-Meteo, and the heating
+//! degree days gas consumption is normalised by so winters of different
+//! severity compare.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::models::{ConsumptionReading, Resource};
+use crate::settlement::{periods_in_day, settlement_date, uk_midnight};
+
+/// Open-Meteo's historical reanalysis, which lags a few days behind today.
+pub const OPEN_METEO_ARCHIVE_URL: &str = "https://archive-api.open-meteo.com/v1/";
+/// Open-Meteo's forecast, which also covers the recent days the archive lacks.
+pub const OPEN_METEO_FORECAST_URL: &str = "https://api.open-meteo.com/v1/";
+
+/// The UK's conventional base temperature: the mean outdoor temperature, in
+/// °C, above which a home needs no heating.
+pub const DEFAULT_BASE_TEMPERATURE: f64 = 15.5;
+
+/// Furthest back the forecast API answers.
+const FORECAST_PAST_DAYS: i64 = 92;
+
+/// One of Open-Meteo's weather variables, as asked for in a request.
+struct Variable {
+    /// `daily` or `hourly`.
+    resolution: &'static str,
+    name: &'static str,
+    /// Timezone times are given in, and daily values taken over.
+    timezone: &'static str,
+    /// `iso8601` dates, or `unixtime` seconds, as times within a day are
+    /// written without the seconds chrono expects.
+    timeformat: &'static str,
+}
+
+const MEAN_TEMPERATURE: Variable = Variable {
+    resolution: "daily",
+    name: "temperature_2m_mean",
+    timezone: "Europe%2FLondon",
+    timeformat: "iso8601",
+};
+
+/// Averaged over the hour before each time given.
+const SHORTWAVE_RADIATION: Variable = Variable {
+    resolution: "hourly",
+    name: "shortwave_radiation",
+    timezone: "GMT",
+    timeformat: "unixtime",
+};
+
+#[derive(Deserialize)]
+struct Response<T> {
+    daily: Option<Series<T>>,
+    hourly: Option<Series<T>>,
+}
+
+#[derive(Deserialize)]
+struct Series<T> {
+    time: Vec<T>,
+    /// Each variable asked for, with `null` where there's no value yet.
+    #[serde(flatten)]
+    values: BTreeMap<String, Vec<Option<f64>>>,
+}
+
+/// Times Open-Meteo gives values at.
+trait Dated {
+    fn date(&self) -> NaiveDate;
+}
+
+impl Dated for NaiveDate {
+    fn date(&self) -> NaiveDate {
+        *self
+    }
+}
+
+impl Dated for i64 {
+    fn date(&self) -> NaiveDate {
+        DateTime::from_timestamp(*self, 0)
+            .unwrap_or_default()
+            .date_naive()
+    }
+}
+
+/// What Open-Meteo answers a bad request with.
+#[derive(Deserialize)]
+struct Failure {
+    reason: String,
+}
+
+/// A UK day's gas consumption against how cold it was.
+#[derive(Clone, Debug)]
+pub struct DegreeDay {
+    /// Midnight at the start of the UK day.
+    pub time: DateTime<Utc>,
+    /// Mean outdoor temperature, in °C.
+    pub temperature: f64,
+    /// How far the mean temperature fell below the base temperature.
+    pub heating_degree_days: f64,
+    pub consumption: f64,
+    /// Unit of `consumption`, e.g. `kWh` or `m3`.
+    pub unit: String,
+    /// n3rgy resource path, written as the tags from [`Resource::add_tags`].
+    pub resource: String,
+}
+
+impl InfluxDbWriteable for DegreeDay {
+    /// Also writes `consumption_per_degree_day` on days that needed heating.
+    fn into_query<I: Into<String>>(self, name: I) -> WriteQuery {
+        let mut query = WriteQuery::new(self.time, name)
+            .add_field("temperature", self.temperature)
+            .add_field("heating_degree_days", self.heating_degree_days)
+            .add_field("consumption", self.consumption)
+            .add_tag("unit", self.unit);
+        if self.heating_degree_days > 0.0 {
+            query = query.add_field(
+                "consumption_per_degree_day",
+                self.consumption / self.heating_degree_days,
+            );
+        }
+        Resource::parse(&self.resource).add_tags(query)
+    }
+}
+
+/// Half-hourly readings summed by UK day, ignoring repeats of a reading
+/// already seen (e.g. at window seams).
+#[derive(Default)]
+pub struct DailyConsumption {
+    seen: HashSet<(String, DateTime<Utc>)>,
+    /// `(consumption, readings, unit)` by resource and day.
+    days: BTreeMap<(String, NaiveDate), (f64, u32, String)>,
+}
+
+impl DailyConsumption {
+    pub fn add_reading(&mut self, reading: &ConsumptionReading) {
+        if !self.seen.insert((reading.resource.clone(), reading.time)) {
+            return;
+        }
+        let day = self
+            .days
+            .entry((reading.resource.clone(), settlement_date(reading.time)))
+            .or_insert_with(|| (0.0, 0, reading.unit.clone()));
+        day.0 += reading.consumption;
+        day.1 += 1;
+    }
+
+    /// First and last days holding every one of their half hours.
+    pub fn complete_range(&self) -> Option<(NaiveDate, NaiveDate)> {
+        let mut dates = self.complete().map(|((_, date), _)| *date);
+        let first = dates.next()?;
+        Some(dates.fold((first, first), |(first, last), date| {
+            (first.min(date), last.max(date))
+        }))
+    }
+
+    fn complete(&self) -> impl Iterator<Item = (&(String, NaiveDate), &(f64, u32, String))> {
+        self.days
+            .iter()
+            .filter(|((_, date), (_, readings, _))| *readings == periods_in_day(*date))
+    }
+
+    /// Each complete day with a known temperature, against `base_temperature`.
+    pub fn degree_days(
+        &self,
+        temperatures: &BTreeMap<NaiveDate, f64>,
+        base_temperature: f64,
+    ) -> Vec<DegreeDay> {
+        self.complete()
+            .filter_map(|((resource, date), (consumption, _, unit))| {
+                let temperature = *temperatures.get(date)?;
+                Some(DegreeDay {
+                    time: uk_midnight(*date),
+                    temperature,
+                    heating_degree_days: (base_temperature - temperature).max(0.0),
+                    consumption: *consumption,
+                    unit: unit.clone(),
+                    resource: resource.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct WeatherClient {
+    http: reqwest::Client,
+    archive_url: String,
+    forecast_url: String,
+}
+
+impl Default for WeatherClient {
+    fn default() -> Self {
+        WeatherClient::new()
+    }
+}
+
+fn with_slash(url: impl Into<String>) -> String {
+    let mut url = url.into();
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    url
+}
+
+impl WeatherClient {
+    pub fn new() -> WeatherClient {
+        WeatherClient {
+            http: reqwest::Client::new(),
+            archive_url: OPEN_METEO_ARCHIVE_URL.to_string(),
+            forecast_url: OPEN_METEO_FORECAST_URL.to_string(),
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> WeatherClient {
+        self.http = http;
+        self
+    }
+
+    pub fn with_archive_url(mut self, archive_url: impl Into<String>) -> WeatherClient {
+        self.archive_url = with_slash(archive_url);
+        self
+    }
+
+    pub fn with_forecast_url(mut self, forecast_url: impl Into<String>) -> WeatherClient {
+        self.forecast_url = with_slash(forecast_url);
+        self
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        let error = |reason: String| Error::Weather {
+            url: url.to_string(),
+            reason,
+        };
+        let res = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| error(e.to_string()))?;
+        let status = res.status();
+        let body = res.text().await.map_err(|e| error(e.to_string()))?;
+        if !status.is_success() {
+            return Err(error(match serde_json::from_str::<Failure>(&body) {
+                Ok(failure) => failure.reason,
+                Err(_) => format!("responded {}", status),
+            }));
+        }
+        serde_json::from_str(&body).map_err(|source| Error::UnexpectedBody {
+            url: url.to_string(),
+            source,
+        })
+    }
+
+    /// Fill in `values` from one of the APIs, returning the first day it
+    /// had no value for, as times come in order.
+    async fn fetch<T>(
+        &self,
+        (base_url, endpoint): (&str, &str),
+        (latitude, longitude): (f64, f64),
+        (start, end): (NaiveDate, NaiveDate),
+        variable: &Variable,
+        values: &mut BTreeMap<T, f64>,
+    ) -> Result<Option<NaiveDate>, Error>
+    where
+        T: DeserializeOwned + Ord + Dated + Display,
+    {
+        let url = format!(
+            "{}{}?latitude={}&longitude={}&start_date={}&end_date={}&{}={}&timezone={}&timeformat={}",
+            base_url,
+            endpoint,
+            latitude,
+            longitude,
+            start,
+            end,
+            variable.resolution,
+            variable.name,
+            variable.timezone,
+            variable.timeformat
+        );
+        let response = self.get::<Response<T>>(&url).await?;
+        let Some(mut series) = response.daily.or(response.hourly) else {
+            return Ok(Some(start));
+        };
+        let measured = series.values.remove(variable.name).unwrap_or_default();
+        let mut missing = None;
+        for (time, value) in series.time.into_iter().zip(measured) {
+            match value {
+                // the archive's reanalysis wins over the forecast
+                Some(value) => {
+                    values.entry(time).or_insert(value);
+                }
+                None => {
+                    debug!("no {} for {} from {}", variable.name, time, endpoint);
+                    missing.get_or_insert(time.date());
+                }
+            }
+        }
+        Ok(missing)
+    }
+
+    /// `variable` at a location from `start` to `end` inclusive, from the
+    /// archive where it has it and the forecast for the days since.
+    async fn series<T>(
+        &self,
+        location: (f64, f64),
+        (start, end): (NaiveDate, NaiveDate),
+        variable: &Variable,
+    ) -> Result<BTreeMap<T, f64>, Error>
+    where
+        T: DeserializeOwned + Ord + Dated + Display,
+    {
+        let mut values = BTreeMap::new();
+        let archive = (self.archive_url.as_str(), "archive");
+        let missing = self
+            .fetch(archive, location, (start, end), variable, &mut values)
+            .await?;
+        let earliest = Utc::now().date_naive() - Duration::days(FORECAST_PAST_DAYS);
+        if let Some(missing) = missing {
+            let forecast = (self.forecast_url.as_str(), "forecast");
+            let from = std::cmp::max(missing, earliest);
+            if from <= end {
+                self.fetch(forecast, location, (from, end), variable, &mut values)
+                    .await?;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Mean outdoor temperature, in °C, of each UK day from `start` to `end`
+    /// inclusive at a location.
+    pub async fn daily_temperatures(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<BTreeMap<NaiveDate, f64>, Error> {
+        self.series((latitude, longitude), (start, end), &MEAN_TEMPERATURE)
+            .await
+    }
+
+    /// Mean solar irradiance on flat ground, in W/m², over each hour of the
+    /// UTC days from `start` to `end` inclusive at a location, keyed by the
+    /// hour's end.
+    pub async fn solar_radiation(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<BTreeMap<DateTime<Utc>, f64>, Error> {
+        let hourly: BTreeMap<i64, f64> = self
+            .series((latitude, longitude), (start, end), &SHORTWAVE_RADIATION)
+            .await?;
+        Ok(hourly
+            .into_iter()
+            .filter_map(|(time, value)| Some((DateTime::from_timestamp(time, 0)?, value)))
+            .collect())
+    }
+}
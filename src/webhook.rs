@@ -0,0 +1,25 @@
+use log::warn;
+use serde_json::Value;
+
+/// A URL events are POSTed to as JSON, e.g. a Slack or Discord incoming
+/// webhook or a home automation endpoint.
+pub struct Webhook {
+    pub http: reqwest::Client,
+    pub url: String,
+}
+
+impl Webhook {
+    /// Send `body`, logging rather than failing the run when it can't be.
+    pub async fn post(&self, body: &Value) {
+        let sent = self
+            .http
+            .post(&self.url)
+            .json(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = sent {
+            warn!("could not send to webhook {}: {}", self.url, e);
+        }
+    }
+}